@@ -0,0 +1,164 @@
+//! Read latency of [`Cache`] while a geyser-like writer inserts account updates.
+//!
+//! Run with `cargo bench -p nexus --bench cache_contention`.
+//! Compares the published snapshots of [`Cache`] against the exclusive mutex and the shared
+//! read lock the cache used before.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use nexus::drift_client::{Cache, InnerCache};
+use nexus::{AcctCtx, RingMap};
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::{Mutex, RwLock};
+
+/// Drift user accounts streamed from geyser
+const ACCOUNTS: usize = 10_000;
+/// Account updates per second at processed commitment on mainnet
+const UPDATES_PER_SEC: u64 = 5_000;
+/// Geyser delivers the updates of a slot in a burst
+const SLOT: Duration = Duration::from_millis(400);
+/// Engine loops reading the cache concurrently
+const READERS: usize = 4;
+/// Each engine iteration takes the cache this many times
+const READS_PER_ITER: usize = 3;
+const DURATION: Duration = Duration::from_secs(5);
+const DEPTH: usize = 200;
+
+fn inner_cache(keys: &[Pubkey]) -> InnerCache {
+  let mut cache = InnerCache {
+    slot: 0,
+    depth: DEPTH,
+    blocks: RingMap::new(DEPTH),
+    accounts: im::HashMap::new(),
+    key_registry: HashMap::new(),
+  };
+  for key in keys {
+    cache.ring_mut(*key).insert(0, ctx(*key, 0));
+  }
+  cache
+}
+
+fn ctx(key: Pubkey, slot: u64) -> AcctCtx {
+  AcctCtx {
+    key,
+    account: Account {
+      lamports: slot,
+      data: vec![0; 4376],
+      ..Default::default()
+    },
+    slot,
+  }
+}
+
+struct Report {
+  waits: Vec<Duration>,
+  writes: u64,
+}
+
+impl Report {
+  fn print(mut self, name: &str) {
+    self.waits.sort();
+    let pct = |p: f64| self.waits[((self.waits.len() - 1) as f64 * p) as usize];
+    println!(
+      "{:<8} reads: {:>9}, writes/s: {:>6}, p50: {:>9?}, p99: {:>9?}, p99.9: {:>9?}, max: {:>9?}",
+      name,
+      self.waits.len(),
+      self.writes / DURATION.as_secs(),
+      pct(0.5),
+      pct(0.99),
+      pct(0.999),
+      self.waits.last().copied().unwrap_or_default(),
+    );
+  }
+}
+
+/// Spawn a writer bursting a slot of updates every [`SLOT`] and `READERS` engine loops against
+/// `$lock`, where `$read` takes the lock and `$insert` applies one update, and time how long
+/// readers wait for it.
+macro_rules! bench {
+  ($lock:expr, $read:ident, $insert:path) => {{
+    let keys: Arc<Vec<Pubkey>> = Arc::new((0..ACCOUNTS).map(|_| Pubkey::new_unique()).collect());
+    let lock = Arc::new($lock(inner_cache(&keys)));
+    let run = Arc::new(AtomicBool::new(true));
+    let writes = Arc::new(AtomicU64::new(0));
+
+    let writer = {
+      let (lock, keys, run, writes) = (lock.clone(), keys.clone(), run.clone(), writes.clone());
+      tokio::task::spawn(async move {
+        let per_slot = UPDATES_PER_SEC * SLOT.as_millis() as u64 / 1000;
+        let mut interval = tokio::time::interval(SLOT);
+        let mut n = 0;
+        let mut slot = 0;
+        while run.load(Ordering::Relaxed) {
+          interval.tick().await;
+          slot += 1;
+          for _ in 0..per_slot {
+            n += 1;
+            let key = keys[n % keys.len()];
+            $insert(&lock, ctx(key, slot)).await;
+            // the stream yields between updates while it awaits the next one
+            tokio::task::yield_now().await;
+          }
+          writes.fetch_add(per_slot, Ordering::Relaxed);
+        }
+      })
+    };
+
+    let readers: Vec<_> = (0..READERS)
+      .map(|i| {
+        let (lock, keys, run) = (lock.clone(), keys.clone(), run.clone());
+        tokio::task::spawn(async move {
+          let mut waits = vec![];
+          let mut n = i;
+          while run.load(Ordering::Relaxed) {
+            for _ in 0..READS_PER_ITER {
+              let now = Instant::now();
+              let cache = lock.$read().await;
+              waits.push(now.elapsed());
+              n = (n + 7919) % keys.len();
+              let _ = cache.account(&keys[n], None);
+            }
+            tokio::task::yield_now().await;
+          }
+          waits
+        })
+      })
+      .collect();
+
+    tokio::time::sleep(DURATION).await;
+    run.store(false, Ordering::Relaxed);
+    writer.await.unwrap();
+    let mut waits = vec![];
+    for reader in readers {
+      waits.extend(reader.await.unwrap());
+    }
+    Report {
+      waits,
+      writes: writes.load(Ordering::Relaxed),
+    }
+  }};
+}
+
+async fn insert_mutex(lock: &Mutex<InnerCache>, update: AcctCtx) {
+  let mut cache = lock.lock().await;
+  cache.ring_mut(update.key).insert(update.slot, update);
+}
+
+async fn insert_rwlock(lock: &RwLock<InnerCache>, update: AcctCtx) {
+  let mut cache = lock.write().await;
+  cache.ring_mut(update.key).insert(update.slot, update);
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+  let mutex = bench!(Mutex::new, lock, insert_mutex);
+  mutex.print("mutex");
+  let rwlock = bench!(RwLock::new, read, insert_rwlock);
+  rwlock.print("rwlock");
+  let cache = bench!(Cache::from, read, Cache::insert);
+  cache.print("cache");
+}
@@ -0,0 +1,406 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use log::*;
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_rpc_client_api::config::RpcTransactionConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::UiTransactionEncoding;
+
+use crate::{LookupTableCache, NexusClient, RpcSource, TokenBucket, TrxData};
+
+/// Attempts per `getTransaction` before its signature is skipped
+const FETCH_ATTEMPTS: usize = 4;
+/// Wait before the first retry, doubled after each failed attempt
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Progress of a [`Backfill`] crawl, written to disk after every page
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BackfillCheckpoint {
+  pub key: String,
+  /// Newest signature of the crawl. Use as `until` in a later crawl to fetch only newer history.
+  pub newest: Option<String>,
+  /// Oldest signature processed, the next page is fetched `before` it
+  pub before: Option<String>,
+  /// Stop once this signature is reached, exclusive
+  pub until: Option<String>,
+  /// Transactions processed
+  pub fetched: usize,
+  /// History back to `until` (or the first transaction of the account) is processed
+  pub done: bool,
+  /// Signatures that still failed to fetch after retrying, left out of their page
+  #[serde(default)]
+  pub skipped: Vec<String>,
+}
+
+impl BackfillCheckpoint {
+  pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+    if !path.exists() {
+      return Ok(None);
+    }
+    let bytes = std::fs::read(path)?;
+    Ok(Some(serde_json::from_slice(&bytes)?))
+  }
+
+  /// Write to a temporary file and rename, so a crash mid-write never corrupts the checkpoint
+  pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+  }
+}
+
+/// Crawls the transaction history of an account from newest to oldest.
+/// Transactions are fetched with bounded concurrency behind a token bucket rate limit,
+/// and a [`BackfillCheckpoint`] is persisted after each page so the crawl resumes after a restart.
+pub struct Backfill {
+  rpc: Arc<dyn RpcSource>,
+  key: Pubkey,
+  checkpoint: PathBuf,
+  until: Option<Signature>,
+  page_size: usize,
+  concurrency: usize,
+  max_transactions: Option<usize>,
+  limiter: TokenBucket,
+  lookup_tables: LookupTableCache,
+}
+
+impl Backfill {
+  /// Defaults to 10 concurrent requests and 10 requests per second
+  pub fn new(rpc: Arc<dyn RpcSource>, key: Pubkey, checkpoint: impl Into<PathBuf>) -> Self {
+    Self {
+      rpc,
+      key,
+      checkpoint: checkpoint.into(),
+      until: None,
+      page_size: 1000,
+      concurrency: 10,
+      max_transactions: None,
+      limiter: TokenBucket::new(10.0, 10),
+      lookup_tables: LookupTableCache::default(),
+    }
+  }
+
+  /// Stop at this signature instead of the first transaction of the account.
+  /// Ignored when resuming from a checkpoint, which already records its bound.
+  pub fn until(mut self, until: Signature) -> Self {
+    self.until = Some(until);
+    self
+  }
+
+  /// Signatures per `getSignaturesForAddress` request, max 1000
+  pub fn page_size(mut self, page_size: usize) -> Self {
+    self.page_size = page_size.clamp(1, 1000);
+    self
+  }
+
+  /// Max `getTransaction` requests in flight
+  pub fn concurrency(mut self, concurrency: usize) -> Self {
+    self.concurrency = concurrency.max(1);
+    self
+  }
+
+  /// Requests per second across signature pages and transactions
+  pub fn rate_limit(mut self, per_second: f64, burst: usize) -> Self {
+    self.limiter = TokenBucket::new(per_second, burst);
+    self
+  }
+
+  /// Stop after this many transactions in total, counting those processed before a restart
+  pub fn max_transactions(mut self, max: usize) -> Self {
+    self.max_transactions = Some(max);
+    self
+  }
+
+  /// Run until history is exhausted or `max_transactions` is reached, passing each page to `on_page`.
+  /// The checkpoint only advances after `on_page` succeeds, so an error never loses a page,
+  /// but a page may be handed out again if the process dies between `on_page` and the checkpoint write.
+  pub async fn run<F>(&self, mut on_page: F) -> anyhow::Result<BackfillCheckpoint>
+  where
+    F: FnMut(Vec<TrxData>) -> anyhow::Result<()>,
+  {
+    let mut checkpoint = match BackfillCheckpoint::load(&self.checkpoint)? {
+      Some(checkpoint) => {
+        if checkpoint.key != self.key.to_string() {
+          return Err(anyhow::anyhow!(
+            "Checkpoint {:?} is for {}, not {}",
+            self.checkpoint,
+            checkpoint.key,
+            self.key
+          ));
+        }
+        info!(
+          "Resume backfill of {} before {:?}, {} fetched",
+          self.key, checkpoint.before, checkpoint.fetched
+        );
+        checkpoint
+      }
+      None => BackfillCheckpoint {
+        key: self.key.to_string(),
+        until: self.until.map(|s| s.to_string()),
+        ..Default::default()
+      },
+    };
+    let until = checkpoint
+      .until
+      .as_deref()
+      .map(Signature::from_str)
+      .transpose()?;
+
+    while !checkpoint.done {
+      let remaining = match self.max_transactions {
+        Some(max) if checkpoint.fetched >= max => break,
+        Some(max) => max - checkpoint.fetched,
+        None => usize::MAX,
+      };
+      let cfg = GetConfirmedSignaturesForAddress2Config {
+        before: checkpoint
+          .before
+          .as_deref()
+          .map(Signature::from_str)
+          .transpose()?,
+        until,
+        limit: Some(self.page_size.min(remaining)),
+        commitment: Some(CommitmentConfig::confirmed()),
+      };
+      self.limiter.acquire().await;
+      let page = self
+        .rpc
+        .get_signatures_for_address_with_config(&self.key, cfg)
+        .await?;
+      // a short page is not proof the history is exhausted, only an empty one is
+      let last = match page.last() {
+        Some(last) => last.signature.clone(),
+        None => {
+          checkpoint.done = true;
+          checkpoint.save(&self.checkpoint)?;
+          break;
+        }
+      };
+      if checkpoint.newest.is_none() {
+        checkpoint.newest = Some(page[0].signature.clone());
+      }
+      let sigs = page
+        .iter()
+        .map(|s| Signature::from_str(&s.signature))
+        .collect::<Result<Vec<_>, _>>()?;
+
+      let (txs, skipped) = self.transactions(&sigs).await;
+      on_page(txs)?;
+
+      checkpoint
+        .skipped
+        .extend(skipped.iter().map(|s| s.to_string()));
+      // `before` is exclusive, so the oldest signature of this page is the cursor for the next
+      checkpoint.before = Some(last);
+      checkpoint.fetched += sigs.len();
+      checkpoint.save(&self.checkpoint)?;
+      debug!(
+        "Backfill {} fetched {} before {:?}",
+        self.key, checkpoint.fetched, checkpoint.before
+      );
+    }
+    Ok(checkpoint)
+  }
+
+  async fn transactions(&self, sigs: &[Signature]) -> (Vec<TrxData>, Vec<Signature>) {
+    Self::fetch_transactions(
+      self.rpc.as_ref(),
+      &self.lookup_tables,
+      sigs,
+      self.concurrency,
+      Some(&self.limiter),
+    )
+    .await
+  }
+
+  /// Fetch and decode transactions with at most `concurrency` requests in flight, in the order of `sigs`.
+  /// Transactions that can't be decoded are skipped. A failed request is retried with backoff,
+  /// and signatures that still fail are returned as the second element instead of failing the batch.
+  pub async fn fetch_transactions(
+    rpc: &dyn RpcSource,
+    lookup_tables: &LookupTableCache,
+    sigs: &[Signature],
+    concurrency: usize,
+    limiter: Option<&TokenBucket>,
+  ) -> (Vec<TrxData>, Vec<Signature>) {
+    let opts = RpcTransactionConfig {
+      // json encoded transactions can't be decoded
+      encoding: Some(UiTransactionEncoding::Base64),
+      commitment: Some(CommitmentConfig::confirmed()),
+      max_supported_transaction_version: Some(0),
+    };
+    let results: Vec<(Signature, anyhow::Result<Option<TrxData>>)> = futures::stream::iter(sigs)
+      .map(|signature| async move {
+        let res = Self::fetch_transaction(rpc, lookup_tables, signature, opts, limiter).await;
+        (*signature, res)
+      })
+      .buffered(concurrency.max(1))
+      .collect()
+      .await;
+
+    let mut txs = Vec::with_capacity(results.len());
+    let mut skipped = vec![];
+    for (signature, res) in results {
+      match res {
+        Ok(Some(tx)) => txs.push(tx),
+        Ok(None) => {}
+        Err(e) => {
+          warn!(
+            "Skip transaction {} after {} attempts: {:?}",
+            signature, FETCH_ATTEMPTS, e
+          );
+          skipped.push(signature);
+        }
+      }
+    }
+    (txs, skipped)
+  }
+
+  async fn fetch_transaction(
+    rpc: &dyn RpcSource,
+    lookup_tables: &LookupTableCache,
+    signature: &Signature,
+    opts: RpcTransactionConfig,
+    limiter: Option<&TokenBucket>,
+  ) -> anyhow::Result<Option<TrxData>> {
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 1;
+    loop {
+      if let Some(limiter) = limiter {
+        limiter.acquire().await;
+      }
+      let res = match rpc.get_transaction_with_config(signature, opts).await {
+        Ok(tx_info) => {
+          NexusClient::trx_data(
+            rpc,
+            lookup_tables,
+            *signature,
+            tx_info.slot,
+            tx_info.block_time,
+            tx_info.transaction,
+          )
+          .await
+        }
+        Err(e) => Err(e),
+      };
+      match res {
+        Err(e) if attempt < FETCH_ATTEMPTS => {
+          debug!("Retry transaction {} in {:?}: {:?}", signature, backoff, e);
+          tokio::time::sleep(backoff).await;
+          backoff *= 2;
+          attempt += 1;
+        }
+        res => return res,
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn checkpoint_round_trip() -> anyhow::Result<()> {
+    let path = std::env::temp_dir().join(format!("backfill_{}.json", Pubkey::new_unique()));
+    assert_eq!(BackfillCheckpoint::load(&path)?, None);
+
+    let checkpoint = BackfillCheckpoint {
+      key: Pubkey::new_unique().to_string(),
+      newest: Some(Signature::new_unique().to_string()),
+      before: Some(Signature::new_unique().to_string()),
+      until: None,
+      fetched: 2000,
+      done: false,
+      skipped: vec![Signature::new_unique().to_string()],
+    };
+    checkpoint.save(&path)?;
+    assert_eq!(BackfillCheckpoint::load(&path)?, Some(checkpoint));
+    std::fs::remove_file(&path)?;
+    Ok(())
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn run_pages_resumes_and_skips() -> anyhow::Result<()> {
+    use solana_sdk::hash::Hash;
+    use solana_sdk::instruction::{AccountMeta, Instruction};
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_sdk::transaction::{Transaction, VersionedTransaction};
+
+    use crate::FakeRpc;
+
+    let key = Pubkey::new_unique();
+    let payer = Keypair::new();
+    let mut rpc = FakeRpc::new();
+    // oldest first
+    let mut sigs = vec![];
+    for slot in 1..=6 {
+      let ix = Instruction::new_with_bytes(
+        Pubkey::new_unique(),
+        &[],
+        vec![AccountMeta::new_readonly(key, false)],
+      );
+      let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&payer.pubkey()),
+        &[&payer],
+        Hash::new_unique(),
+      );
+      sigs.push(tx.signatures[0]);
+      rpc = rpc.transaction(&[key], slot, VersionedTransaction::from(tx));
+    }
+    // one recovers after retrying, the other never does
+    let rpc = Arc::new(
+      rpc
+        .fail_transaction(sigs[4], FETCH_ATTEMPTS - 1)
+        .fail_transaction(sigs[3], usize::MAX),
+    );
+    let path = std::env::temp_dir().join(format!("backfill_{}.json", Pubkey::new_unique()));
+
+    let mut seen = vec![];
+    let checkpoint = Backfill::new(rpc.clone(), key, &path)
+      .until(sigs[0])
+      .page_size(2)
+      .rate_limit(1000.0, 1000)
+      .max_transactions(3)
+      .run(|txs| {
+        seen.extend(txs.iter().map(|tx| tx.signature));
+        Ok(())
+      })
+      .await?;
+    // the second page is cut short by `max_transactions`
+    assert_eq!(seen, vec![sigs[5], sigs[4]]);
+    assert_eq!(checkpoint.fetched, 3);
+    assert!(!checkpoint.done);
+    assert_eq!(checkpoint.newest, Some(sigs[5].to_string()));
+    assert_eq!(checkpoint.before, Some(sigs[3].to_string()));
+    assert_eq!(checkpoint.skipped, vec![sigs[3].to_string()]);
+
+    // resumes before the last signature and stops at the checkpoint's `until`, not the new one
+    let checkpoint = Backfill::new(rpc.clone(), key, &path)
+      .until(sigs[2])
+      .page_size(2)
+      .rate_limit(1000.0, 1000)
+      .run(|txs| {
+        seen.extend(txs.iter().map(|tx| tx.signature));
+        Ok(())
+      })
+      .await?;
+    assert_eq!(seen, vec![sigs[5], sigs[4], sigs[2], sigs[1]]);
+    assert_eq!(checkpoint.fetched, 5);
+    assert!(checkpoint.done);
+    assert_eq!(checkpoint.skipped, vec![sigs[3].to_string()]);
+    assert_eq!(BackfillCheckpoint::load(&path)?, Some(checkpoint));
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+  }
+}
@@ -64,6 +64,8 @@ pub struct Backtest<T, S: Strategy<T>> {
   pub leverage: u8,
   /// False if spot trading, true if margin trading which allows short selling
   pub short_selling: bool,
+  /// Write an HTML report alongside the PNG plot in [`Backtest::execute`]
+  pub html_report: bool,
   pub series: HashMap<String, Vec<Data>>,
   pub trades: HashMap<String, Vec<Trade>>,
   pub signals: HashMap<String, Vec<Trade>>,
@@ -88,6 +90,7 @@ impl Default for Backtest<f64, EmptyStrategy> {
       bet: Bet::Percent(100.0),
       leverage: 1,
       short_selling: false,
+      html_report: false,
       series: HashMap::new(),
       trades: HashMap::new(),
       signals: HashMap::new(),
@@ -112,6 +115,7 @@ impl<T, S: Strategy<T>> Backtest<T, S> {
       bet: Bet::Percent(100.0),
       leverage: 1,
       short_selling: false,
+      html_report: false,
       series: HashMap::new(),
       trades: HashMap::new(),
       signals: HashMap::new(),
@@ -153,6 +157,10 @@ impl<T, S: Strategy<T>> Backtest<T, S> {
     self.short_selling = value;
     self
   }
+  pub fn html_report(mut self, value: bool) -> Self {
+    self.html_report = value;
+    self
+  }
 
   pub fn get_series(&self, ticker: &str) -> anyhow::Result<&Vec<Data>> {
     self
@@ -689,7 +697,7 @@ impl<T, S: Strategy<T>> Backtest<T, S> {
                 label: "Strategy".to_string(),
               },
               Series {
-                data: bah,
+                data: bah.clone(),
                 label: "Buy & Hold".to_string(),
               },
             ],
@@ -704,6 +712,19 @@ impl<T, S: Strategy<T>> Backtest<T, S> {
             "Unix Millis",
             Some(summary.pct_roi(ticker) > 0.0),
           )?;
+          if self.html_report {
+            HtmlReport::new(&summary, ticker, plot_title)
+              .benchmark(Series {
+                data: bah,
+                label: "Buy & Hold".to_string(),
+              })
+              .write(&format!(
+                "{}_{}_{}_backtest.html",
+                self.strategy.title(),
+                ticker.to_ascii_lowercase(),
+                timeframe
+              ))?;
+          }
         }
       }
     }
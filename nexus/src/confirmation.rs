@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use solana_sdk::clock::Slot;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::TransactionError;
+use solana_transaction_status::TransactionConfirmationStatus;
+use tokio::sync::oneshot;
+use yellowstone_grpc_proto::prelude::{
+  CommitmentLevel, SubscribeRequestFilterTransactions, SubscribeUpdateTransaction,
+};
+
+use crate::{StreamHealth, TransactionResult, TxError};
+
+/// Resolves transactions we sent as soon as the geyser stream sees them land, instead of
+/// polling `get_signature_statuses`. Feed it with [`crate::NexusClient::confirmations`] and
+/// a [`ConfirmationTracker::geyser_filter`] on our signer, then hand it to
+/// [`crate::TrxBuilder::confirmations`].
+/// Transactions resolve at the commitment of the stream, and the builder waits over RPC for
+/// those below its [`crate::ConfirmTransactionConfig::min_confirmation`].
+#[derive(Clone, Default)]
+pub struct ConfirmationTracker {
+  pending: Arc<Mutex<HashMap<Signature, oneshot::Sender<TransactionResult<Slot>>>>>,
+  /// Stream feeding the tracker, unavailable until a client is set
+  stream: Arc<RwLock<Option<TrackedStream>>>,
+}
+
+#[derive(Clone)]
+struct TrackedStream {
+  health: StreamHealth,
+  commitment: TransactionConfirmationStatus,
+}
+
+impl ConfirmationTracker {
+  /// Transactions signed by `signer`, including failed ones so their error resolves too
+  pub fn geyser_filter(signer: &Pubkey) -> SubscribeRequestFilterTransactions {
+    SubscribeRequestFilterTransactions {
+      vote: Some(false),
+      failed: None,
+      signature: None,
+      account_include: vec![],
+      account_exclude: vec![],
+      account_required: vec![signer.to_string()],
+    }
+  }
+
+  /// Follow the stream of a client, see [`crate::NexusClient::confirmations`]
+  pub(crate) fn follow(&self, health: StreamHealth, commitment: CommitmentLevel) {
+    let commitment = match commitment {
+      CommitmentLevel::Processed => TransactionConfirmationStatus::Processed,
+      CommitmentLevel::Confirmed => TransactionConfirmationStatus::Confirmed,
+      CommitmentLevel::Finalized => TransactionConfirmationStatus::Finalized,
+    };
+    if let Ok(mut stream) = self.stream.write() {
+      *stream = Some(TrackedStream { health, commitment });
+    }
+  }
+
+  pub fn is_available(&self) -> bool {
+    self.stream().is_some_and(|s| s.health.is_healthy())
+  }
+
+  /// Commitment transactions resolve at, `None` until the tracker follows a stream
+  pub fn commitment(&self) -> Option<TransactionConfirmationStatus> {
+    self.stream().map(|s| s.commitment)
+  }
+
+  fn stream(&self) -> Option<TrackedStream> {
+    self.stream.read().ok().and_then(|s| s.clone())
+  }
+
+  /// Wait for `sig`, call before sending so a fast confirmation isn't missed
+  pub fn watch(&self, sig: Signature) -> PendingTx {
+    let (tx, rx) = oneshot::channel();
+    self.pending.lock().unwrap().insert(sig, tx);
+    PendingTx {
+      sig,
+      rx,
+      tracker: self.clone(),
+    }
+  }
+
+  /// Resolve the pending transaction of a streamed update, if we are waiting on it
+  pub fn resolve(&self, event: &SubscribeUpdateTransaction) {
+    let info = match &event.transaction {
+      Some(info) => info,
+      None => return,
+    };
+    let sig = match Signature::try_from(info.signature.as_slice()) {
+      Ok(sig) => sig,
+      Err(_) => return,
+    };
+    let sender = match self.pending.lock().unwrap().remove(&sig) {
+      Some(sender) => sender,
+      None => return,
+    };
+    let meta = info.meta.as_ref();
+    let error = meta
+      .and_then(|meta| meta.err.as_ref())
+      .map(|err| bincode::deserialize::<TransactionError>(&err.err));
+    let res = match error {
+      None => Ok(event.slot),
+      Some(Ok(error)) => Err(TxError::TxError {
+        slot: event.slot,
+        error,
+        logs: meta.map(|meta| meta.log_messages.clone()),
+      }),
+      // dropping the sender leaves the waiter to fetch the error over RPC
+      Some(Err(e)) => {
+        log::error!("Failed to decode error of transaction {}: {:?}", sig, e);
+        return;
+      }
+    };
+    // the waiter may have timed out
+    let _ = sender.send(res);
+  }
+
+  fn forget(&self, sig: &Signature) {
+    self.pending.lock().unwrap().remove(sig);
+  }
+}
+
+/// What the stream has seen of a [`PendingTx`]
+#[derive(Debug)]
+pub enum Streamed {
+  Landed(TransactionResult<Slot>),
+  /// Not seen yet
+  Pending,
+  /// The stream is down or couldn't decode the result, check over RPC instead
+  Unavailable,
+}
+
+/// A transaction waiting on a [`ConfirmationTracker`]
+pub struct PendingTx {
+  pub sig: Signature,
+  rx: oneshot::Receiver<TransactionResult<Slot>>,
+  tracker: ConfirmationTracker,
+}
+
+impl PendingTx {
+  /// Commitment the transaction resolves at, see [`ConfirmationTracker::commitment`]
+  pub fn commitment(&self) -> Option<TransactionConfirmationStatus> {
+    self.tracker.commitment()
+  }
+
+  /// Wait up to `timeout` for the stream to see the transaction
+  pub async fn next(&mut self, timeout: Duration) -> Streamed {
+    if !self.tracker.is_available() {
+      return Streamed::Unavailable;
+    }
+    match tokio::time::timeout(timeout, &mut self.rx).await {
+      Ok(Ok(res)) => Streamed::Landed(res),
+      Ok(Err(_)) => Streamed::Unavailable,
+      Err(_) if self.tracker.is_available() => Streamed::Pending,
+      Err(_) => Streamed::Unavailable,
+    }
+  }
+}
+
+impl Drop for PendingTx {
+  fn drop(&mut self) {
+    self.tracker.forget(&self.sig);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::StreamStatus;
+  use yellowstone_grpc_proto::prelude::{SubscribeUpdateTransactionInfo, TransactionStatusMeta};
+
+  fn update(
+    sig: Signature,
+    slot: Slot,
+    err: Option<TransactionError>,
+  ) -> SubscribeUpdateTransaction {
+    SubscribeUpdateTransaction {
+      transaction: Some(SubscribeUpdateTransactionInfo {
+        signature: sig.as_ref().to_vec(),
+        meta: Some(TransactionStatusMeta {
+          err: err.map(|e| yellowstone_grpc_proto::prelude::TransactionError {
+            err: bincode::serialize(&e).unwrap(),
+          }),
+          ..Default::default()
+        }),
+        ..Default::default()
+      }),
+      slot,
+    }
+  }
+
+  #[tokio::test]
+  async fn resolve_from_stream() {
+    let (tracker, health) = (ConfirmationTracker::default(), StreamHealth::default());
+    assert!(!tracker.is_available());
+    tracker.follow(health.clone(), CommitmentLevel::Processed);
+    health.set(StreamStatus::Healthy);
+    let (ok, failed) = (Signature::new_unique(), Signature::new_unique());
+    let timeout = Duration::from_millis(10);
+
+    let mut pending = tracker.watch(ok);
+    assert!(matches!(pending.next(timeout).await, Streamed::Pending));
+    tracker.resolve(&update(ok, 10, None));
+    assert!(matches!(
+      pending.next(timeout).await,
+      Streamed::Landed(Ok(10))
+    ));
+
+    let mut pending = tracker.watch(failed);
+    tracker.resolve(&update(failed, 11, Some(TransactionError::AccountNotFound)));
+    assert!(matches!(
+      pending.next(timeout).await,
+      Streamed::Landed(Err(TxError::TxError {
+        slot: 11,
+        error: TransactionError::AccountNotFound,
+        ..
+      }))
+    ));
+
+    // nothing streamed while the stream is down
+    let mut pending = tracker.watch(Signature::new_unique());
+    health.set(StreamStatus::Down);
+    assert!(matches!(pending.next(timeout).await, Streamed::Unavailable));
+    drop(pending);
+    assert!(tracker.pending.lock().unwrap().is_empty());
+  }
+}
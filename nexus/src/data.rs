@@ -1,4 +1,4 @@
-use crate::{derivative, ema, slope, Bar, Time};
+use crate::{derivative, ema, mean, slope, std_dev, Bar, Time};
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
@@ -292,6 +292,37 @@ impl Dataset {
   pub fn ema(&self, period: usize) -> Vec<f64> {
     self.y().windows(period).map(ema).collect()
   }
+
+  /// Standard deviation of y over a trailing window, stamped with the x of the last point in the window.
+  pub fn rolling_std(&self, window: usize) -> Self {
+    self.rolling(window, std_dev)
+  }
+
+  /// Mean over standard deviation of y over a trailing window, stamped like [`Dataset::rolling_std`].
+  pub fn rolling_sharpe(&self, window: usize) -> Self {
+    self.rolling(window, |y| {
+      let std_dev = std_dev(y);
+      match std_dev == 0.0 {
+        true => 0.0,
+        false => mean(y) / std_dev,
+      }
+    })
+  }
+
+  fn rolling(&self, window: usize, f: impl Fn(&[f64]) -> f64) -> Self {
+    if window == 0 {
+      return Self::new(vec![]);
+    }
+    let y = self.y();
+    let data = self
+      .0
+      .iter()
+      .skip(window - 1)
+      .zip(y.windows(window))
+      .map(|(d, y)| Data { x: d.x(), y: f(y) })
+      .collect();
+    Self::new(data)
+  }
 }
 
 impl From<&[f64]> for Dataset {
@@ -317,3 +348,28 @@ impl From<Vec<f64>> for Dataset {
     )
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rolling_window_edges() {
+    let data = Dataset::from(vec![1.0, 3.0, 1.0, 3.0, 5.0]);
+
+    let std = data.rolling_std(2);
+    assert_eq!(std.x(), vec![1, 2, 3, 4]);
+    assert_eq!(std.y(), vec![1.0, 1.0, 1.0, 1.0]);
+
+    // the full series is a single window, longer windows have none
+    assert_eq!(data.rolling_std(5).x(), vec![4]);
+    assert!(data.rolling_std(6).0.is_empty());
+    assert!(data.rolling_std(0).0.is_empty());
+
+    let sharpe = data.rolling_sharpe(2);
+    assert_eq!(sharpe.y(), vec![2.0, 2.0, 2.0, 4.0]);
+    // a flat window has no volatility to divide by
+    let flat = Dataset::from(vec![2.0, 2.0, 2.0]).rolling_sharpe(2);
+    assert_eq!(flat.y(), vec![0.0, 0.0]);
+  }
+}
@@ -3,6 +3,7 @@
 #![allow(unused_assignments)]
 
 use crate::drift_client::types::*;
+use crate::drift_client::{AmmDepth, AmmLiquidity, L2Level};
 use crate::drift_cpi::*;
 use crate::Time;
 use num_bigint::BigInt;
@@ -39,6 +40,81 @@ impl AmmUtils {
     }
   }
 
+  /// Virtual levels a taker fills against, each the average price of swapping
+  /// `depth.base_per_level` against the spread reserves, until the AMM's reserve limits
+  pub fn liquidity(
+    market: PerpMarket,
+    oracle: OraclePriceData,
+    with_update: bool,
+    depth: &AmmDepth,
+  ) -> AmmLiquidity {
+    let amm = if with_update {
+      Self::calc_updated_amm(&market.amm, oracle)
+    } else {
+      market.amm
+    };
+    let SpreadReserves {
+      bid_reserves,
+      ask_reserves,
+    } = Self::calc_spread_reserves(&amm, oracle);
+    let step = BigInt::from((depth.base_per_level * AMM_RESERVE_PRECISION as f64) as u128);
+    // the AMM fills at most 1 / `max_fill_reserve_fraction` of its base reserve per order
+    let max_fill = match amm.max_fill_reserve_fraction {
+      0 => None,
+      fraction => Some(BigInt::from(amm.base_asset_reserve).div(fraction)),
+    };
+    let walk = |reserves: SpreadReserve, taker_long: bool| -> Vec<L2Level> {
+      let invariant = (&reserves.base_asset_reserve).mul(&reserves.quote_asset_reserve);
+      let (mut base, mut quote) = (reserves.base_asset_reserve, reserves.quote_asset_reserve);
+      let mut filled = BigInt::from(0);
+      let mut levels = vec![];
+      for _ in 0..depth.levels {
+        // a taker long removes base from the AMM and a short adds it, within the reserve limits
+        let mut room = match taker_long {
+          true => (&base).sub(BigInt::from(amm.min_base_asset_reserve.max(1))),
+          false if amm.max_base_asset_reserve > 0 => {
+            BigInt::from(amm.max_base_asset_reserve).sub(&base)
+          }
+          false => step.clone(),
+        };
+        if let Some(max_fill) = &max_fill {
+          room = room.min(max_fill.sub(&filled));
+        }
+        let swap = step.clone().min(room);
+        if !swap.is_positive() {
+          break;
+        }
+        let direction = match taker_long {
+          true => SwapDirection::Remove,
+          false => SwapDirection::Add,
+        };
+        // base is the input here, so the "quote" reserve after the swap is the new base reserve
+        let AmmReservesAfterSwap {
+          new_quote_asset_reserve: new_base,
+          new_base_asset_reserve: new_quote,
+        } = Self::calc_swap_output(base.clone(), swap.clone(), direction, invariant.clone());
+        let price = Self::calc_price(
+          swap.clone(),
+          (&new_quote).sub(&quote).abs(),
+          BigInt::from(amm.peg_multiplier),
+        );
+        levels.push(L2Level {
+          price: price.to_f64().unwrap_or_default() / PRICE_PRECISION as f64,
+          size: swap.to_f64().unwrap_or_default() / AMM_RESERVE_PRECISION as f64,
+          orders: 0,
+        });
+        filled += swap;
+        base = new_base;
+        quote = new_quote;
+      }
+      levels
+    };
+    AmmLiquidity {
+      bids: walk(bid_reserves, false),
+      asks: walk(ask_reserves, true),
+    }
+  }
+
   pub fn ask_price(market: PerpMarket, oracle: OraclePriceData) -> f64 {
     let UpdatedAmmSpreadReserves {
       base_asset_reserve,
@@ -1041,3 +1117,64 @@ impl AmmUtils {
       .div(BigInt::from(base_asset_reserve))
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drift_client::fixtures::zeroed;
+
+  /// Market with 1000 base in reserve at a price of 100 and no spread
+  fn market() -> PerpMarket {
+    let mut market: PerpMarket = zeroed();
+    let reserve = 1_000 * AMM_RESERVE_PRECISION;
+    market.amm.base_asset_reserve = reserve;
+    market.amm.quote_asset_reserve = reserve;
+    market.amm.sqrt_k = reserve;
+    market.amm.peg_multiplier = 100 * PEG_PRECISION;
+    market.amm.max_base_asset_reserve = 2 * reserve;
+    market
+  }
+
+  fn sizes(levels: &[L2Level]) -> Vec<f64> {
+    levels.iter().map(|l| l.size).collect()
+  }
+
+  #[test]
+  fn liquidity_walks_reserves() {
+    let oracle = OraclePriceData {
+      price: 100 * PRICE_PRECISION as i64,
+      confidence: 0,
+      delay: 0,
+      has_sufficient_number_of_data_points: true,
+    };
+    let depth = |levels, base_per_level| AmmDepth {
+      levels,
+      base_per_level,
+    };
+
+    let top = AmmUtils::liquidity(market(), oracle, false, &depth(1, 0.001));
+    let BidAsk { bid, ask } = AmmUtils::bid_ask_prices(market(), oracle, false);
+    assert!(top.bids[0].price <= bid && (bid - top.bids[0].price) / bid < 1e-4);
+    assert!(top.asks[0].price >= ask && (top.asks[0].price - ask) / ask < 1e-4);
+
+    let liquidity = AmmUtils::liquidity(market(), oracle, false, &depth(10, 3.0));
+    assert!(liquidity.bids.windows(2).all(|l| l[0].price > l[1].price));
+    assert!(liquidity.asks.windows(2).all(|l| l[0].price < l[1].price));
+    assert_eq!(liquidity.bids.len(), 10);
+
+    // at most 1/100 of the base reserve per order
+    let mut capped = market();
+    capped.amm.max_fill_reserve_fraction = 100;
+    let liquidity = AmmUtils::liquidity(capped, oracle, false, &depth(10, 3.0));
+    assert_eq!(sizes(&liquidity.bids), vec![3.0, 3.0, 3.0, 1.0]);
+    assert_eq!(sizes(&liquidity.asks), vec![3.0, 3.0, 3.0, 1.0]);
+
+    // 5 base above the min reserve and 4 below the max
+    let mut bounded = market();
+    bounded.amm.min_base_asset_reserve = 995 * AMM_RESERVE_PRECISION;
+    bounded.amm.max_base_asset_reserve = 1_004 * AMM_RESERVE_PRECISION;
+    let liquidity = AmmUtils::liquidity(bounded, oracle, false, &depth(10, 3.0));
+    assert_eq!(sizes(&liquidity.asks), vec![3.0, 2.0]);
+    assert_eq!(sizes(&liquidity.bids), vec![3.0, 1.0]);
+  }
+}
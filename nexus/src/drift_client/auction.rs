@@ -0,0 +1,317 @@
+use std::cmp::Ordering;
+
+use drift_cpi::{Order, OrderTriggerCondition, PRICE_PRECISION};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::drift_client::{DlobNode, DriftUtils, InnerOrderbook, L3Orderbook, MarketId, OrderInfo};
+
+/// Stop or take-profit order waiting for the oracle to cross its trigger price
+#[derive(Debug, Clone)]
+pub struct TriggerOrder {
+  pub user: Pubkey,
+  pub order: Order,
+  pub trigger_price: f64,
+}
+
+/// Untriggered orders of a market, each side first to trigger first
+#[derive(Debug, Clone, Default)]
+pub struct TriggerBook {
+  /// Trigger once the oracle rises above the trigger price, lowest first
+  pub above: Vec<TriggerOrder>,
+  /// Trigger once the oracle falls below the trigger price, highest first
+  pub below: Vec<TriggerOrder>,
+}
+
+impl TriggerBook {
+  /// Orders a keeper can trigger at `oracle_price`
+  pub fn triggerable(&self, oracle_price: f64) -> Vec<&TriggerOrder> {
+    self
+      .above
+      .iter()
+      .take_while(|o| oracle_price > o.trigger_price)
+      .chain(
+        self
+          .below
+          .iter()
+          .take_while(|o| oracle_price < o.trigger_price),
+      )
+      .collect()
+  }
+}
+
+/// Order still in its auction and the auction price at the slot it was queried for
+#[derive(Debug, Clone)]
+pub struct AuctionOrder {
+  pub user: Pubkey,
+  pub order: Order,
+  pub price: f64,
+  /// Slots until the auction ends at its end price
+  pub slots_left: u64,
+}
+
+impl InnerOrderbook {
+  /// Stop and take-profit orders in `market` whose trigger condition has not been met
+  pub fn triggers(&self, market: &MarketId) -> anyhow::Result<TriggerBook> {
+    let mut book = TriggerBook::default();
+    for node in self.nodes(market)? {
+      let order = &node.order;
+      if !DriftUtils::order_must_be_triggered(order) || DriftUtils::order_triggered(order) {
+        continue;
+      }
+      let trigger = TriggerOrder {
+        user: node.user,
+        order: node.order,
+        trigger_price: DriftUtils::price_to_f64(order.trigger_price),
+      };
+      match order.trigger_condition {
+        OrderTriggerCondition::Above => book.above.push(trigger),
+        _ => book.below.push(trigger),
+      }
+    }
+    let by_trigger = |a: &TriggerOrder, b: &TriggerOrder| {
+      a.trigger_price
+        .partial_cmp(&b.trigger_price)
+        .unwrap_or(Ordering::Equal)
+    };
+    book.above.sort_by(by_trigger);
+    book.below.sort_by(|a, b| by_trigger(b, a));
+    Ok(book)
+  }
+
+  /// Orders in `market` still in their auction at `slot`, priced against `oracle_price`
+  pub fn auctions(
+    &self,
+    market: &MarketId,
+    slot: u64,
+    oracle_price: f64,
+  ) -> anyhow::Result<Vec<AuctionOrder>> {
+    let oracle_price = (oracle_price * PRICE_PRECISION as f64).round() as i64;
+    let mut auctions = vec![];
+    for node in self.nodes(market)? {
+      let order = &node.order;
+      // untriggered orders start their auction once triggered
+      if DriftUtils::order_must_be_triggered(order) && !DriftUtils::order_triggered(order) {
+        continue;
+      }
+      if DriftUtils::order_auction_complete(order, slot)? {
+        continue;
+      }
+      if let Some(price) = DriftUtils::auction_price(order, slot, oracle_price) {
+        auctions.push(AuctionOrder {
+          user: node.user,
+          order: node.order,
+          price: price as f64 / PRICE_PRECISION as f64,
+          slots_left: (order.slot + order.auction_duration as u64).saturating_sub(slot),
+        });
+      }
+    }
+    Ok(auctions)
+  }
+
+  fn nodes(&self, market: &MarketId) -> anyhow::Result<impl Iterator<Item = &DlobNode>> {
+    Ok(self.orders(&market.key())?.values().flatten())
+  }
+}
+
+/// Taker order that crosses a resting limit order a filler can match it against
+pub struct Crossing<'a> {
+  pub taker: &'a OrderInfo,
+  pub maker: &'a OrderInfo,
+}
+
+impl L3Orderbook {
+  /// Bids and asks that cross, paired as the taker and the resting limit order it fills against.
+  /// The newer order takes unless only the older one can, and each order is paired
+  /// until its size is used up.
+  pub fn crossing_orders(&self) -> Vec<Crossing<'_>> {
+    let slot = self.slot;
+    let can_take = |o: &OrderInfo| {
+      !o.order.post_only
+        && (!DriftUtils::order_must_be_triggered(&o.order) || DriftUtils::order_triggered(&o.order))
+        && DriftUtils::order_auction_complete(&o.order, slot).unwrap_or(false)
+    };
+    let can_make =
+      |o: &OrderInfo| DriftUtils::order_is_resting_limit(&o.order, slot).unwrap_or(false);
+
+    let mut bids_left: Vec<f64> = self.bids.iter().map(|o| o.size).collect();
+    let mut asks_left: Vec<f64> = self.asks.iter().map(|o| o.size).collect();
+    let mut crossing = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < self.bids.len() && j < self.asks.len() {
+      let (bid, ask) = (&self.bids[i], &self.asks[j]);
+      if bid.price < ask.price {
+        break;
+      }
+      let bid_is_newer = bid.slot >= ask.slot;
+      let (newer, older) = match bid_is_newer {
+        true => (bid, ask),
+        false => (ask, bid),
+      };
+      let pair = if bid.user == ask.user {
+        None
+      } else if can_take(newer) && can_make(older) {
+        Some((newer, older))
+      } else if can_take(older) && can_make(newer) {
+        Some((older, newer))
+      } else {
+        None
+      };
+      match pair {
+        Some((taker, maker)) => {
+          crossing.push(Crossing { taker, maker });
+          let filled = bids_left[i].min(asks_left[j]);
+          bids_left[i] -= filled;
+          asks_left[j] -= filled;
+          if bids_left[i] <= 0.0 {
+            i += 1;
+          }
+          if asks_left[j] <= 0.0 {
+            j += 1;
+          }
+        }
+        // the older order may still cross one deeper in the book
+        None if bid_is_newer => i += 1,
+        None => j += 1,
+      }
+    }
+    crossing
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use drift_cpi::{MarketType, OrderStatus, OrderType, PositionDirection};
+
+  fn order(order_type: OrderType, direction: PositionDirection) -> Order {
+    Order {
+      status: OrderStatus::Open,
+      order_type,
+      market_type: MarketType::Perp,
+      direction,
+      base_asset_amount: 100,
+      slot: 100,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn auction_price_curve() {
+    let market = Order {
+      auction_start_price: 100_000_000,
+      auction_end_price: 110_000_000,
+      auction_duration: 10,
+      ..order(OrderType::Market, PositionDirection::Long)
+    };
+    assert_eq!(
+      DriftUtils::auction_price(&market, 100, 0),
+      Some(100_000_000)
+    );
+    assert_eq!(
+      DriftUtils::auction_price(&market, 105, 0),
+      Some(105_000_000)
+    );
+    assert_eq!(
+      DriftUtils::auction_price(&market, 200, 0),
+      Some(110_000_000)
+    );
+
+    // oracle auctions are offsets from the oracle
+    let oracle = Order {
+      order_type: OrderType::Oracle,
+      auction_start_price: -1_000_000,
+      auction_end_price: 1_000_000,
+      ..market
+    };
+    assert_eq!(
+      DriftUtils::auction_price(&oracle, 105, 50_000_000),
+      Some(50_000_000)
+    );
+
+    // a limit order's auction never crosses its limit price
+    let limit = Order {
+      order_type: OrderType::Limit,
+      price: 104_000_000,
+      ..market
+    };
+    assert_eq!(DriftUtils::auction_price(&limit, 108, 0), Some(104_000_000));
+  }
+
+  #[test]
+  fn triggers_and_auctions() -> anyhow::Result<()> {
+    let market = MarketId::perp(0);
+    let (user, key) = (Pubkey::new_unique(), market.key());
+    let mut book = InnerOrderbook::from_markets(vec![market]);
+    let stop = |order_id, trigger_price, trigger_condition| Order {
+      order_id,
+      trigger_price,
+      trigger_condition,
+      ..order(OrderType::TriggerMarket, PositionDirection::Short)
+    };
+    book.insert_order(key, user, stop(1, 90_000_000, OrderTriggerCondition::Below))?;
+    book.insert_order(key, user, stop(2, 95_000_000, OrderTriggerCondition::Below))?;
+    book.insert_order(
+      key,
+      user,
+      stop(3, 110_000_000, OrderTriggerCondition::Above),
+    )?;
+    let auction = Order {
+      order_id: 4,
+      auction_start_price: 100_000_000,
+      auction_end_price: 98_000_000,
+      auction_duration: 20,
+      ..order(OrderType::Market, PositionDirection::Short)
+    };
+    book.insert_order(key, user, auction)?;
+
+    let triggers = book.triggers(&market)?;
+    assert_eq!(triggers.below.len(), 2);
+    assert_eq!(triggers.below[0].order.order_id, 2);
+    let ids: Vec<u32> = triggers
+      .triggerable(94.0)
+      .iter()
+      .map(|o| o.order.order_id)
+      .collect();
+    assert_eq!(ids, vec![2]);
+
+    let auctions = book.auctions(&market, 110, 100.0)?;
+    assert_eq!(auctions.len(), 1);
+    assert_eq!((auctions[0].price, auctions[0].slots_left), (99.0, 10));
+    assert!(book.auctions(&market, 121, 100.0)?.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn crossing_limit_orders() {
+    let info = |price: f64, size: f64, slot: u64, direction: PositionDirection| OrderInfo {
+      price,
+      size,
+      slot,
+      user: Pubkey::new_unique(),
+      order: Order {
+        slot,
+        ..order(OrderType::Limit, direction)
+      },
+    };
+    let mut post_only = info(100.5, 2.0, 20, PositionDirection::Long);
+    post_only.order.post_only = true;
+    let l3 = L3Orderbook {
+      bids: vec![info(101.0, 1.0, 10, PositionDirection::Long), post_only],
+      asks: vec![
+        info(100.0, 2.0, 15, PositionDirection::Short),
+        info(102.0, 1.0, 5, PositionDirection::Short),
+      ],
+      spread: 0.0,
+      slot: 30,
+      oracle_price: 100.0,
+      last_price: 100.0,
+    };
+    let pairs: Vec<(f64, f64)> = l3
+      .crossing_orders()
+      .iter()
+      .map(|c| (c.taker.price, c.maker.price))
+      .collect();
+    // the newer ask takes the first bid, then takes the post only bid that can't take
+    assert_eq!(pairs, vec![(100.0, 101.0), (100.0, 100.5)]);
+  }
+}
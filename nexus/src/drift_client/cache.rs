@@ -1,69 +1,130 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use anchor_lang::AccountDeserialize;
+use anchor_lang::{AccountDeserialize, Discriminator};
+use borsh::{BorshDeserialize, BorshSerialize};
 use drift_cpi::User;
-use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_rpc_client_api::config::RpcBlockConfig;
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::pubkey::Pubkey;
-use tokio::sync::{Mutex, MutexGuard};
 
-use crate::drift_client::{DriftUtils, PerpOracle, SpotOracle};
-use crate::{AcctCtx, BlockInfo, DecodedAcctCtx, RingMap, Time};
+use crate::drift_client::{CacheView, DriftUtils, PerpOracle, SpotOracle, Watcher, Watchers};
+use crate::{
+  AcctCtx, BlockInfo, DecodedAcctCtx, RingMap, RpcSource, SwapCell, SwapWriteGuard, Time,
+};
 
-#[derive(Debug, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
 pub enum CacheKeyRegistry {
   PerpMarkets,
   SpotMarkets,
 }
 
+/// Readers load the latest published [`InnerCache`] without waiting, while the geyser stream
+/// writes a copy and publishes it. The copy is cheap since unchanged rings and accounts are shared
+/// with the previous version.
 pub struct Cache {
-  cache: Arc<Mutex<InnerCache>>,
+  cache: Arc<SwapCell<InnerCache>>,
+  watchers: Arc<std::sync::Mutex<Watchers>>,
 }
 
 impl Clone for Cache {
   fn clone(&self) -> Self {
     Self {
       cache: self.cache.clone(),
+      watchers: self.watchers.clone(),
     }
   }
 }
 
+#[derive(Clone)]
 pub struct InnerCache {
   /// How many versions back to keep in the cache
   pub slot: u64,
   pub depth: usize,
   /// Key is slot
   pub blocks: RingMap<u64, BlockInfo>,
-  pub accounts: HashMap<Pubkey, RingMap<u64, AcctCtx>>,
+  pub accounts: im::HashMap<Pubkey, Arc<RingMap<u64, AcctCtx>>>,
   pub key_registry: HashMap<CacheKeyRegistry, Vec<Pubkey>>,
 }
 
-// pub type ReadCache<'a> = RwLockReadGuard<'a, Cache>;
-// pub type WriteCache<'a> = RwLockWriteGuard<'a, Cache>;
-pub type ReadCache<'a> = MutexGuard<'a, InnerCache>;
-pub type WriteCache<'a> = MutexGuard<'a, InnerCache>;
+/// Version of the cache published when it was read, later writes don't change it
+pub type ReadCache = Arc<InnerCache>;
+pub type WriteCache<'a> = SwapWriteGuard<'a, InnerCache>;
 
-impl Cache {
-  pub fn new(depth: usize) -> Self {
+impl From<InnerCache> for Cache {
+  fn from(cache: InnerCache) -> Self {
     Self {
-      cache: Arc::new(Mutex::new(InnerCache {
-        slot: 0,
-        blocks: RingMap::new(depth),
-        depth,
-        accounts: HashMap::new(),
-        key_registry: HashMap::new(),
-      })),
+      cache: Arc::new(SwapCell::new(cache)),
+      watchers: Arc::default(),
     }
   }
+}
+
+impl Cache {
+  pub fn new(depth: usize) -> Self {
+    Self::from(InnerCache {
+      slot: 0,
+      blocks: RingMap::new(depth),
+      depth,
+      accounts: im::HashMap::new(),
+      key_registry: HashMap::new(),
+    })
+  }
 
+  /// Never waits on the stream, hold the result for a consistent view across reads
   pub async fn read(&self) -> ReadCache {
-    self.cache.lock().await
+    self.cache.load()
   }
 
+  /// Waits for other writers, readers keep seeing the previous version until this is dropped
   pub async fn write(&self) -> WriteCache {
-    self.cache.lock().await
+    self.cache.write().await
+  }
+
+  /// Insert a streamed account update and send it to the account's watchers
+  /// if it is newer than the cached state and its data changed
+  pub async fn insert(&self, ctx: AcctCtx) {
+    let watched = self
+      .watchers
+      .lock()
+      .expect("Cache watchers lock poisoned")
+      .is_watched(&ctx);
+    if !watched {
+      self.write().await.ring_mut(ctx.key).insert(ctx.slot, ctx);
+      return;
+    }
+    let old = {
+      let mut cache = self.write().await;
+      // newest by slot, a recovery re-fetch can insert an older slot last
+      let old = cache
+        .ring(&ctx.key)
+        .ok()
+        .and_then(|ring| ring.values().max_by_key(|old| old.slot))
+        .cloned();
+      cache.ring_mut(ctx.key).insert(ctx.slot, ctx.clone());
+      old
+    };
+    // notify once published and without the writer held, so watchers never delay the stream
+    let changed = match &old {
+      Some(old) => ctx.slot >= old.slot && old.account.data != ctx.account.data,
+      None => true,
+    };
+    if changed {
+      let mut watchers = self.watchers.lock().expect("Cache watchers lock poisoned");
+      watchers.notify(old, ctx);
+    }
+  }
+
+  /// Changes to the account at `key`, such as `watch::<User>(key)` to react to fills
+  pub fn watch<T: AccountDeserialize + Clone>(&self, key: Pubkey) -> Watcher<T> {
+    let mut watchers = self.watchers.lock().expect("Cache watchers lock poisoned");
+    Watcher::new(watchers.watch_key(key))
+  }
+
+  /// Changes to every account of type `T`
+  pub fn watch_all<T: AccountDeserialize + Discriminator + Clone>(&self) -> Watcher<T> {
+    let mut watchers = self.watchers.lock().expect("Cache watchers lock poisoned");
+    Watcher::new(watchers.watch_discriminator(T::discriminator()))
   }
 }
 
@@ -84,6 +145,11 @@ impl InnerCache {
     })
   }
 
+  /// Consistent view of every account as of `slot`, see [`CacheView`]
+  pub fn snapshot_at(&self, slot: u64) -> CacheView<'_> {
+    CacheView::new(self, slot)
+  }
+
   fn take_closest_slot_for_account(
     &self,
     key: &Pubkey,
@@ -94,20 +160,7 @@ impl InnerCache {
       .get(key)
       .ok_or(anyhow::anyhow!("Cache not found for key: {}", key))?;
     Ok(match slot {
-      Some(slot) => match ring.get(&slot) {
-        Some(acct) => acct,
-        None => {
-          let recent_updates = ring
-            .values()
-            .filter(|a| a.slot <= slot)
-            .collect::<Vec<&AcctCtx>>();
-          recent_updates.last().ok_or(anyhow::anyhow!(
-            "Failed to get any slot <= {} for key: {}",
-            slot,
-            key
-          ))?
-        }
-      },
+      Some(slot) => self.snapshot_at(slot).account(key)?,
       None => {
         ring
           .newest()
@@ -179,8 +232,10 @@ impl InnerCache {
     self.take_closest_slot_for_account(key, slot)
   }
 
+  /// Accounts updated exactly at `slot`, or the newest version of every account.
+  /// Use [`InnerCache::snapshot_at`] for the state of all accounts as of a slot.
   pub fn accounts(&self, slot: Option<u64>) -> anyhow::Result<Vec<&AcctCtx>> {
-    let accts: Vec<&RingMap<u64, AcctCtx>> = self.accounts.values().collect();
+    let accts: Vec<&RingMap<u64, AcctCtx>> = self.accounts.values().map(Arc::as_ref).collect();
     Ok(match slot {
       Some(slot) => accts.iter().flat_map(|r| r.get(&slot)).collect(),
       None => accts
@@ -209,7 +264,7 @@ impl InnerCache {
     &self,
     slot: Option<u64>,
   ) -> anyhow::Result<Vec<DecodedAcctCtx<T>>> {
-    let accts: Vec<&RingMap<u64, AcctCtx>> = self.accounts.values().collect();
+    let accts: Vec<&RingMap<u64, AcctCtx>> = self.accounts.values().map(Arc::as_ref).collect();
     let accts: Vec<DecodedAcctCtx<T>> = match slot {
       Some(slot) => accts
         .iter()
@@ -269,19 +324,24 @@ impl InnerCache {
     self
       .accounts
       .get(key)
+      .map(Arc::as_ref)
       .ok_or(anyhow::anyhow!("RingMap not found for key: {}", key))
   }
 
+  /// Copies the ring if a published version still shares it
   pub fn ring_mut(&mut self, key: Pubkey) -> &mut RingMap<u64, AcctCtx> {
-    if self.accounts.get_mut(&key).is_none() {
-      self.accounts.insert(key, RingMap::new(self.depth));
-    }
-    self.accounts.get_mut(&key).unwrap()
+    let depth = self.depth;
+    Arc::make_mut(
+      self
+        .accounts
+        .entry(key)
+        .or_insert_with(|| Arc::new(RingMap::new(depth))),
+    )
   }
 
   pub async fn load(
     &mut self,
-    rpc: &RpcClient,
+    rpc: &dyn RpcSource,
     users: &[Pubkey],
     accounts: Option<&[Pubkey]>,
     auths: &[Pubkey],
@@ -301,7 +361,7 @@ impl InnerCache {
 
   pub async fn load_with_all_users(
     &mut self,
-    rpc: &RpcClient,
+    rpc: &dyn RpcSource,
     users: Option<Vec<DecodedAcctCtx<User>>>,
     accounts: Option<&[Pubkey]>,
     auths: &[Pubkey],
@@ -319,7 +379,7 @@ impl InnerCache {
     Ok(())
   }
 
-  async fn load_perp_markets(&mut self, rpc: &RpcClient) -> anyhow::Result<()> {
+  async fn load_perp_markets(&mut self, rpc: &dyn RpcSource) -> anyhow::Result<()> {
     let perps = DriftUtils::perp_markets(rpc).await?;
     for perp in perps {
       self.ring_mut(perp.key).insert(
@@ -334,7 +394,7 @@ impl InnerCache {
     Ok(())
   }
 
-  async fn load_spot_markets(&mut self, rpc: &RpcClient) -> anyhow::Result<()> {
+  async fn load_spot_markets(&mut self, rpc: &dyn RpcSource) -> anyhow::Result<()> {
     let spots = DriftUtils::spot_markets(rpc).await?;
     for spot in spots {
       self.ring_mut(spot.key).insert(
@@ -351,7 +411,7 @@ impl InnerCache {
 
   async fn load_select_users_from_rpc(
     &mut self,
-    rpc: &RpcClient,
+    rpc: &dyn RpcSource,
     filter: &[Pubkey],
   ) -> anyhow::Result<()> {
     let res = rpc
@@ -374,7 +434,7 @@ impl InnerCache {
 
   async fn load_users(
     &mut self,
-    rpc: &RpcClient,
+    rpc: &dyn RpcSource,
     users: Option<Vec<DecodedAcctCtx<User>>>,
   ) -> anyhow::Result<()> {
     let users = match users {
@@ -394,7 +454,7 @@ impl InnerCache {
     Ok(())
   }
 
-  async fn load_user_stats(&mut self, rpc: &RpcClient, auths: &[Pubkey]) -> anyhow::Result<()> {
+  async fn load_user_stats(&mut self, rpc: &dyn RpcSource, auths: &[Pubkey]) -> anyhow::Result<()> {
     let accts = DriftUtils::user_stats(rpc, auths).await?;
     for ctx in accts {
       self.ring_mut(ctx.key).insert(
@@ -409,7 +469,7 @@ impl InnerCache {
     Ok(())
   }
 
-  async fn load_oracles(&mut self, rpc: &RpcClient) -> anyhow::Result<()> {
+  async fn load_oracles(&mut self, rpc: &dyn RpcSource) -> anyhow::Result<()> {
     let perp_markets = DriftUtils::perp_markets(rpc).await?;
     let spot_markets = DriftUtils::spot_markets(rpc).await?;
     let mut perp_oracles = HashMap::new();
@@ -498,7 +558,7 @@ impl InnerCache {
     Ok(())
   }
 
-  async fn load_accounts(&mut self, rpc: &RpcClient, filter: &[Pubkey]) -> anyhow::Result<()> {
+  async fn load_accounts(&mut self, rpc: &dyn RpcSource, filter: &[Pubkey]) -> anyhow::Result<()> {
     let res = rpc
       .get_multiple_accounts_with_commitment(filter, CommitmentConfig::confirmed())
       .await?;
@@ -515,7 +575,7 @@ impl InnerCache {
     Ok(())
   }
 
-  async fn load_block(&mut self, rpc: &RpcClient) -> anyhow::Result<()> {
+  async fn load_block(&mut self, rpc: &dyn RpcSource) -> anyhow::Result<()> {
     let slot = rpc
       .get_slot_with_commitment(CommitmentConfig::confirmed())
       .await?;
@@ -540,7 +600,7 @@ impl InnerCache {
     Ok(())
   }
 
-  async fn load_slot(&mut self, rpc: &RpcClient) -> anyhow::Result<()> {
+  async fn load_slot(&mut self, rpc: &dyn RpcSource) -> anyhow::Result<()> {
     let slot = rpc
       .get_slot_with_commitment(CommitmentConfig::confirmed())
       .await?;
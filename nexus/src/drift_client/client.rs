@@ -6,7 +6,6 @@ use anchor_lang::{InstructionData, ToAccountMetas};
 use log::info;
 use num_bigint::BigInt;
 use num_traits::{Signed, ToPrimitive};
-use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::account_info::AccountInfo;
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::instruction::{AccountMeta, Instruction};
@@ -22,7 +21,7 @@ use crate::*;
 pub struct DriftClient {
   /// either User account authority or delegate
   signer: Arc<Keypair>,
-  rpc: Arc<RpcClient>,
+  rpc: Arc<dyn RpcSource>,
   /// contextual on-chain program data
   program_data: ProgramData,
   /// the drift subaccount address
@@ -30,12 +29,16 @@ pub struct DriftClient {
   gql: Option<GraphqlClient>,
   read_only: bool,
   retry_until_confirmed: bool,
+  /// max-age limits enforced before building any order instruction
+  staleness: StalenessConfig,
+  /// resolves sent transactions from the geyser stream
+  confirmations: Option<ConfirmationTracker>,
 }
 
 impl DriftClient {
   pub async fn new(
     signer: Arc<Keypair>,
-    rpc: Arc<RpcClient>,
+    rpc: Arc<dyn RpcSource>,
     sub_account_id: u16,
     graphql_url: Option<String>,
     read_only: bool,
@@ -55,9 +58,45 @@ impl DriftClient {
       gql,
       read_only,
       retry_until_confirmed,
+      staleness: StalenessConfig::default(),
+      confirmations: None,
     })
   }
 
+  pub fn staleness(mut self, staleness: StalenessConfig) -> Self {
+    self.staleness = staleness;
+    self
+  }
+
+  /// Confirm our transactions over the geyser stream that feeds `tracker`
+  pub fn confirmations(mut self, tracker: ConfirmationTracker) -> Self {
+    self.confirmations = Some(tracker);
+    self
+  }
+
+  /// Errors with [`StaleDataError`] if cached data for `markets` or our user is too old to trade on.
+  /// Called by every order instruction builder.
+  pub fn check_staleness(
+    &self,
+    cache: &ReadCache,
+    markets: &[MarketId],
+  ) -> Result<(), StaleDataError> {
+    self.staleness.check(cache, markets, &[self.sub_account])
+  }
+
+  /// [`DriftClient::check_staleness`] that also requires the stream feeding the cache to be healthy,
+  /// for engines to pause on before building orders
+  pub fn ready_to_trade(
+    &self,
+    health: &StreamHealth,
+    cache: &ReadCache,
+    markets: &[MarketId],
+  ) -> Result<(), StaleDataError> {
+    self
+      .staleness
+      .check_stream(health, cache, markets, &[self.sub_account])
+  }
+
   pub fn new_tx(&self, with_lookup_tables: bool) -> KeypairTrx<'_> {
     let alt = if with_lookup_tables {
       vec![self.program_data.lookup_table.clone()]
@@ -77,12 +116,15 @@ impl DriftClient {
     if self.read_only {
       trx = trx.read_only();
     }
+    if let Some(tracker) = &self.confirmations {
+      trx = trx.confirmations(tracker.clone());
+    }
     trx
   }
 
   pub async fn add_spot_market_to_remaining_accounts_map(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     market_index: u16,
     writable: bool,
     oracle_account_map: &mut HashMap<String, AccountInfo<'static>>,
@@ -109,7 +151,7 @@ impl DriftClient {
 
   pub async fn add_perp_market_to_remaining_accounts_map(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     market_index: u16,
     writable: bool,
     oracle_account_map: &mut HashMap<String, AccountInfo<'static>>,
@@ -155,7 +197,7 @@ impl DriftClient {
   /// https://github.com/drift-labs/protocol-v2/blob/6808189602a5f255905018f769ca01bc0344a4bc/sdk/src/driftClient.ts#L1689
   pub async fn remaining_account_maps_for_users(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     users: &[User],
   ) -> anyhow::Result<RemainingAccountMaps> {
     let mut oracle_account_map: HashMap<String, AccountInfo> = HashMap::new();
@@ -215,7 +257,7 @@ impl DriftClient {
   /// https://github.com/drift-labs/protocol-v2/blob/6808189602a5f255905018f769ca01bc0344a4bc/sdk/src/driftClient.ts#L1519
   pub async fn remaining_accounts(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     params: RemainingAccountParams,
   ) -> anyhow::Result<Vec<AccountInfo<'static>>> {
     let RemainingAccountMaps {
@@ -507,13 +549,17 @@ impl DriftClient {
 
   pub async fn place_and_take_order_ix(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     order: OrderParams,
     maker_info: Option<User>,
     // For spot orders only
     fulfillment_type: Option<SpotFulfillmentType>,
     trx: &mut KeypairTrx<'_>,
   ) -> anyhow::Result<()> {
+    self.check_staleness(
+      cache,
+      &[MarketId::from((order.market_index, order.market_type))],
+    )?;
     let order_price = self.order_price(
       MarketId::from((order.market_index, order.market_type)),
       cache,
@@ -583,7 +629,7 @@ impl DriftClient {
 
   pub async fn place_orders_ix(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     params: Vec<OrderParams>,
     trx: &mut KeypairTrx<'_>,
   ) -> anyhow::Result<()> {
@@ -596,6 +642,7 @@ impl DriftClient {
       .iter()
       .map(|o| (o.market_index, o.market_type).into())
       .collect();
+    self.check_staleness(cache, &readable_accounts)?;
 
     let accounts = accounts::PlaceOrders {
       state,
@@ -624,7 +671,7 @@ impl DriftClient {
   pub async fn copy_place_orders_ix(
     &self,
     tx_slot: u64,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     params: Vec<OrderParams>,
     market_filter: Option<&[MarketId]>,
     trx: &mut KeypairTrx<'_>,
@@ -638,6 +685,7 @@ impl DriftClient {
       .iter()
       .map(|o| (o.market_index, o.market_type).into())
       .collect();
+    self.check_staleness(cache, &readable_accounts)?;
 
     let accounts = accounts::PlaceOrders {
       state,
@@ -727,7 +775,7 @@ impl DriftClient {
 
   pub async fn cancel_orders_ix(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     market: Option<MarketId>,
     direction: Option<PositionDirection>,
     trx: &mut KeypairTrx<'_>,
@@ -771,7 +819,7 @@ impl DriftClient {
 
   pub async fn cancel_orders_by_ids_ix(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     orders: Vec<&Order>,
     trx: &mut KeypairTrx<'_>,
   ) -> anyhow::Result<()> {
@@ -806,7 +854,7 @@ impl DriftClient {
 
   pub async fn close_perp_positions(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     markets: &[MarketId],
     must_be_maker: bool,
     attempt_breakeven: bool,
@@ -911,7 +959,7 @@ impl DriftClient {
 
   pub async fn perp_take_profit(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     pos: &PerpPosition,
     tp_price: f64,
     trx: &mut KeypairTrx<'_>,
@@ -966,11 +1014,12 @@ impl DriftClient {
 
   pub async fn arb_perp_ix(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     market: MarketId,
     makers: Vec<MakerInfo>,
     trx: &mut KeypairTrx<'_>,
   ) -> anyhow::Result<()> {
+    self.check_staleness(cache, &[market])?;
     let user = cache
       .decoded_account::<User>(&self.sub_account, None)?
       .decoded;
@@ -1038,6 +1087,323 @@ impl DriftClient {
     Ok(())
   }
 
+  /// Fill the taker's order during its auction through the jit proxy.
+  /// The proxy only fills if the auction price crosses `params.bid` or `params.ask`,
+  /// and no more than keeps our position within `params.min_position` and `params.max_position`.
+  pub async fn jit_ix(
+    &self,
+    cache: &ReadCache,
+    taker: &TakerInfo,
+    params: jit_proxy_cpi::JitParams,
+    trx: &mut KeypairTrx<'_>,
+  ) -> anyhow::Result<()> {
+    let market = MarketId::from((taker.order.market_index, taker.order.market_type));
+    self.check_staleness(cache, &[market])?;
+    let user = cache
+      .decoded_account::<User>(&self.sub_account, None)?
+      .decoded;
+
+    let ctx = jit_proxy_cpi::accounts::Jit {
+      state: DriftUtils::state_pda(),
+      user: self.sub_account,
+      user_stats: DriftUtils::user_stats_pda(&self.signer.pubkey()),
+      taker: taker.taker,
+      taker_stats: taker.taker_user_stats,
+      authority: self.signer.pubkey(),
+      drift_program: id(),
+    };
+    let users = [&taker.taker_user, &user];
+    let accounts = match market.kind {
+      MarketType::Perp => self.build_accounts(ctx, &users, &[], &[market]),
+      MarketType::Spot => {
+        let mut accounts = self.build_accounts(ctx, &users, &[], &[market, MarketId::QUOTE_SPOT]);
+        // spot fills move tokens between the base and quote vaults
+        for index in [market.index, QUOTE_SPOT_MARKET_INDEX] {
+          accounts.push(AccountMeta {
+            pubkey: DriftUtils::spot_market_vault(index),
+            is_writable: false,
+            is_signer: false,
+          });
+        }
+        accounts
+      }
+    };
+
+    trx.add_ixs(vec![Instruction {
+      program_id: jit_proxy_cpi::id(),
+      accounts,
+      data: jit_proxy_cpi::instruction::Jit { _params: params }.data(),
+    }]);
+
+    Ok(())
+  }
+
+  /// Fill the taker's perp order against the makers' resting orders, or the vAMM without makers,
+  /// for the filler reward
+  pub async fn fill_perp_order_ix(
+    &self,
+    cache: &ReadCache,
+    taker: &TakerInfo,
+    makers: &[MakerInfo],
+    trx: &mut KeypairTrx<'_>,
+  ) -> anyhow::Result<()> {
+    let market = MarketId::perp(taker.order.market_index);
+    self.check_staleness(cache, &[market])?;
+
+    let ctx = accounts::FillPerpOrder {
+      state: DriftUtils::state_pda(),
+      authority: self.signer.pubkey(),
+      filler: self.sub_account,
+      filler_stats: DriftUtils::user_stats_pda(&self.signer.pubkey()),
+      user: taker.taker,
+      user_stats: taker.taker_user_stats,
+    };
+    let users: Vec<&User> = std::iter::once(&taker.taker_user)
+      .chain(makers.iter().map(|m| &m.maker_user))
+      .collect();
+    let mut accounts = self.build_accounts(ctx, &users, &[], &[market]);
+    for maker in makers.iter() {
+      accounts.push(AccountMeta {
+        pubkey: maker.maker,
+        is_writable: true,
+        is_signer: false,
+      });
+      accounts.push(AccountMeta {
+        pubkey: maker.maker_user_stats,
+        is_writable: true,
+        is_signer: false,
+      });
+    }
+
+    trx.add_ixs(vec![Instruction {
+      program_id: id(),
+      accounts,
+      data: instruction::FillPerpOrder {
+        _order_id: Some(taker.order.order_id),
+        _maker_order_id: None,
+      }
+      .data(),
+    }]);
+
+    Ok(())
+  }
+
+  /// Trigger a stop or take profit order whose trigger condition the oracle has met,
+  /// for the keeper reward
+  pub async fn trigger_order_ix(
+    &self,
+    cache: &ReadCache,
+    user: &Pubkey,
+    user_account: &User,
+    order: &Order,
+    trx: &mut KeypairTrx<'_>,
+  ) -> anyhow::Result<()> {
+    let market = MarketId::from((order.market_index, order.market_type));
+    self.check_staleness(cache, &[market])?;
+
+    let ctx = accounts::TriggerOrder {
+      state: DriftUtils::state_pda(),
+      authority: self.signer.pubkey(),
+      filler: self.sub_account,
+      user: *user,
+    };
+    let perp_writable = [market];
+    let spot_writable = [market, MarketId::QUOTE_SPOT];
+    let accounts = self.build_accounts(
+      ctx,
+      &[user_account],
+      &[],
+      match market.kind {
+        MarketType::Perp => &perp_writable,
+        MarketType::Spot => &spot_writable,
+      },
+    );
+
+    trx.add_ixs(vec![Instruction {
+      program_id: id(),
+      accounts,
+      data: instruction::TriggerOrder {
+        _order_id: order.order_id,
+      }
+      .data(),
+    }]);
+
+    Ok(())
+  }
+
+  /// Take over up to `max_base` of the liquidatee's perp position at the oracle price
+  /// (discounted by the liquidation fee), or no worse than `limit_price`
+  pub async fn liquidate_perp_ix(
+    &self,
+    cache: &ReadCache,
+    user: &Pubkey,
+    user_account: &User,
+    market_index: u16,
+    max_base: u64,
+    limit_price: Option<u64>,
+    trx: &mut KeypairTrx<'_>,
+  ) -> anyhow::Result<()> {
+    let market = MarketId::perp(market_index);
+    self.check_staleness(cache, &[market])?;
+    let liquidator = cache
+      .decoded_account::<User>(&self.sub_account, None)?
+      .decoded;
+
+    let accounts = self.build_accounts(
+      self.liquidate_accounts(user, user_account),
+      &[&liquidator, user_account],
+      &[],
+      &[market],
+    );
+
+    trx.add_ixs(vec![Instruction {
+      program_id: id(),
+      accounts,
+      data: instruction::LiquidatePerp {
+        _market_index: market_index,
+        _liquidator_max_base_asset_amount: max_base,
+        _limit_price: limit_price,
+      }
+      .data(),
+    }]);
+
+    Ok(())
+  }
+
+  /// Repay up to `max_liability` of the liquidatee's borrow in exchange for its deposit
+  pub async fn liquidate_spot_ix(
+    &self,
+    cache: &ReadCache,
+    user: &Pubkey,
+    user_account: &User,
+    asset_market_index: u16,
+    liability_market_index: u16,
+    max_liability: u128,
+    limit_price: Option<u64>,
+    trx: &mut KeypairTrx<'_>,
+  ) -> anyhow::Result<()> {
+    let markets = [
+      MarketId::spot(liability_market_index),
+      MarketId::spot(asset_market_index),
+    ];
+    self.check_staleness(cache, &markets)?;
+    let liquidator = cache
+      .decoded_account::<User>(&self.sub_account, None)?
+      .decoded;
+
+    let accounts = self.build_accounts(
+      self.liquidate_accounts(user, user_account),
+      &[&liquidator, user_account],
+      &[],
+      &markets,
+    );
+
+    trx.add_ixs(vec![Instruction {
+      program_id: id(),
+      accounts,
+      data: instruction::LiquidateSpot {
+        _asset_market_index: asset_market_index,
+        _liability_market_index: liability_market_index,
+        _liquidator_max_liability_transfer: max_liability,
+        _limit_price: limit_price,
+      }
+      .data(),
+    }]);
+
+    Ok(())
+  }
+
+  /// Repay up to `max_liability` of the liquidatee's borrow in exchange for its positive perp pnl
+  pub async fn liquidate_borrow_for_perp_pnl_ix(
+    &self,
+    cache: &ReadCache,
+    user: &Pubkey,
+    user_account: &User,
+    perp_market_index: u16,
+    spot_market_index: u16,
+    max_liability: u128,
+    limit_price: Option<u64>,
+    trx: &mut KeypairTrx<'_>,
+  ) -> anyhow::Result<()> {
+    let markets = [
+      MarketId::perp(perp_market_index),
+      MarketId::spot(spot_market_index),
+    ];
+    self.check_staleness(cache, &markets)?;
+    let liquidator = cache
+      .decoded_account::<User>(&self.sub_account, None)?
+      .decoded;
+
+    let accounts = self.build_accounts(
+      self.liquidate_accounts(user, user_account),
+      &[&liquidator, user_account],
+      &[],
+      &markets,
+    );
+
+    trx.add_ixs(vec![Instruction {
+      program_id: id(),
+      accounts,
+      data: instruction::LiquidateBorrowForPerpPnl {
+        _perp_market_index: perp_market_index,
+        _spot_market_index: spot_market_index,
+        _liquidator_max_liability_transfer: max_liability,
+        _limit_price: limit_price,
+      }
+      .data(),
+    }]);
+
+    Ok(())
+  }
+
+  /// Every liquidate instruction has the same accounts as `LiquidatePerp`, with us as liquidator
+  fn liquidate_accounts(&self, user: &Pubkey, user_account: &User) -> accounts::LiquidatePerp {
+    accounts::LiquidatePerp {
+      state: DriftUtils::state_pda(),
+      authority: self.signer.pubkey(),
+      liquidator: self.sub_account,
+      liquidator_stats: DriftUtils::user_stats_pda(&self.signer.pubkey()),
+      user: *user,
+      user_stats: DriftUtils::user_stats_pda(&user_account.authority),
+    }
+  }
+
+  /// Fail the transaction if our position in a constrained market ends outside its bounds.
+  /// Add after fills in the same transaction to cap the inventory they can build.
+  pub async fn check_order_constraints_ix(
+    &self,
+    cache: &ReadCache,
+    constraints: Vec<jit_proxy_cpi::OrderConstraint>,
+    trx: &mut KeypairTrx<'_>,
+  ) -> anyhow::Result<()> {
+    let user = cache
+      .decoded_account::<User>(&self.sub_account, None)?
+      .decoded;
+    let markets: Vec<MarketId> = constraints
+      .iter()
+      .map(|c| match c.market_type {
+        jit_proxy_cpi::MarketType::Perp => MarketId::perp(c.market_index),
+        jit_proxy_cpi::MarketType::Spot => MarketId::spot(c.market_index),
+      })
+      .collect();
+
+    let ctx = jit_proxy_cpi::accounts::CheckOrderConstraints {
+      user: self.sub_account,
+    };
+    let accounts = self.build_accounts(ctx, &[&user], &markets, &[]);
+
+    trx.add_ixs(vec![Instruction {
+      program_id: jit_proxy_cpi::id(),
+      accounts,
+      data: jit_proxy_cpi::instruction::CheckOrderConstraints {
+        _constraints: constraints,
+      }
+      .data(),
+    }]);
+
+    Ok(())
+  }
+
   // ======================================================================
   // Utilities
   // ======================================================================
@@ -1045,7 +1411,7 @@ impl DriftClient {
   pub fn perp_oracle_price_data(
     &self,
     pm: PerpMarket,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
   ) -> anyhow::Result<OraclePriceData> {
     let oracle_key = pm.amm.oracle;
     let oracle_source = pm.amm.oracle_source;
@@ -1059,7 +1425,7 @@ impl DriftClient {
   pub fn spot_oracle_price_data(
     &self,
     pm: SpotMarket,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
   ) -> anyhow::Result<OraclePriceData> {
     let oracle_key = pm.oracle;
     let oracle_source = pm.oracle_source;
@@ -1072,7 +1438,7 @@ impl DriftClient {
 
   pub fn perp_market_price(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     perp_market_index: u16,
   ) -> anyhow::Result<OrderPrice> {
     // let cache = cache.read().await;
@@ -1089,7 +1455,7 @@ impl DriftClient {
 
   pub fn spot_market_price(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     spot_market_index: u16,
   ) -> anyhow::Result<OrderPrice> {
     let sm_key = DriftUtils::spot_market_pda(spot_market_index);
@@ -1211,14 +1577,14 @@ impl DriftClient {
   pub fn market_info(
     &self,
     market: MarketId,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     slot: Option<u64>,
   ) -> anyhow::Result<MarketInfo> {
     Ok(match market.kind {
       MarketType::Spot => {
         let market_key = DriftUtils::spot_market_pda(market.index);
         let market_ctx = cache.decoded_account::<SpotMarket>(&market_key, slot)?;
-        let price = DriftUtils::oracle_price(&market, cache, None)?;
+        let price = DriftUtils::oracle_price(&market, cache, slot)?;
 
         let name = DriftUtils::decode_name(&market_ctx.decoded.name);
         MarketInfo {
@@ -1231,7 +1597,7 @@ impl DriftClient {
       MarketType::Perp => {
         let market_key = DriftUtils::perp_market_pda(market.index);
         let market_ctx = cache.decoded_account::<PerpMarket>(&market_key, slot)?;
-        let price = DriftUtils::oracle_price(&market, cache, None)?;
+        let price = DriftUtils::oracle_price(&market, cache, slot)?;
         let name = DriftUtils::decode_name(&market_ctx.decoded.name);
         MarketInfo {
           price,
@@ -1246,7 +1612,7 @@ impl DriftClient {
   pub fn order_price(
     &self,
     market: MarketId,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     slot: Option<u64>,
     order: &OrderParams,
   ) -> anyhow::Result<OrderPrice> {
@@ -1296,7 +1662,7 @@ impl DriftClient {
   pub async fn users_with_order_for_market(
     &self,
     market: MarketId,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     slot: Option<u64>,
   ) -> anyhow::Result<Vec<DecodedAcctCtx<User>>> {
     let mut users = cache.decoded_accounts::<User>(slot)?;
@@ -1315,7 +1681,7 @@ impl DriftClient {
   pub fn quote_balance(
     &self,
     market: MarketId,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     slot: Option<u64>,
   ) -> anyhow::Result<f64> {
     let market_info = self.market_info(market, cache, slot)?;
@@ -1335,11 +1701,73 @@ impl DriftClient {
     Ok(quote_balance)
   }
 
+  /// Collateral, margin requirements and health of our sub account
+  pub fn user_margin(
+    &self,
+    cache: &ReadCache,
+    slot: Option<u64>,
+  ) -> anyhow::Result<UserMargin> {
+    let user = cache
+      .decoded_account::<User>(&self.sub_account, slot)?
+      .decoded;
+    UserMargin::from_cache(&user, cache, slot)
+  }
+
+  /// Collateral, margin, leverage and liquidation prices of our sub account
+  pub fn user_health(
+    &self,
+    cache: &ReadCache,
+    slot: Option<u64>,
+  ) -> anyhow::Result<UserHealth> {
+    let user = cache
+      .decoded_account::<User>(&self.sub_account, slot)?
+      .decoded;
+    UserHealth::from_cache(&user, cache, slot)
+  }
+
+  /// Quote notional our sub account can add in `market` at up to `leverage`, capped by the most
+  /// the market allows. Our open perp orders are left out since engines replace them each time.
+  pub fn buying_power(
+    &self,
+    market: MarketId,
+    leverage: f64,
+    cache: &ReadCache,
+    slot: Option<u64>,
+  ) -> anyhow::Result<f64> {
+    let mut user = cache
+      .decoded_account::<User>(&self.sub_account, slot)?
+      .decoded;
+    for pos in user.perp_positions.iter_mut() {
+      pos.open_bids = 0;
+      pos.open_asks = 0;
+      pos.open_orders = 0;
+    }
+    let health = UserHealth::from_cache(&user, cache, slot)?;
+    let max_leverage = match market.kind {
+      MarketType::Perp => {
+        let perp_market = cache
+          .decoded_account::<PerpMarket>(&market.key(), slot)?
+          .decoded;
+        let ratio = perp_market.margin_ratio_initial.max(user.max_margin_ratio);
+        MARGIN_PRECISION as f64 / ratio as f64
+      }
+      MarketType::Spot => {
+        // each quote of the asset bought is weighted collateral against a quote borrow
+        let spot_market = cache
+          .decoded_account::<SpotMarket>(&market.key(), slot)?
+          .decoded;
+        let unweighted = SPOT_WEIGHT_PRECISION.saturating_sub(spot_market.initial_asset_weight);
+        SPOT_WEIGHT_PRECISION as f64 / unweighted as f64
+      }
+    };
+    Ok(health.free_collateral * leverage.min(max_leverage))
+  }
+
   pub fn bid_ask_prices(
     &self,
     market: MarketId,
     with_update: bool,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
   ) -> anyhow::Result<BidAsk> {
     let pm = cache
       .decoded_account::<PerpMarket>(&market.key(), None)?
@@ -1348,9 +1776,24 @@ impl DriftClient {
     Ok(AmmUtils::bid_ask_prices(pm, oracle, with_update))
   }
 
+  /// Virtual vAMM levels of a perp market to merge into the orderbook with [`L3Orderbook::l2_with_amm`]
+  pub fn amm_liquidity(
+    &self,
+    market: MarketId,
+    with_update: bool,
+    depth: &AmmDepth,
+    cache: &ReadCache,
+  ) -> anyhow::Result<AmmLiquidity> {
+    let pm = cache
+      .decoded_account::<PerpMarket>(&market.key(), None)?
+      .decoded;
+    let oracle = self.perp_oracle_price_data(pm, cache)?;
+    Ok(AmmUtils::liquidity(pm, oracle, with_update, depth))
+  }
+
   pub fn perp_market_is_volatile(
     &self,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     market: MarketId,
     pct_volatility_threshold: Option<f64>,
   ) -> anyhow::Result<bool> {
@@ -1392,3 +1835,379 @@ impl DriftClient {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drift_client::fixtures::*;
+
+  async fn client(rpc: &Arc<FakeRpc>, signer: Keypair) -> anyhow::Result<DriftClient> {
+    let client = DriftClient::new(Arc::new(signer), rpc.clone(), 0, None, false, false).await?;
+    Ok(client.staleness(StalenessConfig::disabled()))
+  }
+
+  /// Cache holding only our user, funded with $1000
+  async fn our_user(drift: &DriftClient) -> ReadCache {
+    let user = funded_user(&drift.signer.pubkey(), 0, 1_000.0, &[]);
+    let cache = Cache::new(1);
+    cache
+      .insert(AcctCtx {
+        key: drift.sub_account,
+        account: drift_account(&user),
+        slot: 1,
+      })
+      .await;
+    cache.read().await
+  }
+
+  /// Keys and whether they are writable
+  fn metas(accounts: &[AccountMeta]) -> Vec<(Pubkey, bool)> {
+    accounts.iter().map(|a| (a.pubkey, a.is_writable)).collect()
+  }
+
+  /// Whether `key` is among `accounts`, and writable
+  fn writable(accounts: &[AccountMeta], key: &Pubkey) -> Option<bool> {
+    accounts
+      .iter()
+      .find(|a| a.pubkey == *key)
+      .map(|a| a.is_writable)
+  }
+
+  #[tokio::test]
+  async fn fill_perp_order_appends_makers() -> anyhow::Result<()> {
+    let rpc = Arc::new(FakeRpc::new().drift_markets(100.0));
+    let drift = client(&rpc, Keypair::new()).await?;
+    let cache = Cache::new(1).read().await;
+    let taker_authority = Pubkey::new_unique();
+    let order = perp_order(7, PositionDirection::Long, 100.0, 1.0);
+    let taker = TakerInfo {
+      taker: DriftUtils::user_pda(&taker_authority, 0),
+      taker_user_stats: DriftUtils::user_stats_pda(&taker_authority),
+      taker_user: funded_user(&taker_authority, 0, 1_000.0, &[order]),
+      order,
+    };
+    let makers: Vec<MakerInfo> = (0..2)
+      .map(|_| {
+        let authority = Pubkey::new_unique();
+        MakerInfo {
+          maker: DriftUtils::user_pda(&authority, 0),
+          maker_user_stats: DriftUtils::user_stats_pda(&authority),
+          maker_user: funded_user(&authority, 0, 1_000.0, &[]),
+        }
+      })
+      .collect();
+
+    let mut trx = drift.new_tx(false);
+    drift
+      .fill_perp_order_ix(&cache, &taker, &makers, &mut trx)
+      .await?;
+    let accounts = trx.ixs()[0].accounts.clone();
+    let maker_accounts = metas(&accounts[accounts.len() - 4..]);
+    let expected: Vec<(Pubkey, bool)> = makers
+      .iter()
+      .flat_map(|m| [(m.maker, true), (m.maker_user_stats, true)])
+      .collect();
+    assert_eq!(maker_accounts, expected);
+    assert_eq!(writable(&accounts, &MarketId::perp(0).key()), Some(true));
+
+    trx.send_tx(id(), None).await?;
+    match rpc.sent_drift_ixs()?.as_slice() {
+      [InstructionType::FillPerpOrder(ix)] => {
+        assert_eq!((ix._order_id, ix._maker_order_id), (Some(7), None))
+      }
+      ixs => panic!("Expected one FillPerpOrder instruction, got {}", ixs.len()),
+    }
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn trigger_order_writes_quote_for_spot() -> anyhow::Result<()> {
+    let rpc = Arc::new(FakeRpc::new().drift_markets(100.0).drift_sol_market(100.0));
+    let drift = client(&rpc, Keypair::new()).await?;
+    let cache = Cache::new(1).read().await;
+    let authority = Pubkey::new_unique();
+    let user_key = DriftUtils::user_pda(&authority, 0);
+    let perp = Order {
+      order_type: OrderType::TriggerMarket,
+      ..perp_order(3, PositionDirection::Short, 90.0, 1.0)
+    };
+    let spot = Order {
+      market_type: MarketType::Spot,
+      market_index: 1,
+      order_id: 4,
+      ..perp
+    };
+    let user = funded_user(&authority, 0, 1_000.0, &[perp, spot]);
+
+    let quote = MarketId::QUOTE_SPOT.key();
+    let mut trx = drift.new_tx(false);
+    drift
+      .trigger_order_ix(&cache, &user_key, &user, &perp, &mut trx)
+      .await?;
+    let accounts = &trx.ixs()[0].accounts;
+    assert_eq!(writable(accounts, &MarketId::perp(0).key()), Some(true));
+    assert_eq!(writable(accounts, &quote), Some(false));
+    trx.send_tx(id(), None).await?;
+
+    let mut trx = drift.new_tx(false);
+    drift
+      .trigger_order_ix(&cache, &user_key, &user, &spot, &mut trx)
+      .await?;
+    let accounts = &trx.ixs()[0].accounts;
+    assert_eq!(writable(accounts, &MarketId::spot(1).key()), Some(true));
+    assert_eq!(writable(accounts, &quote), Some(true));
+    trx.send_tx(id(), None).await?;
+
+    let order_ids: Vec<u32> = rpc
+      .sent_drift_ixs()?
+      .iter()
+      .map(|ix| match ix {
+        InstructionType::TriggerOrder(ix) => ix._order_id,
+        _ => panic!("Expected only TriggerOrder instructions"),
+      })
+      .collect();
+    assert_eq!(order_ids, vec![3, 4]);
+    Ok(())
+  }
+  #[tokio::test]
+  async fn liquidations_write_markets_and_user_stats() -> anyhow::Result<()> {
+    let signer = Keypair::new();
+    let liquidator_stats = DriftUtils::user_stats_pda(&signer.pubkey());
+    let rpc = Arc::new(FakeRpc::new().drift_markets(100.0).drift_sol_market(100.0));
+    let drift = client(&rpc, signer).await?;
+    let cache = our_user(&drift).await;
+    let authority = Pubkey::new_unique();
+    let user_key = DriftUtils::user_pda(&authority, 0);
+    let user = funded_user(&authority, 0, 100.0, &[]);
+    let user_stats = DriftUtils::user_stats_pda(&authority);
+
+    let (perp, sol) = (MarketId::perp(0), MarketId::spot(1));
+    let check = |trx: &KeypairTrx<'_>, markets: &[MarketId]| {
+      let accounts = &trx.ixs()[0].accounts;
+      assert_eq!(
+        metas(&accounts[..6]),
+        vec![
+          (DriftUtils::state_pda(), false),
+          (drift.signer.pubkey(), false),
+          (drift.sub_account, true),
+          (liquidator_stats, true),
+          (user_key, true),
+          (user_stats, true),
+        ]
+      );
+      for market in markets {
+        assert_eq!(writable(accounts, &market.key()), Some(true));
+      }
+    };
+
+    let mut trx = drift.new_tx(false);
+    drift
+      .liquidate_perp_ix(&cache, &user_key, &user, 0, 5, None, &mut trx)
+      .await?;
+    check(&trx, &[perp]);
+    trx.send_tx(id(), None).await?;
+
+    let mut trx = drift.new_tx(false);
+    drift
+      .liquidate_spot_ix(&cache, &user_key, &user, 0, 1, 6, None, &mut trx)
+      .await?;
+    check(&trx, &[MarketId::QUOTE_SPOT, sol]);
+    trx.send_tx(id(), None).await?;
+
+    let mut trx = drift.new_tx(false);
+    drift
+      .liquidate_borrow_for_perp_pnl_ix(&cache, &user_key, &user, 0, 1, 7, None, &mut trx)
+      .await?;
+    check(&trx, &[perp, sol]);
+    trx.send_tx(id(), None).await?;
+
+    // market indexes, the perp market's twice, and max amounts
+    let args: Vec<(u16, u16, u128)> = rpc
+      .sent_drift_ixs()?
+      .iter()
+      .map(|ix| match ix {
+        InstructionType::LiquidatePerp(ix) => (
+          ix._market_index,
+          ix._market_index,
+          ix._liquidator_max_base_asset_amount as u128,
+        ),
+        InstructionType::LiquidateSpot(ix) => (
+          ix._asset_market_index,
+          ix._liability_market_index,
+          ix._liquidator_max_liability_transfer,
+        ),
+        InstructionType::LiquidateBorrowForPerpPnl(ix) => (
+          ix._perp_market_index,
+          ix._spot_market_index,
+          ix._liquidator_max_liability_transfer,
+        ),
+        _ => panic!("Expected only liquidate instructions"),
+      })
+      .collect();
+    assert_eq!(args, vec![(0, 0, 5), (0, 1, 6), (0, 1, 7)]);
+    Ok(())
+  }
+  #[tokio::test]
+  async fn jit_orders_accounts_for_perp_and_spot() -> anyhow::Result<()> {
+    let signer = Keypair::new();
+    let our_stats = DriftUtils::user_stats_pda(&signer.pubkey());
+    let rpc = Arc::new(FakeRpc::new().drift_markets(100.0).drift_sol_market(100.0));
+    let drift = client(&rpc, signer).await?;
+    let cache = our_user(&drift).await;
+    let authority = Pubkey::new_unique();
+    let taker = |order: Order| TakerInfo {
+      taker: DriftUtils::user_pda(&authority, 0),
+      taker_user_stats: DriftUtils::user_stats_pda(&authority),
+      taker_user: funded_user(&authority, 0, 1_000.0, &[order]),
+      order,
+    };
+    let params = || jit_proxy_cpi::JitParams {
+      taker_order_id: 1,
+      max_position: 0,
+      min_position: 0,
+      bid: 0,
+      ask: 0,
+      price_type: jit_proxy_cpi::PriceType::Oracle,
+      post_only: None,
+    };
+    let base = vec![
+      (DriftUtils::state_pda(), false),
+      (drift.sub_account, true),
+      (our_stats, true),
+      (DriftUtils::user_pda(&authority, 0), true),
+      (DriftUtils::user_stats_pda(&authority), true),
+      (drift.signer.pubkey(), false),
+      (id(), false),
+    ];
+    let (usdc, sol) = (MarketId::QUOTE_SPOT.key(), MarketId::spot(1).key());
+
+    // oracles, spot and perp markets in the order the program reads them
+    let perp = perp_order(1, PositionDirection::Long, 100.0, 1.0);
+    let mut trx = drift.new_tx(false);
+    drift
+      .jit_ix(&cache, &taker(perp), params(), &mut trx)
+      .await?;
+    let mut expected = base.clone();
+    expected.extend([
+      (USDC_ORACLE, false),
+      (PERP_ORACLE, false),
+      (usdc, false),
+      (MarketId::perp(0).key(), true),
+    ]);
+    assert_eq!(trx.ixs()[0].program_id, jit_proxy_cpi::id());
+    assert_eq!(metas(&trx.ixs()[0].accounts), expected);
+
+    // both spot markets are written, and their vaults follow the remaining accounts
+    let spot = Order {
+      market_type: MarketType::Spot,
+      market_index: 1,
+      ..perp
+    };
+    let mut trx = drift.new_tx(false);
+    drift
+      .jit_ix(&cache, &taker(spot), params(), &mut trx)
+      .await?;
+    let mut spots = [(usdc, true), (sol, true)];
+    spots.sort();
+    let mut expected = base;
+    expected.extend([(USDC_ORACLE, false), (SOL_ORACLE, false)]);
+    expected.extend(spots);
+    expected.extend([
+      (DriftUtils::spot_market_vault(1), false),
+      (DriftUtils::spot_market_vault(0), false),
+    ]);
+    assert_eq!(metas(&trx.ixs()[0].accounts), expected);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn order_constraints_read_constrained_markets() -> anyhow::Result<()> {
+    let rpc = Arc::new(FakeRpc::new().drift_markets(100.0).drift_sol_market(100.0));
+    let drift = client(&rpc, Keypair::new()).await?;
+    let cache = our_user(&drift).await;
+    let constraint = |market_index, market_type| jit_proxy_cpi::OrderConstraint {
+      max_position: 10,
+      min_position: -10,
+      market_index,
+      market_type,
+    };
+    let constraints = vec![
+      constraint(0, jit_proxy_cpi::MarketType::Perp),
+      constraint(1, jit_proxy_cpi::MarketType::Spot),
+    ];
+
+    let mut trx = drift.new_tx(false);
+    drift
+      .check_order_constraints_ix(&cache, constraints, &mut trx)
+      .await?;
+    let mut spots = [
+      (MarketId::QUOTE_SPOT.key(), false),
+      (MarketId::spot(1).key(), false),
+    ];
+    spots.sort();
+    let mut expected = vec![
+      (drift.sub_account, false),
+      (USDC_ORACLE, false),
+      (PERP_ORACLE, false),
+      (SOL_ORACLE, false),
+    ];
+    expected.extend(spots);
+    expected.push((MarketId::perp(0).key(), false));
+    assert_eq!(trx.ixs()[0].program_id, jit_proxy_cpi::id());
+    assert_eq!(metas(&trx.ixs()[0].accounts), expected);
+    Ok(())
+  }
+  #[tokio::test]
+  async fn buying_power_leaves_out_open_orders() -> anyhow::Result<()> {
+    let signer = Keypair::new();
+    let mut user = funded_user(
+      &signer.pubkey(),
+      0,
+      1_000.0,
+      &[perp_order(1, PositionDirection::Long, 99.0, 5.0)],
+    );
+    let pos = &mut user.perp_positions[0];
+    pos.open_bids = DriftUtils::base_to_u64(5.0) as i64;
+    pos.open_orders = 1;
+    let rpc = Arc::new(
+      FakeRpc::new()
+        .drift_markets(100.0)
+        .drift_sol_market(100.0)
+        .drift_user(&user),
+    );
+    let drift = client(&rpc, signer).await?;
+    let cache = Cache::new(10);
+    cache
+      .write()
+      .await
+      .load(rpc.as_ref(), &[drift.sub_account], None, &[])
+      .await?;
+
+    // the $500 bid holds at least $50 of initial margin
+    let read = cache.read().await;
+    let health = drift.user_health(&read, None)?;
+    assert_eq!(health.total_collateral, 1_000.0);
+    assert!(health.initial_margin >= 50.0);
+    assert!(health.free_collateral <= 950.0);
+    let perp = MarketId::perp(0);
+    assert_eq!(drift.buying_power(perp, 1.0, &read, None)?, 1_000.0);
+    // 10x perp leverage at 10% initial margin, 5x spot leverage at 80% asset weight
+    assert_eq!(drift.buying_power(perp, 20.0, &read, None)?, 10_000.0);
+    let sol = MarketId::spot(1);
+    assert_eq!(drift.buying_power(sol, 20.0, &read, None)?, 5_000.0);
+    assert_eq!(drift.buying_power(sol, 2.0, &read, None)?, 2_000.0);
+
+    // a user margin ratio above the market's lowers the perp cap
+    user.max_margin_ratio = 2000;
+    cache
+      .insert(AcctCtx {
+        key: drift.sub_account,
+        account: drift_account(&user),
+        slot: 2,
+      })
+      .await;
+    let read = cache.read().await;
+    assert_eq!(drift.buying_power(perp, 20.0, &read, None)?, 5_000.0);
+    Ok(())
+  }
+}
@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+
+use drift_cpi::PositionDirection;
+
+use crate::drift_client::{InnerOrderbook, L3Orderbook, MarketId, OrderInfo, ReadCache};
+
+/// Resting orders at one price level of an [`L2Orderbook`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct L2Level {
+  pub price: f64,
+  /// Unfilled base of every order at this level
+  pub size: f64,
+  /// Number of user orders, a level only filled by the vAMM has none
+  pub orders: usize,
+}
+
+/// How far to walk the vAMM curve for [`AmmLiquidity`]
+#[derive(Debug, Clone)]
+pub struct AmmDepth {
+  pub levels: usize,
+  pub base_per_level: f64,
+}
+
+/// Virtual levels of a perp market's AMM, see [`crate::drift_client::DriftClient::amm_liquidity`]
+#[derive(Debug, Clone, Default)]
+pub struct AmmLiquidity {
+  /// First index is highest/best bid
+  pub bids: Vec<L2Level>,
+  /// First index is lowest/best ask
+  pub asks: Vec<L2Level>,
+}
+
+/// Average price and cost of taking liquidity from an [`L2Orderbook`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fill {
+  /// Base filled, less than requested if the book is too thin
+  pub size: f64,
+  pub avg_price: f64,
+  /// Price of the last level taken
+  pub worst_price: f64,
+  /// Percent the average price is worse than the best level
+  pub pct_slippage: f64,
+  /// Percent the average price is worse than the oracle
+  pub pct_impact: f64,
+}
+
+/// Resting orders aggregated into price levels `tick` apart.
+/// Bids are rounded down and asks up to their level, so fills and depth are conservative.
+pub struct L2Orderbook {
+  /// First index is highest/best bid
+  pub bids: Vec<L2Level>,
+  /// First index is lowest/best ask
+  pub asks: Vec<L2Level>,
+  pub tick: f64,
+  pub slot: u64,
+  pub oracle_price: f64,
+}
+
+impl L3Orderbook {
+  /// Group the bids below and asks above the oracle into levels `tick` apart in quote.
+  /// Bids round down and asks up to a multiple of `tick` in `f64`, so a level can be a tick
+  /// away from its orders' prices, even with a `tick` of `1.0 / PRICE_PRECISION as f64`.
+  pub fn l2(&self, tick: f64) -> anyhow::Result<L2Orderbook> {
+    self.l2_with_amm(tick, &AmmLiquidity::default())
+  }
+
+  /// [`L3Orderbook::l2`] with the vAMM levels merged in, so the best bid and ask and the cost
+  /// of a fill are what a taker gets from Drift
+  pub fn l2_with_amm(&self, tick: f64, amm: &AmmLiquidity) -> anyhow::Result<L2Orderbook> {
+    if tick.is_nan() || tick <= 0.0 {
+      return Err(anyhow::anyhow!("Tick must be positive, got {}", tick));
+    }
+    let group = |levels: &mut dyn Iterator<Item = L2Level>, round: fn(f64) -> f64| {
+      let mut grouped: BTreeMap<i64, L2Level> = BTreeMap::new();
+      for l in levels {
+        let bucket = round(l.price / tick) as i64;
+        let level = grouped.entry(bucket).or_insert(L2Level {
+          price: bucket as f64 * tick,
+          size: 0.0,
+          orders: 0,
+        });
+        level.size += l.size;
+        level.orders += l.orders;
+      }
+      grouped.into_values()
+    };
+    let order = |o: &OrderInfo| L2Level {
+      price: o.price,
+      size: o.size,
+      orders: 1,
+    };
+    let mut bids = self
+      .bids
+      .iter()
+      .filter(|o| o.price < self.oracle_price)
+      .map(order)
+      .chain(amm.bids.iter().copied());
+    let mut asks = self
+      .asks
+      .iter()
+      .filter(|o| o.price > self.oracle_price)
+      .map(order)
+      .chain(amm.asks.iter().copied());
+    Ok(L2Orderbook {
+      bids: group(&mut bids, f64::floor).rev().collect(),
+      asks: group(&mut asks, f64::ceil).collect(),
+      tick,
+      slot: self.slot,
+      oracle_price: self.oracle_price,
+    })
+  }
+}
+
+impl L2Orderbook {
+  /// Levels a taker in `direction` fills against, best first
+  fn levels(&self, direction: &PositionDirection) -> &[L2Level] {
+    match direction {
+      PositionDirection::Long => &self.asks,
+      PositionDirection::Short => &self.bids,
+    }
+  }
+
+  /// Walk the book to take `base` in `direction`
+  pub fn fill(&self, direction: PositionDirection, base: f64) -> anyhow::Result<Fill> {
+    if base.is_nan() || base <= 0.0 {
+      return Err(anyhow::anyhow!("Fill size must be positive, got {}", base));
+    }
+    let levels = self.levels(&direction);
+    let best = levels
+      .first()
+      .ok_or(anyhow::anyhow!("No liquidity to fill {:?}", direction))?
+      .price;
+    let (mut size, mut quote, mut worst_price) = (0.0, 0.0, best);
+    for level in levels {
+      if size >= base {
+        break;
+      }
+      let take = level.size.min(base - size);
+      size += take;
+      quote += take * level.price;
+      worst_price = level.price;
+    }
+    let avg_price = quote / size;
+    // positive when the fill is worse than the reference price
+    let pct_worse = |reference: f64| {
+      let pct = (avg_price / reference - 1.0) * 100.0;
+      match direction {
+        PositionDirection::Long => pct,
+        PositionDirection::Short => -pct,
+      }
+    };
+    Ok(Fill {
+      size,
+      avg_price,
+      worst_price,
+      pct_slippage: pct_worse(best),
+      pct_impact: pct_worse(self.oracle_price),
+    })
+  }
+
+  /// Base resting on bids at most `pct` below the oracle
+  pub fn bid_depth(&self, pct: f64) -> f64 {
+    let cutoff = self.oracle_price * (1.0 - pct / 100.0);
+    self
+      .bids
+      .iter()
+      .take_while(|l| l.price >= cutoff)
+      .map(|l| l.size)
+      .sum()
+  }
+
+  /// Base resting on asks at most `pct` above the oracle
+  pub fn ask_depth(&self, pct: f64) -> f64 {
+    let cutoff = self.oracle_price * (1.0 + pct / 100.0);
+    self
+      .asks
+      .iter()
+      .take_while(|l| l.price <= cutoff)
+      .map(|l| l.size)
+      .sum()
+  }
+
+  /// Bid minus ask depth within `pct` of the oracle over their sum, from -1 (all asks) to 1 (all bids)
+  pub fn imbalance(&self, pct: f64) -> f64 {
+    let (bids, asks) = (self.bid_depth(pct), self.ask_depth(pct));
+    match bids + asks {
+      total if total > 0.0 => (bids - asks) / total,
+      _ => 0.0,
+    }
+  }
+}
+
+impl InnerOrderbook {
+  pub fn l2(&self, market: &MarketId, cache: &ReadCache, tick: f64) -> anyhow::Result<L2Orderbook> {
+    self.l3(market, cache)?.l2(tick)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use drift_cpi::Order;
+  use solana_sdk::pubkey::Pubkey;
+
+  fn order(price: f64, size: f64) -> OrderInfo {
+    OrderInfo {
+      price,
+      size,
+      slot: 1,
+      user: Pubkey::new_unique(),
+      order: Order::default(),
+    }
+  }
+
+  #[test]
+  fn l2_depth_and_fills() -> anyhow::Result<()> {
+    let l3 = L3Orderbook {
+      // the crossed bid above the oracle is excluded
+      bids: vec![
+        order(100.5, 9.0),
+        order(99.9, 1.0),
+        order(99.8, 1.0),
+        order(98.0, 4.0),
+      ],
+      asks: vec![order(100.1, 2.0), order(100.25, 2.0), order(103.0, 10.0)],
+      spread: 0.2,
+      slot: 1,
+      oracle_price: 100.0,
+      last_price: 100.0,
+    };
+    assert!(l3.l2(0.0).is_err());
+    let l2 = l3.l2(0.5)?;
+    assert_eq!(
+      l2.bids
+        .iter()
+        .map(|l| (l.price, l.size, l.orders))
+        .collect::<Vec<_>>(),
+      vec![(99.5, 2.0, 2), (98.0, 4.0, 1)]
+    );
+    assert_eq!(
+      l2.asks
+        .iter()
+        .map(|l| (l.price, l.size))
+        .collect::<Vec<_>>(),
+      vec![(100.5, 4.0), (103.0, 10.0)]
+    );
+
+    let fill = l2.fill(PositionDirection::Long, 6.0)?;
+    assert_eq!((fill.size, fill.worst_price), (6.0, 103.0));
+    assert!((fill.avg_price - (4.0 * 100.5 + 2.0 * 103.0) / 6.0).abs() < 1e-9);
+    assert!(fill.pct_slippage > 0.0 && fill.pct_impact > fill.pct_slippage);
+    // more than the book holds
+    assert_eq!(l2.fill(PositionDirection::Short, 10.0)?.size, 6.0);
+    assert!(l2.fill(PositionDirection::Long, 0.0).is_err());
+
+    assert_eq!((l2.bid_depth(1.0), l2.ask_depth(1.0)), (2.0, 4.0));
+    assert!((l2.imbalance(1.0) + 1.0 / 3.0).abs() < 1e-9);
+
+    let amm = AmmLiquidity {
+      bids: vec![L2Level {
+        price: 99.7,
+        size: 3.0,
+        orders: 0,
+      }],
+      asks: vec![L2Level {
+        price: 100.2,
+        size: 5.0,
+        orders: 0,
+      }],
+    };
+    let l2 = l3.l2_with_amm(0.5, &amm)?;
+    assert_eq!((l2.bids[0].size, l2.bids[0].orders), (5.0, 2));
+    assert_eq!((l2.asks[0].size, l2.asks[0].orders), (9.0, 2));
+    Ok(())
+  }
+}
@@ -0,0 +1,296 @@
+use std::sync::{Arc, Mutex};
+
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use crossbeam::channel::{Receiver, Sender};
+use drift_cpi::{
+  FundingPaymentRecord, InstructionType, LiquidationRecord, OrderActionRecord, OrderRecord,
+  SettlePnlRecord,
+};
+use log::*;
+use solana_sdk::clock::Slot;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+
+use crate::{parse_log_data, TxStub};
+
+/// Drift event emitted as a base64 `Program data:` log line
+#[derive(Debug)]
+pub enum DriftEvent {
+  OrderAction(OrderActionRecord),
+  Order(OrderRecord),
+  FundingPayment(FundingPaymentRecord),
+  Liquidation(LiquidationRecord),
+  SettlePnl(SettlePnlRecord),
+}
+
+impl DriftEvent {
+  /// Decode by the 8 byte anchor event discriminator.
+  /// Returns `None` for events that aren't one of the supported types.
+  pub fn decode(data: &[u8]) -> Option<anyhow::Result<Self>> {
+    if data.len() < 8 {
+      return None;
+    }
+    let (discrim, mut body) = data.split_at(8);
+    let res = match discrim {
+      d if d == OrderActionRecord::discriminator() => {
+        OrderActionRecord::deserialize(&mut body).map(DriftEvent::OrderAction)
+      }
+      d if d == OrderRecord::discriminator() => {
+        OrderRecord::deserialize(&mut body).map(DriftEvent::Order)
+      }
+      d if d == FundingPaymentRecord::discriminator() => {
+        FundingPaymentRecord::deserialize(&mut body).map(DriftEvent::FundingPayment)
+      }
+      d if d == LiquidationRecord::discriminator() => {
+        LiquidationRecord::deserialize(&mut body).map(DriftEvent::Liquidation)
+      }
+      d if d == SettlePnlRecord::discriminator() => {
+        SettlePnlRecord::deserialize(&mut body).map(DriftEvent::SettlePnl)
+      }
+      _ => return None,
+    };
+    Some(res.map_err(|e| anyhow::anyhow!("Failed to decode Drift event: {:?}", e)))
+  }
+
+  /// Decode every supported event emitted by the Drift program in a transaction's log messages.
+  /// Works for both RPC fetched ([`crate::TrxData::logs`]) and geyser streamed ([`crate::TxStub::logs`]) transactions.
+  pub fn from_logs(logs: &[String]) -> Vec<DriftLogEvent> {
+    parse_log_data(logs)
+      .into_iter()
+      .filter(|log| log.program == drift_cpi::id())
+      .filter_map(|log| match Self::decode(&log.data)? {
+        Ok(event) => Some(DriftLogEvent {
+          ix_index: log.ix_index,
+          depth: log.depth,
+          event,
+        }),
+        Err(e) => {
+          warn!("{}", e);
+          None
+        }
+      })
+      .collect()
+  }
+}
+
+/// Drift event and the invocation that emitted it
+#[derive(Debug)]
+pub struct DriftLogEvent {
+  /// Index of the top level instruction, which may be another program that CPIs into Drift
+  pub ix_index: usize,
+  /// Invoke depth, 1 if Drift was called directly
+  pub depth: usize,
+  pub event: DriftEvent,
+}
+
+/// Decoded Drift instruction
+#[derive(Debug)]
+pub struct DriftIx {
+  pub name: String,
+  pub accounts: Vec<Pubkey>,
+  pub ix: InstructionType,
+}
+
+/// Drift instructions and events of one transaction
+#[derive(Debug)]
+pub struct DriftTx {
+  pub slot: Slot,
+  pub signature: Signature,
+  pub signer: Pubkey,
+  pub failed: bool,
+  pub ixs: Vec<DriftIx>,
+  pub events: Vec<DriftLogEvent>,
+}
+
+impl DriftTx {
+  /// Decode the top level Drift instructions and the events in the logs.
+  /// Instructions that fail to decode are skipped.
+  pub fn decode(stub: &TxStub) -> Self {
+    let ixs = stub
+      .ixs
+      .iter()
+      .filter(|ix| ix.program == drift_cpi::id() && ix.data.len() >= 8)
+      .filter_map(|ix| {
+        let decoded = InstructionType::decode(&ix.data[..])
+          .map_err(|e| anyhow::anyhow!("Failed to decode instruction: {:?}", e))
+          .and_then(|decoded| {
+            let name = InstructionType::discrim_to_name(ix.data[..8].try_into()?)
+              .map_err(|e| anyhow::anyhow!("Failed to decode discrim to name: {:?}", e))?;
+            Ok((name.to_string(), decoded))
+          });
+        match decoded {
+          Ok((name, decoded)) => Some(DriftIx {
+            name,
+            accounts: ix.accounts.clone(),
+            ix: decoded,
+          }),
+          Err(e) => {
+            debug!("{} in {}", e, stub.signature);
+            None
+          }
+        }
+      })
+      .collect();
+    Self {
+      slot: stub.slot,
+      signature: stub.signature,
+      signer: stub.signer,
+      failed: stub.failed,
+      ixs,
+      events: DriftEvent::from_logs(&stub.logs),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.ixs.is_empty() && self.events.is_empty()
+  }
+}
+
+/// Fan out decoded Drift transactions from [`crate::NexusClient::stream`] to any number of subscribers.
+#[derive(Clone, Default)]
+pub struct EventBus {
+  subscribers: Arc<Mutex<Vec<Sender<Arc<DriftTx>>>>>,
+}
+
+impl EventBus {
+  pub fn subscribe(&self) -> Receiver<Arc<DriftTx>> {
+    let (tx, rx) = crossbeam::channel::unbounded();
+    if let Ok(mut subscribers) = self.subscribers.lock() {
+      subscribers.push(tx);
+    }
+    rx
+  }
+
+  /// Send to every subscriber, dropping those whose receiver is gone
+  pub fn publish(&self, tx: DriftTx) {
+    let tx = Arc::new(tx);
+    if let Ok(mut subscribers) = self.subscribers.lock() {
+      subscribers.retain(|s| s.send(tx.clone()).is_ok());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drift_client::fixtures::zeroed;
+  use crate::Ix;
+  use anchor_lang::{AnchorSerialize, InstructionData};
+  use base64::engine::general_purpose;
+  use base64::Engine;
+  use drift_cpi::{instruction, MarketType};
+
+  /// `Program data:` line of an anchor event
+  fn data_log<T: AnchorSerialize + Discriminator>(event: &T) -> String {
+    let mut data = T::discriminator().to_vec();
+    data.extend(event.try_to_vec().unwrap());
+    format!("Program data: {}", general_purpose::STANDARD.encode(data))
+  }
+
+  fn stub(ixs: Vec<Ix>, logs: Vec<String>) -> TxStub {
+    TxStub {
+      ixs,
+      slot: 10,
+      blockhash: String::new(),
+      signer: Pubkey::new_unique(),
+      signature: Signature::new_unique(),
+      filters: vec![],
+      logs,
+      failed: false,
+    }
+  }
+
+  #[test]
+  fn decode_ixs_and_events() {
+    let drift = drift_cpi::id();
+    let other = Pubkey::new_unique();
+    let mut record: OrderRecord = zeroed();
+    record.order.order_id = 7;
+    let mut settle: SettlePnlRecord = zeroed();
+    settle.market_index = 3;
+
+    let ixs = vec![
+      Ix {
+        program: drift,
+        accounts: vec![],
+        data: instruction::CancelOrders {
+          _market_index: Some(1),
+          _market_type: Some(MarketType::Perp),
+          _direction: None,
+        }
+        .data(),
+      },
+      // not Drift, or too short for a discriminator
+      Ix {
+        program: other,
+        accounts: vec![],
+        data: vec![0; 16],
+      },
+      Ix {
+        program: drift,
+        accounts: vec![],
+        data: vec![1, 2, 3],
+      },
+    ];
+    let logs = vec![
+      format!("Program {} invoke [1]", drift),
+      data_log(&record),
+      // unsupported event
+      format!(
+        "Program data: {}",
+        general_purpose::STANDARD.encode([9u8; 16])
+      ),
+      format!("Program {} success", drift),
+      format!("Program {} invoke [1]", other),
+      data_log(&record),
+      format!("Program {} invoke [2]", drift),
+      data_log(&settle),
+      format!("Program {} success", drift),
+      format!("Program {} success", other),
+    ];
+    let tx = DriftTx::decode(&stub(ixs, logs));
+
+    assert_eq!(tx.ixs.len(), 1);
+    assert!(matches!(
+      &tx.ixs[0].ix,
+      InstructionType::CancelOrders(ix) if ix._market_index == Some(1)
+    ));
+    // events logged by the other program are not Drift's, even if they decode
+    assert_eq!(tx.events.len(), 2);
+    assert!(matches!(
+      &tx.events[0],
+      DriftLogEvent {
+        ix_index: 0,
+        depth: 1,
+        event: DriftEvent::Order(OrderRecord { order, .. }),
+      } if order.order_id == 7
+    ));
+    assert!(matches!(
+      &tx.events[1],
+      DriftLogEvent {
+        ix_index: 1,
+        depth: 2,
+        event: DriftEvent::SettlePnl(SettlePnlRecord {
+          market_index: 3,
+          ..
+        }),
+      }
+    ));
+    assert!(DriftTx::decode(&stub(vec![], vec![])).is_empty());
+  }
+
+  #[test]
+  fn event_bus_fan_out() {
+    let bus = EventBus::default();
+    let (a, b) = (bus.subscribe(), bus.subscribe());
+    bus.publish(DriftTx::decode(&stub(vec![], vec![])));
+    let (from_a, from_b) = (a.try_recv().unwrap(), b.try_recv().unwrap());
+    assert!(Arc::ptr_eq(&from_a, &from_b));
+
+    // subscribers whose receiver is gone are dropped on the next publish
+    drop(b);
+    bus.publish(DriftTx::decode(&stub(vec![], vec![])));
+    assert!(a.try_recv().is_ok());
+    assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+  }
+}
@@ -0,0 +1,210 @@
+//! Drift accounts to seed a [`FakeRpc`] with, so engines can be built and run in tests
+//! without a cluster. Only the fields the client reads are set, the rest are zero.
+
+use anchor_lang::{AccountSerialize, AnchorDeserialize, Discriminator};
+use drift_cpi::{
+  InstructionType, MarketType, OracleSource, Order, OrderStatus, OrderType, PerpMarket,
+  PositionDirection, SpotBalanceType, SpotMarket, State, User, _PrelaunchOracle,
+  MARKET_LOOKUP_TABLE, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+};
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::drift_client::DriftUtils;
+use crate::FakeRpc;
+
+/// Oracle of USDC, spot market 0
+pub const USDC_ORACLE: Pubkey = Pubkey::new_from_array([1; 32]);
+/// Prelaunch oracle of perp market 0
+pub const PERP_ORACLE: Pubkey = Pubkey::new_from_array([2; 32]);
+/// Prelaunch oracle of SOL, spot market 1
+pub const SOL_ORACLE: Pubkey = Pubkey::new_from_array([3; 32]);
+
+/// Account with every field zero, for tests to set only what they need
+pub fn zeroed<T: AnchorDeserialize>() -> T {
+  T::deserialize(&mut [0u8; 8192].as_slice()).unwrap()
+}
+
+/// Account owned by the Drift program holding `account` after its discriminator
+pub fn drift_account<T: AccountSerialize>(account: &T) -> Account {
+  let mut data = vec![];
+  account.try_serialize(&mut data).unwrap();
+  Account {
+    lamports: 1,
+    data,
+    owner: drift_cpi::id(),
+    executable: false,
+    rent_epoch: 0,
+  }
+}
+
+/// Prelaunch oracle account at `price`
+fn prelaunch_oracle(price: f64) -> Account {
+  let oracle = _PrelaunchOracle {
+    price: DriftUtils::price_to_u64(price) as i64,
+    max_price: i64::MAX,
+    confidence: 0,
+    last_update_slot: 0,
+    amm_last_update_slot: 0,
+    perp_market_index: 0,
+    padding: [0; 70],
+  };
+  let mut data = _PrelaunchOracle::discriminator().to_vec();
+  data.extend_from_slice(bytemuck::bytes_of(&oracle));
+  Account {
+    lamports: 1,
+    data,
+    owner: drift_cpi::id(),
+    ..Default::default()
+  }
+}
+
+/// Open limit order in perp market 0
+pub fn perp_order(order_id: u32, direction: PositionDirection, price: f64, base: f64) -> Order {
+  Order {
+    status: OrderStatus::Open,
+    order_type: OrderType::Limit,
+    market_type: MarketType::Perp,
+    direction,
+    order_id,
+    price: DriftUtils::price_to_u64(price),
+    base_asset_amount: DriftUtils::base_to_u64(base),
+    ..Default::default()
+  }
+}
+
+/// Open long order in perp market 0 for 100 base lots with `filled` of them filled
+pub fn partially_filled_order(order_id: u32, filled: u64) -> Order {
+  Order {
+    status: OrderStatus::Open,
+    market_type: MarketType::Perp,
+    direction: PositionDirection::Long,
+    order_id,
+    base_asset_amount: 100,
+    base_asset_amount_filled: filled,
+    ..Default::default()
+  }
+}
+
+/// User with `deposit` USDC in spot market 0 and `orders`
+pub fn funded_user(
+  authority: &Pubkey,
+  sub_account_id: u16,
+  deposit: f64,
+  orders: &[Order],
+) -> User {
+  let mut user: User = zeroed();
+  user.authority = *authority;
+  user.sub_account_id = sub_account_id;
+  let usdc = &mut user.spot_positions[0];
+  usdc.market_index = 0;
+  usdc.balance_type = SpotBalanceType::Deposit;
+  // scaled balances have 9 decimals
+  usdc.scaled_balance = (deposit * 1e9).round() as u64;
+  usdc.cumulative_deposits = (deposit * 1e6).round() as i64;
+  user.has_open_order = !orders.is_empty();
+  for (slot, order) in user.orders.iter_mut().zip(orders) {
+    *slot = *order;
+  }
+  user
+}
+
+impl FakeRpc {
+  /// The Drift state with USDC as spot market 0 and perp market 0 quoted in it at `price`,
+  /// their oracles and an empty market lookup table
+  pub fn drift_markets(self, price: f64) -> Self {
+    let mut state: State = zeroed();
+    state.number_of_markets = 1;
+    state.number_of_spot_markets = 1;
+
+    let mut usdc: SpotMarket = zeroed();
+    usdc.pubkey = DriftUtils::spot_market_pda(0);
+    usdc.oracle = USDC_ORACLE;
+    usdc.oracle_source = OracleSource::QuoteAsset;
+    usdc.decimals = 6;
+    usdc.cumulative_deposit_interest = SPOT_CUMULATIVE_INTEREST_PRECISION;
+    usdc.initial_asset_weight = SPOT_WEIGHT_PRECISION;
+    usdc.maintenance_asset_weight = SPOT_WEIGHT_PRECISION;
+
+    let mut perp: PerpMarket = zeroed();
+    perp.pubkey = DriftUtils::perp_market_pda(0);
+    perp.amm.oracle = PERP_ORACLE;
+    perp.amm.oracle_source = OracleSource::Prelaunch;
+    // 10x initial and 20x maintenance leverage
+    perp.margin_ratio_initial = 1000;
+    perp.margin_ratio_maintenance = 500;
+    perp.unrealized_pnl_initial_asset_weight = SPOT_WEIGHT_PRECISION;
+    perp.unrealized_pnl_maintenance_asset_weight = SPOT_WEIGHT_PRECISION;
+
+    self
+      .account(DriftUtils::state_pda(), drift_account(&state))
+      .account(usdc.pubkey, drift_account(&usdc))
+      .account(perp.pubkey, drift_account(&perp))
+      .account(
+        USDC_ORACLE,
+        Account {
+          lamports: 1,
+          ..Default::default()
+        },
+      )
+      .account(PERP_ORACLE, prelaunch_oracle(price))
+      .account(
+        MARKET_LOOKUP_TABLE,
+        Account {
+          lamports: 1,
+          // table meta without addresses
+          data: vec![0; 56],
+          ..Default::default()
+        },
+      )
+  }
+
+  /// SOL as spot market 1 at `price`, with 80% initial and 90% maintenance asset weight.
+  /// Call after [`FakeRpc::drift_markets`], whose state it replaces.
+  pub fn drift_sol_market(self, price: f64) -> Self {
+    let mut state: State = zeroed();
+    state.number_of_markets = 1;
+    state.number_of_spot_markets = 2;
+
+    let mut sol: SpotMarket = zeroed();
+    sol.pubkey = DriftUtils::spot_market_pda(1);
+    sol.market_index = 1;
+    sol.oracle = SOL_ORACLE;
+    sol.oracle_source = OracleSource::Prelaunch;
+    sol.decimals = 9;
+    sol.cumulative_deposit_interest = SPOT_CUMULATIVE_INTEREST_PRECISION;
+    sol.cumulative_borrow_interest = SPOT_CUMULATIVE_INTEREST_PRECISION;
+    sol.initial_asset_weight = SPOT_WEIGHT_PRECISION * 8 / 10;
+    sol.maintenance_asset_weight = SPOT_WEIGHT_PRECISION * 9 / 10;
+
+    self
+      .account(DriftUtils::state_pda(), drift_account(&state))
+      .account(sol.pubkey, drift_account(&sol))
+      .account(SOL_ORACLE, prelaunch_oracle(price))
+  }
+
+  /// Add `user` at the account of its authority and sub account
+  pub fn drift_user(self, user: &User) -> Self {
+    self.account(
+      DriftUtils::user_pda(&user.authority, user.sub_account_id),
+      drift_account(user),
+    )
+  }
+
+  /// Drift instructions of every sent transaction, in order
+  pub fn sent_drift_ixs(&self) -> anyhow::Result<Vec<InstructionType>> {
+    let mut ixs = vec![];
+    for tx in self.sent() {
+      let keys = tx.message.static_account_keys();
+      for ix in tx.message.instructions() {
+        if keys.get(ix.program_id_index as usize) != Some(&drift_cpi::id()) {
+          continue;
+        }
+        let decoded = InstructionType::decode(&ix.data)
+          .map_err(|e| anyhow::anyhow!("Failed to decode instruction: {:?}", e))?;
+        ixs.push(decoded);
+      }
+    }
+    Ok(ixs)
+  }
+}
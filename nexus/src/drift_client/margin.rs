@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use drift_cpi::{
+  MarginRequirementType, PerpMarket, PerpPosition, SpotBalanceType, SpotMarket, SpotPosition, User,
+  AMM_RESERVE_PRECISION_I128, BASE_PRECISION, MARGIN_PRECISION_U128, OPEN_ORDER_MARGIN_REQUIREMENT,
+  PRICE_PRECISION, QUOTE_PRECISION, SPOT_WEIGHT_PRECISION_I128, SPOT_WEIGHT_PRECISION_U128,
+};
+
+use crate::drift_client::{DriftUtils, MarketId, ReadCache};
+
+/// Markets of a user's positions and their oracle prices in `PRICE_PRECISION`
+#[derive(Default)]
+pub struct MarginMarkets {
+  pub spot: HashMap<u16, (SpotMarket, i64)>,
+  pub perp: HashMap<u16, (PerpMarket, i64)>,
+}
+
+impl MarginMarkets {
+  /// Markets of every open position of the user, as cached at `slot`
+  pub fn from_cache(user: &User, cache: &ReadCache, slot: Option<u64>) -> anyhow::Result<Self> {
+    let price = |market: &MarketId| -> anyhow::Result<i64> {
+      let price = DriftUtils::oracle_price(market, cache, slot)?;
+      Ok((price * PRICE_PRECISION as f64).round() as i64)
+    };
+    let mut markets = Self::default();
+    for pos in user.spot_positions.iter().filter(|p| is_open_spot(p)) {
+      let market = MarketId::spot(pos.market_index);
+      let decoded = cache
+        .decoded_account::<SpotMarket>(&market.key(), slot)?
+        .decoded;
+      markets
+        .spot
+        .insert(pos.market_index, (decoded, price(&market)?));
+    }
+    for pos in user.perp_positions.iter().filter(|p| is_open_perp(p)) {
+      let market = MarketId::perp(pos.market_index);
+      let decoded = cache
+        .decoded_account::<PerpMarket>(&market.key(), slot)?
+        .decoded;
+      markets
+        .perp
+        .insert(pos.market_index, (decoded, price(&market)?));
+    }
+    Ok(markets)
+  }
+
+  /// Every cached market with an oracle price, to compute the margin of many users at once
+  pub fn all(cache: &ReadCache, slot: Option<u64>) -> anyhow::Result<Self> {
+    let price = |market: &MarketId| {
+      DriftUtils::oracle_price(market, cache, slot)
+        .ok()
+        .map(|price| (price * PRICE_PRECISION as f64).round() as i64)
+    };
+    let spot = cache
+      .decoded_accounts::<SpotMarket>(slot)?
+      .into_iter()
+      .flat_map(|m| {
+        let price = price(&MarketId::spot(m.decoded.market_index))?;
+        Some((m.decoded.market_index, (m.decoded, price)))
+      })
+      .collect();
+    let perp = cache
+      .decoded_accounts::<PerpMarket>(slot)?
+      .into_iter()
+      .flat_map(|m| {
+        let price = price(&MarketId::perp(m.decoded.market_index))?;
+        Some((m.decoded.market_index, (m.decoded, price)))
+      })
+      .collect();
+    Ok(Self { spot, perp })
+  }
+}
+
+fn is_open_spot(pos: &SpotPosition) -> bool {
+  pos.scaled_balance != 0 || pos.open_orders != 0
+}
+
+fn is_open_perp(pos: &PerpPosition) -> bool {
+  pos.base_asset_amount != 0 || pos.quote_asset_amount != 0 || pos.open_orders != 0
+}
+
+/// Size of the position if open orders fill on whichever side grows it most
+fn worst_base(pos: &PerpPosition) -> i128 {
+  let base = pos.base_asset_amount as i128;
+  (base + pos.open_bids as i128)
+    .abs()
+    .max((base + pos.open_asks as i128).abs())
+}
+
+/// Weighted collateral and the margin it must cover, both in `QUOTE_PRECISION`.
+/// Funding, size premiums (IMF) and LP shares are not included, so it is an estimate of Drift's
+/// own calculation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MarginCalculation {
+  pub total_collateral: i128,
+  pub margin_requirement: u128,
+}
+
+impl MarginCalculation {
+  pub fn new(
+    user: &User,
+    markets: &MarginMarkets,
+    kind: MarginRequirementType,
+  ) -> anyhow::Result<Self> {
+    let maintenance = matches!(kind, MarginRequirementType::Maintenance);
+    let mut calc = Self::default();
+
+    for pos in user.spot_positions.iter().filter(|p| is_open_spot(p)) {
+      let (market, price) = markets.spot.get(&pos.market_index).ok_or(anyhow::anyhow!(
+        "Spot market {} not found",
+        pos.market_index
+      ))?;
+      let value =
+        Self::token_amount(pos, market) * (*price).max(0) as u128 / 10_u128.pow(market.decimals);
+      match pos.balance_type {
+        SpotBalanceType::Deposit => {
+          let weight = match maintenance {
+            true => market.maintenance_asset_weight,
+            false => market.initial_asset_weight,
+          };
+          calc.total_collateral += (value * weight as u128 / SPOT_WEIGHT_PRECISION_U128) as i128;
+        }
+        SpotBalanceType::Borrow => {
+          let weight = match maintenance {
+            true => market.maintenance_liability_weight,
+            false => market.initial_liability_weight,
+          };
+          calc.margin_requirement += value * weight as u128 / SPOT_WEIGHT_PRECISION_U128;
+        }
+      }
+    }
+
+    for pos in user.perp_positions.iter().filter(|p| is_open_perp(p)) {
+      let (market, price) = markets.perp.get(&pos.market_index).ok_or(anyhow::anyhow!(
+        "Perp market {} not found",
+        pos.market_index
+      ))?;
+      let price = *price as i128;
+      let base = pos.base_asset_amount as i128;
+      let worst_base = worst_base(pos);
+      let ratio = match maintenance {
+        true => market.margin_ratio_maintenance,
+        false => market.margin_ratio_initial.max(user.max_margin_ratio),
+      };
+      let worst_value = (worst_base * price.abs() / AMM_RESERVE_PRECISION_I128) as u128;
+      calc.margin_requirement += worst_value * ratio as u128 / MARGIN_PRECISION_U128
+        + pos.open_orders as u128 * OPEN_ORDER_MARGIN_REQUIREMENT;
+
+      let pnl = base * price / AMM_RESERVE_PRECISION_I128 + pos.quote_asset_amount as i128;
+      calc.total_collateral += match pnl > 0 {
+        true => {
+          let weight = match maintenance {
+            true => market.unrealized_pnl_maintenance_asset_weight,
+            false => market.unrealized_pnl_initial_asset_weight,
+          };
+          pnl * weight as i128 / SPOT_WEIGHT_PRECISION_I128
+        }
+        false => pnl,
+      };
+    }
+    Ok(calc)
+  }
+
+  /// Tokens of a spot balance with interest, in the market's decimals
+  pub fn token_amount(pos: &SpotPosition, market: &SpotMarket) -> u128 {
+    let interest = match pos.balance_type {
+      SpotBalanceType::Deposit => market.cumulative_deposit_interest,
+      SpotBalanceType::Borrow => market.cumulative_borrow_interest,
+    };
+    // scaled balance is 1e9 and interest 1e10 precision
+    pos.scaled_balance as u128 * interest / 10_u128.pow(19 - market.decimals)
+  }
+
+  /// Collateral left over the requirement, zero when below it
+  pub fn free_collateral(&self) -> u128 {
+    self
+      .total_collateral
+      .saturating_sub(self.margin_requirement as i128)
+      .max(0) as u128
+  }
+
+  pub fn meets_requirement(&self) -> bool {
+    self.total_collateral >= self.margin_requirement as i128
+  }
+}
+
+/// Initial and maintenance margin of a [`User`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UserMargin {
+  /// Requirement to open new positions or withdraw
+  pub initial: MarginCalculation,
+  /// Requirement to avoid liquidation
+  pub maintenance: MarginCalculation,
+}
+
+impl UserMargin {
+  pub fn new(user: &User, markets: &MarginMarkets) -> anyhow::Result<Self> {
+    Ok(Self {
+      initial: MarginCalculation::new(user, markets, MarginRequirementType::Initial)?,
+      maintenance: MarginCalculation::new(user, markets, MarginRequirementType::Maintenance)?,
+    })
+  }
+
+  /// Margin of the user with markets and oracle prices as cached at `slot`
+  pub fn from_cache(user: &User, cache: &ReadCache, slot: Option<u64>) -> anyhow::Result<Self> {
+    Self::new(user, &MarginMarkets::from_cache(user, cache, slot)?)
+  }
+
+  /// Quote available to open new positions
+  pub fn free_collateral(&self) -> f64 {
+    self.initial.free_collateral() as f64 / QUOTE_PRECISION as f64
+  }
+
+  /// Share of maintenance collateral not needed for its requirement, from 1 without any
+  /// requirement down to -1. Below zero the user can be liquidated.
+  pub fn health(&self) -> f64 {
+    let collateral = self.maintenance.total_collateral as f64;
+    let requirement = self.maintenance.margin_requirement as f64;
+    match requirement {
+      r if r == 0.0 => 1.0,
+      _ if collateral <= 0.0 => -1.0,
+      r => (1.0 - r / collateral).max(-1.0),
+    }
+  }
+
+  pub fn can_be_liquidated(&self) -> bool {
+    !self.maintenance.meets_requirement()
+  }
+}
+
+/// Liquidation risk of one perp position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PerpHealth {
+  pub market_index: u16,
+  pub base: f64,
+  pub notional: f64,
+  /// Oracle price at which the user can be liquidated if every other price holds,
+  /// `None` if no positive price would
+  pub liquidation_price: Option<f64>,
+}
+
+/// Collateral, margin and leverage of a [`User`] in quote
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserHealth {
+  /// Collateral weighted for the initial margin
+  pub total_collateral: f64,
+  /// Collateral left over the initial margin to open new positions
+  pub free_collateral: f64,
+  pub initial_margin: f64,
+  pub maintenance_margin: f64,
+  /// Perp notional over the maintenance collateral
+  pub leverage: f64,
+  /// See [`UserMargin::health`]
+  pub health: f64,
+  pub positions: Vec<PerpHealth>,
+}
+
+impl UserHealth {
+  pub fn new(user: &User, markets: &MarginMarkets) -> anyhow::Result<Self> {
+    let margin = UserMargin::new(user, markets)?;
+    let quote = |amount: i128| amount as f64 / QUOTE_PRECISION as f64;
+    let maintenance = margin.maintenance;
+    let free_maintenance =
+      quote(maintenance.total_collateral - maintenance.margin_requirement as i128);
+
+    let mut positions = vec![];
+    for pos in user
+      .perp_positions
+      .iter()
+      .filter(|p| p.base_asset_amount != 0)
+    {
+      let (market, price) = markets.perp.get(&pos.market_index).ok_or(anyhow::anyhow!(
+        "Perp market {} not found",
+        pos.market_index
+      ))?;
+      let price = *price as f64 / PRICE_PRECISION as f64;
+      let base = pos.base_asset_amount as f64 / BASE_PRECISION as f64;
+      let worst_base = worst_base(pos) as f64 / BASE_PRECISION as f64;
+      let ratio = market.margin_ratio_maintenance as f64 / MARGIN_PRECISION_U128 as f64;
+      // as the price moves, free collateral changes by the pnl less the margin of the position
+      let slope = base - worst_base * ratio;
+      let liquidation_price = match slope {
+        s if s == 0.0 => None,
+        s => Some(price - free_maintenance / s).filter(|p| *p > 0.0),
+      };
+      positions.push(PerpHealth {
+        market_index: pos.market_index,
+        base,
+        notional: base.abs() * price,
+        liquidation_price,
+      });
+    }
+
+    let notional: f64 = positions.iter().map(|p| p.notional).sum();
+    let collateral = quote(maintenance.total_collateral);
+    let leverage = match (notional, collateral) {
+      (n, _) if n == 0.0 => 0.0,
+      (_, c) if c <= 0.0 => f64::INFINITY,
+      (n, c) => n / c,
+    };
+    Ok(Self {
+      total_collateral: quote(margin.initial.total_collateral),
+      free_collateral: margin.free_collateral(),
+      initial_margin: quote(margin.initial.margin_requirement as i128),
+      maintenance_margin: quote(maintenance.margin_requirement as i128),
+      leverage,
+      health: margin.health(),
+      positions,
+    })
+  }
+
+  /// Health of the user with markets and oracle prices as cached at `slot`
+  pub fn from_cache(user: &User, cache: &ReadCache, slot: Option<u64>) -> anyhow::Result<Self> {
+    Self::new(user, &MarginMarkets::from_cache(user, cache, slot)?)
+  }
+
+  pub fn position(&self, market_index: u16) -> Option<&PerpHealth> {
+    self
+      .positions
+      .iter()
+      .find(|p| p.market_index == market_index)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drift_client::fixtures::zeroed;
+  use drift_cpi::{SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION};
+
+  #[test]
+  fn collateral_and_health() -> anyhow::Result<()> {
+    let mut usdc: SpotMarket = zeroed();
+    usdc.decimals = 6;
+    usdc.cumulative_deposit_interest = SPOT_CUMULATIVE_INTEREST_PRECISION;
+    usdc.initial_asset_weight = SPOT_WEIGHT_PRECISION;
+    usdc.maintenance_asset_weight = SPOT_WEIGHT_PRECISION;
+    let mut sol: PerpMarket = zeroed();
+    // 10x initial and 20x maintenance leverage
+    sol.margin_ratio_initial = 1000;
+    sol.margin_ratio_maintenance = 500;
+    sol.unrealized_pnl_initial_asset_weight = SPOT_WEIGHT_PRECISION;
+    sol.unrealized_pnl_maintenance_asset_weight = SPOT_WEIGHT_PRECISION;
+    let mut markets = MarginMarkets::default();
+    markets.spot.insert(0, (usdc, PRICE_PRECISION as i64));
+    markets.perp.insert(0, (sol, 100 * PRICE_PRECISION as i64));
+
+    // $100 deposited and long 5 SOL entered at $100
+    let mut user: User = zeroed();
+    user.spot_positions[0].scaled_balance = 100_000_000_000;
+    user.perp_positions[0].base_asset_amount = 5_000_000_000;
+    user.perp_positions[0].quote_asset_amount = -500_000_000;
+
+    let margin = UserMargin::new(&user, &markets)?;
+    assert_eq!(margin.maintenance.total_collateral, 100_000_000);
+    assert_eq!(margin.maintenance.margin_requirement, 25_000_000);
+    assert_eq!(margin.free_collateral(), 50.0);
+    assert!((margin.health() - 0.75).abs() < 1e-9);
+    assert!(!margin.can_be_liquidated());
+
+    // SOL falls to $81, losing $95 of the $100
+    markets.perp.get_mut(&0).unwrap().1 = 81 * PRICE_PRECISION as i64;
+    let margin = UserMargin::new(&user, &markets)?;
+    assert_eq!(margin.maintenance.total_collateral, 5_000_000);
+    assert_eq!(margin.free_collateral(), 0.0);
+    assert!(margin.health() < 0.0);
+    assert!(margin.can_be_liquidated());
+
+    markets.perp.get_mut(&0).unwrap().1 = 100 * PRICE_PRECISION as i64;
+    let health = UserHealth::new(&user, &markets)?;
+    assert_eq!((health.total_collateral, health.leverage), (100.0, 5.0));
+    assert_eq!(health.initial_margin, 50.0);
+    // $75 of maintenance collateral to spare, lost at $4.75 per dollar the price falls
+    let liquidation_price = health.position(0).unwrap().liquidation_price.unwrap();
+    assert!((liquidation_price - (100.0 - 75.0 / 4.75)).abs() < 1e-9);
+    Ok(())
+  }
+}
@@ -1,18 +1,36 @@
+pub use auction::*;
 pub use cache::*;
 pub use client::*;
+pub use depth::*;
+pub use events::*;
 pub use historical::*;
+pub use margin::*;
 pub use orderbook::*;
 pub use program_data::*;
+pub use snapshot::*;
+pub use staleness::*;
 pub use trader::*;
 pub use types::*;
 pub use utils::*;
+pub use view::*;
+pub use watch::*;
 
 mod amm;
+pub mod auction;
 pub mod cache;
 pub mod client;
+pub mod depth;
+pub mod events;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
 pub mod historical;
+pub mod margin;
 pub mod orderbook;
 pub mod program_data;
+pub mod snapshot;
+pub mod staleness;
 pub mod trader;
 pub mod types;
 pub mod utils;
+pub mod view;
+pub mod watch;
@@ -2,23 +2,24 @@ use rayon::prelude::{IntoParallelIterator, IntoParallelRefIterator, ParallelIter
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
-use drift_cpi::{Order, User};
-use solana_client::nonblocking::rpc_client::RpcClient;
+use drift_cpi::{Order, OrderStatus, User};
+use log::*;
 use solana_sdk::pubkey::Pubkey;
-use tokio::sync::{Mutex, MutexGuard};
 
 use crate::drift_client::{DlobNode, DriftUtils, L3Orderbook, MarketId, OrderInfo, ReadCache};
-use crate::DecodedAcctCtx;
+use crate::{DecodedAcctCtx, RpcSource, SwapCell, SwapWriteGuard};
 
 type MarketKey = Pubkey;
 type UserKey = Pubkey;
 
-pub type ReadOrderbook<'a> = MutexGuard<'a, InnerOrderbook>;
-pub type WriteOrderbook<'a> = MutexGuard<'a, InnerOrderbook>;
+/// Version of the orderbook published when it was read, later writes don't change it
+pub type ReadOrderbook = Arc<InnerOrderbook>;
+pub type WriteOrderbook<'a> = SwapWriteGuard<'a, InnerOrderbook>;
 
+/// Readers never wait on the stream, see [`crate::drift_client::Cache`]
 #[derive(Default)]
 pub struct Orderbook {
-  orderbook: Arc<Mutex<InnerOrderbook>>,
+  orderbook: Arc<SwapCell<InnerOrderbook>>,
 }
 
 impl Clone for Orderbook {
@@ -29,37 +30,76 @@ impl Clone for Orderbook {
   }
 }
 
+impl From<InnerOrderbook> for Orderbook {
+  fn from(orderbook: InnerOrderbook) -> Self {
+    Self {
+      orderbook: Arc::new(SwapCell::new(orderbook)),
+    }
+  }
+}
+
 impl Orderbook {
   pub async fn new(
     markets: Vec<MarketId>,
     users: &Vec<DecodedAcctCtx<User>>,
   ) -> anyhow::Result<Self> {
-    Ok(Self {
-      orderbook: Arc::new(Mutex::new(InnerOrderbook::new(markets, users).await?)),
-    })
+    Ok(Self::from(InnerOrderbook::new(markets, users).await?))
   }
 
-  pub async fn new_from_rpc(markets: Vec<MarketId>, rpc: &RpcClient) -> anyhow::Result<Self> {
-    Ok(Self {
-      orderbook: Arc::new(Mutex::new(
-        InnerOrderbook::new_from_rpc(markets, rpc).await?,
-      )),
-    })
+  pub async fn new_from_rpc(markets: Vec<MarketId>, rpc: &dyn RpcSource) -> anyhow::Result<Self> {
+    Ok(Self::from(
+      InnerOrderbook::new_from_rpc(markets, rpc).await?,
+    ))
   }
 
+  /// Never waits on the stream
   pub async fn read(&self) -> ReadOrderbook {
-    self.orderbook.lock().await
+    self.orderbook.load()
   }
 
   pub async fn write(&self) -> WriteOrderbook {
-    self.orderbook.lock().await
+    self.orderbook.write().await
+  }
+
+  /// Compare against a fresh load of every user over RPC to detect drift in the streamed book
+  pub async fn check_consistency(&self, rpc: &dyn RpcSource) -> anyhow::Result<OrderbookDiff> {
+    let markets = self.read().await.markets.clone();
+    let fresh = InnerOrderbook::new_from_rpc(markets, rpc).await?;
+    let diff = self.read().await.diff(&fresh);
+    if !diff.is_empty() {
+      warn!(
+        "Orderbook drifted from RPC: {} missing, {} extra, {} changed orders",
+        diff.missing.len(),
+        diff.extra.len(),
+        diff.changed.len()
+      );
+    }
+    Ok(diff)
   }
 }
 
-#[derive(Default)]
+/// Result of [`InnerOrderbook::diff`]
+#[derive(Debug, Default)]
+pub struct OrderbookDiff {
+  /// Open in the fresh book but not in this one
+  pub missing: Vec<(UserKey, Order)>,
+  /// In this book but no longer open in the fresh one
+  pub extra: Vec<(UserKey, Order)>,
+  /// Open in both with a different size, fill or price, as in the fresh book
+  pub changed: Vec<(UserKey, Order)>,
+}
+
+impl OrderbookDiff {
+  pub fn is_empty(&self) -> bool {
+    self.missing.is_empty() && self.extra.is_empty() && self.changed.is_empty()
+  }
+}
+
+/// Users of each market are a persistent map, so publishing a copy after an update is cheap
+#[derive(Default, Clone)]
 pub struct InnerOrderbook {
   markets: Vec<MarketId>,
-  orderbook: HashMap<MarketKey, HashMap<UserKey, HashSet<DlobNode>>>,
+  orderbook: HashMap<MarketKey, im::HashMap<UserKey, HashSet<DlobNode>>>,
 }
 
 impl InnerOrderbook {
@@ -75,7 +115,7 @@ impl InnerOrderbook {
     Ok(this)
   }
 
-  pub async fn new_from_rpc(markets: Vec<MarketId>, rpc: &RpcClient) -> anyhow::Result<Self> {
+  pub async fn new_from_rpc(markets: Vec<MarketId>, rpc: &dyn RpcSource) -> anyhow::Result<Self> {
     let mut this = Self {
       markets,
       orderbook: HashMap::new(),
@@ -84,12 +124,37 @@ impl InnerOrderbook {
     Ok(this)
   }
 
+  /// Empty orderbook for `markets`
+  pub fn from_markets(markets: Vec<MarketId>) -> Self {
+    Self {
+      markets,
+      orderbook: HashMap::new(),
+    }
+  }
+
+  pub fn markets(&self) -> &[MarketId] {
+    &self.markets
+  }
+
+  /// Open orders of every user across all markets
+  pub fn orders_by_user(&self) -> HashMap<UserKey, Vec<Order>> {
+    let mut res: HashMap<UserKey, Vec<Order>> = HashMap::new();
+    for users in self.orderbook.values() {
+      for (user, nodes) in users {
+        res
+          .entry(*user)
+          .or_default()
+          .extend(nodes.iter().map(|node| node.order));
+      }
+    }
+    res
+  }
+
   pub fn ready(&self, market: &MarketKey) -> bool {
-    !self
+    self
       .orderbook
       .get(market)
-      .unwrap_or(&HashMap::new())
-      .is_empty()
+      .is_some_and(|users| !users.is_empty())
   }
 
   pub fn market_users(&self, market: &MarketId) -> anyhow::Result<usize> {
@@ -109,7 +174,7 @@ impl InnerOrderbook {
     )
   }
 
-  pub fn l3(&self, market: &MarketId, cache: &ReadCache<'_>) -> anyhow::Result<L3Orderbook> {
+  pub fn l3(&self, market: &MarketId, cache: &ReadCache) -> anyhow::Result<L3Orderbook> {
     let mut bids: Vec<_> = self
       .orders(&market.key())?
       .values()
@@ -188,7 +253,10 @@ impl InnerOrderbook {
     })
   }
 
-  pub fn orders(&self, market: &MarketKey) -> anyhow::Result<&HashMap<UserKey, HashSet<DlobNode>>> {
+  pub fn orders(
+    &self,
+    market: &MarketKey,
+  ) -> anyhow::Result<&im::HashMap<UserKey, HashSet<DlobNode>>> {
     self
       .orderbook
       .get(market)
@@ -202,14 +270,15 @@ impl InnerOrderbook {
     order: Order,
   ) -> anyhow::Result<()> {
     let node = DlobNode::new(user, order);
-    if !node.filled() && !node.canceled() && !node.expired() {
+    if node.is_valid() {
+      // replace, since an equal node may have a different fill
       self
         .orderbook
         .entry(market)
         .or_default()
         .entry(user)
         .or_default()
-        .insert(node);
+        .replace(node);
     } else {
       let orders = self
         .orderbook
@@ -222,14 +291,73 @@ impl InnerOrderbook {
     Ok(())
   }
 
+  /// Replace the user's orders with the open orders in the account,
+  /// so orders that were filled or canceled and cleared from their slot leave the book
   pub fn insert_user(&mut self, user: DecodedAcctCtx<User>) -> anyhow::Result<()> {
+    let mut next: HashMap<MarketKey, HashSet<DlobNode>> = HashMap::new();
     for o in user.decoded.orders {
-      let market = MarketId::from((o.market_index, o.market_type)).key();
-      self.insert_order(market, user.key, o)?;
+      let node = DlobNode::new(user.key, o);
+      let market = MarketId::from((o.market_index, o.market_type));
+      if matches!(o.status, OrderStatus::Open) && node.is_valid() && self.markets.contains(&market)
+      {
+        next.entry(market.key()).or_default().insert(node);
+      }
+    }
+    for (market, users) in self.orderbook.iter_mut() {
+      match next.remove(market) {
+        Some(nodes) => {
+          users.insert(user.key, nodes);
+        }
+        None => {
+          users.remove(&user.key);
+        }
+      }
+    }
+    for (market, nodes) in next {
+      self
+        .orderbook
+        .entry(market)
+        .or_default()
+        .insert(user.key, nodes);
     }
     Ok(())
   }
 
+  /// Orders in the tracked markets that differ from `fresh`, such as a book loaded over RPC.
+  /// Orders that changed between the slots the two books were loaded at also show up here.
+  pub fn diff(&self, fresh: &InnerOrderbook) -> OrderbookDiff {
+    let open = |book: &InnerOrderbook| -> HashMap<(UserKey, u32), Order> {
+      self
+        .markets
+        .iter()
+        .flat_map(|m| book.orderbook.get(&m.key()))
+        .flat_map(|users| users.values().flatten())
+        .map(|node| ((node.user, node.order.order_id), node.order))
+        .collect()
+    };
+    let (ours, theirs) = (open(self), open(fresh));
+    let mut diff = OrderbookDiff::default();
+    for ((user, id), order) in &theirs {
+      match ours.get(&(*user, *id)) {
+        None => diff.missing.push((*user, *order)),
+        Some(o)
+          if o.base_asset_amount != order.base_asset_amount
+            || o.base_asset_amount_filled != order.base_asset_amount_filled
+            || o.price != order.price =>
+        {
+          diff.changed.push((*user, *order))
+        }
+        Some(_) => {}
+      }
+    }
+    for ((user, id), order) in &ours {
+      if !theirs.contains_key(&(*user, *id)) {
+        diff.extra.push((*user, *order));
+      }
+    }
+    diff
+  }
+
   pub fn load(&mut self, users: &Vec<DecodedAcctCtx<User>>) -> anyhow::Result<()> {
     let markets = Arc::new(self.markets.clone());
     let results: Vec<(UserKey, Vec<Order>)> = users
@@ -263,7 +391,7 @@ impl InnerOrderbook {
     Ok(())
   }
 
-  pub async fn load_from_rpc(&mut self, rpc: &RpcClient) -> anyhow::Result<()> {
+  pub async fn load_from_rpc(&mut self, rpc: &dyn RpcSource) -> anyhow::Result<()> {
     let users = DriftUtils::users(rpc).await?;
     let markets = Arc::new(self.markets.clone());
     let results: Vec<(UserKey, Vec<Order>)> = users
@@ -297,3 +425,52 @@ impl InnerOrderbook {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drift_client::fixtures::{
+    drift_account as account, funded_user, partially_filled_order as order,
+  };
+  use crate::FakeRpc;
+
+  fn user(orders: &[Order]) -> User {
+    funded_user(&Pubkey::default(), 0, 0.0, orders)
+  }
+
+  #[tokio::test]
+  async fn insert_user_removes_cleared_orders() -> anyhow::Result<()> {
+    let market = MarketId::perp(0);
+    let key = Pubkey::new_unique();
+    let rpc = FakeRpc::new().account(key, account(&user(&[order(1, 0), order(2, 0)])));
+    let mut book = InnerOrderbook::new_from_rpc(vec![market], &rpc).await?;
+    assert_eq!(book.market_orders(&market)?, 2);
+
+    // order 1 partially filled, order 2 filled and its slot zeroed, order 3 in an untracked market
+    let untracked = Order {
+      market_index: 1,
+      ..order(3, 0)
+    };
+    let updated = user(&[order(1, 40), untracked]);
+    rpc.set_account(key, account(&updated));
+    let fresh = InnerOrderbook::new_from_rpc(vec![market], &rpc).await?;
+    let diff = book.diff(&fresh);
+    assert_eq!(
+      (diff.missing.len(), diff.extra.len(), diff.changed.len()),
+      (0, 1, 1)
+    );
+
+    book.insert_user(DecodedAcctCtx {
+      key,
+      account: account(&updated),
+      slot: 2,
+      decoded: updated,
+    })?;
+    assert_eq!(book.market_orders(&market)?, 1);
+    assert!(book.orders(&MarketId::perp(1).key()).is_err());
+    let node = book.orders(&market.key())?[&key].iter().next().unwrap();
+    assert_eq!(node.order.base_asset_amount_filled, 40);
+    assert!(book.diff(&fresh).is_empty());
+    Ok(())
+  }
+}
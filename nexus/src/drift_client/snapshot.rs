@@ -0,0 +1,456 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use drift_cpi::{MarketType, Order};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::*;
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::drift_client::{
+  Cache, CacheKeyRegistry, InnerCache, InnerOrderbook, MarketId, Orderbook,
+};
+use crate::{AcctCtx, BlockInfo, RingMap, StreamHealth, Time};
+
+/// Bumped when the layout of [`Snapshot`] changes, so an old file is ignored rather than misread
+const SNAPSHOT_VERSION: u8 = 1;
+
+/// Newest version of a cached account
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SnapshotAccount {
+  pub key: Pubkey,
+  pub slot: u64,
+  pub lamports: u64,
+  pub owner: Pubkey,
+  pub executable: bool,
+  pub rent_epoch: u64,
+  pub data: Vec<u8>,
+}
+
+impl From<&AcctCtx> for SnapshotAccount {
+  fn from(ctx: &AcctCtx) -> Self {
+    Self {
+      key: ctx.key,
+      slot: ctx.slot,
+      lamports: ctx.account.lamports,
+      owner: ctx.account.owner,
+      executable: ctx.account.executable,
+      rent_epoch: ctx.account.rent_epoch,
+      data: ctx.account.data.clone(),
+    }
+  }
+}
+
+impl From<SnapshotAccount> for AcctCtx {
+  fn from(acct: SnapshotAccount) -> Self {
+    Self {
+      key: acct.key,
+      slot: acct.slot,
+      account: Account {
+        lamports: acct.lamports,
+        data: acct.data,
+        owner: acct.owner,
+        executable: acct.executable,
+        rent_epoch: acct.rent_epoch,
+      },
+    }
+  }
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SnapshotBlock {
+  pub slot: u64,
+  pub blockhash: String,
+  pub unix: i64,
+}
+
+/// Open orders of one user in the orderbook
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SnapshotOrders {
+  pub user: Pubkey,
+  pub orders: Vec<Order>,
+}
+
+/// [`Cache`] and [`Orderbook`] state at a slot, persisted so an engine can warm start after a restart
+/// instead of loading every market, oracle and user over RPC.
+/// Accounts that changed after `slot` are reconciled by the geyser stream,
+/// see [`crate::NexusClient::resume_from`].
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct Snapshot {
+  pub version: u8,
+  pub slot: u64,
+  pub depth: u64,
+  pub accounts: Vec<SnapshotAccount>,
+  pub blocks: Vec<SnapshotBlock>,
+  pub registry: Vec<(CacheKeyRegistry, Vec<Pubkey>)>,
+  pub markets: Vec<(u16, MarketType)>,
+  pub orders: Vec<SnapshotOrders>,
+}
+
+impl Snapshot {
+  /// Newest version of every cached account, and the open orders of every user if `orderbook` is set
+  pub fn capture(cache: &InnerCache, orderbook: Option<&InnerOrderbook>) -> Self {
+    let accounts = cache
+      .accounts
+      .values()
+      .flat_map(|ring| ring.newest().map(|(_, ctx)| SnapshotAccount::from(ctx)))
+      .collect();
+    let blocks = cache
+      .blocks
+      .values()
+      .map(|block| SnapshotBlock {
+        slot: block.slot,
+        blockhash: block.blockhash.clone(),
+        unix: block.time.to_unix(),
+      })
+      .collect();
+    let (markets, orders) = match orderbook {
+      Some(orderbook) => (
+        orderbook
+          .markets()
+          .iter()
+          .map(|m| (m.index, m.kind))
+          .collect(),
+        orderbook
+          .orders_by_user()
+          .into_iter()
+          .map(|(user, orders)| SnapshotOrders { user, orders })
+          .collect(),
+      ),
+      None => (vec![], vec![]),
+    };
+    Self {
+      version: SNAPSHOT_VERSION,
+      slot: cache.slot,
+      depth: cache.depth as u64,
+      accounts,
+      blocks,
+      registry: cache
+        .key_registry
+        .iter()
+        .map(|(key, keys)| (*key, keys.clone()))
+        .collect(),
+      markets,
+      orders,
+    }
+  }
+
+  /// Write gzipped to a temporary file and rename, so a crash mid-write never corrupts the last snapshot
+  pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut writer = GzEncoder::new(BufWriter::new(File::create(&tmp)?), Compression::fast());
+    self.serialize(&mut writer)?;
+    writer.finish()?.flush()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+  }
+
+  /// Returns `None` if there is no snapshot at `path` or it was written by an incompatible version
+  pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+    if !path.exists() {
+      return Ok(None);
+    }
+    let mut bytes = vec![];
+    GzDecoder::new(BufReader::new(File::open(path)?)).read_to_end(&mut bytes)?;
+    if bytes.first() != Some(&SNAPSHOT_VERSION) {
+      warn!("Ignoring snapshot {:?} written by another version", path);
+      return Ok(None);
+    }
+    Ok(Some(Self::try_from_slice(&bytes)?))
+  }
+
+  /// Save a snapshot to `path` every `interval` while the stream is healthy, until the task is aborted
+  pub fn save_every(
+    path: PathBuf,
+    interval: Duration,
+    cache: Cache,
+    orderbook: Option<Orderbook>,
+    health: StreamHealth,
+  ) -> tokio::task::JoinHandle<()> {
+    tokio::task::spawn(async move {
+      let mut interval = tokio::time::interval(interval);
+      // the first tick completes immediately, before the stream has caught up
+      interval.tick().await;
+      loop {
+        interval.tick().await;
+        // accounts missed while the stream is down would be saved as current
+        if !health.is_healthy() {
+          continue;
+        }
+        // lock the orderbook before the cache, the order engines read them in
+        let snapshot = match &orderbook {
+          Some(orderbook) => {
+            let orderbook = orderbook.read().await;
+            Self::capture(&*cache.read().await, Some(&orderbook))
+          }
+          None => Self::capture(&*cache.read().await, None),
+        };
+        let path = path.clone();
+        let now = std::time::Instant::now();
+        match tokio::task::spawn_blocking(move || snapshot.save(&path)).await {
+          Ok(Ok(())) => debug!("Saved snapshot in {:?}", now.elapsed()),
+          Ok(Err(e)) => error!("Failed to save snapshot: {:?}", e),
+          Err(e) => error!("Snapshot task panicked: {:?}", e),
+        }
+      }
+    })
+  }
+
+  pub fn cache(&self) -> Cache {
+    let depth = self.depth as usize;
+    let mut blocks = RingMap::new(depth);
+    for block in &self.blocks {
+      blocks.insert(
+        block.slot,
+        BlockInfo {
+          slot: block.slot,
+          blockhash: block.blockhash.clone(),
+          time: Time::from_unix(block.unix),
+        },
+      );
+    }
+    let mut accounts = im::HashMap::new();
+    for acct in &self.accounts {
+      let mut ring = RingMap::new(depth);
+      ring.insert(acct.slot, AcctCtx::from(acct.clone()));
+      accounts.insert(acct.key, Arc::new(ring));
+    }
+    Cache::from(InnerCache {
+      slot: self.slot,
+      depth,
+      blocks,
+      accounts,
+      key_registry: self.registry.iter().cloned().collect(),
+    })
+  }
+
+  /// Markets whose orders were captured
+  pub fn markets(&self) -> Vec<MarketId> {
+    self
+      .markets
+      .iter()
+      .map(|(index, kind)| MarketId::from((*index, *kind)))
+      .collect()
+  }
+
+  /// Whether the orders of every one of `markets` were captured
+  pub fn has_markets(&self, markets: &[MarketId]) -> bool {
+    let captured = self.markets();
+    markets.iter().all(|m| captured.contains(m))
+  }
+
+  /// Orderbook of `markets`, the markets the engine is configured for.
+  /// Errors if the snapshot didn't capture one of them, check [`Snapshot::has_markets`] first.
+  pub fn orderbook(&self, markets: &[MarketId]) -> anyhow::Result<Orderbook> {
+    let captured = self.markets();
+    if let Some(missing) = markets.iter().find(|m| !captured.contains(m)) {
+      return Err(anyhow::anyhow!(
+        "Snapshot has no orders for market {:?}",
+        missing
+      ));
+    }
+    let mut orderbook = InnerOrderbook::from_markets(markets.to_vec());
+    for user in &self.orders {
+      for order in &user.orders {
+        let market = MarketId::from((order.market_index, order.market_type));
+        if markets.contains(&market) {
+          orderbook.insert_order(market.key(), user.user, *order)?;
+        }
+      }
+    }
+    Ok(Orderbook::from(orderbook))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drift_client::fixtures::*;
+  use crate::drift_client::DriftUtils;
+  use crate::{FakeGeyser, FakeRpc, GeyserConfig, NexusClient, ReconnectConfig};
+  use drift_cpi::{OrderStatus, PositionDirection, User};
+  use std::collections::HashSet;
+  use yellowstone_grpc_proto::prelude::subscribe_update::UpdateOneof;
+  use yellowstone_grpc_proto::prelude::{CommitmentLevel, SubscribeUpdate, SubscribeUpdateSlot};
+
+  fn user(authority: &Pubkey, deposit: f64, orders: &[Order], last_active_slot: u64) -> User {
+    let mut user = funded_user(authority, 0, deposit, orders);
+    user.last_active_slot = last_active_slot;
+    user
+  }
+
+  fn cached(cache: &mut InnerCache, key: Pubkey, account: Account, slot: u64) {
+    cache
+      .ring_mut(key)
+      .insert(slot, AcctCtx { key, account, slot });
+  }
+
+  #[tokio::test]
+  async fn snapshot_round_trip() -> anyhow::Result<()> {
+    let market = MarketId::perp(0);
+    let key = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let cache = Cache::new(10);
+    {
+      let mut cache = cache.write().await;
+      cache.slot = 12;
+      for slot in [10, 12] {
+        cache.ring_mut(key).insert(
+          slot,
+          AcctCtx {
+            key,
+            account: Account {
+              lamports: slot,
+              ..Default::default()
+            },
+            slot,
+          },
+        );
+      }
+    }
+    let mut orderbook = InnerOrderbook::from_markets(vec![market]);
+    let order = Order {
+      status: OrderStatus::Open,
+      market_type: market.kind,
+      market_index: market.index,
+      direction: PositionDirection::Long,
+      order_id: 1,
+      base_asset_amount: 100,
+      ..Default::default()
+    };
+    orderbook.insert_order(market.key(), user, order)?;
+
+    let path = std::env::temp_dir().join(format!("snapshot_{}.bin", Pubkey::new_unique()));
+    Snapshot::capture(&*cache.read().await, Some(&orderbook)).save(&path)?;
+    let snapshot = Snapshot::load(&path)?.ok_or(anyhow::anyhow!("Snapshot not found"))?;
+    std::fs::remove_file(&path)?;
+
+    assert_eq!(snapshot.slot, 12);
+    let restored = snapshot.cache();
+    let restored = restored.read().await;
+    let ctx = restored.account(&key, None)?;
+    assert_eq!((ctx.slot, ctx.account.lamports), (12, 12));
+    let restored = snapshot.orderbook(&[market])?;
+    let restored = restored.read().await;
+    assert_eq!(restored.market_orders(&market)?, 1);
+    assert!(!snapshot.has_markets(&[MarketId::perp(1)]));
+    assert!(snapshot.orderbook(&[MarketId::perp(1)]).is_err());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn resume_reconciles_changes_since_snapshot() -> anyhow::Result<()> {
+    let market = MarketId::perp(0);
+    let market_acct = Pubkey::new_unique();
+    let (a, b, c, d) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let key = |authority: &Pubkey| DriftUtils::user_pda(authority, 0);
+    let bid = perp_order(1, PositionDirection::Long, 99.0, 1.0);
+    let ask = perp_order(2, PositionDirection::Short, 101.0, 1.0);
+
+    // a and b have orders at the snapshot, c only has a cached account
+    let a_before = user(&a, 100.0, &[bid], 40);
+    let b_before = user(&b, 100.0, &[ask], 10);
+    let c_before = user(&c, 100.0, &[], 10);
+    let cache = Cache::new(10);
+    {
+      let mut cache = cache.write().await;
+      cache.slot = 50;
+      cached(
+        &mut cache,
+        market_acct,
+        Account {
+          lamports: 1,
+          ..Default::default()
+        },
+        50,
+      );
+      for user in [&a_before, &b_before, &c_before] {
+        cached(&mut cache, key(&user.authority), drift_account(user), 50);
+      }
+    }
+    let mut orderbook = InnerOrderbook::from_markets(vec![market]);
+    orderbook.insert_order(market.key(), key(&a), bid)?;
+    orderbook.insert_order(market.key(), key(&b), ask)?;
+    let snapshot = Snapshot::capture(&*cache.read().await, Some(&orderbook));
+
+    // since the snapshot a canceled its bid and d placed an ask, c changed without being marked active
+    let a_after = user(&a, 100.0, &[], 60);
+    let c_after = user(&c, 200.0, &[], 10);
+    let d_after = user(
+      &d,
+      100.0,
+      &[perp_order(3, PositionDirection::Short, 102.0, 1.0)],
+      70,
+    );
+    let rpc = FakeRpc::new()
+      .slot(100)
+      .account(
+        market_acct,
+        Account {
+          lamports: 2,
+          ..Default::default()
+        },
+      )
+      .drift_user(&a_after)
+      .drift_user(&b_before)
+      .drift_user(&c_after)
+      .drift_user(&d_after);
+    let geyser = Arc::new(FakeGeyser::new().updates([SubscribeUpdate {
+      filters: vec!["slots".to_string()],
+      update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+        slot: 100,
+        ..Default::default()
+      })),
+    }]));
+    let cfg = GeyserConfig::new(String::new(), String::new(), CommitmentLevel::Processed);
+    let nexus = NexusClient::with_source(cfg, geyser)
+      .rpc(Arc::new(rpc))
+      .resume_from(snapshot.slot)
+      .reconnect(ReconnectConfig {
+        max_retries: Some(0),
+        idle_timeout: Duration::from_millis(100),
+        ..Default::default()
+      });
+    let cache = snapshot.cache();
+    let orderbook = snapshot.orderbook(&[market])?;
+    // the fake never closes, so the stream ends once the idle timeout runs out of retries
+    assert!(nexus
+      .stream(&cache, None, Some(&orderbook), None)
+      .await
+      .is_err());
+
+    let cache = cache.read().await;
+    assert_eq!(cache.slot, 100);
+    assert_eq!(cache.account(&market_acct, None)?.account.lamports, 2);
+    let data = |key| {
+      cache
+        .account(&key, None)
+        .map(|ctx| ctx.account.data.clone())
+    };
+    assert_eq!(data(key(&a))?, drift_account(&a_after).data);
+    // every cached user is re-fetched, active or not
+    assert_eq!(data(key(&c))?, drift_account(&c_after).data);
+    // only the orderbook tracks users that weren't cached
+    assert!(data(key(&d)).is_err());
+
+    let users: HashSet<Pubkey> = orderbook
+      .read()
+      .await
+      .orders_by_user()
+      .into_keys()
+      .collect();
+    assert_eq!(users, HashSet::from([key(&b), key(&d)]));
+    Ok(())
+  }
+}
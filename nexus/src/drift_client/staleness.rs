@@ -0,0 +1,377 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use drift_cpi::{get_oracle_price, MarketType, OracleSource, PerpMarket, SpotMarket};
+use solana_sdk::clock::Slot;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::drift_client::{MarketId, ReadCache};
+use crate::{StreamHealth, StreamStatus, Time, ToAccountInfo};
+
+/// Error returned when cached data is too old to safely build orders from.
+/// Engines should pause until the stream catches up instead of exiting.
+#[derive(Debug, thiserror::Error)]
+pub enum StaleDataError {
+  /// An account was last streamed too many slots before the latest cached slot
+  #[error(
+    "Account {key} updated at slot {slot} lags cache slot {latest} by {lag} slots (max {max})"
+  )]
+  AccountSlotLag {
+    key: Pubkey,
+    slot: Slot,
+    latest: Slot,
+    lag: u64,
+    max: u64,
+  },
+  /// The oracle price was published too many slots ago
+  #[error("Oracle {oracle} for market {market:?} is delayed {delay} slots (max {max})")]
+  OracleDelay {
+    market: MarketId,
+    oracle: Pubkey,
+    delay: i64,
+    max: i64,
+  },
+  /// The newest cached block is too far behind wall clock
+  #[error("Newest block at slot {slot} is {age:?} old (max {max:?})")]
+  BlockAge {
+    slot: Slot,
+    age: Duration,
+    max: Duration,
+  },
+  /// Data required for the check is not in the cache
+  #[error("Missing data for staleness check: {0}")]
+  Missing(String),
+  /// The geyser stream feeding the cache is down or recovering
+  #[error("Geyser stream is {0:?}")]
+  Stream(StreamStatus),
+}
+
+/// Max-age limits checked by [`crate::drift_client::DriftClient`] before building any order instruction.
+/// `None` disables a check.
+#[derive(Debug, Clone)]
+pub struct StalenessConfig {
+  /// Max slots an oracle account may lag [`crate::drift_client::InnerCache::slot`]
+  pub max_oracle_slot_lag: Option<u64>,
+  /// Max slots a market account may lag the cache slot.
+  /// Markets are written by fills and funding updates, so this is looser than for oracles.
+  pub max_market_slot_lag: Option<u64>,
+  /// Max slots our user account may lag the cache slot.
+  /// It is only written when we trade, so by default it only has to be cached.
+  pub max_user_slot_lag: Option<u64>,
+  /// Max `OraclePriceData::delay` in slots, measured against the latest cached slot
+  pub max_oracle_delay: Option<i64>,
+  /// Max age of the newest cached block versus wall clock.
+  /// Requires `blocks_meta` in the geyser subscription, otherwise the cache only has the block loaded at startup.
+  pub max_block_age: Option<Duration>,
+}
+
+impl Default for StalenessConfig {
+  fn default() -> Self {
+    Self {
+      // 150 slots = 60 seconds
+      max_oracle_slot_lag: Some(150),
+      // funding is updated at least hourly
+      max_market_slot_lag: Some(9_000),
+      max_user_slot_lag: None,
+      max_oracle_delay: Some(30),
+      max_block_age: None,
+    }
+  }
+}
+
+impl StalenessConfig {
+  pub fn disabled() -> Self {
+    Self {
+      max_oracle_slot_lag: None,
+      max_market_slot_lag: None,
+      max_user_slot_lag: None,
+      max_oracle_delay: None,
+      max_block_age: None,
+    }
+  }
+
+  /// Errors with [`StaleDataError::Stream`] unless `health` is healthy, then runs [`StalenessConfig::check`]
+  pub fn check_stream(
+    &self,
+    health: &StreamHealth,
+    cache: &ReadCache,
+    markets: &[MarketId],
+    users: &[Pubkey],
+  ) -> Result<(), StaleDataError> {
+    if !health.is_healthy() {
+      return Err(StaleDataError::Stream(health.status()));
+    }
+    self.check(cache, markets, users)
+  }
+
+  /// Check `markets`, their oracles, `users` and the newest block against the configured limits
+  pub fn check(
+    &self,
+    cache: &ReadCache,
+    markets: &[MarketId],
+    users: &[Pubkey],
+  ) -> Result<(), StaleDataError> {
+    if let Some(max) = self.max_block_age {
+      let (slot, block) = cache
+        .blocks
+        .newest()
+        .ok_or(StaleDataError::Missing("no block in cache".to_string()))?;
+      let age_secs = (Time::now().to_unix() - block.time.to_unix()).max(0) as u64;
+      let age = Duration::from_secs(age_secs);
+      if age > max {
+        return Err(StaleDataError::BlockAge {
+          slot: *slot,
+          age,
+          max,
+        });
+      }
+    }
+
+    for user in users {
+      Self::check_slot_lag(cache, user, self.max_user_slot_lag)?;
+    }
+
+    let markets: HashSet<MarketId> = markets.iter().cloned().collect();
+    for market in markets {
+      Self::check_slot_lag(cache, &market.key(), self.max_market_slot_lag)?;
+      let (oracle, oracle_source) = Self::market_oracle(cache, &market)?;
+      if matches!(oracle_source, OracleSource::QuoteAsset) || oracle == Pubkey::default() {
+        continue;
+      }
+      Self::check_slot_lag(cache, &oracle, self.max_oracle_slot_lag)?;
+      let oracle_ctx = cache
+        .account(&oracle, None)
+        .map_err(|e| StaleDataError::Missing(e.to_string()))?;
+
+      if let Some(max) = self.max_oracle_delay {
+        let acct_info = oracle_ctx
+          .account
+          .to_account_info(oracle, false, false, false);
+        let price_data = get_oracle_price(&oracle_source, &acct_info, cache.slot)
+          .map_err(|e| StaleDataError::Missing(format!("oracle price for {}: {:?}", oracle, e)))?;
+        if price_data.delay > max {
+          return Err(StaleDataError::OracleDelay {
+            market,
+            oracle,
+            delay: price_data.delay,
+            max,
+          });
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Errors if `key` is not cached or its newest version lags the cache slot by more than `max`
+  fn check_slot_lag(
+    cache: &ReadCache,
+    key: &Pubkey,
+    max: Option<u64>,
+  ) -> Result<(), StaleDataError> {
+    let ctx = cache
+      .account(key, None)
+      .map_err(|e| StaleDataError::Missing(e.to_string()))?;
+    if let Some(max) = max {
+      let lag = cache.slot.saturating_sub(ctx.slot);
+      if lag > max {
+        return Err(StaleDataError::AccountSlotLag {
+          key: *key,
+          slot: ctx.slot,
+          latest: cache.slot,
+          lag,
+          max,
+        });
+      }
+    }
+    Ok(())
+  }
+
+  fn market_oracle(
+    cache: &ReadCache,
+    market: &MarketId,
+  ) -> Result<(Pubkey, OracleSource), StaleDataError> {
+    let missing = |e: anyhow::Error| StaleDataError::Missing(e.to_string());
+    Ok(match market.kind {
+      MarketType::Perp => {
+        let perp = cache
+          .decoded_account::<PerpMarket>(&market.key(), None)
+          .map_err(missing)?
+          .decoded;
+        (perp.amm.oracle, perp.amm.oracle_source)
+      }
+      MarketType::Spot => {
+        let spot = cache
+          .decoded_account::<SpotMarket>(&market.key(), None)
+          .map_err(missing)?
+          .decoded;
+        (spot.oracle, spot.oracle_source)
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drift_client::fixtures::zeroed;
+  use crate::drift_client::Cache;
+  use crate::{AcctCtx, BlockInfo};
+  use anchor_lang::AccountSerialize;
+  use solana_sdk::account::Account;
+
+  fn ctx(key: Pubkey, slot: Slot, data: Vec<u8>) -> AcctCtx {
+    AcctCtx {
+      key,
+      account: Account {
+        lamports: 1,
+        data,
+        ..Default::default()
+      },
+      slot,
+    }
+  }
+
+  /// Cache at slot 1000 with a perp market written at `market_slot`, its oracle at `oracle_slot`
+  /// and a user at `user_slot`
+  async fn cache(market_slot: Slot, oracle_slot: Slot, user_slot: Slot) -> (Cache, Pubkey, Pubkey) {
+    let (oracle, user) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut market: PerpMarket = zeroed();
+    market.amm.oracle = oracle;
+    market.amm.oracle_source = OracleSource::Pyth;
+    let mut data = vec![];
+    market.try_serialize(&mut data).unwrap();
+
+    let cache = Cache::new(10);
+    {
+      let mut inner = cache.write().await;
+      inner.slot = 1000;
+      let key = MarketId::perp(0).key();
+      inner
+        .ring_mut(key)
+        .insert(market_slot, ctx(key, market_slot, data));
+      inner
+        .ring_mut(oracle)
+        .insert(oracle_slot, ctx(oracle, oracle_slot, vec![]));
+      inner
+        .ring_mut(user)
+        .insert(user_slot, ctx(user, user_slot, vec![]));
+    }
+    (cache, oracle, user)
+  }
+
+  /// Pyth price account with a $100 price last valid at `valid_slot`
+  fn pyth_price(valid_slot: Slot) -> Vec<u8> {
+    // `pyth_client::Price` is 3312 bytes, only the fields read are set
+    let mut data = vec![0u8; 3312];
+    data[20..24].copy_from_slice(&(-6i32).to_le_bytes()); // expo
+    data[40..48].copy_from_slice(&valid_slot.to_le_bytes()); // valid_slot
+    data[208..216].copy_from_slice(&100_000_000i64.to_le_bytes()); // agg.price
+    data
+  }
+
+  fn config() -> StalenessConfig {
+    StalenessConfig {
+      max_oracle_delay: None,
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  async fn account_slot_lag() {
+    let market = [MarketId::perp(0)];
+    let (cache, _, user) = cache(900, 990, 10).await;
+    let cache = cache.read().await;
+    // our user isn't written unless we trade, so it only has to be cached by default
+    assert!(config().check(&cache, &market, &[user]).is_ok());
+    assert!(matches!(
+      config().check(&cache, &market, &[Pubkey::new_unique()]),
+      Err(StaleDataError::Missing(_))
+    ));
+    let strict = StalenessConfig {
+      max_user_slot_lag: Some(100),
+      max_market_slot_lag: Some(50),
+      ..config()
+    };
+    assert!(matches!(
+      strict.check(&cache, &market, &[user]),
+      Err(StaleDataError::AccountSlotLag { key, lag: 990, .. }) if key == user
+    ));
+    assert!(matches!(
+      strict.check(&cache, &market, &[]),
+      Err(StaleDataError::AccountSlotLag { key, lag: 100, .. }) if key == MarketId::perp(0).key()
+    ));
+
+    let (cache, oracle, user) = self::cache(900, 800, 1000).await;
+    assert!(matches!(
+      config().check(&cache.read().await, &market, &[user]),
+      Err(StaleDataError::AccountSlotLag { key, lag: 200, max: 150, .. }) if key == oracle
+    ));
+  }
+
+  #[tokio::test]
+  async fn block_age_and_stream() {
+    let (cache, _, user) = cache(1000, 1000, 1000).await;
+    let cache = cache.read().await;
+    let with_block_age = StalenessConfig {
+      max_block_age: Some(Duration::from_secs(60)),
+      ..config()
+    };
+    assert!(matches!(
+      with_block_age.check(&cache, &[], &[user]),
+      Err(StaleDataError::Missing(_))
+    ));
+
+    let health = StreamHealth::default();
+    health.set(StreamStatus::Recovering);
+    assert!(matches!(
+      config().check_stream(&health, &cache, &[MarketId::perp(0)], &[user]),
+      Err(StaleDataError::Stream(StreamStatus::Recovering))
+    ));
+    health.set(StreamStatus::Healthy);
+    assert!(config()
+      .check_stream(&health, &cache, &[MarketId::perp(0)], &[user])
+      .is_ok());
+  }
+  #[tokio::test]
+  async fn oracle_delay() {
+    let market = [MarketId::perp(0)];
+    let (cache, oracle, user) = cache(1000, 990, 1000).await;
+    // the oracle account was streamed recently but its price is 100 slots old
+    let price = ctx(oracle, 990, pyth_price(900));
+    cache.write().await.ring_mut(oracle).insert(990, price);
+    let delayed = StalenessConfig::default().check(&cache.read().await, &market, &[user]);
+    assert!(matches!(
+      delayed,
+      Err(StaleDataError::OracleDelay { oracle: key, delay: 100, max: 30, .. }) if key == oracle
+    ));
+    let price = ctx(oracle, 990, pyth_price(980));
+    cache.write().await.ring_mut(oracle).insert(990, price);
+    assert!(StalenessConfig::default()
+      .check(&cache.read().await, &market, &[user])
+      .is_ok());
+  }
+
+  #[tokio::test]
+  async fn old_block() {
+    let (cache, _, user) = cache(1000, 1000, 1000).await;
+    let block = |slot, age: i64| BlockInfo {
+      slot,
+      blockhash: String::new(),
+      time: Time::from_unix(Time::now().to_unix() - age),
+    };
+    cache.write().await.blocks.insert(990, block(990, 120));
+    let with_block_age = StalenessConfig {
+      max_block_age: Some(Duration::from_secs(60)),
+      ..config()
+    };
+    assert!(matches!(
+      with_block_age.check(&cache.read().await, &[], &[user]),
+      Err(StaleDataError::BlockAge { slot: 990, .. })
+    ));
+    // only the newest block counts
+    cache.write().await.blocks.insert(1000, block(1000, 1));
+    assert!(with_block_age
+      .check(&cache.read().await, &[], &[user])
+      .is_ok());
+  }
+}
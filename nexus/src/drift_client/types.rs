@@ -199,6 +199,7 @@ impl From<RemainingAccount> for AccountMeta {
   }
 }
 
+#[derive(Clone)]
 pub struct DlobNode {
   pub order: Order,
   pub user: Pubkey,
@@ -232,8 +233,8 @@ impl Hash for DlobNode {
       MarketType::Spot => 1.hash(state),
     };
     self.order.market_index.hash(state);
+    // only fields compared by `eq`, so a modified order replaces the node instead of duplicating it
     self.order.order_id.hash(state);
-    self.order.base_asset_amount.hash(state);
   }
 }
 
@@ -242,7 +243,7 @@ impl DlobNode {
     Self { user, order }
   }
 
-  pub fn price(&self, cache: &ReadCache<'_>) -> anyhow::Result<f64> {
+  pub fn price(&self, cache: &ReadCache) -> anyhow::Result<f64> {
     Ok(match self.order.price == 0 {
       true => {
         let market = MarketId::from((self.order.market_index, self.order.market_type));
@@ -296,7 +297,7 @@ impl DlobNode {
     matches!(self.order.direction, PositionDirection::Short)
   }
 
-  pub fn bid(&self, cache: &ReadCache<'_>) -> anyhow::Result<OrderInfo> {
+  pub fn bid(&self, cache: &ReadCache) -> anyhow::Result<OrderInfo> {
     if !self.is_bid() {
       return Err(anyhow::anyhow!("Order is not a bid"));
     }
@@ -309,7 +310,7 @@ impl DlobNode {
     })
   }
 
-  pub fn ask(&self, cache: &ReadCache<'_>) -> anyhow::Result<OrderInfo> {
+  pub fn ask(&self, cache: &ReadCache) -> anyhow::Result<OrderInfo> {
     if !self.is_ask() {
       return Err(anyhow::anyhow!("Order is not an ask"));
     }
@@ -628,3 +629,11 @@ pub struct MakerInfo {
   pub maker_user_stats: Pubkey,
   pub maker_user: User,
 }
+
+/// Taker of an order in its auction, see [`crate::drift_client::DriftClient::jit_ix`]
+pub struct TakerInfo {
+  pub taker: Pubkey,
+  pub taker_user_stats: Pubkey,
+  pub taker_user: User,
+  pub order: Order,
+}
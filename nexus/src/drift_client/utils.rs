@@ -7,13 +7,10 @@ use borsh::BorshDeserialize;
 use log::info;
 use rayon::prelude::*;
 use reqwest::Client;
-use solana_account_decoder::UiAccountEncoding;
-use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
 use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
 use solana_client::rpc_filter::{Memcmp, RpcFilterType};
 use solana_rpc_client_api::filter::MemcmpEncodedBytes;
-use solana_rpc_client_api::request::RpcRequest;
-use solana_rpc_client_api::response::{OptionalContext, RpcKeyedAccount};
 use solana_sdk::account::Account;
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::pubkey::Pubkey;
@@ -27,6 +24,12 @@ use crate::drift_client::*;
 use crate::*;
 use crate::{trunc, DecodedAcctCtx, Time};
 
+/// Offset of `last_active_slot` in `User` account data, including the discriminator
+const USER_LAST_ACTIVE_SLOT_OFFSET: usize = 4328;
+/// Bits of `User::status`
+const USER_STATUS_BEING_LIQUIDATED: u8 = 1;
+const USER_STATUS_BANKRUPT: u8 = 2;
+
 pub struct DriftUtils;
 
 impl DriftUtils {
@@ -112,7 +115,9 @@ impl DriftUtils {
     })
   }
 
-  pub async fn perp_markets(client: &RpcClient) -> anyhow::Result<Vec<DecodedAcctCtx<PerpMarket>>> {
+  pub async fn perp_markets(
+    client: &dyn RpcSource,
+  ) -> anyhow::Result<Vec<DecodedAcctCtx<PerpMarket>>> {
     let state_key = DriftUtils::state_pda();
     let state_data = client.get_account_data(&state_key).await?;
     let state = State::try_deserialize(&mut state_data.as_slice())?;
@@ -145,7 +150,9 @@ impl DriftUtils {
     Ok(markets)
   }
 
-  pub async fn spot_markets(client: &RpcClient) -> anyhow::Result<Vec<DecodedAcctCtx<SpotMarket>>> {
+  pub async fn spot_markets(
+    client: &dyn RpcSource,
+  ) -> anyhow::Result<Vec<DecodedAcctCtx<SpotMarket>>> {
     let state_key = DriftUtils::state_pda();
     let state_data = client.get_account_data(&state_key).await?;
     let state = State::try_deserialize(&mut state_data.as_slice())?;
@@ -242,7 +249,7 @@ impl DriftUtils {
     }
   }
 
-  pub async fn users(rpc: &RpcClient) -> anyhow::Result<Vec<DecodedAcctCtx<User>>> {
+  pub async fn users(rpc: &dyn RpcSource) -> anyhow::Result<Vec<DecodedAcctCtx<User>>> {
     let filters = Some(vec![Self::users_filter()]);
     let account_config = RpcAccountInfoConfig {
       encoding: Some(UiAccountEncoding::Base64),
@@ -254,34 +261,63 @@ impl DriftUtils {
       with_context: Some(true),
     };
 
-    let response = rpc
-      .send::<OptionalContext<Vec<RpcKeyedAccount>>>(
-        RpcRequest::GetProgramAccounts,
-        serde_json::json!([crate::drift_cpi::id().to_string(), config]),
-      )
+    let accounts = rpc
+      .get_program_accounts_with_context(&crate::drift_cpi::id(), config)
       .await?;
 
-    let mut users = vec![];
-    if let OptionalContext::Context(accounts) = response {
-      users = accounts
-        .value
-        .into_par_iter()
-        .map(|account| {
-          let slot = accounts.context.slot;
-          Result::<_, anyhow::Error>::Ok(DecodedAcctCtx {
-            key: Pubkey::from_str(&account.pubkey)?,
-            account: account.account.to_account()?,
-            slot,
-            decoded: account.account.decode_account::<User>()?,
-          })
+    let slot = accounts.context.slot;
+    let users = accounts
+      .value
+      .into_par_iter()
+      .map(|account| {
+        Result::<_, anyhow::Error>::Ok(DecodedAcctCtx {
+          key: Pubkey::from_str(&account.pubkey)?,
+          account: account.account.to_account()?,
+          slot,
+          decoded: account.account.decode_account::<User>()?,
         })
-        .flatten()
-        .collect();
-    }
+      })
+      .flatten()
+      .collect();
     Ok(users)
   }
 
-  pub async fn users_with_order(rpc: &RpcClient) -> anyhow::Result<Vec<DecodedAcctCtx<User>>> {
+  /// Keys of users whose `last_active_slot` is at or after `since`, fetching only that field.
+  /// Drift bumps it when the user places, cancels or fills an order, deposits or withdraws.
+  pub async fn users_active_since(rpc: &dyn RpcSource, since: u64) -> anyhow::Result<Vec<Pubkey>> {
+    let account_config = RpcAccountInfoConfig {
+      encoding: Some(UiAccountEncoding::Base64),
+      data_slice: Some(UiDataSliceConfig {
+        offset: USER_LAST_ACTIVE_SLOT_OFFSET,
+        length: 8,
+      }),
+      ..Default::default()
+    };
+    let config = RpcProgramAccountsConfig {
+      filters: Some(vec![Self::users_filter()]),
+      account_config,
+      with_context: Some(true),
+    };
+
+    let accounts = rpc
+      .get_program_accounts_with_context(&crate::drift_cpi::id(), config)
+      .await?;
+
+    let mut keys = vec![];
+    for account in accounts.value {
+      let data = account.account.to_account()?.data;
+      let bytes: [u8; 8] = data
+        .as_slice()
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("User {} last active slot not returned", account.pubkey))?;
+      if u64::from_le_bytes(bytes) >= since {
+        keys.push(Pubkey::from_str(&account.pubkey)?);
+      }
+    }
+    Ok(keys)
+  }
+
+  pub async fn users_with_order(rpc: &dyn RpcSource) -> anyhow::Result<Vec<DecodedAcctCtx<User>>> {
     let filters = Some(vec![Self::users_with_order_filter()]);
     let account_config = RpcAccountInfoConfig {
       encoding: Some(UiAccountEncoding::Base64),
@@ -293,30 +329,24 @@ impl DriftUtils {
       with_context: Some(true),
     };
 
-    let response = rpc
-      .send::<OptionalContext<Vec<RpcKeyedAccount>>>(
-        RpcRequest::GetProgramAccounts,
-        serde_json::json!([crate::drift_cpi::id().to_string(), config]),
-      )
+    let accounts = rpc
+      .get_program_accounts_with_context(&crate::drift_cpi::id(), config)
       .await?;
 
     let mut users = vec![];
-    if let OptionalContext::Context(accounts) = response {
-      for account in accounts.value {
-        let slot = accounts.context.slot;
-        users.push(DecodedAcctCtx {
-          key: Pubkey::from_str(&account.pubkey)?,
-          account: account.account.to_account()?,
-          slot,
-          decoded: account.account.decode_account::<User>()?,
-        });
-      }
+    for account in accounts.value {
+      users.push(DecodedAcctCtx {
+        key: Pubkey::from_str(&account.pubkey)?,
+        account: account.account.to_account()?,
+        slot: accounts.context.slot,
+        decoded: account.account.decode_account::<User>()?,
+      });
     }
     Ok(users)
   }
 
   pub async fn user_stats(
-    rpc: &RpcClient,
+    rpc: &dyn RpcSource,
     user_auths: &[Pubkey],
   ) -> anyhow::Result<Vec<DecodedAcctCtx<UserStats>>> {
     let pdas = user_auths
@@ -355,7 +385,7 @@ impl DriftUtils {
   /// Fetches those 1,000 users' [`UserStats`] accounts to derive "PnL to volume ratio",
   /// and filters out users who have not traded in the last 30 days.
   /// Since one authority can have many User accounts, we map all User accounts to each authority and return.
-  pub async fn top_traders(rpc: &RpcClient) -> anyhow::Result<HashMap<Pubkey, DriftTrader>> {
+  pub async fn top_traders(rpc: &dyn RpcSource) -> anyhow::Result<HashMap<Pubkey, DriftTrader>> {
     let start = Instant::now();
     let mut users = DriftUtils::users(rpc).await?;
     let end = Instant::now();
@@ -409,7 +439,7 @@ impl DriftUtils {
   }
 
   /// Top perp traders, sorted by ROI as a ratio of settled perp pnl to total deposits.
-  pub async fn top_traders_by_pnl(rpc: &RpcClient) -> anyhow::Result<Vec<DriftTrader>> {
+  pub async fn top_traders_by_pnl(rpc: &dyn RpcSource) -> anyhow::Result<Vec<DriftTrader>> {
     let traders_map = DriftUtils::top_traders(rpc).await?;
     let mut traders = traders_map.into_values().collect::<Vec<DriftTrader>>();
     traders.retain(|t| t.settled_perp_pnl() > 0_f64);
@@ -418,7 +448,7 @@ impl DriftUtils {
   }
 
   /// Formatted into [`TraderStats`] struct for easy display and less memory usage.
-  pub async fn top_trader_stats_by_pnl(rpc: &RpcClient) -> anyhow::Result<Vec<TraderStats>> {
+  pub async fn top_trader_stats_by_pnl(rpc: &dyn RpcSource) -> anyhow::Result<Vec<TraderStats>> {
     let best_traders = DriftUtils::top_traders_by_pnl(rpc).await?;
     let mut trader_stats: Vec<TraderStats> =
       best_traders.into_iter().map(TraderStats::from).collect();
@@ -499,7 +529,7 @@ impl DriftUtils {
   }
 
   pub async fn perp_market_info(
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     perp_market_index: u16,
   ) -> anyhow::Result<OrderPrice> {
     let market_pda = DriftUtils::perp_market_pda(perp_market_index);
@@ -551,7 +581,7 @@ impl DriftUtils {
 
   /// Fetch all market accounts from drift program (does not require `getProgramAccounts` RPC which is often unavailable)
   pub async fn market_accounts(
-    client: &RpcClient,
+    client: &dyn RpcSource,
   ) -> anyhow::Result<(Vec<SpotMarket>, Vec<PerpMarket>)> {
     let state_key = DriftUtils::state_pda();
     let state_data = client.get_account_data(&state_key).await?;
@@ -589,7 +619,7 @@ impl DriftUtils {
 
   pub fn oracle_price(
     market: &MarketId,
-    cache: &ReadCache<'_>,
+    cache: &ReadCache,
     slot: Option<u64>,
   ) -> anyhow::Result<f64> {
     match market.kind {
@@ -711,6 +741,14 @@ impl DriftUtils {
     matches!(order.order_type, OrderType::Limit | OrderType::TriggerLimit)
   }
 
+  pub fn user_being_liquidated(user: &User) -> bool {
+    user.status & USER_STATUS_BEING_LIQUIDATED != 0
+  }
+
+  pub fn user_bankrupt(user: &User) -> bool {
+    user.status & USER_STATUS_BANKRUPT != 0
+  }
+
   pub fn order_auction_complete(order: &Order, slot: u64) -> anyhow::Result<bool> {
     Ok(if order.auction_duration == 0 {
       true
@@ -720,6 +758,32 @@ impl DriftUtils {
     })
   }
 
+  /// Price of the order's auction at `slot` in `PRICE_PRECISION`, `None` if it has no auction.
+  /// Moves linearly from the start to the end price over `auction_duration` slots,
+  /// as offsets from `oracle_price` for oracle orders, and never past a limit order's price.
+  pub fn auction_price(order: &Order, slot: u64, oracle_price: i64) -> Option<i64> {
+    if order.auction_duration == 0 {
+      return None;
+    }
+    let duration = order.auction_duration as i128;
+    let elapsed = (slot.saturating_sub(order.slot) as i128).min(duration);
+    let (start, end) = (
+      order.auction_start_price as i128,
+      order.auction_end_price as i128,
+    );
+    let price = start + (end - start) * elapsed / duration;
+    let price = match order.order_type {
+      OrderType::Oracle => oracle_price as i128 + price,
+      _ => price,
+    } as i64;
+    let has_limit = Self::order_is_limit(order) && order.price > 0;
+    Some(match order.direction {
+      _ if !has_limit => price,
+      PositionDirection::Long => price.min(order.price as i64),
+      PositionDirection::Short => price.max(order.price as i64),
+    })
+  }
+
   pub fn order_is_resting_limit(order: &Order, slot: u64) -> anyhow::Result<bool> {
     if !DriftUtils::order_is_limit(order) {
       return Ok(false);
@@ -750,3 +814,18 @@ impl DriftUtils {
     format!("https://app.drift.trade/overview?userAccount={}", user)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drift_client::fixtures::{drift_account, zeroed};
+
+  #[test]
+  fn user_last_active_slot_offset() {
+    let mut user: User = zeroed();
+    user.last_active_slot = 0x0102_0304_0506_0708;
+    let data = drift_account(&user).data;
+    let at = &data[USER_LAST_ACTIVE_SLOT_OFFSET..USER_LAST_ACTIVE_SLOT_OFFSET + 8];
+    assert_eq!(at, user.last_active_slot.to_le_bytes());
+  }
+}
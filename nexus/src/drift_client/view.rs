@@ -0,0 +1,217 @@
+use anchor_lang::AccountDeserialize;
+use solana_sdk::clock::Slot;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::drift_client::InnerCache;
+use crate::{AcctCtx, DecodedAcctCtx};
+
+/// Why an account has no usable state in a [`CacheView`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ViewError {
+  #[error("Account {key} is not cached")]
+  NotCached { key: Pubkey },
+  /// Only updates after the view slot are cached, older ones were evicted or the account is newer
+  #[error("Account {key} has no state at or before slot {slot}, oldest cached is slot {oldest}")]
+  Evicted {
+    key: Pubkey,
+    slot: Slot,
+    oldest: Slot,
+  },
+  /// The last update at or before the view slot is older than [`CacheView::max_age`]
+  #[error(
+    "Account {key} updated at slot {updated} is {age} slots older than slot {slot} (max {max})"
+  )]
+  Stale {
+    key: Pubkey,
+    slot: Slot,
+    updated: Slot,
+    age: u64,
+    max: u64,
+  },
+}
+
+impl ViewError {
+  pub fn key(&self) -> &Pubkey {
+    match self {
+      ViewError::NotCached { key } => key,
+      ViewError::Evicted { key, .. } => key,
+      ViewError::Stale { key, .. } => key,
+    }
+  }
+}
+
+/// Accounts of a [`CacheView`] and the keys that had no usable state
+pub struct ViewAccounts<'a> {
+  pub found: Vec<&'a AcctCtx>,
+  pub missing: Vec<ViewError>,
+}
+
+impl<'a> ViewAccounts<'a> {
+  pub fn is_complete(&self) -> bool {
+    self.missing.is_empty()
+  }
+
+  /// Every requested account, or the first one that is missing
+  pub fn require(self) -> Result<Vec<&'a AcctCtx>, ViewError> {
+    match self.missing.into_iter().next() {
+      Some(e) => Err(e),
+      None => Ok(self.found),
+    }
+  }
+}
+
+/// The cache as it was at `slot`: each account is its last update at or before the slot,
+/// so states read together never mix in updates that landed after it.
+/// Created by [`InnerCache::snapshot_at`].
+pub struct CacheView<'a> {
+  cache: &'a InnerCache,
+  pub slot: Slot,
+  /// Max slots an account's last update may lag `slot`, `None` accepts any age
+  pub max_age: Option<u64>,
+}
+
+impl<'a> CacheView<'a> {
+  pub fn new(cache: &'a InnerCache, slot: Slot) -> Self {
+    Self {
+      cache,
+      slot,
+      max_age: None,
+    }
+  }
+
+  pub fn max_age(mut self, slots: u64) -> Self {
+    self.max_age = Some(slots);
+    self
+  }
+
+  /// Newest update at or before the view slot
+  pub fn account(&self, key: &Pubkey) -> Result<&'a AcctCtx, ViewError> {
+    let ring = self
+      .cache
+      .accounts
+      .get(key)
+      .ok_or(ViewError::NotCached { key: *key })?;
+    // the ring is in insertion order, which is not slot order after a recovery re-fetch
+    let ctx = ring
+      .values()
+      .filter(|ctx| ctx.slot <= self.slot)
+      .max_by_key(|ctx| ctx.slot);
+    let ctx = match ctx {
+      Some(ctx) => ctx,
+      None => {
+        return Err(match ring.values().map(|ctx| ctx.slot).min() {
+          Some(oldest) => ViewError::Evicted {
+            key: *key,
+            slot: self.slot,
+            oldest,
+          },
+          None => ViewError::NotCached { key: *key },
+        })
+      }
+    };
+    if let Some(max) = self.max_age {
+      let age = self.slot - ctx.slot;
+      if age > max {
+        return Err(ViewError::Stale {
+          key: *key,
+          slot: self.slot,
+          updated: ctx.slot,
+          age,
+          max,
+        });
+      }
+    }
+    Ok(ctx)
+  }
+
+  pub fn decoded_account<T: AccountDeserialize + Clone>(
+    &self,
+    key: &Pubkey,
+  ) -> anyhow::Result<DecodedAcctCtx<T>> {
+    let acct = self.account(key)?;
+    let decoded = T::try_deserialize(&mut acct.account.data.as_slice())?;
+    Ok(DecodedAcctCtx {
+      key: acct.key,
+      account: acct.account.clone(),
+      slot: acct.slot,
+      decoded,
+    })
+  }
+
+  /// State of each key at the view slot, and why any of them are missing or stale
+  pub fn accounts(&self, keys: &[Pubkey]) -> ViewAccounts<'a> {
+    let mut found = Vec::with_capacity(keys.len());
+    let mut missing = vec![];
+    for key in keys {
+      match self.account(key) {
+        Ok(ctx) => found.push(ctx),
+        Err(e) => missing.push(e),
+      }
+    }
+    ViewAccounts { found, missing }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drift_client::Cache;
+  use solana_sdk::account::Account;
+
+  fn ctx(key: Pubkey, slot: Slot) -> AcctCtx {
+    AcctCtx {
+      key,
+      account: Account {
+        lamports: slot,
+        ..Default::default()
+      },
+      slot,
+    }
+  }
+
+  #[tokio::test]
+  async fn snapshot_at_slot() {
+    let (market, oracle, user) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let cache = Cache::new(10);
+    let mut cache = cache.write().await;
+    // recovery can insert an older slot after a newer one
+    for (key, slot) in [
+      (market, 10),
+      (oracle, 12),
+      (oracle, 9),
+      (market, 15),
+      (user, 20),
+    ] {
+      cache.ring_mut(key).insert(slot, ctx(key, slot));
+    }
+
+    let view = cache.snapshot_at(13);
+    assert_eq!(view.account(&market).unwrap().slot, 10);
+    assert_eq!(view.account(&oracle).unwrap().slot, 12);
+    assert_eq!(
+      view.account(&user).err(),
+      Some(ViewError::Evicted {
+        key: user,
+        slot: 13,
+        oldest: 20
+      })
+    );
+
+    let unknown = Pubkey::new_unique();
+    let accounts = cache
+      .snapshot_at(13)
+      .max_age(2)
+      .accounts(&[market, oracle, unknown]);
+    assert_eq!(accounts.found.len(), 1);
+    let missing: Vec<Pubkey> = accounts.missing.iter().map(|e| *e.key()).collect();
+    assert_eq!(missing, vec![market, unknown]);
+    assert!(matches!(
+      accounts.missing[0],
+      ViewError::Stale { age: 3, .. }
+    ));
+  }
+}
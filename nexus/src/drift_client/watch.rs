@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use anchor_lang::AccountDeserialize;
+use drift_cpi::{
+  MarketType, Order, OrderStatus, PerpPosition, PositionDirection, SpotBalanceType, User,
+};
+use log::warn;
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+use crate::{AcctCtx, DecodedAcctCtx};
+
+/// Shared by every watcher of the account, each decodes its own copy
+type RawChange = Arc<(Option<AcctCtx>, AcctCtx)>;
+
+/// Changes a [`Watcher`] can fall behind by before new ones are dropped for it,
+/// so a slow engine never blocks the stream or grows memory without bound
+const WATCHER_CAPACITY: usize = 1024;
+
+/// Senders for [`Watcher`]s by account key and by account discriminator
+#[derive(Default)]
+pub struct Watchers {
+  by_key: HashMap<Pubkey, Vec<Sender<RawChange>>>,
+  by_discriminator: HashMap<[u8; 8], Vec<Sender<RawChange>>>,
+}
+
+impl Watchers {
+  pub fn watch_key(&mut self, key: Pubkey) -> Receiver<RawChange> {
+    let (tx, rx) = tokio::sync::mpsc::channel(WATCHER_CAPACITY);
+    self.by_key.entry(key).or_default().push(tx);
+    rx
+  }
+
+  pub fn watch_discriminator(&mut self, discriminator: [u8; 8]) -> Receiver<RawChange> {
+    let (tx, rx) = tokio::sync::mpsc::channel(WATCHER_CAPACITY);
+    self
+      .by_discriminator
+      .entry(discriminator)
+      .or_default()
+      .push(tx);
+    rx
+  }
+
+  pub fn is_watched(&self, ctx: &AcctCtx) -> bool {
+    self.by_key.contains_key(&ctx.key)
+      || Self::discriminator(ctx).is_some_and(|d| self.by_discriminator.contains_key(&d))
+  }
+
+  /// Send the change to every watcher of the account without waiting, dropping watchers that
+  /// were dropped. A watcher `WATCHER_CAPACITY` changes behind misses this one, and its next
+  /// change is diffed against the cached state before that change rather than the last it saw.
+  pub fn notify(&mut self, old: Option<AcctCtx>, new: AcctCtx) {
+    let key = new.key;
+    let discriminator = Self::discriminator(&new);
+    let change: RawChange = Arc::new((old, new));
+    let send = |senders: &mut Vec<Sender<RawChange>>| {
+      senders.retain(|tx| match tx.try_send(change.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+          warn!(
+            "Watcher of {} is {} changes behind, dropping a change",
+            key, WATCHER_CAPACITY
+          );
+          true
+        }
+        Err(TrySendError::Closed(_)) => false,
+      });
+      !senders.is_empty()
+    };
+    if let Some(senders) = self.by_key.get_mut(&key) {
+      if !send(senders) {
+        self.by_key.remove(&key);
+      }
+    }
+    if let Some(d) = discriminator {
+      if let Some(senders) = self.by_discriminator.get_mut(&d) {
+        if !send(senders) {
+          self.by_discriminator.remove(&d);
+        }
+      }
+    }
+  }
+
+  fn discriminator(ctx: &AcctCtx) -> Option<[u8; 8]> {
+    ctx.account.data.get(..8)?.try_into().ok()
+  }
+}
+
+/// An update to a watched account and the newest cached state before it
+#[derive(Clone)]
+pub struct AccountChange<T: Clone> {
+  pub old: Option<DecodedAcctCtx<T>>,
+  pub new: DecodedAcctCtx<T>,
+}
+
+impl AccountChange<User> {
+  /// Order and position changes, empty for the first state of the account
+  pub fn events(&self) -> Vec<UserEvent> {
+    match &self.old {
+      Some(old) => UserEvent::diff(&old.decoded, &self.new.decoded),
+      None => vec![],
+    }
+  }
+}
+
+/// Stream of changes to accounts in the [`crate::drift_client::Cache`],
+/// created by [`crate::drift_client::Cache::watch`] and [`crate::drift_client::Cache::watch_all`]
+pub struct Watcher<T> {
+  rx: Receiver<RawChange>,
+  _account: PhantomData<T>,
+}
+
+impl<T: AccountDeserialize + Clone> Watcher<T> {
+  pub fn new(rx: Receiver<RawChange>) -> Self {
+    Self {
+      rx,
+      _account: PhantomData,
+    }
+  }
+
+  /// Next change that decodes as `T`, `None` once the cache is dropped
+  pub async fn next(&mut self) -> Option<AccountChange<T>> {
+    while let Some(change) = self.rx.recv().await {
+      let (old, new) = change.as_ref();
+      let new = match Self::decode(new) {
+        Some(new) => new,
+        None => continue,
+      };
+      return Some(AccountChange {
+        old: old.as_ref().and_then(Self::decode),
+        new,
+      });
+    }
+    None
+  }
+
+  fn decode(ctx: &AcctCtx) -> Option<DecodedAcctCtx<T>> {
+    let decoded = T::try_deserialize(&mut ctx.account.data.as_slice()).ok()?;
+    Some(DecodedAcctCtx {
+      key: ctx.key,
+      account: ctx.account.clone(),
+      slot: ctx.slot,
+      decoded,
+    })
+  }
+}
+
+/// Change to the orders or perp positions of a [`User`] between two states
+#[derive(Debug, Clone)]
+pub enum UserEvent {
+  OrderPlaced(Order),
+  /// Base amount filled since the previous state of an order that is still open
+  OrderPartiallyFilled {
+    order: Order,
+    filled: u64,
+  },
+  /// The order as it was last seen open
+  OrderFilled(Order),
+  /// The order as it was last seen open
+  OrderCanceled(Order),
+  PositionOpened(PerpPosition),
+  /// The position as it was before it closed
+  PositionClosed(PerpPosition),
+  PositionChanged {
+    old: PerpPosition,
+    new: PerpPosition,
+  },
+}
+
+impl UserEvent {
+  /// Changes from `old` to `new`.
+  /// Drift clears the order slot once an order fills or is canceled, so when the status is gone
+  /// a fill is inferred from the order's market position moving in the order's direction.
+  pub fn diff(old: &User, new: &User) -> Vec<UserEvent> {
+    let mut events = vec![];
+    let is_open = |o: &&Order| matches!(o.status, OrderStatus::Open);
+    for order in old.orders.iter().filter(is_open) {
+      let same = new.orders.iter().find(|o| o.order_id == order.order_id);
+      let event = match same {
+        Some(o) if matches!(o.status, OrderStatus::Open) => {
+          match o.base_asset_amount_filled > order.base_asset_amount_filled {
+            true => UserEvent::OrderPartiallyFilled {
+              order: *o,
+              filled: o.base_asset_amount_filled - order.base_asset_amount_filled,
+            },
+            false => continue,
+          }
+        }
+        Some(o) if matches!(o.status, OrderStatus::Filled) => UserEvent::OrderFilled(*order),
+        Some(o) if matches!(o.status, OrderStatus::Canceled) => UserEvent::OrderCanceled(*order),
+        _ => match Self::position_moved(old, new, order) {
+          true => UserEvent::OrderFilled(*order),
+          false => UserEvent::OrderCanceled(*order),
+        },
+      };
+      events.push(event);
+    }
+    for order in new.orders.iter().filter(is_open) {
+      let existed = old
+        .orders
+        .iter()
+        .filter(is_open)
+        .any(|o| o.order_id == order.order_id);
+      if !existed {
+        events.push(UserEvent::OrderPlaced(*order));
+        if order.base_asset_amount_filled > 0 {
+          events.push(UserEvent::OrderPartiallyFilled {
+            order: *order,
+            filled: order.base_asset_amount_filled,
+          });
+        }
+      }
+    }
+
+    let position = |user: &User, market_index: u16| {
+      user
+        .perp_positions
+        .iter()
+        .find(|p| p.market_index == market_index && p.base_asset_amount != 0)
+        .copied()
+    };
+    let mut markets: Vec<u16> = old
+      .perp_positions
+      .iter()
+      .chain(new.perp_positions.iter())
+      .filter(|p| p.base_asset_amount != 0)
+      .map(|p| p.market_index)
+      .collect();
+    markets.sort();
+    markets.dedup();
+    for market_index in markets {
+      match (position(old, market_index), position(new, market_index)) {
+        (None, Some(new)) => events.push(UserEvent::PositionOpened(new)),
+        (Some(old), None) => events.push(UserEvent::PositionClosed(old)),
+        (Some(old), Some(new)) if old.base_asset_amount != new.base_asset_amount => {
+          events.push(UserEvent::PositionChanged { old, new })
+        }
+        _ => {}
+      }
+    }
+    events
+  }
+
+  fn position_moved(old: &User, new: &User, order: &Order) -> bool {
+    let balance = |user: &User| -> i128 {
+      match order.market_type {
+        MarketType::Perp => user
+          .perp_positions
+          .iter()
+          .filter(|p| p.market_index == order.market_index)
+          .map(|p| p.base_asset_amount as i128)
+          .sum(),
+        MarketType::Spot => user
+          .spot_positions
+          .iter()
+          .filter(|p| p.market_index == order.market_index)
+          .map(|p| match p.balance_type {
+            SpotBalanceType::Deposit => p.scaled_balance as i128,
+            SpotBalanceType::Borrow => -(p.scaled_balance as i128),
+          })
+          .sum(),
+      }
+    };
+    let delta = balance(new) - balance(old);
+    match order.direction {
+      PositionDirection::Long => delta > 0,
+      PositionDirection::Short => delta < 0,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drift_client::fixtures::{partially_filled_order as order, zeroed};
+
+  #[test]
+  fn user_order_and_position_diff() {
+    let mut old: User = zeroed();
+    old.orders[0] = order(1, 0);
+    old.orders[1] = order(2, 0);
+    old.orders[2] = order(3, 0);
+
+    let mut new: User = zeroed();
+    // order 1 partially filled, order 2 filled and cleared, order 3 canceled, order 4 placed
+    new.orders[0] = order(1, 40);
+    new.orders[2] = Order {
+      status: OrderStatus::Canceled,
+      ..order(3, 0)
+    };
+    new.orders[3] = order(4, 0);
+    new.perp_positions[0].base_asset_amount = 140;
+
+    let events = UserEvent::diff(&old, &new);
+    assert!(matches!(
+      events.as_slice(),
+      [
+        UserEvent::OrderPartiallyFilled { filled: 40, .. },
+        UserEvent::OrderFilled(Order { order_id: 2, .. }),
+        UserEvent::OrderCanceled(Order { order_id: 3, .. }),
+        UserEvent::OrderPlaced(Order { order_id: 4, .. }),
+        UserEvent::PositionOpened(PerpPosition {
+          base_asset_amount: 140,
+          ..
+        }),
+      ]
+    ));
+
+    // the position closes without any open order
+    let events = UserEvent::diff(&new, &zeroed());
+    assert!(matches!(events.last(), Some(UserEvent::PositionClosed(_))));
+  }
+}
@@ -1,12 +1,12 @@
 use std::fmt::Debug;
 
 use futures::channel::mpsc::SendError;
-use futures::Stream;
+use futures::{Sink, Stream};
 use futures_util::sink::SinkExt;
 use log::*;
 use thiserror::Error;
 use yellowstone_grpc_client::{GeyserGrpcBuilderError, GeyserGrpcClient, GeyserGrpcClientError};
-use yellowstone_grpc_proto::prelude::{SubscribeRequest, SubscribeUpdate};
+use yellowstone_grpc_proto::prelude::{SubscribeRequest, SubscribeRequestPing, SubscribeUpdate};
 use yellowstone_grpc_proto::tonic::Status;
 
 use crate::{GeyserConfig, TxStub};
@@ -37,17 +37,51 @@ pub struct GrpcClient {
 
 impl GrpcClient {
   pub fn new(cfg: GeyserConfig) -> Self {
-    Self {
-      cfg,
-    }
+    Self { cfg }
   }
 
-  pub async fn subscribe(&self) -> GeyserClientResult<impl Stream<Item=Result<SubscribeUpdate, Status>>> {
+  /// Connects and subscribes with the configured filters.
+  /// The returned sink is kept open to answer server pings on the same subscription.
+  pub async fn subscribe(
+    &self,
+  ) -> GeyserClientResult<(
+    impl Sink<SubscribeRequest, Error = SendError>,
+    impl Stream<Item = Result<SubscribeUpdate, Status>>,
+  )> {
+    self
+      .subscribe_with(SubscribeRequest::from(self.cfg.clone()))
+      .await
+  }
+
+  /// Connects to the configured endpoint and subscribes with the filters in `request`
+  pub async fn subscribe_with(
+    &self,
+    request: SubscribeRequest,
+  ) -> GeyserClientResult<(
+    impl Sink<SubscribeRequest, Error = SendError>,
+    impl Stream<Item = Result<SubscribeUpdate, Status>>,
+  )> {
     let cfg = self.cfg.clone();
     let x_token: Option<String> = Some(cfg.x_token);
-    let mut client = GeyserGrpcClient::build_from_shared(cfg.grpc)?.x_token(x_token)?.connect().await?;
+    let mut client = GeyserGrpcClient::build_from_shared(cfg.grpc)?
+      .x_token(x_token)?
+      .connect()
+      .await?;
     let (mut subscribe_tx, stream) = client.subscribe().await?;
-    subscribe_tx.send(SubscribeRequest::from(self.cfg.clone())).await?;
-    Ok(stream)
+    subscribe_tx.send(request).await?;
+    Ok((subscribe_tx, stream))
   }
-}
\ No newline at end of file
+
+  /// Reply to a server ping so load balancers don't close an idle subscription
+  pub async fn pong(
+    sink: &mut (impl Sink<SubscribeRequest, Error = SendError> + Unpin),
+  ) -> GeyserClientResult {
+    sink
+      .send(SubscribeRequest {
+        ping: Some(SubscribeRequestPing { id: 1 }),
+        ..Default::default()
+      })
+      .await?;
+    Ok(())
+  }
+}
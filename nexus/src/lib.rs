@@ -1,6 +1,8 @@
+pub use backfill::*;
 pub use backtest::*;
 pub use bar::*;
 pub use bytes::*;
+pub use confirmation::*;
 pub use constants::*;
 pub use data::*;
 pub use drift_cpi::*;
@@ -8,13 +10,18 @@ pub use graphql::*;
 pub use grpc::*;
 pub use math::*;
 pub use nexus_client::*;
+pub use recorder::*;
+pub use report::*;
+pub use source::*;
 pub use trx_builder::*;
 pub use types::*;
 pub use utils::*;
 
+pub mod backfill;
 pub mod backtest;
 pub mod bar;
 pub mod bytes;
+pub mod confirmation;
 pub mod constants;
 pub mod data;
 pub mod drift_client;
@@ -22,6 +29,9 @@ pub mod graphql;
 pub mod grpc;
 pub mod math;
 pub mod nexus_client;
+pub mod recorder;
+pub mod report;
+pub mod source;
 pub mod trx_builder;
 pub mod types;
 pub mod utils;
@@ -29,3 +39,7 @@ pub mod utils;
 pub mod drift_cpi {
   pub use drift_cpi::*;
 }
+
+pub mod jit_proxy_cpi {
+  pub use jit_proxy_cpi::*;
+}
@@ -1,35 +1,146 @@
 use std::collections::HashSet;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
+use anchor_lang::Discriminator;
 use crossbeam::channel::Sender;
-use drift_cpi::AccountType;
-use futures_util::future::try_join_all;
-use solana_client::nonblocking::rpc_client::RpcClient;
+use drift_cpi::{AccountType, User};
+use futures::channel::mpsc::SendError;
+use futures::TryStreamExt;
+use futures::{Sink, Stream};
+use log::*;
 use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
-use solana_rpc_client_api::config::RpcTransactionConfig;
 use solana_rpc_client_api::response::RpcConfirmedTransactionStatusWithSignature;
+use solana_sdk::clock::{Slot, UnixTimestamp};
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::hash::Hash;
+use solana_sdk::instruction::CompiledInstruction;
+use solana_sdk::message::v0::{LoadedAddresses, MessageAddressTableLookup};
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Signature;
+use solana_transaction_status::{EncodedTransactionWithStatusMeta, UiLoadedAddresses};
 use tokio_stream::StreamExt;
 use yellowstone_grpc_proto::prelude::subscribe_update::UpdateOneof;
+use yellowstone_grpc_proto::prelude::{
+  SubscribeRequest, SubscribeUpdate, SubscribeUpdateTransaction,
+};
+use yellowstone_grpc_proto::tonic::Status;
 
-use crate::drift_client::{Cache, Orderbook};
+use crate::drift_client::{Cache, DriftTx, DriftUtils, EventBus, Orderbook};
 use crate::types::*;
-use crate::{Decode, GrpcClient, Time, ToAccount};
+use crate::{
+  Backfill, ConfirmationTracker, Decode, GeyserSource, GrpcClient, LookupTableCache,
+  RecordedUpdate, Recorder, ReplaySpeed, Replayer, RpcSource, Time, ToAccount,
+};
+
+/// Max `getMultipleAccounts` requests in flight in [`NexusClient::accounts`]
+const MAX_CONCURRENT_REQUESTS: usize = 8;
 
 pub struct NexusClient {
-  pub geyser: GrpcClient,
+  pub cfg: GeyserConfig,
+  pub geyser: Arc<dyn GeyserSource>,
+  /// Publishes decoded Drift instructions and events from streamed transactions
+  pub events: Option<EventBus>,
+  /// Used to re-fetch cached accounts after the stream reconnects across a slot gap
+  pub rpc: Option<Arc<dyn RpcSource>>,
+  pub reconnect: ReconnectConfig,
+  /// Lookup tables of v0 transactions whose meta has no loaded addresses
+  pub lookup_tables: LookupTableCache,
+  /// Appends every streamed update to a local log
+  pub recorder: Option<Recorder>,
+  /// Slot of a restored [`crate::drift_client::Snapshot`] the cache is caught up to
+  pub resume_slot: Option<u64>,
+  /// Resolves our sent transactions matched by the transaction filters
+  pub confirmations: Option<ConfirmationTracker>,
+  health: StreamHealth,
+}
+
+/// Bookkeeping carried across reconnects of [`NexusClient::stream`]
+#[derive(Default)]
+struct StreamState {
+  /// Highest slot seen across all connections
+  last_slot: u64,
+  /// Set after a reconnect until the first slot of the new connection is seen
+  gap_check: bool,
+  /// Whether the current connection has received any update
+  received: bool,
 }
 
 impl NexusClient {
   pub fn new(cfg: GeyserConfig) -> anyhow::Result<Self> {
-    Ok(Self {
-      geyser: GrpcClient::new(cfg),
-    })
+    Ok(Self::with_source(
+      cfg.clone(),
+      Arc::new(GrpcClient::new(cfg)),
+    ))
+  }
+
+  /// Subscribe with the filters in `cfg` through any [`GeyserSource`], such as [`crate::FakeGeyser`] in tests
+  pub fn with_source(cfg: GeyserConfig, geyser: Arc<dyn GeyserSource>) -> Self {
+    Self {
+      cfg,
+      geyser,
+      events: None,
+      rpc: None,
+      reconnect: ReconnectConfig::default(),
+      lookup_tables: LookupTableCache::default(),
+      recorder: None,
+      resume_slot: None,
+      confirmations: None,
+      health: StreamHealth::default(),
+    }
+  }
+
+  /// RPC client used to recover accounts missed while the stream was down
+  pub fn rpc(mut self, rpc: Arc<dyn RpcSource>) -> Self {
+    self.rpc = Some(rpc);
+    self
+  }
+
+  /// Decode Drift transactions matched by the transaction filters and publish them to `events`
+  pub fn events(mut self, events: EventBus) -> Self {
+    self.events = Some(events);
+    self
+  }
+
+  pub fn reconnect(mut self, reconnect: ReconnectConfig) -> Self {
+    self.reconnect = reconnect;
+    self
   }
 
+  /// Record every update received by [`NexusClient::stream`] for [`NexusClient::replay`]
+  pub fn recorder(mut self, recorder: Recorder) -> Self {
+    self.recorder = Some(recorder);
+    self
+  }
+
+  /// Treat the cache as up to date at `slot`, e.g. after restoring a [`crate::drift_client::Snapshot`].
+  /// If the first streamed slot is later, cached accounts and the users the orderbook holds or
+  /// Drift marked active since `slot` are re-fetched over [`NexusClient::rpc`] before the stream
+  /// reports healthy, as after a reconnect.
+  pub fn resume_from(mut self, slot: u64) -> Self {
+    self.resume_slot = Some(slot);
+    self
+  }
+
+  /// Resolve transactions matched by [`ConfirmationTracker::geyser_filter`].
+  /// The tracker follows the health and commitment of this client's stream.
+  pub fn confirmations(mut self, tracker: ConfirmationTracker) -> Self {
+    tracker.follow(self.health.clone(), self.cfg.commitment);
+    self.confirmations = Some(tracker);
+    self
+  }
+
+  /// Handle to the stream status, clone this before moving the client into [`NexusClient::stream`]
+  pub fn health(&self) -> StreamHealth {
+    self.health.clone()
+  }
+
+  /// Stream geyser updates into the cache (and orderbook) forever, reconnecting with exponential backoff.
+  /// If slots were skipped while disconnected, accounts that may have changed are re-fetched
+  /// over RPC before the stream reports healthy again.
+  /// Returns an error if [`ReconnectConfig::max_retries`] is exceeded or the `channel` receiver is dropped.
   pub async fn stream(
     &self,
     cache: &Cache,
@@ -37,278 +148,773 @@ impl NexusClient {
     orderbook: Option<&Orderbook>,
     filter: Option<HashSet<Pubkey>>,
   ) -> anyhow::Result<()> {
-    let mut stream = self.geyser.subscribe().await?;
-    while let Some(update) = stream.next().await {
-      let update = update?;
-      if let Some(update) = update.update_oneof {
-        match update {
-          UpdateOneof::Transaction(event) => {
-            if let Some(channel) = &channel {
-              if let Some(tx_info) = event.transaction {
-                if let Some(tx) = tx_info.transaction {
-                  if let Some(msg) = tx.message {
-                    let account_keys: Vec<Pubkey> = msg
-                      .account_keys
-                      .iter()
-                      .flat_map(|k| Pubkey::try_from(k.as_slice()))
-                      .collect();
-                    assert_eq!(account_keys.len(), msg.account_keys.len());
-
-                    let mut ixs = vec![];
-                    for ix in msg.instructions {
-                      let program: Pubkey =
-                        *account_keys
-                          .get(ix.program_id_index as usize)
-                          .ok_or(anyhow::anyhow!(
-                            "Program not found at account key index: {}",
-                            ix.program_id_index
-                          ))?;
-                      let accounts: Vec<Pubkey> = ix
-                        .accounts
-                        .iter()
-                        .flat_map(|ix| account_keys.get(*ix as usize).cloned())
-                        .collect();
-                      let data = ix.data.clone();
-
-                      ixs.push(Ix {
-                        program,
-                        accounts,
-                        data,
-                      });
-                    }
-
-                    let signer = *account_keys
-                      .first()
-                      .ok_or(anyhow::anyhow!("Signer not found at account key index: 0"))?;
-                    let signature = Signature::try_from(tx_info.signature.as_slice())?;
-                    let hash_bytes: [u8; 32] = msg
-                      .recent_blockhash
-                      .try_into()
-                      .map_err(|e| anyhow::anyhow!("Failed to convert blockhash: {:?}", e))?;
-                    channel.send(TxStub {
-                      slot: event.slot,
-                      blockhash: Hash::from(hash_bytes).to_string(),
-                      ixs,
-                      signature,
-                      signer,
-                    })?;
-                  }
-                }
-              }
-            }
+    let mut state = StreamState {
+      last_slot: self.resume_slot.unwrap_or_default(),
+      gap_check: self.resume_slot.is_some(),
+      ..Default::default()
+    };
+    let mut backoff = self.reconnect.initial_backoff;
+    let mut retries = 0;
+    loop {
+      self.health.set(StreamStatus::Connecting);
+      let res = match self
+        .geyser
+        .subscribe(SubscribeRequest::from(self.cfg.clone()))
+        .await
+      {
+        Ok((mut sink, mut updates)) => {
+          self
+            .consume(
+              &mut sink,
+              &mut updates,
+              cache,
+              &channel,
+              orderbook,
+              &filter,
+              &mut state,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+      };
+      self.health.set(StreamStatus::Down);
+      match res {
+        Ok(()) => warn!("Geyser stream ended"),
+        Err(e) => {
+          if e
+            .downcast_ref::<crossbeam::channel::SendError<TxStub>>()
+            .is_some()
+          {
+            return Err(anyhow::anyhow!("Geyser transaction channel closed: {}", e));
           }
-          UpdateOneof::Account(event) => {
-            if let Some(account) = event.account {
-              let key = Pubkey::try_from(account.pubkey.as_slice())
-                .map_err(|e| anyhow::anyhow!("Failed to convert pubkey: {:?}", e))?;
-
-              let account = account.to_account()?.clone();
-              if account.owner == drift_cpi::id() {
-                if let Some(orderbook) = orderbook {
-                  let acct = AccountType::decode(account.data.as_slice())
-                    .map_err(|e| anyhow::anyhow!("Failed to decode account: {:?}", e))?;
-                  if let AccountType::User(user) = acct {
-                    let ctx = DecodedAcctCtx {
-                      key,
-                      account: account.clone(),
-                      decoded: user,
-                      slot: event.slot,
-                    };
-                    orderbook.write().await.insert_user(ctx)?;
-                  }
-                }
-              }
+          error!("Geyser stream failed: {:?}", e);
+        }
+      }
 
-              let allow = match &filter {
-                Some(filter) => filter.contains(&key),
-                None => true,
-              };
-              if allow {
-                cache.write().await.ring_mut(key).insert(
-                  event.slot,
-                  AcctCtx {
-                    key,
-                    account,
-                    slot: event.slot,
-                  },
-                );
-              }
-            }
+      // a connection that received updates was healthy, so start the backoff over
+      if state.received {
+        retries = 0;
+        backoff = self.reconnect.initial_backoff;
+      }
+      retries += 1;
+      if let Some(max_retries) = self.reconnect.max_retries {
+        if retries > max_retries {
+          return Err(anyhow::anyhow!(
+            "Geyser stream failed to reconnect after {} attempts",
+            max_retries
+          ));
+        }
+      }
+      state.received = false;
+      state.gap_check = state.last_slot > 0;
+      warn!(
+        "Reconnecting to geyser in {:?}, attempt {}",
+        backoff, retries
+      );
+      tokio::time::sleep(backoff).await;
+      backoff = self.reconnect.next_backoff(backoff);
+      self.health.reconnected();
+    }
+  }
+
+  /// Feed a [`Recorder`] log into the cache, orderbook and `channel` as if it were streamed,
+  /// so engines can be run offline against a recorded session.
+  /// Transactions whose meta lacks loaded addresses still need [`NexusClient::rpc`] to resolve lookup tables.
+  /// Returns the number of updates replayed.
+  pub async fn replay(
+    &self,
+    path: impl AsRef<Path>,
+    speed: ReplaySpeed,
+    cache: &Cache,
+    channel: Option<Sender<TxStub>>,
+    orderbook: Option<&Orderbook>,
+    filter: Option<HashSet<Pubkey>>,
+  ) -> anyhow::Result<usize> {
+    speed.validate()?;
+    let replayer = Replayer::open(path)?;
+    self.health.set(StreamStatus::Healthy);
+    let start = std::time::Instant::now();
+    let mut first: Option<u64> = None;
+    let mut count = 0;
+    for frame in replayer {
+      let RecordedUpdate { received, update } = frame?;
+      let first = *first.get_or_insert(received);
+      if let Some(offset) = speed.offset(Duration::from_micros(received.saturating_sub(first))) {
+        let elapsed = start.elapsed();
+        if offset > elapsed {
+          tokio::time::sleep(offset - elapsed).await;
+        }
+      }
+      self.health.touch();
+      if let Some(oneof) = update.update_oneof {
+        self
+          .handle(update.filters, oneof, cache, &channel, orderbook, &filter)
+          .await?;
+      }
+      count += 1;
+    }
+    self.health.set(StreamStatus::Down);
+    Ok(count)
+  }
+
+  #[allow(clippy::too_many_arguments)]
+  async fn consume(
+    &self,
+    sink: &mut (impl Sink<SubscribeRequest, Error = SendError> + Unpin),
+    updates: &mut (impl Stream<Item = Result<SubscribeUpdate, Status>> + Unpin),
+    cache: &Cache,
+    channel: &Option<Sender<TxStub>>,
+    orderbook: Option<&Orderbook>,
+    filter: &Option<HashSet<Pubkey>>,
+    state: &mut StreamState,
+  ) -> anyhow::Result<()> {
+    loop {
+      let update = match tokio::time::timeout(self.reconnect.idle_timeout, updates.next()).await {
+        Ok(Some(update)) => update?,
+        Ok(None) => return Ok(()),
+        Err(_) => {
+          return Err(anyhow::anyhow!(
+            "No geyser update received in {:?}",
+            self.reconnect.idle_timeout
+          ))
+        }
+      };
+      self.health.touch();
+      if let Some(recorder) = &self.recorder {
+        if !matches!(update.update_oneof, Some(UpdateOneof::Ping(_))) {
+          if let Err(e) = recorder.record(&update) {
+            error!("Failed to record geyser update: {:?}", e);
           }
-          UpdateOneof::BlockMeta(event) => {
-            if let Some(block_time) = event.block_time {
-              cache.write().await.blocks.insert(
-                event.slot,
-                BlockInfo {
-                  slot: event.slot,
-                  blockhash: event.blockhash,
-                  time: Time::from_unix(block_time.timestamp),
-                },
-              );
-            }
+        }
+      }
+      if !state.received {
+        state.received = true;
+        if !state.gap_check {
+          self.health.set(StreamStatus::Healthy);
+        }
+      }
+      let filters = update.filters;
+      let update = match update.update_oneof {
+        Some(update) => update,
+        None => continue,
+      };
+
+      if state.gap_check {
+        if let Some(slot) = Self::update_slot(&update) {
+          state.gap_check = false;
+          if slot > state.last_slot + 1 {
+            self.health.set(StreamStatus::Recovering);
+            warn!(
+              "Geyser skipped slots {} to {} while disconnected, recovering accounts",
+              state.last_slot + 1,
+              slot - 1
+            );
+            self.recover(cache, orderbook, state.last_slot).await?;
           }
-          UpdateOneof::Slot(event) => {
-            if event.slot > cache.read().await.slot {
-              cache.write().await.slot = event.slot;
+          self.health.set(StreamStatus::Healthy);
+        }
+      }
+      if let Some(slot) = Self::update_slot(&update) {
+        state.last_slot = state.last_slot.max(slot);
+      }
+
+      if let UpdateOneof::Ping(_) = update {
+        GrpcClient::pong(sink).await?;
+        continue;
+      }
+      self
+        .handle(filters, update, cache, channel, orderbook, filter)
+        .await?;
+    }
+  }
+
+  /// Apply one update to the cache and orderbook, and decode and forward transactions
+  async fn handle(
+    &self,
+    filters: Vec<String>,
+    update: UpdateOneof,
+    cache: &Cache,
+    channel: &Option<Sender<TxStub>>,
+    orderbook: Option<&Orderbook>,
+    filter: &Option<HashSet<Pubkey>>,
+  ) -> anyhow::Result<()> {
+    match update {
+      UpdateOneof::Transaction(event) => {
+        if let Some(confirmations) = &self.confirmations {
+          confirmations.resolve(&event);
+        }
+        if channel.is_some() || self.events.is_some() {
+          // one transaction that can't be resolved shouldn't tear down the stream
+          let stub = match self.tx_stub(event, filters).await {
+            Ok(stub) => stub,
+            Err(e) => {
+              error!("Failed to resolve streamed transaction: {:?}", e);
+              None
+            }
+          };
+          if let Some(stub) = stub {
+            if let Some(events) = &self.events {
+              let drift_tx = DriftTx::decode(&stub);
+              if !drift_tx.is_empty() {
+                events.publish(drift_tx);
+              }
+            }
+            if let Some(channel) = channel {
+              channel.send(stub)?;
             }
           }
-          _ => {}
         }
       }
+      UpdateOneof::Account(event) => {
+        if let Some(account) = event.account {
+          let key = Pubkey::try_from(account.pubkey.as_slice())
+            .map_err(|e| anyhow::anyhow!("Failed to convert pubkey: {:?}", e))?;
+          let account = account.to_account()?.clone();
+          let routes = self.cfg.matched_routes(&filters);
+          Self::apply_account(
+            cache,
+            orderbook,
+            filter.as_ref(),
+            &routes,
+            AcctCtx {
+              key,
+              account,
+              slot: event.slot,
+            },
+          )
+          .await?;
+        }
+      }
+      UpdateOneof::BlockMeta(event) => {
+        if let Some(block_time) = event.block_time {
+          cache.write().await.blocks.insert(
+            event.slot,
+            BlockInfo {
+              slot: event.slot,
+              blockhash: event.blockhash,
+              time: Time::from_unix(block_time.timestamp),
+            },
+          );
+        }
+      }
+      UpdateOneof::Slot(event) => {
+        // one write lock, so a concurrent update can't move the slot backwards in between
+        let mut cache = cache.write().await;
+        cache.slot = cache.slot.max(event.slot);
+      }
+      _ => {}
+    }
+    Ok(())
+  }
+
+  /// Resolve instruction accounts, including those loaded from lookup tables by v0 transactions,
+  /// and capture the logs of a streamed transaction
+  async fn tx_stub(
+    &self,
+    event: SubscribeUpdateTransaction,
+    filters: Vec<String>,
+  ) -> anyhow::Result<Option<TxStub>> {
+    let tx_info = match event.transaction {
+      Some(tx_info) => tx_info,
+      None => return Ok(None),
+    };
+    let msg = match tx_info.transaction.and_then(|tx| tx.message) {
+      Some(msg) => msg,
+      None => return Ok(None),
+    };
+    // a missing key would shift every instruction account index after it
+    let mut account_keys = Self::pubkeys(&msg.account_keys)?;
+
+    let (logs, failed, loaded) = match tx_info.meta {
+      Some(meta) => {
+        let writable = Self::pubkeys(&meta.loaded_writable_addresses)?;
+        let readonly = Self::pubkeys(&meta.loaded_readonly_addresses)?;
+        (
+          meta.log_messages,
+          meta.err.is_some(),
+          Some(LoadedAddresses { writable, readonly }),
+        )
+      }
+      None => (vec![], false, None),
+    };
+    if msg.versioned && !msg.address_table_lookups.is_empty() {
+      let loaded = match loaded {
+        Some(loaded) if !loaded.is_empty() => loaded,
+        _ => {
+          let rpc = self.rpc.as_ref().ok_or(anyhow::anyhow!(
+            "Transaction meta has no loaded addresses and no RPC client is set to fetch lookup tables"
+          ))?;
+          let lookups = msg
+            .address_table_lookups
+            .iter()
+            .map(|l| {
+              Ok(MessageAddressTableLookup {
+                account_key: Pubkey::try_from(l.account_key.as_slice())
+                  .map_err(|e| anyhow::anyhow!("Failed to convert pubkey: {:?}", e))?,
+                writable_indexes: l.writable_indexes.clone(),
+                readonly_indexes: l.readonly_indexes.clone(),
+              })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+          self
+            .lookup_tables
+            .load_addresses(rpc.as_ref(), &lookups)
+            .await?
+        }
+      };
+      account_keys.extend(loaded.writable);
+      account_keys.extend(loaded.readonly);
+    }
+
+    let ixs: Vec<CompiledInstruction> = msg
+      .instructions
+      .into_iter()
+      .map(|ix| CompiledInstruction {
+        program_id_index: ix.program_id_index as u8,
+        accounts: ix.accounts,
+        data: ix.data,
+      })
+      .collect();
+    let ixs = Self::resolve_ixs(&account_keys, &ixs)?;
+
+    let signer = *account_keys
+      .first()
+      .ok_or(anyhow::anyhow!("Signer not found at account key index: 0"))?;
+    let signature = Signature::try_from(tx_info.signature.as_slice())?;
+    let hash_bytes: [u8; 32] = msg
+      .recent_blockhash
+      .try_into()
+      .map_err(|e| anyhow::anyhow!("Failed to convert blockhash: {:?}", e))?;
+    Ok(Some(TxStub {
+      slot: event.slot,
+      blockhash: Hash::from(hash_bytes).to_string(),
+      ixs,
+      signature,
+      signer,
+      filters,
+      logs,
+      failed,
+    }))
+  }
+
+  fn pubkeys(keys: &[Vec<u8>]) -> anyhow::Result<Vec<Pubkey>> {
+    keys
+      .iter()
+      .map(|k| {
+        Pubkey::try_from(k.as_slice())
+          .map_err(|e| anyhow::anyhow!("Failed to convert pubkey: {:?}", e))
+      })
+      .collect()
+  }
+
+  /// Map compiled instruction indexes to the full list of static and loaded account keys
+  fn resolve_ixs(account_keys: &[Pubkey], ixs: &[CompiledInstruction]) -> anyhow::Result<Vec<Ix>> {
+    let mut res = vec![];
+    for ix in ixs {
+      let program: Pubkey =
+        *account_keys
+          .get(ix.program_id_index as usize)
+          .ok_or(anyhow::anyhow!(
+            "Program not found at account key index: {}",
+            ix.program_id_index
+          ))?;
+      let accounts = ix
+        .accounts
+        .iter()
+        .map(|i| {
+          account_keys
+            .get(*i as usize)
+            .cloned()
+            .ok_or(anyhow::anyhow!(
+              "Account not found at account key index: {}",
+              i
+            ))
+        })
+        .collect::<anyhow::Result<Vec<Pubkey>>>()?;
+      res.push(Ix {
+        program,
+        accounts,
+        data: ix.data.clone(),
+      });
+    }
+    Ok(res)
+  }
+
+  fn update_slot(update: &UpdateOneof) -> Option<u64> {
+    match update {
+      UpdateOneof::Slot(event) => Some(event.slot),
+      UpdateOneof::Account(event) => Some(event.slot),
+      UpdateOneof::Transaction(event) => Some(event.slot),
+      UpdateOneof::BlockMeta(event) => Some(event.slot),
+      _ => None,
+    }
+  }
+
+  /// Write an account update to the cache, and to the orderbook if it is a Drift `User`,
+  /// according to the routes of the filters it matched
+  async fn apply_account(
+    cache: &Cache,
+    orderbook: Option<&Orderbook>,
+    filter: Option<&HashSet<Pubkey>>,
+    routes: &HashSet<FilterRoute>,
+    ctx: AcctCtx,
+  ) -> anyhow::Result<()> {
+    if ctx.account.owner == drift_cpi::id() && routes.contains(&FilterRoute::Orderbook) {
+      if let Some(orderbook) = orderbook {
+        let acct = AccountType::decode(ctx.account.data.as_slice())
+          .map_err(|e| anyhow::anyhow!("Failed to decode account: {:?}", e))?;
+        if let AccountType::User(user) = acct {
+          orderbook.write().await.insert_user(DecodedAcctCtx {
+            key: ctx.key,
+            account: ctx.account.clone(),
+            decoded: user,
+            slot: ctx.slot,
+          })?;
+        }
+      }
+    }
+
+    let allow = routes.contains(&FilterRoute::Cache)
+      && match filter {
+        Some(filter) => filter.contains(&ctx.key),
+        None => true,
+      };
+    if allow {
+      cache.insert(ctx).await;
     }
     Ok(())
   }
 
+  /// Re-fetch accounts that may have changed since `since` over RPC after updates were missed.
+  /// Every cached account is re-fetched, since Drift changes balances through settled pnl, funding
+  /// or liquidations without marking a user active. The orderbook reconciles the users it holds
+  /// and those Drift marked active since `since`, so it doesn't reload every user.
+  async fn recover(
+    &self,
+    cache: &Cache,
+    orderbook: Option<&Orderbook>,
+    since: u64,
+  ) -> anyhow::Result<()> {
+    let rpc = match &self.rpc {
+      Some(rpc) => rpc,
+      None => {
+        warn!("No RPC client set on NexusClient, cache may be stale until accounts update");
+        return Ok(());
+      }
+    };
+    let now = std::time::Instant::now();
+    let mut cached_users: HashSet<Pubkey> = HashSet::new();
+    let mut others: Vec<Pubkey> = vec![];
+    for ctx in cache.read().await.accounts(None)? {
+      if ctx.account.owner == drift_cpi::id()
+        && ctx.account.data.starts_with(&User::discriminator())
+      {
+        cached_users.insert(ctx.key);
+      } else {
+        others.push(ctx.key);
+      }
+    }
+
+    let mut users: HashSet<Pubkey> = HashSet::new();
+    if let Some(orderbook) = orderbook {
+      // users the orderbook doesn't know yet may have placed orders
+      users.extend(DriftUtils::users_active_since(rpc.as_ref(), since).await?);
+      // the orders it holds may be from a snapshot, so reconcile them all
+      users.extend(orderbook.read().await.orders_by_user().into_keys());
+    }
+
+    let mut keys = others;
+    keys.extend(cached_users.union(&users).copied());
+    let accts = Self::accounts(rpc.as_ref(), &keys).await?;
+    let num_accts = accts.len();
+    let mut slot = 0;
+    for ctx in accts {
+      slot = slot.max(ctx.slot);
+      let mut routes = HashSet::new();
+      if users.contains(&ctx.key) {
+        routes.insert(FilterRoute::Orderbook);
+      }
+      // only keys already in the cache, so don't filter
+      if !users.contains(&ctx.key) || cached_users.contains(&ctx.key) {
+        routes.insert(FilterRoute::Cache);
+      }
+      Self::apply_account(cache, orderbook, None, &routes, ctx).await?;
+    }
+    let mut cache = cache.write().await;
+    cache.slot = cache.slot.max(slot);
+    info!(
+      "Recovered {} accounts after slot {} from RPC at slot {} in {:?}",
+      num_accts,
+      since,
+      slot,
+      now.elapsed()
+    );
+    Ok(())
+  }
+
   // ===================================================================================
   // HTTP API
   // ===================================================================================
 
+  /// Newest `limit` signatures for `key`, paginated 1000 at a time
   pub async fn historical_signatures(
-    rpc: &RpcClient,
+    rpc: &dyn RpcSource,
     key: &Pubkey,
     limit: Option<usize>,
   ) -> anyhow::Result<Vec<RpcConfirmedTransactionStatusWithSignature>> {
-    let limit = limit.unwrap_or(1000);
+    // max signatures per request
+    let page_size = 1000;
+    let limit = limit.unwrap_or(page_size);
 
-    if limit <= 1000 {
-      let config = GetConfirmedSignaturesForAddress2Config {
-        limit: Some(limit),
+    let mut sigs: Vec<RpcConfirmedTransactionStatusWithSignature> = Vec::with_capacity(limit);
+    let mut before: Option<Signature> = None;
+    while sigs.len() < limit {
+      let cfg = GetConfirmedSignaturesForAddress2Config {
+        limit: Some((limit - sigs.len()).min(page_size)),
+        before,
         ..Default::default()
       };
-      // by default this fetches last 1000 signatures
-      let res = rpc
-        .get_signatures_for_address_with_config(key, config)
-        .await?;
-      Ok(res)
-    } else {
-      let mut chunks: Vec<Chunk> = Vec::new();
-      let mut eat_limit = limit;
-      let chunk_size = 1000;
-      while eat_limit > 0 {
-        let start = limit - eat_limit;
-        let end = std::cmp::min(start + chunk_size, limit);
-        eat_limit -= &chunk_size;
-        chunks.push(Chunk { start, end });
-      }
-
-      let mut sigs: Vec<RpcConfirmedTransactionStatusWithSignature> = Vec::with_capacity(limit);
-
-      // zeroth index is handled differently
-      let zeroth = &chunks[0];
-      let zeroth_cfg = GetConfirmedSignaturesForAddress2Config {
-        limit: Some(zeroth.end - zeroth.start),
-        ..Default::default()
+      let page = rpc.get_signatures_for_address_with_config(key, cfg).await?;
+      // `before` is exclusive, so the oldest signature of this page is the cursor for the next
+      before = match page.last() {
+        Some(last) => Some(Signature::from_str(&last.signature)?),
+        None => break,
       };
-      let sigs_for_zeroth_chunk = rpc
-        .get_signatures_for_address_with_config(key, zeroth_cfg)
-        .await?;
-      let mut border_sig: RpcConfirmedTransactionStatusWithSignature =
-        sigs_for_zeroth_chunk[sigs_for_zeroth_chunk.len() - 1].clone();
-      sigs.extend(sigs_for_zeroth_chunk);
-
-      // iterate everything after zeroth index
-      let after_zeroth = &chunks[1..chunks.len() - 1];
-      for chunk in after_zeroth {
-        let cfg = GetConfirmedSignaturesForAddress2Config {
-          limit: Some(chunk.end - chunk.start),
-          before: Some(Signature::from_str(&border_sig.signature)?),
-          ..Default::default()
-        };
-        let sigs_for_chunk = rpc.get_signatures_for_address_with_config(key, cfg).await?;
-        border_sig = sigs_for_chunk[sigs_for_chunk.len() - 1].clone();
-        sigs.extend(sigs_for_chunk);
-      }
-
-      Ok(sigs)
+      sigs.extend(page);
     }
+    Ok(sigs)
   }
 
+  /// Decoded transactions of the newest `limit` signatures for `key`, and the signatures that
+  /// still failed to fetch after retries
   pub async fn historical_transactions(
-    rpc: &RpcClient,
+    rpc: &dyn RpcSource,
     key: &Pubkey,
     limit: Option<usize>,
-  ) -> anyhow::Result<Vec<TrxData>> {
-    let sigs = Self::historical_signatures(rpc, key, limit).await?;
+  ) -> anyhow::Result<(Vec<TrxData>, Vec<Signature>)> {
+    let sigs = Self::historical_signatures(rpc, key, limit)
+      .await?
+      .iter()
+      .map(|sig| Signature::from_str(&sig.signature))
+      .collect::<Result<Vec<_>, _>>()?;
+    let (txs, skipped) =
+      Backfill::fetch_transactions(rpc, &LookupTableCache::default(), &sigs, 10, None).await;
+    Ok((txs, skipped))
+  }
 
-    let mut txs = Vec::<TrxData>::new();
-    let opts = RpcTransactionConfig {
-      max_supported_transaction_version: Some(1),
-      ..Default::default()
+  /// Decode a legacy or v0 transaction fetched over RPC and resolve every instruction account.
+  /// Loaded addresses are read from the meta, or from `lookup_tables` if the meta omits them.
+  pub async fn trx_data(
+    rpc: &dyn RpcSource,
+    lookup_tables: &LookupTableCache,
+    signature: Signature,
+    slot: Slot,
+    block_time: Option<UnixTimestamp>,
+    encoded: EncodedTransactionWithStatusMeta,
+  ) -> anyhow::Result<Option<TrxData>> {
+    let tx = match encoded.transaction.decode() {
+      Some(tx) => tx,
+      None => return Ok(None),
     };
-    for sig in sigs {
-      let tx_info = rpc
-        .get_transaction_with_config(&Signature::from_str(&sig.signature)?, opts)
-        .await?;
-
-      let decoded_tx = tx_info.transaction.transaction.decode();
-      if let Some(decoded_tx) = decoded_tx {
-        let signature = Signature::from_str(&sig.signature)?;
-        let tx = decoded_tx.into_legacy_transaction();
-        if let Some(tx) = &tx {
-          let signer = tx.message.account_keys.first();
-          if let Some(signer) = signer {
-            let trx_data = TrxData {
-              tx: tx.clone(),
-              signature,
-              signer: *signer,
-              slot: tx_info.slot,
-              block_time: tx_info.block_time.unwrap_or(0),
+    let (logs, failed, loaded) = match encoded.meta {
+      Some(meta) => {
+        let loaded = Option::<UiLoadedAddresses>::from(meta.loaded_addresses)
+          .map(|loaded| {
+            let parse = |keys: Vec<String>| {
+              keys
+                .iter()
+                .map(|k| Pubkey::from_str(k))
+                .collect::<Result<Vec<Pubkey>, _>>()
             };
-            txs.push(trx_data);
-          }
-        }
+            Result::<_, anyhow::Error>::Ok(LoadedAddresses {
+              writable: parse(loaded.writable)?,
+              readonly: parse(loaded.readonly)?,
+            })
+          })
+          .transpose()?;
+        (
+          Option::<Vec<String>>::from(meta.log_messages).unwrap_or_default(),
+          meta.err.is_some(),
+          loaded,
+        )
+      }
+      None => (vec![], false, None),
+    };
+
+    let mut account_keys = tx.message.static_account_keys().to_vec();
+    if let Some(lookups) = tx.message.address_table_lookups() {
+      if !lookups.is_empty() {
+        let loaded = match loaded {
+          Some(loaded) if !loaded.is_empty() => loaded,
+          _ => lookup_tables.load_addresses(rpc, lookups).await?,
+        };
+        account_keys.extend(loaded.writable);
+        account_keys.extend(loaded.readonly);
       }
     }
-    Ok(txs)
+    let signer = match account_keys.first() {
+      Some(signer) => *signer,
+      None => return Ok(None),
+    };
+    let ixs = Self::resolve_ixs(&account_keys, tx.message.instructions())?;
+    Ok(Some(TrxData {
+      tx,
+      signature,
+      signer,
+      account_keys,
+      ixs,
+      slot,
+      block_time: block_time.unwrap_or(0),
+      logs,
+      failed,
+    }))
   }
 
-  pub async fn accounts(rpc: &RpcClient, keys: &[Pubkey]) -> anyhow::Result<Vec<AcctCtx>> {
+  /// Fetch `keys` 100 at a time, with at most [`MAX_CONCURRENT_REQUESTS`] requests in flight
+  pub async fn accounts(rpc: &dyn RpcSource, keys: &[Pubkey]) -> anyhow::Result<Vec<AcctCtx>> {
     // get_multiple_accounts max Pubkeys is 100
     let chunk_size = 100;
 
-    if keys.len() <= chunk_size {
-      let pre_filter = rpc
-        .get_multiple_accounts_with_commitment(keys, CommitmentConfig::confirmed())
+    let requests = futures::stream::iter(keys.chunks(chunk_size)).map(|chunk| async move {
+      let res = rpc
+        .get_multiple_accounts_with_commitment(chunk, CommitmentConfig::confirmed())
         .await?;
-      let accts = pre_filter.value;
-      let slot = pre_filter.context.slot;
-      let infos = accts
+      let slot = res.context.slot;
+      let accts = res
+        .value
         .into_iter()
-        .enumerate()
-        .filter_map(|(index, acc)| {
-          acc.map(|acc| AcctCtx {
-            key: keys[index],
-            account: acc,
+        .zip(chunk)
+        .flat_map(|(acc, key)| {
+          acc.map(|account| AcctCtx {
+            key: *key,
+            account,
             slot,
           })
         })
         .collect::<Vec<AcctCtx>>();
-      Ok(infos)
-    } else {
-      let chunks = keys.chunks(chunk_size).collect::<Vec<&[Pubkey]>>();
-      let infos = try_join_all(chunks.into_iter().enumerate().map(
-        move |(_index, chunk)| async move {
-          let res = rpc
-            .get_multiple_accounts_with_commitment(chunk, CommitmentConfig::confirmed())
-            .await?;
-          let accs = res.value;
-          let slot = res.context.slot;
-          let accs = accs.into_iter().enumerate().flat_map(move |(index, acc)| {
-            acc.map(|account| AcctCtx {
-              key: chunk[index],
-              account,
-              slot,
-            })
-          });
-          Result::<_, anyhow::Error>::Ok(accs)
-        },
-      ))
-      .await?
-      .into_iter()
-      .flatten()
-      .collect::<Vec<AcctCtx>>();
+      Result::<_, anyhow::Error>::Ok(accts)
+    });
+    let chunks: Vec<Vec<AcctCtx>> =
+      futures::StreamExt::buffer_unordered(requests, MAX_CONCURRENT_REQUESTS)
+        .try_collect()
+        .await?;
+    Ok(chunks.into_iter().flatten().collect())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{FakeGeyser, FakeRpc};
+  use solana_sdk::account::Account;
+  use yellowstone_grpc_proto::prelude::{
+    CommitmentLevel, SubscribeRequestFilterAccounts, SubscribeUpdateAccount,
+    SubscribeUpdateAccountInfo, SubscribeUpdateSlot,
+  };
 
-      Ok(infos)
+  fn slot_update(slot: u64) -> SubscribeUpdate {
+    SubscribeUpdate {
+      filters: vec!["slots".to_string()],
+      update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+        slot,
+        ..Default::default()
+      })),
     }
   }
+
+  fn account_update(key: Pubkey, owner: Pubkey, slot: u64, lamports: u64) -> SubscribeUpdate {
+    SubscribeUpdate {
+      filters: vec!["accounts".to_string()],
+      update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+        slot,
+        is_startup: false,
+        account: Some(SubscribeUpdateAccountInfo {
+          pubkey: key.to_bytes().to_vec(),
+          lamports,
+          owner: owner.to_bytes().to_vec(),
+          executable: false,
+          rent_epoch: 0,
+          data: vec![],
+          write_version: 0,
+          txn_signature: None,
+        }),
+      })),
+    }
+  }
+
+  async fn wait_for(health: &StreamHealth, status: StreamStatus, reconnects: u64) {
+    while health.status() != status || health.reconnects() != reconnects {
+      tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn reconnect_recovers_slot_gap() -> anyhow::Result<()> {
+    let (key, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+    // the first session ends at slot 10 and the second is fed by the test
+    let geyser = Arc::new(
+      FakeGeyser::new()
+        .updates([slot_update(10), account_update(key, owner, 10, 1)])
+        .session(),
+    );
+    let second = geyser.sender();
+    // slow enough to see the stream recovering
+    let rpc = Arc::new(
+      FakeRpc::new()
+        .slot(20)
+        .latency(Duration::from_millis(50))
+        .account(
+          key,
+          Account {
+            lamports: 2,
+            owner,
+            ..Default::default()
+          },
+        ),
+    );
+    let cfg = GeyserConfig::new(String::new(), String::new(), CommitmentLevel::Processed).accounts(
+      "accounts",
+      SubscribeRequestFilterAccounts {
+        account: vec![key.to_string()],
+        owner: vec![],
+        filters: vec![],
+      },
+      &[FilterRoute::Cache],
+    );
+    let nexus = NexusClient::with_source(cfg, geyser)
+      .rpc(rpc.clone())
+      .reconnect(ReconnectConfig {
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_secs(1),
+        max_retries: Some(1),
+        idle_timeout: Duration::from_secs(10),
+      });
+    let health = nexus.health();
+    let cache = Cache::new(10);
+    let stream = {
+      let cache = cache.clone();
+      tokio::spawn(async move { nexus.stream(&cache, None, None, None).await })
+    };
+
+    wait_for(&health, StreamStatus::Connecting, 1).await;
+    assert_eq!(cache.read().await.account(&key, None)?.account.lamports, 1);
+    second.unbounded_send(slot_update(20))?;
+    wait_for(&health, StreamStatus::Recovering, 1).await;
+    wait_for(&health, StreamStatus::Healthy, 1).await;
+    assert_eq!(rpc.fetched(), vec![key]);
+    assert_eq!(cache.read().await.account(&key, None)?.account.lamports, 2);
+    // the slot update that revealed the gap is applied after recovery
+    while cache.read().await.slot != 20 {
+      tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    // the second connection received updates, so its end starts retries and backoff over:
+    // one retry after the initial backoff, which fails for lack of sessions
+    let ended = tokio::time::Instant::now();
+    second.close_channel();
+    assert!(stream.await?.is_err());
+    assert!(ended.elapsed() < Duration::from_millis(20));
+    assert_eq!(health.reconnects(), 2);
+    Ok(())
+  }
 }
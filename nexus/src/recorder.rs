@@ -0,0 +1,248 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+use log::*;
+use yellowstone_grpc_proto::prelude::SubscribeUpdate;
+use yellowstone_grpc_proto::prost::Message;
+
+/// Appends every update received by [`crate::NexusClient::stream`] to a local log for [`crate::NexusClient::replay`].
+/// Each frame is the receive time in microseconds since the unix epoch (u64 LE),
+/// the length of the update (u32 LE), then the protobuf encoded `SubscribeUpdate`,
+/// so account updates keep their slot and transactions keep the filters they matched.
+///
+/// Frames are written by a background thread so the stream never waits on disk,
+/// and flushed every second, so a crash loses at most the updates since the last flush.
+/// Call [`Recorder::flush`] before exiting to keep the tail of the recording.
+#[derive(Clone)]
+pub struct Recorder {
+  frames: Sender<Frame>,
+}
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+enum Frame {
+  Update(Vec<u8>),
+  /// Flush and report the result
+  Flush(Sender<std::io::Result<()>>),
+}
+
+impl Recorder {
+  /// Open or create the log, appending to an existing recording
+  pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let (frames, rx) = crossbeam::channel::unbounded();
+    std::thread::Builder::new()
+      .name("recorder".to_string())
+      .spawn(move || Self::run_writer(BufWriter::new(file), rx))?;
+    Ok(Self { frames })
+  }
+
+  /// Queue an update for the background writer. Errors only once the writer has stopped.
+  pub fn record(&self, update: &SubscribeUpdate) -> anyhow::Result<()> {
+    let received = SystemTime::now().duration_since(UNIX_EPOCH)?.as_micros() as u64;
+    let len = update.encoded_len();
+    let mut frame = Vec::with_capacity(12 + len);
+    frame.extend_from_slice(&received.to_le_bytes());
+    frame.extend_from_slice(&(len as u32).to_le_bytes());
+    update.encode(&mut frame)?;
+    self
+      .frames
+      .send(Frame::Update(frame))
+      .map_err(|_| anyhow::anyhow!("Recorder writer has stopped"))
+  }
+
+  /// Block until every update recorded so far is written and flushed
+  pub fn flush(&self) -> anyhow::Result<()> {
+    let (ack, done) = crossbeam::channel::bounded(1);
+    self
+      .frames
+      .send(Frame::Flush(ack))
+      .map_err(|_| anyhow::anyhow!("Recorder writer has stopped"))?;
+    done.recv()??;
+    Ok(())
+  }
+
+  /// Write frames until every [`Recorder`] is dropped or a write fails
+  fn run_writer(mut writer: BufWriter<File>, frames: Receiver<Frame>) {
+    let mut flushed = Instant::now();
+    loop {
+      let res = match frames.recv_timeout(FLUSH_INTERVAL.saturating_sub(flushed.elapsed())) {
+        Ok(Frame::Update(frame)) => writer.write_all(&frame),
+        Ok(Frame::Flush(ack)) => {
+          let res = writer.flush();
+          flushed = Instant::now();
+          let failed = res.is_err();
+          let _ = ack.send(res);
+          if failed {
+            error!("Failed to flush recording, stop recording");
+            return;
+          }
+          continue;
+        }
+        Err(RecvTimeoutError::Timeout) => Ok(()),
+        Err(RecvTimeoutError::Disconnected) => {
+          if let Err(e) = writer.flush() {
+            error!("Failed to flush recording: {:?}", e);
+          }
+          return;
+        }
+      };
+      let res = match res {
+        Ok(()) if flushed.elapsed() >= FLUSH_INTERVAL => {
+          flushed = Instant::now();
+          writer.flush()
+        }
+        res => res,
+      };
+      if let Err(e) = res {
+        error!("Failed to write recording, stop recording: {:?}", e);
+        return;
+      }
+    }
+  }
+}
+
+/// Update read back from a [`Recorder`] log
+#[derive(Debug, Clone)]
+pub struct RecordedUpdate {
+  /// Microseconds since the unix epoch when the update was received
+  pub received: u64,
+  pub update: SubscribeUpdate,
+}
+
+/// Iterates the frames of a [`Recorder`] log in the order they were received.
+/// A truncated frame at the end of the log, left by a crash mid-write, ends the iteration.
+pub struct Replayer {
+  reader: BufReader<File>,
+}
+
+impl Replayer {
+  pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    Ok(Self {
+      reader: BufReader::new(File::open(path)?),
+    })
+  }
+
+  fn read_frame(&mut self) -> anyhow::Result<Option<RecordedUpdate>> {
+    let mut header = [0u8; 12];
+    match self.reader.read_exact(&mut header) {
+      Ok(()) => {}
+      Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+      Err(e) => return Err(e.into()),
+    }
+    let received = u64::from_le_bytes(header[..8].try_into()?);
+    let len = u32::from_le_bytes(header[8..].try_into()?) as usize;
+    let mut bytes = vec![0u8; len];
+    match self.reader.read_exact(&mut bytes) {
+      Ok(()) => {}
+      Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+        warn!("Recording ends with a truncated update of {} bytes", len);
+        return Ok(None);
+      }
+      Err(e) => return Err(e.into()),
+    }
+    Ok(Some(RecordedUpdate {
+      received,
+      update: SubscribeUpdate::decode(bytes.as_slice())?,
+    }))
+  }
+}
+
+impl Iterator for Replayer {
+  type Item = anyhow::Result<RecordedUpdate>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.read_frame().transpose()
+  }
+}
+
+/// Pace of [`crate::NexusClient::replay`] relative to when updates were recorded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+  Original,
+  /// Multiple of the original speed, e.g. 10.0 replays an hour in 6 minutes
+  Accelerated(f64),
+  /// No delay between updates
+  Max,
+}
+
+impl ReplaySpeed {
+  /// Errors if an accelerated speed is not positive
+  pub fn validate(&self) -> anyhow::Result<()> {
+    match self {
+      ReplaySpeed::Accelerated(speed) if speed.is_nan() || *speed <= 0.0 => Err(anyhow::anyhow!(
+        "Replay speed must be positive, got {}",
+        speed
+      )),
+      _ => Ok(()),
+    }
+  }
+
+  /// When an update recorded `elapsed` after the first should be applied, relative to the replay start
+  pub fn offset(&self, elapsed: Duration) -> Option<Duration> {
+    match self {
+      ReplaySpeed::Original => Some(elapsed),
+      ReplaySpeed::Accelerated(speed) if *speed > 0.0 => Some(elapsed.div_f64(*speed)),
+      // rejected by `validate`
+      ReplaySpeed::Accelerated(_) | ReplaySpeed::Max => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yellowstone_grpc_proto::prelude::subscribe_update::UpdateOneof;
+  use yellowstone_grpc_proto::prelude::SubscribeUpdateSlot;
+
+  #[test]
+  fn record_and_replay() -> anyhow::Result<()> {
+    let path = std::env::temp_dir().join(format!(
+      "recording_{}.bin",
+      solana_sdk::pubkey::Pubkey::new_unique()
+    ));
+    let recorder = Recorder::open(&path)?;
+    for slot in 0..3 {
+      recorder.record(&SubscribeUpdate {
+        filters: vec!["slots".to_string()],
+        update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+          slot,
+          ..Default::default()
+        })),
+      })?;
+    }
+    recorder.flush()?;
+    drop(recorder);
+    // simulate a crash mid-write
+    let mut file = OpenOptions::new().append(true).open(&path)?;
+    file.write_all(&[1, 2, 3])?;
+
+    let slots = Replayer::open(&path)?
+      .map(|frame| match frame?.update.update_oneof {
+        Some(UpdateOneof::Slot(slot)) => Ok(slot.slot),
+        _ => Err(anyhow::anyhow!("Expected slot update")),
+      })
+      .collect::<anyhow::Result<Vec<u64>>>()?;
+    assert_eq!(slots, vec![0, 1, 2]);
+    std::fs::remove_file(&path)?;
+    Ok(())
+  }
+
+  #[test]
+  fn replay_speed() {
+    let elapsed = Duration::from_secs(10);
+    assert_eq!(ReplaySpeed::Original.offset(elapsed), Some(elapsed));
+    assert_eq!(
+      ReplaySpeed::Accelerated(10.0).offset(elapsed),
+      Some(Duration::from_secs(1))
+    );
+    assert_eq!(ReplaySpeed::Max.offset(elapsed), None);
+    assert!(ReplaySpeed::Accelerated(0.5).validate().is_ok());
+    for speed in [0.0, -1.0, f64::NAN] {
+      assert!(ReplaySpeed::Accelerated(speed).validate().is_err());
+    }
+  }
+}
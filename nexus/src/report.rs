@@ -0,0 +1,375 @@
+use crate::{trunc, Data, PerformanceSummary, Series, Summary, TradeAction};
+use chrono::{Datelike, TimeZone, Utc};
+use plotters::prelude::*;
+use plotters::style::full_palette::*;
+use plotters::style::{BLACK, WHITE};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const CHART_SIZE: (u32, u32) = (1200, 450);
+const MONTHS: [&str; 12] = [
+  "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 24px; color: #212121; }
+h1 { font-size: 28px; }
+h2 { font-size: 20px; margin-top: 32px; }
+table { border-collapse: collapse; font-size: 13px; }
+th, td { border: 1px solid #e0e0e0; padding: 4px 8px; text-align: right; }
+th { background: #f5f5f5; }
+td.label { text-align: left; font-weight: bold; }
+.trades { max-height: 480px; overflow-y: auto; display: inline-block; }
+"#;
+
+/// Self-contained HTML report of a single ticker's [`Summary`].
+/// Charts are inline SVG and the page has no scripts or external assets,
+/// so the file can be opened or shared on its own.
+pub struct HtmlReport<'a> {
+  summary: &'a Summary,
+  ticker: &'a str,
+  title: &'a str,
+  benchmark: Option<Series>,
+}
+
+impl<'a> HtmlReport<'a> {
+  pub fn new(summary: &'a Summary, ticker: &'a str, title: &'a str) -> Self {
+    Self {
+      summary,
+      ticker,
+      title,
+      benchmark: None,
+    }
+  }
+
+  /// Series to draw alongside the strategy equity curve, such as buy & hold % ROI.
+  pub fn benchmark(mut self, series: Series) -> Self {
+    self.benchmark = Some(series);
+    self
+  }
+
+  pub fn render(&self) -> anyhow::Result<String> {
+    let metrics = self.summary.summarize(self.ticker)?;
+    let cum_pct = self.summary.cum_pct(self.ticker)?.data().clone();
+    let drawdown = self.summary.drawdown(self.ticker)?.data().clone();
+
+    let mut equity = vec![Series {
+      data: cum_pct.clone(),
+      label: "Strategy".to_string(),
+    }];
+    if let Some(benchmark) = &self.benchmark {
+      equity.push(Series {
+        data: benchmark.data.clone(),
+        label: benchmark.label.clone(),
+      });
+    }
+    let equity_svg = line_chart_svg(&equity, "% ROI")?;
+    let drawdown_svg = area_chart_svg(&drawdown, "Drawdown", "% Drawdown")?;
+
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>")?;
+    writeln!(html, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(
+      html,
+      "<title>{} {}</title>",
+      escape(self.ticker),
+      escape(self.title)
+    )?;
+    writeln!(html, "<style>{}</style></head><body>", STYLE)?;
+    writeln!(
+      html,
+      "<h1>{} {}</h1>",
+      escape(self.ticker),
+      escape(self.title)
+    )?;
+    writeln!(html, "<h2>Performance</h2>")?;
+    html.push_str(&metrics_table(&metrics));
+    writeln!(html, "<h2>Equity Curve</h2>")?;
+    html.push_str(&equity_svg);
+    writeln!(html, "<h2>Drawdown</h2>")?;
+    html.push_str(&drawdown_svg);
+    writeln!(html, "<h2>Monthly Returns</h2>")?;
+    html.push_str(&monthly_returns_table(&cum_pct));
+    writeln!(html, "<h2>Trades</h2>")?;
+    html.push_str(&self.trades_table()?);
+    writeln!(html, "</body></html>")?;
+    Ok(html)
+  }
+
+  pub fn write(&self, out_file: &str) -> anyhow::Result<()> {
+    std::fs::write(out_file, self.render()?)?;
+    Ok(())
+  }
+
+  fn trades_table(&self) -> anyhow::Result<String> {
+    let mut html = String::new();
+    writeln!(html, "<div class=\"trades\"><table>")?;
+    writeln!(
+      html,
+      "<tr><th>#</th><th>Date</th><th>Side</th><th>Price</th><th>Qty</th><th>Value</th></tr>"
+    )?;
+    for (i, trade) in self.summary.trades(self.ticker)?.iter().enumerate() {
+      let qty = trade.qty.unwrap_or(0.0);
+      let side = match trade.side {
+        TradeAction::EnterLong => "Enter Long",
+        TradeAction::ExitLong => "Exit Long",
+        TradeAction::EnterShort => "Enter Short",
+        TradeAction::ExitShort => "Exit Short",
+      };
+      writeln!(
+        html,
+        "<tr><td>{}</td><td>{}</td><td class=\"label\">{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+        i + 1,
+        format_date(trade.date.to_unix_ms()),
+        side,
+        trunc!(trade.price, 2),
+        trunc!(qty, 4),
+        trunc!(trade.price * qty, 2)
+      )?;
+    }
+    writeln!(html, "</table></div>")?;
+    Ok(html)
+  }
+}
+
+fn escape(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+fn format_date(unix_ms: i64) -> String {
+  match Utc.timestamp_millis_opt(unix_ms).single() {
+    Some(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+    None => unix_ms.to_string(),
+  }
+}
+
+fn format_day(unix_ms: i64) -> String {
+  match Utc.timestamp_millis_opt(unix_ms).single() {
+    Some(date) => date.format("%Y-%m-%d").to_string(),
+    None => unix_ms.to_string(),
+  }
+}
+
+fn metrics_table(m: &PerformanceSummary) -> String {
+  let rows = [
+    ("Return %", format!("{}%", m.pct_roi)),
+    ("Return $", format!("${}", m.quote_roi)),
+    ("Total Trades", m.total_trades.to_string()),
+    ("Win Rate", format!("{}%", m.win_rate)),
+    ("Avg Trade Size", format!("${}", m.avg_trade_size)),
+    ("Avg Trade", format!("{}%", m.avg_trade)),
+    ("Avg Winning Trade", format!("{}%", m.avg_winning_trade)),
+    ("Avg Losing Trade", format!("{}%", m.avg_losing_trade)),
+    ("Best Trade", format!("{}%", m.best_trade)),
+    ("Worst Trade", format!("{}%", m.worst_trade)),
+    ("Max Drawdown", format!("{}%", m.max_drawdown)),
+  ];
+  let mut html = String::from("<table>\n");
+  for (label, value) in rows {
+    html.push_str(&format!(
+      "<tr><td class=\"label\">{}</td><td>{}</td></tr>\n",
+      label, value
+    ));
+  }
+  html.push_str("</table>\n");
+  html
+}
+
+/// Compounded % return per calendar month, keyed by (year, month).
+/// Months without a closed trade between the first and last trade are 0%.
+fn monthly_returns(cum_pct: &[Data]) -> BTreeMap<(i32, u32), f64> {
+  let mut month_end = BTreeMap::new();
+  for point in cum_pct {
+    if let Some(date) = Utc.timestamp_millis_opt(point.x).single() {
+      // equity factor, 14% = 1.14, -35% = 0.65
+      month_end.insert((date.year(), date.month()), 1.0 + point.y / 100.0);
+    }
+  }
+  let mut returns = BTreeMap::new();
+  let (first, last) = match (month_end.keys().next(), month_end.keys().next_back()) {
+    (Some(first), Some(last)) => (*first, *last),
+    _ => return returns,
+  };
+  let mut prev = 1.0;
+  let (mut year, mut month) = first;
+  while (year, month) <= last {
+    let equity = month_end.get(&(year, month)).copied().unwrap_or(prev);
+    returns.insert((year, month), (equity / prev - 1.0) * 100.0);
+    prev = equity;
+    if month == 12 {
+      year += 1;
+      month = 1;
+    } else {
+      month += 1;
+    }
+  }
+  returns
+}
+
+/// Table of years by months with each cell shaded green or red by the size of the return.
+fn monthly_returns_table(cum_pct: &[Data]) -> String {
+  let returns = monthly_returns(cum_pct);
+  let max_abs = returns
+    .values()
+    .fold(0.0_f64, |max, r| max.max(r.abs()))
+    .max(f64::EPSILON);
+
+  let cell = |ret: f64| {
+    let alpha = trunc!((ret.abs() / max_abs).min(1.0) * 0.8 + 0.1, 2);
+    let rgb = if ret >= 0.0 { "67,160,71" } else { "229,57,53" };
+    format!(
+      "<td style=\"background: rgba({},{})\">{:.2}%</td>",
+      rgb, alpha, ret
+    )
+  };
+
+  let mut years: BTreeMap<i32, Vec<Option<f64>>> = BTreeMap::new();
+  for ((year, month), ret) in returns.iter() {
+    years.entry(*year).or_insert_with(|| vec![None; 12])[*month as usize - 1] = Some(*ret);
+  }
+
+  let mut html = String::from("<table>\n<tr><th>Year</th>");
+  for month in MONTHS {
+    html.push_str(&format!("<th>{}</th>", month));
+  }
+  html.push_str("<th>Year</th></tr>\n");
+  for (year, months) in years {
+    html.push_str(&format!("<tr><td class=\"label\">{}</td>", year));
+    let mut year_equity = 1.0;
+    for ret in months.iter() {
+      match ret {
+        Some(ret) => {
+          year_equity *= 1.0 + ret / 100.0;
+          html.push_str(&cell(*ret));
+        }
+        None => html.push_str("<td></td>"),
+      }
+    }
+    html.push_str(&cell((year_equity - 1.0) * 100.0));
+    html.push_str("</tr>\n");
+  }
+  html.push_str("</table>\n");
+  html
+}
+
+fn bounds<'a>(data: impl Iterator<Item = &'a Data>) -> Option<(i64, i64, f64, f64)> {
+  let mut min_x = i64::MAX;
+  let mut max_x = i64::MIN;
+  let mut min_y = f64::MAX;
+  let mut max_y = f64::MIN;
+  let mut empty = true;
+  for datum in data {
+    empty = false;
+    min_x = min_x.min(datum.x);
+    max_x = max_x.max(datum.x);
+    min_y = min_y.min(datum.y);
+    max_y = max_y.max(datum.y);
+  }
+  if empty {
+    return None;
+  }
+  // plotters can't build a coordinate system over an empty range
+  if min_x == max_x {
+    max_x += 1;
+  }
+  if min_y == max_y {
+    min_y -= 1.0;
+    max_y += 1.0;
+  }
+  Some((min_x, max_x, min_y, max_y))
+}
+
+fn line_chart_svg(series: &[Series], y_label: &str) -> anyhow::Result<String> {
+  let (min_x, max_x, min_y, max_y) = match bounds(series.iter().flat_map(|s| &s.data)) {
+    Some(bounds) => bounds,
+    None => return Ok("<p>No data</p>\n".to_string()),
+  };
+  let colors = [
+    BLACK,
+    RED_A400,
+    GREEN_500,
+    AMBER_800,
+    BLUE_A700,
+    PURPLE_A400,
+  ];
+
+  let mut svg = String::new();
+  {
+    let root = SVGBackend::with_string(&mut svg, CHART_SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+      .margin(20)
+      .set_all_label_area_size(70)
+      .build_cartesian_2d(min_x..max_x, min_y..max_y)?;
+    chart
+      .configure_mesh()
+      .light_line_style(WHITE)
+      .x_labels(8)
+      .x_label_formatter(&|x| format_day(*x))
+      .y_desc(y_label)
+      .draw()?;
+
+    for (i, s) in series.iter().enumerate() {
+      let color = colors.get(i).copied().unwrap_or(GREY_400);
+      chart
+        .draw_series(LineSeries::new(
+          s.data.iter().map(|d| (d.x, d.y)),
+          color.stroke_width(2),
+        ))?
+        .label(s.label.as_str())
+        .legend(move |(x, y)| PathElement::new([(x, y), (x + 20, y)], color.stroke_width(4)));
+    }
+
+    chart
+      .configure_series_labels()
+      .position(SeriesLabelPosition::UpperLeft)
+      .border_style(BLACK)
+      .background_style(WHITE.mix(0.8))
+      .draw()?;
+    root.present()?;
+  }
+  svg.push('\n');
+  Ok(svg)
+}
+
+fn area_chart_svg(data: &[Data], label: &str, y_label: &str) -> anyhow::Result<String> {
+  let (min_x, max_x, min_y, _) = match bounds(data.iter()) {
+    Some(bounds) => bounds,
+    None => return Ok("<p>No data</p>\n".to_string()),
+  };
+
+  let mut svg = String::new();
+  {
+    let root = SVGBackend::with_string(&mut svg, CHART_SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+      .margin(20)
+      .set_all_label_area_size(70)
+      .build_cartesian_2d(min_x..max_x, min_y.min(-1.0)..0.0)?;
+    chart
+      .configure_mesh()
+      .light_line_style(WHITE)
+      .x_labels(8)
+      .x_label_formatter(&|x| format_day(*x))
+      .y_desc(y_label)
+      .draw()?;
+    chart
+      .draw_series(
+        AreaSeries::new(data.iter().map(|d| (d.x, d.y)), 0.0, RED_A400.mix(0.3))
+          .border_style(RED_A400),
+      )?
+      .label(label)
+      .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], RED_A400.stroke_width(4)));
+    chart
+      .configure_series_labels()
+      .position(SeriesLabelPosition::LowerLeft)
+      .border_style(BLACK)
+      .background_style(WHITE.mix(0.8))
+      .draw()?;
+    root.present()?;
+  }
+  svg.push('\n');
+  Ok(svg)
+}
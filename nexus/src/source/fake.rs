@@ -0,0 +1,664 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::Engine;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use futures::StreamExt;
+use solana_account_decoder::parse_token::UiTokenAccount;
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::{RpcProgramAccountsConfig, RpcSimulateTransactionConfig};
+use solana_rpc_client_api::config::{
+  RpcBlockConfig, RpcSendTransactionConfig, RpcTransactionConfig,
+};
+use solana_rpc_client_api::filter::RpcFilterType;
+use solana_rpc_client_api::response::{
+  Response, RpcConfirmedTransactionStatusWithSignature, RpcKeyedAccount, RpcPrioritizationFee,
+  RpcResponseContext, RpcSimulateTransactionResult,
+};
+use solana_sdk::account::Account;
+use solana_sdk::clock::{Slot, UnixTimestamp, MAX_PROCESSING_AGE};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::VersionedTransaction;
+use solana_transaction_status::{
+  EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction, EncodedTransactionWithStatusMeta,
+  TransactionBinaryEncoding, TransactionConfirmationStatus, TransactionStatus, UiConfirmedBlock,
+};
+use yellowstone_grpc_proto::prelude::{SubscribeRequest, SubscribeUpdate};
+use yellowstone_grpc_proto::tonic::Status;
+
+use crate::{GeyserSink, GeyserSource, GeyserStream, RpcSource, Time};
+
+/// In-memory [`RpcSource`] for tests.
+/// Serves accounts seeded with [`FakeRpc::account`] or [`FakeRpc::load_fixtures`] at a fixed slot,
+/// and records sent transactions, which confirm immediately unless [`FakeRpc::drop_transactions`].
+/// Transaction history seeded with [`FakeRpc::transaction`] is served by signature and per address.
+/// Block height is the slot.
+pub struct FakeRpc {
+  state: Mutex<FakeRpcState>,
+}
+
+struct FakeRpcState {
+  slot: Slot,
+  block_time: UnixTimestamp,
+  blockhash: Hash,
+  units_consumed: u64,
+  accounts: HashMap<Pubkey, Account>,
+  /// Keys requested with `getMultipleAccounts`, in order
+  fetched: Vec<Pubkey>,
+  /// Delay of account requests
+  latency: Duration,
+  sent: Vec<VersionedTransaction>,
+  /// Sent transactions never land
+  drop_transactions: bool,
+  transactions: HashMap<Signature, EncodedConfirmedTransactionWithStatusMeta>,
+  /// Signatures per address, oldest first
+  history: HashMap<Pubkey, Vec<(Signature, Slot)>>,
+  /// Remaining failed `getTransaction` requests per signature
+  transaction_failures: HashMap<Signature, usize>,
+}
+
+impl Default for FakeRpc {
+  fn default() -> Self {
+    Self {
+      state: Mutex::new(FakeRpcState {
+        slot: 1,
+        block_time: Time::now().to_unix(),
+        blockhash: Hash::new_unique(),
+        units_consumed: 200_000,
+        accounts: HashMap::new(),
+        fetched: vec![],
+        latency: Duration::ZERO,
+        sent: vec![],
+        drop_transactions: false,
+        transactions: HashMap::new(),
+        history: HashMap::new(),
+        transaction_failures: HashMap::new(),
+      }),
+    }
+  }
+}
+
+impl FakeRpc {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn account(self, key: Pubkey, account: Account) -> Self {
+    self.set_account(key, account);
+    self
+  }
+
+  pub fn slot(self, slot: Slot) -> Self {
+    self.set_slot(slot);
+    self
+  }
+
+  /// Answer account requests after `latency`, so a test can watch a fetch in progress
+  pub fn latency(self, latency: Duration) -> Self {
+    self.lock().latency = latency;
+    self
+  }
+
+  /// Never confirm sent transactions, so they expire
+  pub fn drop_transactions(self) -> Self {
+    self.lock().drop_transactions = true;
+    self
+  }
+
+  /// Add a landed transaction to the history of every key in `keys`, newer than those added before
+  pub fn transaction(self, keys: &[Pubkey], slot: Slot, tx: VersionedTransaction) -> Self {
+    let signature = tx.signatures[0];
+    let encoded = EncodedConfirmedTransactionWithStatusMeta {
+      slot,
+      transaction: EncodedTransactionWithStatusMeta {
+        transaction: EncodedTransaction::Binary(
+          base64::engine::general_purpose::STANDARD.encode(bincode::serialize(&tx).unwrap()),
+          TransactionBinaryEncoding::Base64,
+        ),
+        meta: None,
+        version: None,
+      },
+      block_time: None,
+    };
+    let mut state = self.lock();
+    state.transactions.insert(signature, encoded);
+    for key in keys {
+      state
+        .history
+        .entry(*key)
+        .or_default()
+        .push((signature, slot));
+    }
+    drop(state);
+    self
+  }
+
+  /// Fail the next `times` requests for the transaction
+  pub fn fail_transaction(self, signature: Signature, times: usize) -> Self {
+    self.lock().transaction_failures.insert(signature, times);
+    self
+  }
+
+  /// Load every `*.json` file in `dir` as a keyed account,
+  /// the format written by `solana account <KEY> --output json --output-file <FILE>`
+  pub fn load_fixtures(self, dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+    for entry in std::fs::read_dir(dir)? {
+      let path = entry?.path();
+      if path.extension().map_or(true, |ext| ext != "json") {
+        continue;
+      }
+      let keyed: RpcKeyedAccount = serde_json::from_slice(&std::fs::read(&path)?)?;
+      let key = Pubkey::from_str(&keyed.pubkey)?;
+      let account: Account = keyed.account.decode().ok_or(anyhow::anyhow!(
+        "Failed to decode fixture account {:?}",
+        path
+      ))?;
+      self.set_account(key, account);
+    }
+    Ok(self)
+  }
+
+  pub fn set_account(&self, key: Pubkey, account: Account) {
+    self.lock().accounts.insert(key, account);
+  }
+
+  pub fn set_slot(&self, slot: Slot) {
+    self.lock().slot = slot;
+  }
+
+  /// Transactions sent so far, in order
+  pub fn sent(&self) -> Vec<VersionedTransaction> {
+    self.lock().sent.clone()
+  }
+
+  /// Keys requested with `getMultipleAccounts` so far, in order
+  pub fn fetched(&self) -> Vec<Pubkey> {
+    self.lock().fetched.clone()
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, FakeRpcState> {
+    // state is always left consistent, so recover from a test that panicked while holding the lock
+    self.state.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  fn context(slot: Slot) -> RpcResponseContext {
+    RpcResponseContext {
+      slot,
+      api_version: None,
+    }
+  }
+
+  fn matches(filter: &RpcFilterType, account: &Account) -> bool {
+    match filter {
+      RpcFilterType::DataSize(size) => account.data.len() as u64 == *size,
+      RpcFilterType::Memcmp(memcmp) => memcmp.bytes_match(&account.data),
+      RpcFilterType::TokenAccountState => false,
+    }
+  }
+}
+
+#[async_trait]
+impl RpcSource for FakeRpc {
+  async fn get_account(&self, key: &Pubkey) -> anyhow::Result<Account> {
+    self
+      .lock()
+      .accounts
+      .get(key)
+      .cloned()
+      .ok_or(anyhow::anyhow!("AccountNotFound: pubkey={}", key))
+  }
+
+  async fn get_account_with_commitment(
+    &self,
+    key: &Pubkey,
+    _commitment: CommitmentConfig,
+  ) -> anyhow::Result<Response<Option<Account>>> {
+    let state = self.lock();
+    Ok(Response {
+      context: Self::context(state.slot),
+      value: state.accounts.get(key).cloned(),
+    })
+  }
+
+  async fn get_multiple_accounts_with_commitment(
+    &self,
+    keys: &[Pubkey],
+    _commitment: CommitmentConfig,
+  ) -> anyhow::Result<Response<Vec<Option<Account>>>> {
+    let latency = self.lock().latency;
+    tokio::time::sleep(latency).await;
+    let mut state = self.lock();
+    state.fetched.extend_from_slice(keys);
+    Ok(Response {
+      context: Self::context(state.slot),
+      value: keys
+        .iter()
+        .map(|key| state.accounts.get(key).cloned())
+        .collect(),
+    })
+  }
+
+  async fn get_program_accounts_with_context(
+    &self,
+    program: &Pubkey,
+    config: RpcProgramAccountsConfig,
+  ) -> anyhow::Result<Response<Vec<RpcKeyedAccount>>> {
+    let state = self.lock();
+    let filters = config.filters.unwrap_or_default();
+    let data_slice = config.account_config.data_slice;
+    let value = state
+      .accounts
+      .iter()
+      .filter(|(_, account)| account.owner == *program)
+      .filter(|(_, account)| filters.iter().all(|f| Self::matches(f, account)))
+      .map(|(key, account)| RpcKeyedAccount {
+        pubkey: key.to_string(),
+        account: UiAccount::encode(key, account, UiAccountEncoding::Base64, None, data_slice),
+      })
+      .collect();
+    Ok(Response {
+      context: Self::context(state.slot),
+      value,
+    })
+  }
+
+  async fn get_slot_with_commitment(&self, _commitment: CommitmentConfig) -> anyhow::Result<Slot> {
+    Ok(self.lock().slot)
+  }
+
+  async fn get_block_with_config(
+    &self,
+    slot: Slot,
+    _config: RpcBlockConfig,
+  ) -> anyhow::Result<UiConfirmedBlock> {
+    let state = self.lock();
+    Ok(UiConfirmedBlock {
+      previous_blockhash: Hash::default().to_string(),
+      blockhash: state.blockhash.to_string(),
+      parent_slot: slot.saturating_sub(1),
+      transactions: None,
+      signatures: None,
+      rewards: None,
+      block_time: Some(state.block_time),
+      block_height: Some(slot),
+    })
+  }
+
+  async fn get_latest_blockhash(&self) -> anyhow::Result<Hash> {
+    Ok(self.lock().blockhash)
+  }
+
+  async fn get_latest_blockhash_with_commitment(
+    &self,
+    _commitment: CommitmentConfig,
+  ) -> anyhow::Result<(Hash, u64)> {
+    let state = self.lock();
+    Ok((state.blockhash, state.slot + MAX_PROCESSING_AGE as u64))
+  }
+
+  async fn get_block_height_with_commitment(
+    &self,
+    _commitment: CommitmentConfig,
+  ) -> anyhow::Result<u64> {
+    Ok(self.lock().slot)
+  }
+
+  async fn get_recent_prioritization_fees(
+    &self,
+    _keys: &[Pubkey],
+  ) -> anyhow::Result<Vec<RpcPrioritizationFee>> {
+    Ok(vec![RpcPrioritizationFee {
+      slot: self.lock().slot,
+      prioritization_fee: 0,
+    }])
+  }
+
+  async fn get_token_account(&self, key: &Pubkey) -> anyhow::Result<Option<UiTokenAccount>> {
+    if self.lock().accounts.contains_key(key) {
+      return Err(anyhow::anyhow!(
+        "FakeRpc does not parse token accounts: {}",
+        key
+      ));
+    }
+    Ok(None)
+  }
+
+  async fn simulate_transaction_with_config(
+    &self,
+    _tx: &VersionedTransaction,
+    _config: RpcSimulateTransactionConfig,
+  ) -> anyhow::Result<Response<RpcSimulateTransactionResult>> {
+    let state = self.lock();
+    Ok(Response {
+      context: Self::context(state.slot),
+      value: RpcSimulateTransactionResult {
+        err: None,
+        logs: Some(vec![]),
+        accounts: None,
+        units_consumed: Some(state.units_consumed),
+        return_data: None,
+      },
+    })
+  }
+
+  async fn send_transaction_with_config(
+    &self,
+    tx: &VersionedTransaction,
+    _config: RpcSendTransactionConfig,
+  ) -> anyhow::Result<Signature> {
+    let signature = *tx
+      .signatures
+      .first()
+      .ok_or(anyhow::anyhow!("Transaction is not signed"))?;
+    self.lock().sent.push(tx.clone());
+    Ok(signature)
+  }
+
+  async fn get_signature_statuses(
+    &self,
+    signatures: &[Signature],
+  ) -> anyhow::Result<Response<Vec<Option<TransactionStatus>>>> {
+    let state = self.lock();
+    let landed = match state.drop_transactions {
+      true => &[][..],
+      false => state.sent.as_slice(),
+    };
+    let sent: HashSet<Signature> = landed
+      .iter()
+      .flat_map(|tx| tx.signatures.first().cloned())
+      .collect();
+    let value = signatures
+      .iter()
+      .map(|sig| {
+        sent.contains(sig).then_some(TransactionStatus {
+          slot: state.slot,
+          confirmations: None,
+          status: Ok(()),
+          err: None,
+          confirmation_status: Some(TransactionConfirmationStatus::Confirmed),
+        })
+      })
+      .collect();
+    Ok(Response {
+      context: Self::context(state.slot),
+      value,
+    })
+  }
+
+  async fn get_transaction_with_config(
+    &self,
+    signature: &Signature,
+    _config: RpcTransactionConfig,
+  ) -> anyhow::Result<EncodedConfirmedTransactionWithStatusMeta> {
+    let mut state = self.lock();
+    if let Some(failures) = state.transaction_failures.get_mut(signature) {
+      if *failures > 0 {
+        *failures -= 1;
+        return Err(anyhow::anyhow!("Transaction request failed: {}", signature));
+      }
+    }
+    state
+      .transactions
+      .get(signature)
+      .cloned()
+      .ok_or(anyhow::anyhow!("Transaction not found: {}", signature))
+  }
+
+  /// Newest first, `before` and `until` are exclusive
+  async fn get_signatures_for_address_with_config(
+    &self,
+    key: &Pubkey,
+    config: GetConfirmedSignaturesForAddress2Config,
+  ) -> anyhow::Result<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+    let state = self.lock();
+    let history = match state.history.get(key) {
+      Some(history) => history,
+      None => return Ok(vec![]),
+    };
+    Ok(
+      history
+        .iter()
+        .rev()
+        .skip_while(|(sig, _)| config.before.map_or(false, |before| *sig != before))
+        .skip(config.before.is_some() as usize)
+        .take_while(|(sig, _)| Some(*sig) != config.until)
+        .take(config.limit.unwrap_or(1000))
+        .map(|(sig, slot)| RpcConfirmedTransactionStatusWithSignature {
+          signature: sig.to_string(),
+          slot: *slot,
+          err: None,
+          memo: None,
+          block_time: None,
+          confirmation_status: Some(TransactionConfirmationStatus::Confirmed),
+        })
+        .collect(),
+    )
+  }
+}
+
+/// In-memory [`GeyserSource`] for tests. Updates pushed with [`FakeGeyser::sender`] are streamed
+/// to the subscriber in order, and subscribe requests, including pongs, are kept for inspection.
+/// Each subscription streams the next session started with [`FakeGeyser::session`],
+/// and subscribing once they are used up fails.
+pub struct FakeGeyser {
+  /// Sender of the last session
+  tx: Mutex<UnboundedSender<SubscribeUpdate>>,
+  /// Sessions not subscribed to yet, in order
+  sessions: Mutex<VecDeque<UnboundedReceiver<SubscribeUpdate>>>,
+  requests: Mutex<Vec<UnboundedReceiver<SubscribeRequest>>>,
+}
+
+impl Default for FakeGeyser {
+  fn default() -> Self {
+    let (tx, rx) = futures::channel::mpsc::unbounded();
+    Self {
+      tx: Mutex::new(tx),
+      sessions: Mutex::new(VecDeque::from([rx])),
+      requests: Mutex::new(vec![]),
+    }
+  }
+}
+
+impl FakeGeyser {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Queue updates to stream on the last session once subscribed
+  pub fn updates(self, updates: impl IntoIterator<Item = SubscribeUpdate>) -> Self {
+    let tx = self.sender();
+    for update in updates {
+      // the receiver is owned by self until subscribed, so this can't fail before then
+      let _ = tx.unbounded_send(update);
+    }
+    self
+  }
+
+  /// End the last session once its updates are streamed, unless a [`FakeGeyser::sender`] of it
+  /// is still held, and start a new one for the next subscription
+  pub fn session(self) -> Self {
+    let (tx, rx) = futures::channel::mpsc::unbounded();
+    *self.tx.lock().unwrap_or_else(|e| e.into_inner()) = tx;
+    self
+      .sessions
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .push_back(rx);
+    self
+  }
+
+  /// Sender of the last session
+  pub fn sender(&self) -> UnboundedSender<SubscribeUpdate> {
+    self.tx.lock().unwrap_or_else(|e| e.into_inner()).clone()
+  }
+
+  /// Requests sent on every subscription so far, starting with the subscribe request itself
+  pub fn requests(&self) -> Vec<SubscribeRequest> {
+    let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+    let mut res = vec![];
+    for rx in requests.iter_mut() {
+      while let Ok(Some(request)) = rx.try_next() {
+        res.push(request);
+      }
+    }
+    res
+  }
+}
+
+#[async_trait]
+impl GeyserSource for FakeGeyser {
+  async fn subscribe(
+    &self,
+    request: SubscribeRequest,
+  ) -> anyhow::Result<(GeyserSink, GeyserStream)> {
+    let updates = self
+      .sessions
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .pop_front()
+      .ok_or(anyhow::anyhow!("FakeGeyser has no session left"))?;
+    let (sink, requests) = futures::channel::mpsc::unbounded();
+    sink.unbounded_send(request)?;
+    self
+      .requests
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .push(requests);
+    Ok((Box::pin(sink), Box::pin(updates.map(Ok::<_, Status>))))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drift_client::Cache;
+  use crate::{GeyserConfig, NexusClient, ReconnectConfig};
+  use std::sync::Arc;
+  use std::time::Duration;
+  use yellowstone_grpc_proto::prelude::subscribe_update::UpdateOneof;
+  use yellowstone_grpc_proto::prelude::{
+    CommitmentLevel, SubscribeRequestFilterAccounts, SubscribeUpdateAccount,
+    SubscribeUpdateAccountInfo, SubscribeUpdateSlot,
+  };
+
+  fn account(owner: Pubkey, data: Vec<u8>) -> Account {
+    Account {
+      lamports: 1_000_000,
+      data,
+      owner,
+      executable: false,
+      rent_epoch: 0,
+    }
+  }
+
+  #[tokio::test]
+  async fn fake_rpc_serves_fixtures() -> anyhow::Result<()> {
+    let program = Pubkey::new_unique();
+    let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let dir = std::env::temp_dir().join(format!("fixtures_{}", Pubkey::new_unique()));
+    std::fs::create_dir_all(&dir)?;
+    let fixture = RpcKeyedAccount {
+      pubkey: a.to_string(),
+      account: UiAccount::encode(
+        &a,
+        &account(program, vec![1, 2, 3]),
+        UiAccountEncoding::Base64,
+        None,
+        None,
+      ),
+    };
+    std::fs::write(dir.join("a.json"), serde_json::to_vec(&fixture)?)?;
+
+    let rpc = FakeRpc::new()
+      .slot(10)
+      .account(b, account(program, vec![4, 5]))
+      .load_fixtures(&dir)?;
+    std::fs::remove_dir_all(&dir)?;
+    let rpc: Arc<dyn RpcSource> = Arc::new(rpc);
+
+    assert_eq!(rpc.get_account_data(&a).await?, vec![1, 2, 3]);
+    let res = rpc
+      .get_multiple_accounts_with_commitment(
+        &[a, Pubkey::new_unique()],
+        CommitmentConfig::confirmed(),
+      )
+      .await?;
+    assert_eq!(res.context.slot, 10);
+    assert!(res.value[0].is_some() && res.value[1].is_none());
+
+    let config = RpcProgramAccountsConfig {
+      filters: Some(vec![RpcFilterType::DataSize(2)]),
+      ..Default::default()
+    };
+    let res = rpc
+      .get_program_accounts_with_context(&program, config)
+      .await?;
+    let keys: Vec<String> = res.value.into_iter().map(|k| k.pubkey).collect();
+    assert_eq!(keys, vec![b.to_string()]);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn fake_geyser_streams_into_cache() -> anyhow::Result<()> {
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let slot_update = |slot| SubscribeUpdate {
+      filters: vec!["slots".to_string()],
+      update_oneof: Some(UpdateOneof::Slot(SubscribeUpdateSlot {
+        slot,
+        ..Default::default()
+      })),
+    };
+    let account_update = SubscribeUpdate {
+      filters: vec!["accounts".to_string()],
+      update_oneof: Some(UpdateOneof::Account(SubscribeUpdateAccount {
+        slot: 5,
+        is_startup: false,
+        account: Some(SubscribeUpdateAccountInfo {
+          pubkey: key.to_bytes().to_vec(),
+          lamports: 1,
+          owner: owner.to_bytes().to_vec(),
+          executable: false,
+          rent_epoch: 0,
+          data: vec![7; 8],
+          write_version: 0,
+          txn_signature: None,
+        }),
+      })),
+    };
+    let geyser =
+      Arc::new(FakeGeyser::new().updates([slot_update(4), account_update, slot_update(5)]));
+    let cfg = GeyserConfig::new(String::new(), String::new(), CommitmentLevel::Processed).accounts(
+      "accounts",
+      SubscribeRequestFilterAccounts {
+        account: vec![key.to_string()],
+        owner: vec![],
+        filters: vec![],
+      },
+      &[crate::FilterRoute::Cache],
+    );
+    let nexus = NexusClient::with_source(cfg, geyser.clone()).reconnect(ReconnectConfig {
+      max_retries: Some(0),
+      idle_timeout: Duration::from_millis(100),
+      ..Default::default()
+    });
+    let cache = Cache::new(10);
+    // the fake never closes, so the stream ends once the idle timeout runs out of retries
+    assert!(nexus.stream(&cache, None, None, None).await.is_err());
+
+    let cache = cache.read().await;
+    assert_eq!(cache.slot, 5);
+    let ctx = cache.account(&key, None)?;
+    assert_eq!((ctx.slot, ctx.account.data.clone()), (5, vec![7; 8]));
+    assert_eq!(geyser.requests().len(), 1);
+    Ok(())
+  }
+}
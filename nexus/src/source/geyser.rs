@@ -0,0 +1,35 @@
+use std::pin::Pin;
+
+use async_trait::async_trait;
+use futures::channel::mpsc::SendError;
+use futures::{Sink, Stream};
+use yellowstone_grpc_proto::prelude::{SubscribeRequest, SubscribeUpdate};
+use yellowstone_grpc_proto::tonic::Status;
+
+use crate::GrpcClient;
+
+/// Sink used to answer server pings on an open subscription
+pub type GeyserSink = Pin<Box<dyn Sink<SubscribeRequest, Error = SendError> + Send>>;
+pub type GeyserStream = Pin<Box<dyn Stream<Item = Result<SubscribeUpdate, Status>> + Send>>;
+
+/// Source of geyser updates for [`crate::NexusClient::stream`].
+/// Implemented by [`GrpcClient`] and by [`crate::FakeGeyser`] to stream without a network.
+#[async_trait]
+pub trait GeyserSource: Send + Sync {
+  /// Open a subscription with the filters in `request`
+  async fn subscribe(
+    &self,
+    request: SubscribeRequest,
+  ) -> anyhow::Result<(GeyserSink, GeyserStream)>;
+}
+
+#[async_trait]
+impl GeyserSource for GrpcClient {
+  async fn subscribe(
+    &self,
+    request: SubscribeRequest,
+  ) -> anyhow::Result<(GeyserSink, GeyserStream)> {
+    let (sink, stream) = self.subscribe_with(request).await?;
+    Ok((Box::pin(sink), Box::pin(stream)))
+  }
+}
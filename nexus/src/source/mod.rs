@@ -0,0 +1,7 @@
+pub use fake::*;
+pub use geyser::*;
+pub use rpc::*;
+
+pub mod fake;
+pub mod geyser;
+pub mod rpc;
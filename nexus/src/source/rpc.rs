@@ -0,0 +1,446 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use solana_account_decoder::parse_token::UiTokenAccount;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::{RpcProgramAccountsConfig, RpcSimulateTransactionConfig};
+use solana_rpc_client_api::config::{
+  RpcBlockConfig, RpcSendTransactionConfig, RpcTransactionConfig,
+};
+use solana_rpc_client_api::request::RpcRequest;
+use solana_rpc_client_api::response::{
+  OptionalContext, Response, RpcConfirmedTransactionStatusWithSignature, RpcKeyedAccount,
+  RpcPrioritizationFee, RpcResponseContext, RpcSimulateTransactionResult,
+};
+use solana_sdk::account::Account;
+use solana_sdk::clock::Slot;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::VersionedTransaction;
+use solana_transaction_status::{
+  EncodedConfirmedTransactionWithStatusMeta, TransactionStatus, UiConfirmedBlock,
+};
+
+/// The RPC methods used by the cache, clients and engines.
+/// Implemented by [`RpcClient`] and by [`crate::FakeRpc`] to run them without a network.
+/// Method names and arguments mirror [`RpcClient`].
+#[async_trait]
+pub trait RpcSource: Send + Sync {
+  async fn get_account(&self, key: &Pubkey) -> anyhow::Result<Account>;
+
+  async fn get_account_with_commitment(
+    &self,
+    key: &Pubkey,
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<Response<Option<Account>>>;
+
+  async fn get_multiple_accounts_with_commitment(
+    &self,
+    keys: &[Pubkey],
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<Response<Vec<Option<Account>>>>;
+
+  /// `getProgramAccounts` with the slot the accounts were read at, or a lower bound of it if the
+  /// provider ignores `withContext`
+  async fn get_program_accounts_with_context(
+    &self,
+    program: &Pubkey,
+    config: RpcProgramAccountsConfig,
+  ) -> anyhow::Result<Response<Vec<RpcKeyedAccount>>>;
+
+  async fn get_slot_with_commitment(&self, commitment: CommitmentConfig) -> anyhow::Result<Slot>;
+
+  async fn get_block_with_config(
+    &self,
+    slot: Slot,
+    config: RpcBlockConfig,
+  ) -> anyhow::Result<UiConfirmedBlock>;
+
+  async fn get_latest_blockhash(&self) -> anyhow::Result<Hash>;
+
+  /// Latest blockhash and the last block height a transaction using it can land in
+  async fn get_latest_blockhash_with_commitment(
+    &self,
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<(Hash, u64)>;
+
+  async fn get_block_height_with_commitment(
+    &self,
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<u64>;
+
+  async fn get_recent_prioritization_fees(
+    &self,
+    keys: &[Pubkey],
+  ) -> anyhow::Result<Vec<RpcPrioritizationFee>>;
+
+  async fn get_token_account(&self, key: &Pubkey) -> anyhow::Result<Option<UiTokenAccount>>;
+
+  async fn simulate_transaction_with_config(
+    &self,
+    tx: &VersionedTransaction,
+    config: RpcSimulateTransactionConfig,
+  ) -> anyhow::Result<Response<RpcSimulateTransactionResult>>;
+
+  async fn send_transaction_with_config(
+    &self,
+    tx: &VersionedTransaction,
+    config: RpcSendTransactionConfig,
+  ) -> anyhow::Result<Signature>;
+
+  async fn get_signature_statuses(
+    &self,
+    signatures: &[Signature],
+  ) -> anyhow::Result<Response<Vec<Option<TransactionStatus>>>>;
+
+  async fn get_transaction_with_config(
+    &self,
+    signature: &Signature,
+    config: RpcTransactionConfig,
+  ) -> anyhow::Result<EncodedConfirmedTransactionWithStatusMeta>;
+
+  async fn get_signatures_for_address_with_config(
+    &self,
+    key: &Pubkey,
+    config: GetConfirmedSignaturesForAddress2Config,
+  ) -> anyhow::Result<Vec<RpcConfirmedTransactionStatusWithSignature>>;
+
+  /// Defaults use confirmed commitment, [`RpcClient`] uses its own
+  async fn get_account_data(&self, key: &Pubkey) -> anyhow::Result<Vec<u8>> {
+    Ok(self.get_account(key).await?.data)
+  }
+
+  async fn get_multiple_accounts(&self, keys: &[Pubkey]) -> anyhow::Result<Vec<Option<Account>>> {
+    Ok(
+      self
+        .get_multiple_accounts_with_commitment(keys, CommitmentConfig::confirmed())
+        .await?
+        .value,
+    )
+  }
+
+  async fn get_slot(&self) -> anyhow::Result<Slot> {
+    self
+      .get_slot_with_commitment(CommitmentConfig::confirmed())
+      .await
+  }
+
+  async fn get_block_height(&self) -> anyhow::Result<u64> {
+    self
+      .get_block_height_with_commitment(CommitmentConfig::confirmed())
+      .await
+  }
+
+  async fn simulate_transaction(
+    &self,
+    tx: &VersionedTransaction,
+  ) -> anyhow::Result<Response<RpcSimulateTransactionResult>> {
+    self
+      .simulate_transaction_with_config(tx, RpcSimulateTransactionConfig::default())
+      .await
+  }
+}
+
+#[async_trait]
+impl RpcSource for RpcClient {
+  async fn get_account(&self, key: &Pubkey) -> anyhow::Result<Account> {
+    Ok(RpcClient::get_account(self, key).await?)
+  }
+
+  async fn get_account_with_commitment(
+    &self,
+    key: &Pubkey,
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<Response<Option<Account>>> {
+    Ok(RpcClient::get_account_with_commitment(self, key, commitment).await?)
+  }
+
+  async fn get_multiple_accounts_with_commitment(
+    &self,
+    keys: &[Pubkey],
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<Response<Vec<Option<Account>>>> {
+    Ok(RpcClient::get_multiple_accounts_with_commitment(self, keys, commitment).await?)
+  }
+
+  async fn get_program_accounts_with_context(
+    &self,
+    program: &Pubkey,
+    config: RpcProgramAccountsConfig,
+  ) -> anyhow::Result<Response<Vec<RpcKeyedAccount>>> {
+    let config = RpcProgramAccountsConfig {
+      with_context: Some(true),
+      ..config
+    };
+    // the accounts are at least as new as a slot taken before the request, not after
+    let commitment = config
+      .account_config
+      .commitment
+      .unwrap_or_else(|| RpcClient::commitment(self));
+    let min_slot = RpcClient::get_slot_with_commitment(self, commitment).await?;
+    let response = self
+      .send::<OptionalContext<Vec<RpcKeyedAccount>>>(
+        RpcRequest::GetProgramAccounts,
+        serde_json::json!([program.to_string(), config]),
+      )
+      .await?;
+    Ok(match response {
+      OptionalContext::Context(response) => response,
+      // some providers ignore `withContext`
+      OptionalContext::NoContext(value) => Response {
+        context: RpcResponseContext {
+          slot: min_slot,
+          api_version: None,
+        },
+        value,
+      },
+    })
+  }
+
+  async fn get_slot_with_commitment(&self, commitment: CommitmentConfig) -> anyhow::Result<Slot> {
+    Ok(RpcClient::get_slot_with_commitment(self, commitment).await?)
+  }
+
+  async fn get_block_with_config(
+    &self,
+    slot: Slot,
+    config: RpcBlockConfig,
+  ) -> anyhow::Result<UiConfirmedBlock> {
+    Ok(RpcClient::get_block_with_config(self, slot, config).await?)
+  }
+
+  async fn get_latest_blockhash(&self) -> anyhow::Result<Hash> {
+    Ok(RpcClient::get_latest_blockhash(self).await?)
+  }
+
+  async fn get_latest_blockhash_with_commitment(
+    &self,
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<(Hash, u64)> {
+    Ok(RpcClient::get_latest_blockhash_with_commitment(self, commitment).await?)
+  }
+
+  async fn get_block_height_with_commitment(
+    &self,
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<u64> {
+    Ok(RpcClient::get_block_height_with_commitment(self, commitment).await?)
+  }
+
+  async fn get_recent_prioritization_fees(
+    &self,
+    keys: &[Pubkey],
+  ) -> anyhow::Result<Vec<RpcPrioritizationFee>> {
+    Ok(RpcClient::get_recent_prioritization_fees(self, keys).await?)
+  }
+
+  async fn get_token_account(&self, key: &Pubkey) -> anyhow::Result<Option<UiTokenAccount>> {
+    Ok(RpcClient::get_token_account(self, key).await?)
+  }
+
+  async fn simulate_transaction_with_config(
+    &self,
+    tx: &VersionedTransaction,
+    config: RpcSimulateTransactionConfig,
+  ) -> anyhow::Result<Response<RpcSimulateTransactionResult>> {
+    Ok(RpcClient::simulate_transaction_with_config(self, tx, config).await?)
+  }
+
+  async fn send_transaction_with_config(
+    &self,
+    tx: &VersionedTransaction,
+    config: RpcSendTransactionConfig,
+  ) -> anyhow::Result<Signature> {
+    Ok(RpcClient::send_transaction_with_config(self, tx, config).await?)
+  }
+
+  async fn get_signature_statuses(
+    &self,
+    signatures: &[Signature],
+  ) -> anyhow::Result<Response<Vec<Option<TransactionStatus>>>> {
+    Ok(RpcClient::get_signature_statuses(self, signatures).await?)
+  }
+
+  async fn get_transaction_with_config(
+    &self,
+    signature: &Signature,
+    config: RpcTransactionConfig,
+  ) -> anyhow::Result<EncodedConfirmedTransactionWithStatusMeta> {
+    Ok(RpcClient::get_transaction_with_config(self, signature, config).await?)
+  }
+
+  async fn get_signatures_for_address_with_config(
+    &self,
+    key: &Pubkey,
+    config: GetConfirmedSignaturesForAddress2Config,
+  ) -> anyhow::Result<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+    Ok(RpcClient::get_signatures_for_address_with_config(self, key, config).await?)
+  }
+
+  async fn get_account_data(&self, key: &Pubkey) -> anyhow::Result<Vec<u8>> {
+    Ok(RpcClient::get_account_data(self, key).await?)
+  }
+
+  async fn get_multiple_accounts(&self, keys: &[Pubkey]) -> anyhow::Result<Vec<Option<Account>>> {
+    Ok(RpcClient::get_multiple_accounts(self, keys).await?)
+  }
+
+  async fn get_slot(&self) -> anyhow::Result<Slot> {
+    Ok(RpcClient::get_slot(self).await?)
+  }
+
+  async fn simulate_transaction(
+    &self,
+    tx: &VersionedTransaction,
+  ) -> anyhow::Result<Response<RpcSimulateTransactionResult>> {
+    Ok(RpcClient::simulate_transaction(self, tx).await?)
+  }
+}
+
+/// Lets `&Arc<RpcClient>` and `&Arc<dyn RpcSource>` be passed where `&dyn RpcSource` is expected
+#[async_trait]
+impl<T: RpcSource + ?Sized> RpcSource for Arc<T> {
+  async fn get_account(&self, key: &Pubkey) -> anyhow::Result<Account> {
+    (**self).get_account(key).await
+  }
+
+  async fn get_account_with_commitment(
+    &self,
+    key: &Pubkey,
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<Response<Option<Account>>> {
+    (**self).get_account_with_commitment(key, commitment).await
+  }
+
+  async fn get_multiple_accounts_with_commitment(
+    &self,
+    keys: &[Pubkey],
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<Response<Vec<Option<Account>>>> {
+    (**self)
+      .get_multiple_accounts_with_commitment(keys, commitment)
+      .await
+  }
+
+  async fn get_program_accounts_with_context(
+    &self,
+    program: &Pubkey,
+    config: RpcProgramAccountsConfig,
+  ) -> anyhow::Result<Response<Vec<RpcKeyedAccount>>> {
+    (**self)
+      .get_program_accounts_with_context(program, config)
+      .await
+  }
+
+  async fn get_slot_with_commitment(&self, commitment: CommitmentConfig) -> anyhow::Result<Slot> {
+    (**self).get_slot_with_commitment(commitment).await
+  }
+
+  async fn get_block_with_config(
+    &self,
+    slot: Slot,
+    config: RpcBlockConfig,
+  ) -> anyhow::Result<UiConfirmedBlock> {
+    (**self).get_block_with_config(slot, config).await
+  }
+
+  async fn get_latest_blockhash(&self) -> anyhow::Result<Hash> {
+    (**self).get_latest_blockhash().await
+  }
+
+  async fn get_latest_blockhash_with_commitment(
+    &self,
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<(Hash, u64)> {
+    (**self)
+      .get_latest_blockhash_with_commitment(commitment)
+      .await
+  }
+
+  async fn get_block_height_with_commitment(
+    &self,
+    commitment: CommitmentConfig,
+  ) -> anyhow::Result<u64> {
+    (**self).get_block_height_with_commitment(commitment).await
+  }
+
+  async fn get_recent_prioritization_fees(
+    &self,
+    keys: &[Pubkey],
+  ) -> anyhow::Result<Vec<RpcPrioritizationFee>> {
+    (**self).get_recent_prioritization_fees(keys).await
+  }
+
+  async fn get_token_account(&self, key: &Pubkey) -> anyhow::Result<Option<UiTokenAccount>> {
+    (**self).get_token_account(key).await
+  }
+
+  async fn simulate_transaction_with_config(
+    &self,
+    tx: &VersionedTransaction,
+    config: RpcSimulateTransactionConfig,
+  ) -> anyhow::Result<Response<RpcSimulateTransactionResult>> {
+    (**self).simulate_transaction_with_config(tx, config).await
+  }
+
+  async fn send_transaction_with_config(
+    &self,
+    tx: &VersionedTransaction,
+    config: RpcSendTransactionConfig,
+  ) -> anyhow::Result<Signature> {
+    (**self).send_transaction_with_config(tx, config).await
+  }
+
+  async fn get_signature_statuses(
+    &self,
+    signatures: &[Signature],
+  ) -> anyhow::Result<Response<Vec<Option<TransactionStatus>>>> {
+    (**self).get_signature_statuses(signatures).await
+  }
+
+  async fn get_transaction_with_config(
+    &self,
+    signature: &Signature,
+    config: RpcTransactionConfig,
+  ) -> anyhow::Result<EncodedConfirmedTransactionWithStatusMeta> {
+    (**self)
+      .get_transaction_with_config(signature, config)
+      .await
+  }
+
+  async fn get_signatures_for_address_with_config(
+    &self,
+    key: &Pubkey,
+    config: GetConfirmedSignaturesForAddress2Config,
+  ) -> anyhow::Result<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+    (**self)
+      .get_signatures_for_address_with_config(key, config)
+      .await
+  }
+
+  async fn get_account_data(&self, key: &Pubkey) -> anyhow::Result<Vec<u8>> {
+    (**self).get_account_data(key).await
+  }
+
+  async fn get_multiple_accounts(&self, keys: &[Pubkey]) -> anyhow::Result<Vec<Option<Account>>> {
+    (**self).get_multiple_accounts(keys).await
+  }
+
+  async fn get_slot(&self) -> anyhow::Result<Slot> {
+    (**self).get_slot().await
+  }
+
+  async fn get_block_height(&self) -> anyhow::Result<u64> {
+    (**self).get_block_height().await
+  }
+
+  async fn simulate_transaction(
+    &self,
+    tx: &VersionedTransaction,
+  ) -> anyhow::Result<Response<RpcSimulateTransactionResult>> {
+    (**self).simulate_transaction(tx).await
+  }
+}
@@ -1,7 +1,6 @@
 use anchor_lang::solana_program::address_lookup_table::AddressLookupTableAccount;
 use log::info;
 use solana_account_decoder::UiAccountEncoding;
-use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_client::rpc_config::{
   RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig,
 };
@@ -22,9 +21,9 @@ use solana_transaction_status::{
   UiTransactionEncoding,
 };
 use std::sync::Arc;
-use tokio::time::MissedTickBehavior;
+use tokio::time::{Instant, MissedTickBehavior};
 
-use crate::ConfirmTransactionConfig;
+use crate::{ConfirmTransactionConfig, ConfirmationTracker, PendingTx, RpcSource, Streamed};
 
 pub type KeypairTrx<'a> = TrxBuilder<'a, Keypair, Vec<&'a Keypair>>;
 
@@ -44,13 +43,16 @@ pub enum TxError {
   /// The transaction was dropped
   #[error("Transaction was dropped")]
   Dropped,
+  /// The blockhash expired before the transaction landed, so it can be signed again
+  #[error("Transaction did not land by block height `{last_valid_block_height}` and expired")]
+  Expired { last_valid_block_height: u64 },
 }
 
 /// The result of a transaction
 pub type TransactionResult<T> = Result<T, TxError>;
 
 pub struct TrxBuilder<'a, S: Signer + Sized, T: Signers> {
-  rpc: Arc<RpcClient>,
+  rpc: Arc<dyn RpcSource>,
   /// ordered list of instructions
   ixs: Vec<Instruction>,
   /// use legacy transaction mode
@@ -60,13 +62,15 @@ pub struct TrxBuilder<'a, S: Signer + Sized, T: Signers> {
   prior_fee_added: bool,
   pub read_only: bool,
   pub retry_until_confirmed: bool,
+  /// Confirm over the geyser stream instead of polling RPC
+  confirmations: Option<ConfirmationTracker>,
   payer: &'a S,
   signers: T,
 }
 
 impl<'a, S: Signer + Sized, T: Signers> TrxBuilder<'a, S, T> {
   pub fn new(
-    rpc: Arc<RpcClient>,
+    rpc: Arc<dyn RpcSource>,
     legacy: bool,
     lookup_tables: Vec<AddressLookupTableAccount>,
     payer: &'a S,
@@ -80,6 +84,7 @@ impl<'a, S: Signer + Sized, T: Signers> TrxBuilder<'a, S, T> {
       prior_fee_added: false,
       read_only: false,
       retry_until_confirmed: false,
+      confirmations: None,
       payer,
       signers,
     }
@@ -104,6 +109,11 @@ impl<'a, S: Signer + Sized, T: Signers> TrxBuilder<'a, S, T> {
     self
   }
 
+  pub fn confirmations(mut self, tracker: ConfirmationTracker) -> Self {
+    self.confirmations = Some(tracker);
+    self
+  }
+
   pub fn is_empty(&self) -> bool {
     if self.ixs().is_empty() {
       true
@@ -171,7 +181,15 @@ impl<'a, S: Signer + Sized, T: Signers> TrxBuilder<'a, S, T> {
 
   /// Build the transaction message ready for signing and sending
   pub async fn build(&self) -> anyhow::Result<VersionedTransaction> {
-    let bh = self.rpc.get_latest_blockhash().await?;
+    Ok(self.build_with_expiry().await?.0)
+  }
+
+  /// [`TrxBuilder::build`] with the last block height the transaction can land in
+  pub async fn build_with_expiry(&self) -> anyhow::Result<(VersionedTransaction, u64)> {
+    let (bh, last_valid_block_height) = self
+      .rpc
+      .get_latest_blockhash_with_commitment(CommitmentConfig::confirmed())
+      .await?;
     let msg = if self.legacy {
       VersionedMessage::Legacy(Message::new_with_blockhash(
         self.ixs.as_ref(),
@@ -187,7 +205,7 @@ impl<'a, S: Signer + Sized, T: Signers> TrxBuilder<'a, S, T> {
       )?)
     };
     let tx = VersionedTransaction::try_new(msg, &self.signers)?;
-    Ok(tx)
+    Ok((tx, last_valid_block_height))
   }
 
   pub async fn compute_units(&mut self) -> anyhow::Result<u32> {
@@ -236,25 +254,37 @@ impl<'a, S: Signer + Sized, T: Signers> TrxBuilder<'a, S, T> {
         let res = self.simulate(priority_key).await?.value;
         info!("Simulation: {:#?}", res);
       } else {
-        let res = self.send(priority_key, cu_limit).await?;
-        if let Err(e) = &res.1 {
-          log::error!("Failed to confirm transaction: {:#?}", e);
-          if self.retry_until_confirmed {
-            let mut retries = 0;
-            while retries < 10 {
-              let res = self.send(priority_key, cu_limit).await?;
-              if res.1.is_ok() {
-                break;
-              }
+        let mut res = self.send(priority_key, cu_limit).await?;
+        let mut retries = 0;
+        // only an expired transaction can't land anymore, so signing it again can't execute twice
+        while self.retry_until_confirmed && retries < 10 {
+          match &res.1 {
+            Err(TxError::Expired { .. }) => {
+              log::warn!("Transaction {} expired, signing again", res.0);
+              res = self.send(priority_key, cu_limit).await?;
               retries += 1;
             }
+            _ => break,
           }
         }
+        if let Err(e) = &res.1 {
+          log::error!("Failed to confirm transaction: {:#?}", e);
+        }
       }
     }
     Ok(())
   }
 
+  fn send_config() -> RpcSendTransactionConfig {
+    RpcSendTransactionConfig {
+      skip_preflight: true,
+      max_retries: Some(0),
+      preflight_commitment: Some(CommitmentLevel::Confirmed),
+      ..Default::default()
+    }
+  }
+
+  /// Sign and send the transaction, and resend it until it lands or expires
   pub async fn send(
     &mut self,
     prior_fee_key: Pubkey,
@@ -277,42 +307,34 @@ impl<'a, S: Signer + Sized, T: Signers> TrxBuilder<'a, S, T> {
         .await?;
     }
 
-    let config = RpcSendTransactionConfig {
-      skip_preflight: true,
-      max_retries: Some(0),
-      preflight_commitment: Some(CommitmentLevel::Confirmed),
-      ..Default::default()
-    };
-
-    let tx = self.build().await?;
+    let (tx, last_valid_block_height) = self.build_with_expiry().await?;
+    // watch before sending so the stream can't see the transaction first
+    let pending = self
+      .confirmations
+      .as_ref()
+      .map(|c| c.watch(tx.signatures[0]));
 
     let now = std::time::Instant::now();
-    // todo: monitor our transactions with grpc, and break loop if cache has confirmed our transaction
-    let sig = match self.rpc.send_transaction_with_config(&tx, config).await {
+    let sig = match self
+      .rpc
+      .send_transaction_with_config(&tx, Self::send_config())
+      .await
+    {
       Ok(sig) => Ok(sig),
       Err(e) => {
         log::error!("Failed to send transaction: {:#?}", e);
-        Err(anyhow::anyhow!(e))
+        Err(e)
       }
     }?;
-    for _ in 0..10 {
-      let rpc = self.rpc.clone();
-      let tx = tx.clone();
-      tokio::task::spawn(async move {
-        match rpc.send_transaction_with_config(&tx, config).await {
-          Ok(sig) => Ok(sig),
-          Err(e) => {
-            log::error!("Failed to send transaction: {:#?}", e);
-            Err(anyhow::anyhow!(e))
-          }
-        }?;
-        Result::<_, anyhow::Error>::Ok(())
-      });
-    }
     Self::log_tx(&sig);
 
     let res = self
-      .confirm(sig, ConfirmTransactionConfig::default())
+      .confirm(
+        &tx,
+        last_valid_block_height,
+        pending,
+        ConfirmTransactionConfig::default(),
+      )
       .await?;
     if res.is_ok() {
       log::warn!("Transaction confirmed in {:?}", now.elapsed());
@@ -346,72 +368,175 @@ impl<'a, S: Signer + Sized, T: Signers> TrxBuilder<'a, S, T> {
     }
   }
 
+  /// Wait for the transaction on the geyser stream, or over RPC while the stream is unavailable,
+  /// and resend it every [`ConfirmTransactionConfig::rebroadcast_rate`] until it lands or the
+  /// block height passes `last_valid_block_height`
   async fn confirm(
     &self,
-    sig: Signature,
+    tx: &VersionedTransaction,
+    last_valid_block_height: u64,
+    mut pending: Option<PendingTx>,
     config: ConfirmTransactionConfig,
   ) -> anyhow::Result<TransactionResult<Slot>> {
-    let mut result: Option<TransactionResult<Slot>> = None;
+    let sig = tx.signatures[0];
     let mut interval = tokio::time::interval(config.loop_rate);
     interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
-    let mut not_resolved = true;
-    let mut iterations = 0;
-    while not_resolved && iterations < config.max_confirmation_checks {
-      interval.tick().await;
-      let res = self
-        .rpc
-        .get_signature_statuses(vec![sig].as_slice())
-        .await?;
-      let status = res
-        .value
-        .first()
-        .ok_or(anyhow::anyhow!("Failed to get signature from response"))?
-        .clone();
-      match status {
-        Some(TransactionStatus {
-          slot,
-          err,
-          confirmation_status: Some(confirmation_status),
-          ..
-        }) if Self::confirmation_at_least(&config.min_confirmation, &confirmation_status) => {
-          result = Some(match err {
-            None => Ok(slot),
-            Some(error) => {
-              let tx: EncodedConfirmedTransactionWithStatusMeta = match self
-                .rpc
-                .get_transaction_with_config(
-                  &sig,
-                  RpcTransactionConfig {
-                    encoding: None,
-                    commitment: Some(Self::tc_into_commitment(&config.min_confirmation)),
-                    max_supported_transaction_version: Some(0),
-                  },
-                )
-                .await
-              {
-                Ok(res) => Ok(res),
-                Err(_) => Err(TxError::Dropped),
-              }?;
-              Err(TxError::TxError {
-                slot,
-                error,
-                logs: tx
-                  .transaction
-                  .meta
-                  .and_then(|meta| meta.log_messages.into()),
-              })
-            }
-          });
-          not_resolved = false;
+    let mut last_broadcast = Instant::now();
+    loop {
+      let streamed = match pending.as_mut() {
+        Some(pending) => pending.next(config.loop_rate).await,
+        None => Streamed::Unavailable,
+      };
+      match streamed {
+        Streamed::Landed(res) => {
+          let commitment = pending.as_ref().and_then(PendingTx::commitment);
+          if commitment.is_some_and(|c| Self::confirmation_at_least(&config.min_confirmation, &c)) {
+            return Ok(res);
+          }
+          // landed below `min_confirmation`, so wait for it to get there over RPC
+          pending = None;
+        }
+        Streamed::Pending => {}
+        Streamed::Unavailable => {
+          if pending.take().is_some() {
+            log::warn!("Geyser stream unavailable, confirming {} over RPC", sig);
+          }
+          interval.tick().await;
+          if let Some(res) = self.signature_status(&sig, &config).await? {
+            return Ok(res);
+          }
+        }
+      }
+
+      if last_broadcast.elapsed() < config.rebroadcast_rate {
+        continue;
+      }
+      if self.rpc.get_block_height().await? > last_valid_block_height {
+        // blocks up to the expiry are confirmed by now, so one not confirmed yet never lands
+        let confirmed = ConfirmTransactionConfig {
+          min_confirmation: TransactionConfirmationStatus::Confirmed,
+          ..config
+        };
+        let res = match self.signature_status(&sig, &confirmed).await? {
+          Some(res) => res,
+          None => {
+            return Ok(Err(TxError::Expired {
+              last_valid_block_height,
+            }))
+          }
+        };
+        if Self::confirmation_at_least(
+          &config.min_confirmation,
+          &TransactionConfirmationStatus::Confirmed,
+        ) {
+          return Ok(res);
+        }
+        // landed for good, but below `min_confirmation`, so wait for it without resending
+        loop {
+          interval.tick().await;
+          if let Some(res) = self.signature_status(&sig, &config).await? {
+            return Ok(res);
+          }
         }
-        _ => {}
       }
-      iterations += 1;
+      if let Err(e) = self
+        .rpc
+        .send_transaction_with_config(tx, Self::send_config())
+        .await
+      {
+        log::error!("Failed to resend transaction: {:#?}", e);
+      }
+      last_broadcast = Instant::now();
     }
-    Ok(if result.is_none() {
-      Err(TxError::Dropped)
-    } else {
-      result.ok_or(anyhow::anyhow!("Failed to define transaction state"))?
-    })
+  }
+
+  /// Result of the transaction once it reaches [`ConfirmTransactionConfig::min_confirmation`]
+  async fn signature_status(
+    &self,
+    sig: &Signature,
+    config: &ConfirmTransactionConfig,
+  ) -> anyhow::Result<Option<TransactionResult<Slot>>> {
+    let res = self.rpc.get_signature_statuses(&[*sig]).await?;
+    let status = res
+      .value
+      .first()
+      .ok_or(anyhow::anyhow!("Failed to get signature from response"))?
+      .clone();
+    let (slot, err) = match status {
+      Some(TransactionStatus {
+        slot,
+        err,
+        confirmation_status: Some(confirmation_status),
+        ..
+      }) if Self::confirmation_at_least(&config.min_confirmation, &confirmation_status) => {
+        (slot, err)
+      }
+      _ => return Ok(None),
+    };
+    let error = match err {
+      None => return Ok(Some(Ok(slot))),
+      Some(error) => error,
+    };
+    let tx: EncodedConfirmedTransactionWithStatusMeta = match self
+      .rpc
+      .get_transaction_with_config(
+        sig,
+        RpcTransactionConfig {
+          encoding: None,
+          commitment: Some(Self::tc_into_commitment(&config.min_confirmation)),
+          max_supported_transaction_version: Some(0),
+        },
+      )
+      .await
+    {
+      Ok(tx) => tx,
+      Err(_) => return Ok(Some(Err(TxError::Dropped))),
+    };
+    Ok(Some(Err(TxError::TxError {
+      slot,
+      error,
+      logs: tx
+        .transaction
+        .meta
+        .and_then(|meta| meta.log_messages.into()),
+    })))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::FakeRpc;
+  use solana_sdk::clock::MAX_PROCESSING_AGE;
+  use solana_sdk::system_instruction;
+  use std::time::Duration;
+
+  #[tokio::test(start_paused = true)]
+  async fn rebroadcast_until_expired() -> anyhow::Result<()> {
+    let rpc = Arc::new(FakeRpc::new().slot(10).drop_transactions());
+    let payer = Keypair::new();
+    let mut trx = KeypairTrx::new(rpc.clone(), false, vec![], &payer, vec![&payer]).with_ixs(vec![
+      system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1),
+    ]);
+
+    let expire = rpc.clone();
+    // the clock is paused, so this advances as soon as the sender waits
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(1000)).await;
+      expire.set_slot(1000);
+    });
+    let (sig, res) = trx.send(Pubkey::new_unique(), Some(200_000)).await?;
+    assert!(matches!(
+      res,
+      Err(TxError::Expired {
+        last_valid_block_height
+      }) if last_valid_block_height == 10 + MAX_PROCESSING_AGE as u64
+    ));
+
+    // the same signed transaction is resent, never signed again
+    let sent = rpc.sent();
+    assert!(sent.len() > 1);
+    assert!(sent.iter().all(|tx| tx.signatures[0] == sig));
+    Ok(())
   }
 }
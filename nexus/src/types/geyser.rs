@@ -1,20 +1,47 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use solana_sdk::clock::Slot;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Signature;
-use yellowstone_grpc_proto::prelude::{CommitmentLevel, SubscribeRequest, SubscribeRequestFilterAccounts, SubscribeRequestFilterBlocksMeta, SubscribeRequestFilterSlots, SubscribeRequestFilterTransactions};
+use yellowstone_grpc_proto::prelude::{
+  CommitmentLevel, SubscribeRequest, SubscribeRequestFilterAccounts,
+  SubscribeRequestFilterBlocksMeta, SubscribeRequestFilterSlots,
+  SubscribeRequestFilterTransactions,
+};
 
 use crate::Time;
 
+/// What [`crate::NexusClient::stream`] does with account updates that matched a named filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FilterRoute {
+  /// Insert the account into the [`crate::drift_client::Cache`]
+  Cache,
+  /// Insert Drift `User` accounts into the [`crate::drift_client::Orderbook`]
+  Orderbook,
+}
+
+impl FilterRoute {
+  /// Routes for updates whose filter has no explicit route
+  pub fn all() -> HashSet<FilterRoute> {
+    HashSet::from([FilterRoute::Cache, FilterRoute::Orderbook])
+  }
+}
+
+/// Geyser subscription with any number of named filters per category.
+/// Each update reports the names of the filters it matched, which are used to route it.
 #[derive(Clone)]
 pub struct GeyserConfig {
   pub grpc: String,
   pub x_token: String,
-  pub slots: Option<SubscribeRequestFilterSlots>,
-  pub accounts: Option<SubscribeRequestFilterAccounts>,
-  pub transactions: Option<SubscribeRequestFilterTransactions>,
-  pub blocks_meta: Option<SubscribeRequestFilterBlocksMeta>,
+  pub slots: HashMap<String, SubscribeRequestFilterSlots>,
+  pub accounts: HashMap<String, SubscribeRequestFilterAccounts>,
+  pub transactions: HashMap<String, SubscribeRequestFilterTransactions>,
+  pub blocks_meta: HashMap<String, SubscribeRequestFilterBlocksMeta>,
+  /// Routes per account filter name, filters without an entry use [`FilterRoute::all`]
+  pub routes: HashMap<String, HashSet<FilterRoute>>,
   pub commitment: CommitmentLevel,
 }
 
@@ -23,37 +50,86 @@ impl Default for GeyserConfig {
     Self {
       grpc: "".to_string(),
       x_token: "".to_string(),
-      slots: Some(SubscribeRequestFilterSlots { filter_by_commitment: Some(false) }),
-      accounts: None,
-      transactions: None,
-      blocks_meta: None,
+      slots: maplit::hashmap! { "slots".to_owned() => SubscribeRequestFilterSlots { filter_by_commitment: Some(false) } },
+      accounts: HashMap::new(),
+      transactions: HashMap::new(),
+      blocks_meta: HashMap::new(),
+      routes: HashMap::new(),
       commitment: CommitmentLevel::Processed,
     }
   }
 }
 
+impl GeyserConfig {
+  /// Config with no filters
+  pub fn new(grpc: String, x_token: String, commitment: CommitmentLevel) -> Self {
+    Self {
+      grpc,
+      x_token,
+      slots: HashMap::new(),
+      accounts: HashMap::new(),
+      transactions: HashMap::new(),
+      blocks_meta: HashMap::new(),
+      routes: HashMap::new(),
+      commitment,
+    }
+  }
+
+  pub fn slots(mut self, name: &str, filter: SubscribeRequestFilterSlots) -> Self {
+    self.slots.insert(name.to_owned(), filter);
+    self
+  }
+
+  /// Add an account filter whose updates are handled by `routes`
+  pub fn accounts(
+    mut self,
+    name: &str,
+    filter: SubscribeRequestFilterAccounts,
+    routes: &[FilterRoute],
+  ) -> Self {
+    self.accounts.insert(name.to_owned(), filter);
+    self
+      .routes
+      .insert(name.to_owned(), routes.iter().cloned().collect());
+    self
+  }
+
+  pub fn transactions(mut self, name: &str, filter: SubscribeRequestFilterTransactions) -> Self {
+    self.transactions.insert(name.to_owned(), filter);
+    self
+  }
+
+  pub fn blocks_meta(mut self, name: &str, filter: SubscribeRequestFilterBlocksMeta) -> Self {
+    self.blocks_meta.insert(name.to_owned(), filter);
+    self
+  }
+
+  /// Union of the routes of every filter an update matched
+  pub fn matched_routes(&self, filters: &[String]) -> HashSet<FilterRoute> {
+    let mut routes = HashSet::new();
+    for name in filters {
+      match self.routes.get(name) {
+        Some(r) => routes.extend(r.iter().cloned()),
+        None => routes.extend(FilterRoute::all()),
+      }
+    }
+    if filters.is_empty() {
+      routes.extend(FilterRoute::all());
+    }
+    routes
+  }
+}
+
 impl From<GeyserConfig> for SubscribeRequest {
   fn from(value: GeyserConfig) -> SubscribeRequest {
     SubscribeRequest {
-      slots: match value.slots {
-        Some(filter) => maplit::hashmap! { "".to_owned() => filter },
-        None => HashMap::new()
-      },
-      accounts: match value.accounts {
-        Some(filter) => maplit::hashmap! { "".to_owned() => filter },
-        None => HashMap::new(),
-      },
-      transactions: match value.transactions {
-        Some(filter) => maplit::hashmap! { "".to_owned() => filter },
-        None => HashMap::new()
-      },
+      slots: value.slots,
+      accounts: value.accounts,
+      transactions: value.transactions,
       transactions_status: HashMap::new(),
       entry: HashMap::new(),
       blocks: HashMap::new(),
-      blocks_meta: match value.blocks_meta {
-        Some(filter) => maplit::hashmap! { "".to_owned() => filter },
-        None => HashMap::new(),
-      },
+      blocks_meta: value.blocks_meta,
       commitment: Some(value.commitment as i32),
       accounts_data_slice: vec![],
       ping: None,
@@ -75,6 +151,12 @@ pub struct TxStub {
   pub blockhash: String,
   pub signer: Pubkey,
   pub signature: Signature,
+  /// Names of the transaction filters this matched
+  pub filters: Vec<String>,
+  /// Log messages from the transaction meta
+  pub logs: Vec<String>,
+  /// The transaction executed with an error
+  pub failed: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -82,4 +164,142 @@ pub struct BlockInfo {
   pub slot: Slot,
   pub blockhash: String,
   pub time: Time,
-}
\ No newline at end of file
+}
+
+/// Connection state of a geyser stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum StreamStatus {
+  /// Subscribing, no update received yet
+  Connecting = 0,
+  /// Receiving updates and the cache is current
+  Healthy = 1,
+  /// Reconnected after a slot gap and re-fetching accounts over RPC
+  Recovering = 2,
+  /// Disconnected and waiting to reconnect
+  Down = 3,
+}
+
+impl From<u8> for StreamStatus {
+  fn from(value: u8) -> Self {
+    match value {
+      0 => StreamStatus::Connecting,
+      1 => StreamStatus::Healthy,
+      2 => StreamStatus::Recovering,
+      _ => StreamStatus::Down,
+    }
+  }
+}
+
+/// Shared handle to the health of a geyser stream.
+/// Engines should pause trading while this is not [`StreamStatus::Healthy`],
+/// since the cache is not being updated.
+#[derive(Debug, Clone)]
+pub struct StreamHealth {
+  status: Arc<AtomicU8>,
+  reconnects: Arc<AtomicU64>,
+  last_update: Arc<RwLock<Option<Instant>>>,
+}
+
+impl Default for StreamHealth {
+  fn default() -> Self {
+    Self {
+      status: Arc::new(AtomicU8::new(StreamStatus::Connecting as u8)),
+      reconnects: Arc::new(AtomicU64::new(0)),
+      last_update: Arc::new(RwLock::new(None)),
+    }
+  }
+}
+
+impl StreamHealth {
+  pub fn status(&self) -> StreamStatus {
+    StreamStatus::from(self.status.load(Ordering::Acquire))
+  }
+
+  pub fn is_healthy(&self) -> bool {
+    self.status() == StreamStatus::Healthy
+  }
+
+  /// Number of times the stream has reconnected since it was started
+  pub fn reconnects(&self) -> u64 {
+    self.reconnects.load(Ordering::Relaxed)
+  }
+
+  /// Time since the last update of any kind was received
+  pub fn since_last_update(&self) -> Option<Duration> {
+    self
+      .last_update
+      .read()
+      .ok()
+      .and_then(|t| t.map(|t| t.elapsed()))
+  }
+
+  pub(crate) fn set(&self, status: StreamStatus) {
+    self.status.store(status as u8, Ordering::Release);
+  }
+
+  pub(crate) fn touch(&self) {
+    if let Ok(mut last_update) = self.last_update.write() {
+      *last_update = Some(Instant::now());
+    }
+  }
+
+  pub(crate) fn reconnected(&self) {
+    self.reconnects.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+/// Controls how [`crate::NexusClient::stream`] reconnects after the geyser stream fails.
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+  /// Delay before the first reconnect attempt
+  pub initial_backoff: Duration,
+  /// Upper bound for the exponential backoff between attempts
+  pub max_backoff: Duration,
+  /// Give up after this many consecutive failed attempts, or retry forever if `None`
+  pub max_retries: Option<usize>,
+  /// Treat the stream as dead if no update (including pings) arrives within this window
+  pub idle_timeout: Duration,
+}
+
+impl Default for ReconnectConfig {
+  fn default() -> Self {
+    Self {
+      initial_backoff: Duration::from_millis(500),
+      max_backoff: Duration::from_secs(30),
+      max_retries: None,
+      idle_timeout: Duration::from_secs(30),
+    }
+  }
+}
+
+impl ReconnectConfig {
+  pub fn next_backoff(&self, backoff: Duration) -> Duration {
+    (backoff * 2).min(self.max_backoff)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn routes_by_filter_name() {
+    let filter = SubscribeRequestFilterAccounts::default();
+    let cfg = GeyserConfig::default()
+      .accounts("markets", filter.clone(), &[FilterRoute::Cache])
+      .accounts("users", filter, &[FilterRoute::Orderbook]);
+    let routes = |names: &[&str]| {
+      let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+      cfg.matched_routes(&names)
+    };
+
+    assert_eq!(routes(&["markets"]), HashSet::from([FilterRoute::Cache]));
+    assert_eq!(routes(&["users"]), HashSet::from([FilterRoute::Orderbook]));
+    // an update matching both filters goes to both
+    assert_eq!(routes(&["markets", "users"]), FilterRoute::all());
+    // filters without a route, or no filter name at all, go everywhere
+    assert_eq!(routes(&["unrouted"]), FilterRoute::all());
+    assert_eq!(routes(&[]), FilterRoute::all());
+  }
+}
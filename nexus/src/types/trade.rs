@@ -1,6 +1,6 @@
 #![allow(clippy::unnecessary_cast)]
 
-use crate::{trunc, Dataset, Time};
+use crate::{trunc, Data, Dataset, Time};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::HashMap;
@@ -115,18 +115,18 @@ pub enum TradeSide {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PerformanceSummary {
-  ticker: String,
-  pct_roi: f64,
-  quote_roi: f64,
-  total_trades: usize,
-  win_rate: f64,
-  avg_trade_size: f64,
-  avg_trade: f64,
-  avg_winning_trade: f64,
-  avg_losing_trade: f64,
-  best_trade: f64,
-  worst_trade: f64,
-  max_drawdown: f64,
+  pub ticker: String,
+  pub pct_roi: f64,
+  pub quote_roi: f64,
+  pub total_trades: usize,
+  pub win_rate: f64,
+  pub avg_trade_size: f64,
+  pub avg_trade: f64,
+  pub avg_winning_trade: f64,
+  pub avg_losing_trade: f64,
+  pub best_trade: f64,
+  pub worst_trade: f64,
+  pub max_drawdown: f64,
 }
 
 #[derive(Debug, Clone)]
@@ -288,6 +288,29 @@ impl Summary {
     trunc!(max_dd, 3)
   }
 
+  /// Percent below the running peak of the cumulative % ROI after each trade.
+  /// Uses the same peak logic as [`Summary::max_drawdown`], so the lowest point equals that metric.
+  pub fn drawdown(&self, ticker: &str) -> anyhow::Result<Dataset> {
+    let data = self.cum_pct(ticker)?.data();
+    let mut peak = match data.first() {
+      Some(first) => first.y,
+      None => return Ok(Dataset::new(vec![])),
+    };
+    let mut drawdown = Vec::with_capacity(data.len());
+    for point in data.iter() {
+      if point.y > peak {
+        peak = point.y;
+      }
+      let y = 1.0 + point.y / 100.0;
+      let p = 1.0 + peak / 100.0;
+      drawdown.push(Data {
+        x: point.x,
+        y: trunc!((y - p) / p * 100.0, 3),
+      });
+    }
+    Ok(Dataset::new(drawdown))
+  }
+
   pub fn avg_trade(&self, ticker: &str) -> f64 {
     let len = self.pct_per_trade.get(ticker).unwrap().data().len();
     let avg_trade = self
@@ -3,17 +3,27 @@ use solana_sdk::{
   clock::{Slot, UnixTimestamp},
   pubkey::Pubkey,
   signature::Signature,
-  transaction::Transaction,
+  transaction::VersionedTransaction,
 };
 use solana_transaction_status::TransactionConfirmationStatus;
 use std::time::Duration;
 
+use crate::Ix;
+
 pub struct TrxData {
-  pub tx: Transaction,
+  pub tx: VersionedTransaction,
   pub signature: Signature,
   pub signer: Pubkey,
+  /// Static account keys followed by the writable and readonly addresses loaded from lookup tables
+  pub account_keys: Vec<Pubkey>,
+  /// Instructions with every account resolved
+  pub ixs: Vec<Ix>,
   pub slot: Slot,
   pub block_time: UnixTimestamp,
+  /// Log messages from the transaction meta
+  pub logs: Vec<String>,
+  /// The transaction executed with an error
+  pub failed: bool,
 }
 
 pub struct SignatureInfo {
@@ -22,15 +32,15 @@ pub struct SignatureInfo {
   pub formatted_time_since: String,
 }
 
-/// Config for [`RpcClientExt::confirm_transactions_with_config`]
+/// Config for confirming a transaction sent by [`crate::TrxBuilder::send`]
 #[derive(Debug)]
 pub struct ConfirmTransactionConfig {
   /// How often to check for confirmations
   pub loop_rate: Duration,
   /// The minimum confirmation status to wait for
   pub min_confirmation: TransactionConfirmationStatus,
-  /// This number of iterations * loop_rate = total duration to wait for confirmation
-  pub max_confirmation_checks: u16,
+  /// How often to resend the same signed transaction until it confirms or its blockhash expires
+  pub rebroadcast_rate: Duration,
 }
 
 impl Default for ConfirmTransactionConfig {
@@ -38,7 +48,7 @@ impl Default for ConfirmTransactionConfig {
     Self {
       loop_rate: Duration::from_millis(200),
       min_confirmation: TransactionConfirmationStatus::Confirmed,
-      max_confirmation_checks: 100,
+      rebroadcast_rate: Duration::from_millis(400),
     }
   }
 }
@@ -0,0 +1,111 @@
+use std::str::FromStr;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use solana_sdk::pubkey::Pubkey;
+
+const PROGRAM_DATA: &str = "Program data: ";
+const LOG_TRUNCATED: &str = "Log truncated";
+
+/// Decoded `Program data:` log line and the program invocation that emitted it
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogData {
+  /// Program executing when the line was logged
+  pub program: Pubkey,
+  /// Index of the top level instruction the invocation belongs to
+  pub ix_index: usize,
+  /// Invoke depth, 1 for top level instructions and greater for CPIs
+  pub depth: usize,
+  pub data: Vec<u8>,
+}
+
+/// Walks transaction log messages and attributes each `Program data:` line to the program
+/// on top of the invoke stack, so events from CPIs are not confused with their caller.
+/// Parsing stops at `Log truncated`, lines after that are lost by the validator.
+pub fn parse_log_data(logs: &[String]) -> Vec<LogData> {
+  let mut stack: Vec<Pubkey> = vec![];
+  let mut ix_index: Option<usize> = None;
+  let mut res = vec![];
+  for log in logs {
+    if log.starts_with(LOG_TRUNCATED) {
+      break;
+    }
+    if let Some(data) = log.strip_prefix(PROGRAM_DATA) {
+      if let Some(program) = stack.last() {
+        // multiple base64 chunks are space separated
+        for chunk in data.split_whitespace() {
+          if let Ok(data) = general_purpose::STANDARD.decode(chunk) {
+            res.push(LogData {
+              program: *program,
+              ix_index: ix_index.unwrap_or(0),
+              depth: stack.len(),
+              data,
+            });
+          }
+        }
+      }
+      continue;
+    }
+    let mut words = log.split_whitespace();
+    if words.next() != Some("Program") {
+      continue;
+    }
+    let program = match words.next().map(Pubkey::from_str) {
+      Some(Ok(program)) => program,
+      _ => continue,
+    };
+    match words.next() {
+      Some("invoke") => {
+        if stack.is_empty() {
+          ix_index = Some(ix_index.map_or(0, |i| i + 1));
+        }
+        stack.push(program);
+      }
+      Some("success") | Some("failed:") => {
+        stack.pop();
+      }
+      _ => {}
+    }
+  }
+  res
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn attributes_data_to_invocation() {
+    let outer = Pubkey::new_unique();
+    let inner = Pubkey::new_unique();
+    let encode = |b: &[u8]| general_purpose::STANDARD.encode(b);
+    let logs: Vec<String> = vec![
+      "Program ComputeBudget111111111111111111111111111111 invoke [1]".to_string(),
+      "Program ComputeBudget111111111111111111111111111111 success".to_string(),
+      format!("Program {} invoke [1]", outer),
+      format!("Program data: {}", encode(&[1, 2])),
+      format!("Program {} invoke [2]", inner),
+      format!("Program data: {}", encode(&[3])),
+      format!("Program {} consumed 100 of 200 compute units", inner),
+      format!("Program {} success", inner),
+      format!("Program data: {} {}", encode(&[4]), encode(&[5])),
+      format!("Program {} success", outer),
+      "Log truncated".to_string(),
+      format!("Program data: {}", encode(&[6])),
+    ];
+    let res = parse_log_data(&logs);
+    let summary: Vec<(Pubkey, usize, usize, Vec<u8>)> = res
+      .into_iter()
+      .map(|d| (d.program, d.ix_index, d.depth, d.data))
+      .collect();
+    assert_eq!(
+      summary,
+      vec![
+        (outer, 1, 1, vec![1, 2]),
+        (inner, 1, 2, vec![3]),
+        (outer, 1, 1, vec![4]),
+        (outer, 1, 1, vec![5]),
+      ]
+    );
+  }
+}
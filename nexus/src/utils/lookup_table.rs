@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::message::v0::{LoadedAddresses, MessageAddressTableLookup};
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::RwLock;
+
+use crate::{deserialize_lookup_table, RpcSource};
+
+/// Address lookup tables fetched over RPC, used to resolve the accounts of v0 transactions
+/// when the transaction meta does not include its loaded addresses.
+#[derive(Clone, Default)]
+pub struct LookupTableCache {
+  tables: Arc<RwLock<HashMap<Pubkey, AddressLookupTableAccount>>>,
+}
+
+impl LookupTableCache {
+  /// Cached table if it has at least `min_len` addresses, otherwise fetch it since tables can be extended
+  pub async fn get(
+    &self,
+    rpc: &dyn RpcSource,
+    key: &Pubkey,
+    min_len: usize,
+  ) -> anyhow::Result<AddressLookupTableAccount> {
+    if let Some(table) = self.tables.read().await.get(key) {
+      if table.addresses.len() >= min_len {
+        return Ok(table.clone());
+      }
+    }
+    let account = rpc.get_account(key).await?;
+    let table = deserialize_lookup_table(*key, &account)?;
+    if table.addresses.len() < min_len {
+      return Err(anyhow::anyhow!(
+        "Lookup table {} has {} addresses, expected at least {}",
+        key,
+        table.addresses.len(),
+        min_len
+      ));
+    }
+    self.tables.write().await.insert(*key, table.clone());
+    Ok(table)
+  }
+
+  /// Resolve the writable and readonly addresses a v0 message loads from its lookup tables
+  pub async fn load_addresses(
+    &self,
+    rpc: &dyn RpcSource,
+    lookups: &[MessageAddressTableLookup],
+  ) -> anyhow::Result<LoadedAddresses> {
+    let mut loaded = LoadedAddresses::default();
+    for lookup in lookups {
+      let min_len = lookup
+        .writable_indexes
+        .iter()
+        .chain(lookup.readonly_indexes.iter())
+        .max()
+        .map_or(0, |i| *i as usize + 1);
+      let table = self.get(rpc, &lookup.account_key, min_len).await?;
+      loaded.writable.extend(
+        lookup
+          .writable_indexes
+          .iter()
+          .map(|i| table.addresses[*i as usize]),
+      );
+      loaded.readonly.extend(
+        lookup
+          .readonly_indexes
+          .iter()
+          .map(|i| table.addresses[*i as usize]),
+      );
+    }
+    Ok(loaded)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{FakeRpc, NexusClient};
+  use base64::engine::general_purpose;
+  use base64::Engine;
+  use solana_sdk::account::Account;
+  use solana_sdk::hash::Hash;
+  use solana_sdk::instruction::{AccountMeta, Instruction};
+  use solana_sdk::message::{v0, VersionedMessage};
+  use solana_sdk::signature::{Keypair, Signature};
+  use solana_sdk::signer::Signer;
+  use solana_sdk::transaction::VersionedTransaction;
+  use solana_transaction_status::{
+    EncodedTransaction, EncodedTransactionWithStatusMeta, TransactionBinaryEncoding,
+  };
+
+  /// Lookup table account data, addresses follow the 56 byte table meta
+  fn table_account(addresses: &[Pubkey]) -> Account {
+    let mut data = vec![0; 56];
+    for address in addresses {
+      data.extend(address.to_bytes());
+    }
+    Account {
+      lamports: 1,
+      data,
+      owner: solana_sdk::address_lookup_table::program::id(),
+      executable: false,
+      rent_epoch: 0,
+    }
+  }
+
+  #[tokio::test]
+  async fn resolve_v0_account_keys() -> anyhow::Result<()> {
+    let payer = Keypair::new();
+    let program = Pubkey::new_unique();
+    let (table, writable, readonly) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let addresses = vec![readonly, Pubkey::new_unique(), writable];
+    let rpc = FakeRpc::new().account(table, table_account(&addresses));
+
+    let ix = Instruction::new_with_bytes(
+      program,
+      &[1],
+      vec![
+        AccountMeta::new_readonly(readonly, false),
+        AccountMeta::new(writable, false),
+        AccountMeta::new(payer.pubkey(), true),
+      ],
+    );
+    let msg = v0::Message::try_compile(
+      &payer.pubkey(),
+      &[ix],
+      &[AddressLookupTableAccount {
+        key: table,
+        addresses,
+      }],
+      Hash::new_unique(),
+    )?;
+    let tx = VersionedTransaction::try_new(VersionedMessage::V0(msg), &[&payer])?;
+    let encoded = EncodedTransactionWithStatusMeta {
+      transaction: EncodedTransaction::Binary(
+        general_purpose::STANDARD.encode(bincode::serialize(&tx)?),
+        TransactionBinaryEncoding::Base64,
+      ),
+      // no loaded addresses in the meta, so they are read from the table
+      meta: None,
+      version: None,
+    };
+
+    let lookup_tables = LookupTableCache::default();
+    let data = NexusClient::trx_data(
+      &rpc,
+      &lookup_tables,
+      Signature::new_unique(),
+      1,
+      None,
+      encoded,
+    )
+    .await?
+    .unwrap();
+    // static keys, then writable and readonly keys loaded from the table
+    assert_eq!(
+      data.account_keys,
+      vec![payer.pubkey(), program, writable, readonly]
+    );
+    assert_eq!(data.ixs[0].program, program);
+    assert_eq!(
+      data.ixs[0].accounts,
+      vec![readonly, writable, payer.pubkey()]
+    );
+
+    // indexes past the end of the table can't be resolved
+    let lookup = MessageAddressTableLookup {
+      account_key: table,
+      writable_indexes: vec![3],
+      readonly_indexes: vec![],
+    };
+    assert!(lookup_tables.load_addresses(&rpc, &[lookup]).await.is_err());
+    Ok(())
+  }
+}
@@ -4,11 +4,15 @@ pub use env::*;
 pub use kagi::*;
 pub use keypair::*;
 pub use logger::*;
+pub use logs::*;
+pub use lookup_table::*;
 pub use plot::*;
+pub use rate_limit::*;
 pub use ring_buffer::*;
 pub use ring_map::*;
 pub use serde::*;
 pub use strings::*;
+pub use swap_cell::*;
 pub use time::*;
 pub use timer::*;
 
@@ -18,11 +22,15 @@ pub mod env;
 pub mod kagi;
 pub mod keypair;
 pub mod logger;
+pub mod logs;
+pub mod lookup_table;
 pub mod macros;
 pub mod plot;
+pub mod rate_limit;
 pub mod ring_buffer;
 pub mod ring_map;
 pub mod serde;
 pub mod strings;
+pub mod swap_cell;
 pub mod time;
 pub mod timer;
@@ -1,4 +1,4 @@
-use crate::Data;
+use crate::{Data, Dataset};
 use plotters::prelude::*;
 use plotters::style::full_palette::*;
 use plotters::style::{BLACK, WHITE};
@@ -474,6 +474,224 @@ impl Plot {
     Ok(())
   }
 
+  /// Underwater curve of percent below the running equity peak, such as [`crate::Summary::drawdown`].
+  pub fn plot_drawdown(
+    data: &[Data],
+    out_file: &str,
+    title: &str,
+    x_label: &str,
+  ) -> anyhow::Result<()> {
+    let (min_x, max_x) = x_bounds(data.iter())?;
+    let min_y = data.iter().map(|d| d.y).fold(-1.0, f64::min);
+
+    let root = BitMapBackend::new(out_file, (2048, 1024)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+      .set_all_label_area_size(150)
+      .margin(20)
+      .caption(title, ("sans-serif", 40.0).into_font())
+      .build_cartesian_2d(min_x..max_x, min_y..0.0)?;
+
+    chart
+      .configure_mesh()
+      .light_line_style(WHITE)
+      .label_style(("sans-serif", 30, &BLACK).into_text_style(&root))
+      .x_desc(x_label)
+      .y_desc("% Drawdown")
+      .y_labels(10)
+      .y_label_formatter(&|y| format!("{:.2}", y))
+      .draw()?;
+
+    chart
+      .draw_series(
+        AreaSeries::new(data.iter().map(|d| (d.x, d.y)), 0.0, SECOND.mix(0.3)).border_style(SECOND),
+      )
+      .map_err(|e| anyhow::anyhow!("Failed to draw series: {}", e))?;
+
+    root
+      .present()
+      .map_err(|e| anyhow::anyhow!("Failed to present root: {}", e))?;
+
+    Ok(())
+  }
+
+  /// Histogram of values split into `bins` equal width buckets, such as the % return of each trade.
+  /// Buckets at or above zero are green and buckets below zero are red.
+  pub fn plot_histogram(
+    values: &[f64],
+    bins: usize,
+    out_file: &str,
+    title: &str,
+    x_label: &str,
+  ) -> anyhow::Result<()> {
+    let (min, width, counts) = histogram_bins(values, bins)?;
+    let max = min + width * bins as f64;
+    let max_count = counts.iter().copied().max().unwrap_or(0);
+
+    let root = BitMapBackend::new(out_file, (2048, 1024)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+      .set_all_label_area_size(150)
+      .margin(20)
+      .caption(title, ("sans-serif", 40.0).into_font())
+      .build_cartesian_2d(min..max, 0_u32..max_count + 1)?;
+
+    chart
+      .configure_mesh()
+      .light_line_style(WHITE)
+      .label_style(("sans-serif", 30, &BLACK).into_text_style(&root))
+      .x_desc(x_label)
+      .y_desc("Count")
+      .x_label_formatter(&|x| format!("{:.2}", x))
+      .draw()?;
+
+    chart
+      .draw_series(counts.iter().enumerate().map(|(i, count)| {
+        let lo = min + i as f64 * width;
+        let hi = lo + width;
+        let color = match lo >= 0.0 {
+          true => THIRD,
+          false => SECOND,
+        };
+        Rectangle::new([(lo, 0), (hi, *count)], color.filled())
+      }))
+      .map_err(|e| anyhow::anyhow!("Failed to draw histogram: {}", e))?;
+
+    root
+      .present()
+      .map_err(|e| anyhow::anyhow!("Failed to present root: {}", e))?;
+
+    Ok(())
+  }
+
+  /// Rolling Sharpe ratio (top) and rolling volatility (bottom) of a return series over a trailing window,
+  /// such as [`crate::Summary::pct_per_trade`].
+  pub fn plot_rolling_metrics(
+    returns: &Dataset,
+    window: usize,
+    out_file: &str,
+    title: &str,
+    x_label: &str,
+  ) -> anyhow::Result<()> {
+    let sharpe = returns.rolling_sharpe(window);
+    let volatility = returns.rolling_std(window);
+    if sharpe.is_empty() {
+      return Err(anyhow::anyhow!(
+        "Window {} is longer than {} returns",
+        window,
+        returns.len()
+      ));
+    }
+
+    let root = BitMapBackend::new(out_file, (2048, 1536)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let root = root.titled(title, ("sans-serif", 40.0).into_font())?;
+    let panels = root.split_evenly((2, 1));
+
+    let charts = [
+      (
+        &panels[0],
+        &sharpe,
+        format!("{} Rolling Sharpe", window),
+        FIRST,
+      ),
+      (
+        &panels[1],
+        &volatility,
+        format!("{} Rolling Volatility", window),
+        FIFTH,
+      ),
+    ];
+    for (area, dataset, y_label, color) in charts {
+      let (min_x, max_x) = x_bounds(dataset.data().iter())?;
+      let (min_y, max_y) = y_bounds(dataset.data().iter());
+      let mut chart = ChartBuilder::on(area)
+        .set_all_label_area_size(150)
+        .margin(20)
+        .build_cartesian_2d(min_x..max_x, min_y..max_y)?;
+
+      chart
+        .configure_mesh()
+        .light_line_style(WHITE)
+        .label_style(("sans-serif", 30, &BLACK).into_text_style(area))
+        .x_desc(x_label)
+        .y_desc(y_label)
+        .y_labels(10)
+        .y_label_formatter(&|y| format!("{:.2}", y))
+        .draw()?;
+
+      chart
+        .draw_series(LineSeries::new(
+          dataset.data().iter().map(|d| (d.x, d.y)),
+          color.stroke_width(2),
+        ))
+        .map_err(|e| anyhow::anyhow!("Failed to draw series: {}", e))?;
+    }
+
+    root
+      .present()
+      .map_err(|e| anyhow::anyhow!("Failed to present root: {}", e))?;
+
+    Ok(())
+  }
+
+  /// Scatter of (parameter, metric) points, such as z-score threshold against % ROI from a parameter sweep.
+  pub fn plot_scatter(
+    points: &[(f64, f64)],
+    out_file: &str,
+    title: &str,
+    y_label: &str,
+    x_label: &str,
+  ) -> anyhow::Result<()> {
+    if points.is_empty() {
+      return Err(anyhow::anyhow!("Scatter requires at least one point"));
+    }
+    let pad = |min: f64, max: f64| match max == min {
+      true => (min - 1.0, max + 1.0),
+      false => (min - (max - min) * 0.05, max + (max - min) * 0.05),
+    };
+    let (min_x, max_x) = pad(
+      points.iter().map(|p| p.0).fold(f64::MAX, f64::min),
+      points.iter().map(|p| p.0).fold(f64::MIN, f64::max),
+    );
+    let (min_y, max_y) = pad(
+      points.iter().map(|p| p.1).fold(f64::MAX, f64::min),
+      points.iter().map(|p| p.1).fold(f64::MIN, f64::max),
+    );
+
+    let root = BitMapBackend::new(out_file, (2048, 1024)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+      .set_all_label_area_size(150)
+      .margin(20)
+      .caption(title, ("sans-serif", 40.0).into_font())
+      .build_cartesian_2d(min_x..max_x, min_y..max_y)?;
+
+    chart
+      .configure_mesh()
+      .light_line_style(WHITE)
+      .label_style(("sans-serif", 30, &BLACK).into_text_style(&root))
+      .x_desc(x_label)
+      .y_desc(y_label)
+      .x_label_formatter(&|x| format!("{:.2}", x))
+      .y_label_formatter(&|y| format!("{:.2}", y))
+      .draw()?;
+
+    chart
+      .draw_series(
+        points
+          .iter()
+          .map(|(x, y)| Circle::new((*x, *y), 5, FIFTH.mix(0.6).filled())),
+      )
+      .map_err(|e| anyhow::anyhow!("Failed to draw series: {}", e))?;
+
+    root
+      .present()
+      .map_err(|e| anyhow::anyhow!("Failed to present root: {}", e))?;
+
+    Ok(())
+  }
+
   pub fn red() -> RGBColor {
     RED_A400
   }
@@ -513,3 +731,68 @@ impl Plot {
     colors[rand::random::<usize>() % colors.len()]
   }
 }
+
+/// Minimum, bucket width and count per bucket of `values` split into `bins` equal width buckets.
+/// The maximum value falls in the last bucket.
+fn histogram_bins(values: &[f64], bins: usize) -> anyhow::Result<(f64, f64, Vec<u32>)> {
+  if values.is_empty() || bins == 0 {
+    return Err(anyhow::anyhow!(
+      "Histogram requires values and at least one bin"
+    ));
+  }
+  let min = values.iter().copied().fold(f64::MAX, f64::min);
+  let mut max = values.iter().copied().fold(f64::MIN, f64::max);
+  if max == min {
+    max = min + 1.0;
+  }
+  let width = (max - min) / bins as f64;
+  let mut counts = vec![0_u32; bins];
+  for v in values {
+    let i = (((v - min) / width) as usize).min(bins - 1);
+    counts[i] += 1;
+  }
+  Ok((min, width, counts))
+}
+
+fn x_bounds<'a>(data: impl Iterator<Item = &'a Data>) -> anyhow::Result<(i64, i64)> {
+  let (min_x, max_x) = data.fold((i64::MAX, i64::MIN), |(min, max), d| {
+    (min.min(d.x), max.max(d.x))
+  });
+  match min_x.cmp(&max_x) {
+    std::cmp::Ordering::Greater => Err(anyhow::anyhow!("No data to plot")),
+    std::cmp::Ordering::Equal => Ok((min_x, max_x + 1)),
+    std::cmp::Ordering::Less => Ok((min_x, max_x)),
+  }
+}
+
+fn y_bounds<'a>(data: impl Iterator<Item = &'a Data>) -> (f64, f64) {
+  let (min_y, max_y) = data.fold((f64::MAX, f64::MIN), |(min, max), d| {
+    (min.min(d.y), max.max(d.y))
+  });
+  match min_y == max_y {
+    true => (min_y - 1.0, max_y + 1.0),
+    false => (min_y, max_y),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn histogram_bin_edges() -> anyhow::Result<()> {
+    let (min, width, counts) = histogram_bins(&[-1.0, 0.0, 0.5, 1.0, 3.0], 4)?;
+    assert_eq!((min, width), (-1.0, 1.0));
+    // lower edges are inclusive and the max lands in the last bucket
+    assert_eq!(counts, vec![1, 2, 1, 1]);
+
+    // identical values span a single unit wide range
+    let (min, width, counts) = histogram_bins(&[2.0, 2.0], 2)?;
+    assert_eq!((min, width), (2.0, 0.5));
+    assert_eq!(counts, vec![2, 0]);
+
+    assert!(histogram_bins(&[], 2).is_err());
+    assert!(histogram_bins(&[1.0], 0).is_err());
+    Ok(())
+  }
+}
@@ -0,0 +1,51 @@
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+/// Token bucket shared by concurrent tasks to stay under an RPC provider's request limit.
+/// Holds up to `burst` tokens and refills at `per_second`, each request takes one token.
+#[derive(Debug)]
+pub struct TokenBucket {
+  burst: f64,
+  per_second: f64,
+  state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+  tokens: f64,
+  last: Instant,
+}
+
+impl TokenBucket {
+  pub fn new(per_second: f64, burst: usize) -> Self {
+    let burst = burst.max(1) as f64;
+    Self {
+      burst,
+      per_second: per_second.max(f64::MIN_POSITIVE),
+      state: Mutex::new(BucketState {
+        tokens: burst,
+        last: Instant::now(),
+      }),
+    }
+  }
+
+  /// Wait until a token is available and take it
+  pub async fn acquire(&self) {
+    loop {
+      let wait = {
+        let mut state = self.state.lock().await;
+        let now = Instant::now();
+        let refill = now.duration_since(state.last).as_secs_f64() * self.per_second;
+        state.tokens = (state.tokens + refill).min(self.burst);
+        state.last = now;
+        if state.tokens >= 1.0 {
+          state.tokens -= 1.0;
+          return;
+        }
+        Duration::from_secs_f64((1.0 - state.tokens) / self.per_second)
+      };
+      tokio::time::sleep(wait).await;
+    }
+  }
+}
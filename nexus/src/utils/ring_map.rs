@@ -1,10 +1,23 @@
+use std::sync::Arc;
+
 use indexmap::IndexMap;
 
+/// Values are shared, so cloning the ring to publish a new version of the cache
+/// doesn't copy them.
 pub struct RingMap<K, V> {
-  map: IndexMap<K, V>,
+  map: IndexMap<K, Arc<V>>,
   capacity: usize,
 }
 
+impl<K: Clone, V> Clone for RingMap<K, V> {
+  fn clone(&self) -> Self {
+    Self {
+      map: self.map.clone(),
+      capacity: self.capacity,
+    }
+  }
+}
+
 impl<K, V> RingMap<K, V>
 where
   K: Eq + std::hash::Hash + Clone,
@@ -19,6 +32,7 @@ where
 
   /// Insert a key-value pair into the ring buffer
   pub fn insert(&mut self, key: K, value: V) {
+    let value = Arc::new(value);
     if self.map.contains_key(&key) {
       // If the key already exists, just update the value
       self.map.insert(key, value);
@@ -34,10 +48,10 @@ where
   }
 
   pub fn get(&self, key: &K) -> Option<&V> {
-    self.map.get(key)
+    self.map.get(key).map(Arc::as_ref)
   }
 
-  pub fn remove(&mut self, key: &K) -> Option<V> {
+  pub fn remove(&mut self, key: &K) -> Option<Arc<V>> {
     self.map.shift_remove(key)
   }
 
@@ -50,14 +64,14 @@ where
   }
 
   pub fn newest(&self) -> Option<(&K, &V)> {
-    self.map.last()
+    self.map.last().map(|(k, v)| (k, v.as_ref()))
   }
 
   pub fn values(&self) -> impl Iterator<Item = &V> {
-    self.map.values()
+    self.map.values().map(Arc::as_ref)
   }
 
   pub fn key_values(&self) -> impl Iterator<Item = (&K, &V)> {
-    self.map.iter()
+    self.map.iter().map(|(k, v)| (k, v.as_ref()))
   }
 }
@@ -0,0 +1,96 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use tokio::sync::{Mutex, MutexGuard};
+
+/// Value readers load without waiting, while one writer at a time edits a copy and publishes it.
+/// Writes are only cheap if cloning `T` shares most of its data with the previous version.
+pub struct SwapCell<T> {
+  current: ArcSwap<T>,
+  writer: Mutex<()>,
+}
+
+impl<T: Clone> SwapCell<T> {
+  pub fn new(value: T) -> Self {
+    Self {
+      current: ArcSwap::from_pointee(value),
+      writer: Mutex::new(()),
+    }
+  }
+
+  /// Latest published version, later writes don't change it
+  pub fn load(&self) -> Arc<T> {
+    self.current.load_full()
+  }
+
+  /// Waits for other writers, readers keep loading the previous version until the guard is dropped
+  pub async fn write(&self) -> SwapWriteGuard<'_, T> {
+    let writer = self.writer.lock().await;
+    SwapWriteGuard {
+      _writer: writer,
+      current: &self.current,
+      next: Some(T::clone(&self.current.load())),
+    }
+  }
+}
+
+impl<T: Clone + Default> Default for SwapCell<T> {
+  fn default() -> Self {
+    Self::new(T::default())
+  }
+}
+
+/// Copy of a [`SwapCell`] value that is published when dropped
+pub struct SwapWriteGuard<'a, T> {
+  _writer: MutexGuard<'a, ()>,
+  current: &'a ArcSwap<T>,
+  next: Option<T>,
+}
+
+impl<T> Deref for SwapWriteGuard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    self
+      .next
+      .as_ref()
+      .expect("SwapWriteGuard already published")
+  }
+}
+
+impl<T> DerefMut for SwapWriteGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    self
+      .next
+      .as_mut()
+      .expect("SwapWriteGuard already published")
+  }
+}
+
+impl<T> Drop for SwapWriteGuard<'_, T> {
+  fn drop(&mut self) {
+    if let Some(next) = self.next.take() {
+      self.current.store(Arc::new(next));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn readers_see_writes_once_published() {
+    let cell = SwapCell::new(vec![1]);
+    let before = cell.load();
+    {
+      let mut next = cell.write().await;
+      next.push(2);
+      // readers never wait on the writer and see the last published version
+      assert_eq!(*cell.load(), vec![1]);
+    }
+    assert_eq!(*cell.load(), vec![1, 2]);
+    assert_eq!(*before, vec![1]);
+  }
+}
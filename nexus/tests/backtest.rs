@@ -290,3 +290,34 @@ fn test_backtest() -> anyhow::Result<()> {
 
   Ok(())
 }
+
+#[test]
+fn test_html_report() -> anyhow::Result<()> {
+  use nexus::*;
+
+  let ticker = "TEST".to_string();
+  let strat = TestBacktest::new(ticker.clone());
+  let mut backtest = Backtest::builder(strat)
+    .fee(1.0)
+    .bet(Bet::Percent(100.0))
+    .short_selling(true);
+  backtest.series.insert(ticker.clone(), DATASET.to_vec());
+
+  let summary = backtest.backtest()?;
+  let html = HtmlReport::new(&summary, &ticker, "Test Backtest").render()?;
+
+  assert!(html.contains("<svg"));
+  assert!(html.contains("Monthly Returns"));
+  assert!(!html.contains("<script"));
+  // one row per trade plus the header
+  let trades = summary.trades(&ticker)?.len();
+  let table = html.split("<div class=\"trades\">").nth(1).unwrap();
+  assert_eq!(table.matches("<tr>").count(), trades + 1);
+  // drawdown never exceeds the reported max drawdown
+  let max_dd = summary.max_drawdown(&ticker);
+  for point in summary.drawdown(&ticker)?.data() {
+    assert!(point.y >= max_dd);
+  }
+
+  Ok(())
+}
@@ -20,6 +20,7 @@ pub struct Config {
   pub pct_max_spread: f64,
   pub pct_min_spread: f64,
   pub pct_take_profit: f64,
+  pub snapshot: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -34,6 +35,8 @@ struct YamlConfig {
   pub pct_max_spread: f64,
   pub pct_min_spread: f64,
   pub pct_take_profit: f64,
+  #[serde(default)]
+  pub snapshot: Option<String>,
 }
 
 impl Config {
@@ -75,6 +78,7 @@ impl Config {
       pct_min_spread: yaml.pct_min_spread,
       stop_loss_is_maker: yaml.stop_loss_is_maker,
       pct_take_profit: yaml.pct_take_profit,
+      snapshot: yaml.snapshot.map(|name| PathBuf::from(&dir).join(name)),
     })
   }
 }
@@ -1,6 +1,5 @@
 #![allow(dead_code)]
 
-use std::collections::HashSet;
 use std::ops::Neg;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -31,11 +30,12 @@ pub struct Engine {
   read_only: bool,
   retry_until_confirmed: bool,
   pub signer: Arc<Keypair>,
-  pub rpc: Arc<RpcClient>,
+  pub rpc: Arc<dyn RpcSource>,
   pub drift: DriftClient,
   pub market: MarketId,
   pub cache: Cache,
   pub orderbook: Orderbook,
+  pub health: StreamHealth,
   pct_spread_brackets: Vec<f64>,
   pct_stop_loss: f64,
   leverage: f64,
@@ -50,12 +50,32 @@ impl Engine {
     sub_account_id: u16,
     market: MarketId,
     cache_depth: Option<usize>,
+  ) -> anyhow::Result<Self> {
+    let config = Config::read()?;
+    let rpc = Arc::new(RpcClient::new_with_timeout(
+      config.rpc_url.clone(),
+      Duration::from_secs(90),
+    ));
+    let geyser = Arc::new(GrpcClient::new(GeyserConfig::new(
+      config.grpc.clone(),
+      config.x_token.clone(),
+      CommitmentLevel::Processed,
+    )));
+    Self::with_sources(config, sub_account_id, market, cache_depth, rpc, geyser).await
+  }
+
+  pub async fn with_sources(
+    config: Config,
+    sub_account_id: u16,
+    market: MarketId,
+    cache_depth: Option<usize>,
+    rpc: Arc<dyn RpcSource>,
+    geyser: Arc<dyn GeyserSource>,
   ) -> anyhow::Result<Self> {
     let Config {
       read_only,
       retry_until_confirmed,
       signer,
-      rpc_url,
       grpc,
       x_token,
       pct_spread_brackets,
@@ -65,23 +85,42 @@ impl Engine {
       pct_min_spread,
       stop_loss_is_maker,
       pct_take_profit,
+      snapshot: snapshot_path,
       ..
-    } = Config::read()?;
+    } = config;
 
     // 200 slots = 80 seconds of account cache
     let cache_depth = cache_depth.unwrap_or(200);
     let signer = Arc::new(signer);
     info!("Engine using wallet: {}", signer.pubkey());
-    let rpc = Arc::new(RpcClient::new_with_timeout(
-      rpc_url,
-      Duration::from_secs(90),
-    ));
     let now = Instant::now();
-    let users = DriftUtils::users(&rpc).await?;
-    let orderbook = Orderbook::new(vec![market], &users).await?;
+    let snapshot = match &snapshot_path {
+      Some(path) => Snapshot::load(path)?,
+      None => None,
+    };
+    // a snapshot taken for other markets has none of our orders
+    let snapshot = snapshot.filter(|snapshot| {
+      let has_market = snapshot.has_markets(&[market]);
+      if !has_market {
+        warn!("ignoring snapshot without market {:?}", market);
+      }
+      has_market
+    });
+    // warm start from the snapshot, the stream reconciles accounts changed since
+    let (cache, orderbook, users) = match &snapshot {
+      Some(snapshot) => {
+        info!("warm start from snapshot at slot {}", snapshot.slot);
+        (snapshot.cache(), snapshot.orderbook(&[market])?, None)
+      }
+      None => {
+        let users = DriftUtils::users(&rpc).await?;
+        let orderbook = Orderbook::new(vec![market], &users).await?;
+        (Cache::new(cache_depth), orderbook, Some(users))
+      }
+    };
     info!("orderbook loaded in {:?}", now.elapsed());
 
-    let this = Self {
+    let mut this = Self {
       read_only,
       retry_until_confirmed,
       drift: DriftClient::new(
@@ -95,8 +134,9 @@ impl Engine {
       .await?,
       rpc,
       signer,
-      cache: Cache::new(cache_depth),
+      cache,
       orderbook,
+      health: StreamHealth::default(),
       market,
       pct_spread_brackets,
       pct_stop_loss,
@@ -108,25 +148,32 @@ impl Engine {
     };
 
     let account_filter = this.account_filter(users).await?;
-    let mut filter = HashSet::new();
-    for a in account_filter {
-      filter.insert(a);
-    }
-    let cfg = this.orderbook_geyser_config(grpc, x_token)?;
+    let cfg = this.orderbook_geyser_config(grpc, x_token, account_filter)?;
     // stream updates from gRPC
-    let nexus = NexusClient::new(cfg)?;
+    let mut nexus = NexusClient::with_source(cfg, geyser).rpc(this.rpc());
+    if let Some(snapshot) = &snapshot {
+      nexus = nexus.resume_from(snapshot.slot);
+    }
+    this.health = nexus.health();
     let cache = this.cache.clone();
     let orderbook = this.orderbook.clone();
     tokio::task::spawn(async move {
-      nexus
-        .stream(&cache, None, Some(&orderbook), Some(filter))
-        .await?;
+      nexus.stream(&cache, None, Some(&orderbook), None).await?;
       Result::<_, anyhow::Error>::Ok(())
     });
+    if let Some(path) = snapshot_path {
+      Snapshot::save_every(
+        path,
+        Duration::from_secs(60),
+        this.cache.clone(),
+        Some(this.orderbook.clone()),
+        this.health.clone(),
+      );
+    }
     Ok(this)
   }
 
-  pub fn rpc(&self) -> Arc<RpcClient> {
+  pub fn rpc(&self) -> Arc<dyn RpcSource> {
     self.rpc.clone()
   }
   pub async fn cache(&self) -> ReadCache {
@@ -139,32 +186,60 @@ impl Engine {
     self.orderbook.read().await
   }
 
-  fn orderbook_geyser_config(&self, grpc: String, x_token: String) -> anyhow::Result<GeyserConfig> {
-    let drift_program_id = id();
-    Ok(GeyserConfig {
-      grpc,
-      x_token,
-      slots: Some(SubscribeRequestFilterSlots {
-        filter_by_commitment: Some(true),
-      }),
-      accounts: Some(SubscribeRequestFilterAccounts {
-        account: vec![],
-        owner: vec![drift_program_id.to_string(), PYTH_PROGRAM_ID.to_string()],
-        filters: vec![],
-      }),
-      transactions: None,
-      blocks_meta: None,
-      commitment: CommitmentLevel::Processed,
-    })
+  fn orderbook_geyser_config(
+    &self,
+    grpc: String,
+    x_token: String,
+    account_filter: Vec<Pubkey>,
+  ) -> anyhow::Result<GeyserConfig> {
+    Ok(
+      GeyserConfig::new(grpc, x_token, CommitmentLevel::Processed)
+        .slots(
+          "slots",
+          SubscribeRequestFilterSlots {
+            filter_by_commitment: Some(true),
+          },
+        )
+        // markets, oracles and our user
+        .accounts(
+          "accounts",
+          SubscribeRequestFilterAccounts {
+            account: account_filter.into_iter().map(|k| k.to_string()).collect(),
+            owner: vec![],
+            filters: vec![],
+          },
+          &[FilterRoute::Cache],
+        )
+        // maker users are read from the cache for take profit orders
+        .accounts(
+          "users",
+          SubscribeRequestFilterAccounts {
+            account: vec![],
+            owner: vec![id().to_string()],
+            filters: vec![DriftUtils::grpc_users_filter()],
+          },
+          &[FilterRoute::Cache, FilterRoute::Orderbook],
+        ),
+    )
   }
 
   pub async fn start(&mut self) -> anyhow::Result<()> {
     self.drift.setup_user().await?;
     self.reset(true).await?;
     let run = AtomicBool::new(true);
+    let mut user_changes = self.cache.watch::<User>(*self.user());
 
     let mut last_update = Instant::now();
     while run.load(Ordering::Relaxed) {
+      // cache is stale while the geyser stream is down or recovering
+      if let Err(e) = self
+        .drift
+        .ready_to_trade(&self.health, &self.cache().await, &[self.market])
+      {
+        warn!("🟡 {}, pause trading", e);
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        continue;
+      }
       let mut did_act = false;
       let user = self
         .cache()
@@ -395,12 +470,53 @@ impl Engine {
         self.reset(true).await?;
         last_update = Instant::now();
       }
-      tokio::time::sleep(Duration::from_millis(400)).await;
+      // react to fills as soon as they stream in, otherwise check prices every 400ms
+      tokio::select! {
+        Some(change) = user_changes.next() => {
+          for event in change.events() {
+            match event {
+              UserEvent::OrderFilled(o) if self.is_market(&o) => {
+                info!("🔵 order {} filled", o.order_id);
+                last_update = Instant::now();
+              }
+              UserEvent::OrderPartiallyFilled { order, filled } if self.is_market(&order) => {
+                info!(
+                  "🔵 order {} filled {}",
+                  order.order_id,
+                  DriftUtils::base_to_f64(filled)
+                );
+                last_update = Instant::now();
+              }
+              // remaining brackets would reopen a position a take profit closed
+              UserEvent::PositionClosed(pos) if MarketId::perp(pos.market_index) == self.market => {
+                if self.has_open_orders(&change.new.decoded) {
+                  info!("🔵 position closed, cancel remaining brackets");
+                  self.reset(false).await?;
+                }
+                last_update = Instant::now();
+              }
+              _ => {}
+            }
+          }
+        }
+        _ = tokio::time::sleep(Duration::from_millis(400)) => {}
+      }
     }
 
     Ok(())
   }
 
+  fn is_market(&self, order: &Order) -> bool {
+    MarketId::from((order.market_index, order.market_type)) == self.market
+  }
+
+  fn has_open_orders(&self, user: &User) -> bool {
+    user
+      .orders
+      .iter()
+      .any(|o| self.is_market(o) && o.base_asset_amount != 0)
+  }
+
   fn blank_order(order: Option<&Order>) -> bool {
     match order {
       Some(o) => o.base_asset_amount == 0,
@@ -470,10 +586,10 @@ impl Engine {
       .await
       .l3(&self.market, &self.cache().await)?;
 
-    let quote_balance = self
-      .drift
-      .quote_balance(self.market, &self.cache().await, None)?;
-    let total_quote = quote_balance * self.leverage;
+    let total_quote =
+      self
+        .drift
+        .buying_power(self.market, self.leverage, &self.cache().await, None)?;
 
     let num_orders = self.pct_spread_brackets.len() * 2;
     let min_base = total_quote / price / num_orders as f64;
@@ -543,26 +659,31 @@ impl Engine {
     Ok(orders)
   }
 
-  /// Stream these accounts from geyser for usage in the engine
+  /// Stream these accounts from geyser for usage in the engine.
+  /// Loads the cache with `users` unless it was restored from a snapshot.
   pub async fn account_filter(
     &self,
-    users: Vec<DecodedAcctCtx<User>>,
+    users: Option<Vec<DecodedAcctCtx<User>>>,
   ) -> anyhow::Result<Vec<Pubkey>> {
     // accounts to subscribe to
     let perps = DriftUtils::perp_markets(&self.rpc()).await?;
     let spots = DriftUtils::spot_markets(&self.rpc()).await?;
     let perp_markets: Vec<Pubkey> = perps.iter().map(|p| p.key).collect();
     let spot_markets: Vec<Pubkey> = spots.iter().map(|s| s.key).collect();
-    let user_keys: Vec<Pubkey> = users.iter().map(|u| u.key).collect();
+    // all other users are streamed by the "users" memcmp filter
+    let user_keys = [*self.user()];
     let perp_oracles: Vec<Pubkey> = perps.iter().map(|p| p.decoded.amm.oracle).collect();
     let spot_oracles: Vec<Pubkey> = spots.iter().map(|s| s.decoded.oracle).collect();
-    let auths = [self.signer.pubkey()];
-    self
-      .cache
-      .write()
-      .await
-      .load_with_all_users(&self.rpc(), Some(users), None, &auths)
-      .await?;
+    // a cache restored from a snapshot is already loaded
+    if let Some(users) = users {
+      let auths = [self.signer.pubkey()];
+      self
+        .cache
+        .write()
+        .await
+        .load_with_all_users(&self.rpc(), Some(users), None, &auths)
+        .await?;
+    }
     let keys = perp_markets
       .iter()
       .chain(spot_markets.iter())
@@ -656,3 +777,90 @@ impl Engine {
       .await
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nexus::drift_client::fixtures::*;
+
+  #[tokio::test]
+  async fn brackets_around_oracle() -> anyhow::Result<()> {
+    let signer = Keypair::new();
+    let maker = Pubkey::new_unique();
+    // $2 book spread around a $100 oracle
+    let rpc = Arc::new(
+      FakeRpc::new()
+        .drift_markets(100.0)
+        .drift_user(&funded_user(&signer.pubkey(), 0, 1_000.0, &[]))
+        .drift_user(&funded_user(
+          &maker,
+          0,
+          1_000.0,
+          &[
+            perp_order(1, PositionDirection::Long, 99.0, 1.0),
+            perp_order(2, PositionDirection::Short, 101.0, 1.0),
+          ],
+        )),
+    );
+    let config = Config {
+      read_only: false,
+      retry_until_confirmed: false,
+      signer,
+      rpc_url: String::new(),
+      grpc: String::new(),
+      x_token: String::new(),
+      stop_loss_is_maker: false,
+      pct_spread_brackets: vec![50.0, 100.0],
+      pct_stop_loss: 1.0,
+      leverage: 1.0,
+      pct_max_spread: 1.0,
+      pct_min_spread: 0.2,
+      pct_take_profit: 0.01,
+      snapshot: None,
+    };
+    let market = MarketId::perp(0);
+    let engine = Engine::with_sources(
+      config,
+      0,
+      market,
+      None,
+      rpc.clone(),
+      Arc::new(FakeGeyser::new()),
+    )
+    .await?;
+
+    // the book spread is capped at 1% of the price, and $1000 of buying power is split across 4 orders
+    let orders = engine.build_orders().await?;
+    let quotes: Vec<(bool, f64, f64)> = orders
+      .iter()
+      .map(|o| {
+        (
+          matches!(o.direction, PositionDirection::Long),
+          DriftUtils::price_to_f64(o.price),
+          DriftUtils::base_to_f64(o.base_asset_amount),
+        )
+      })
+      .collect();
+    assert_eq!(
+      quotes,
+      vec![
+        (true, 99.75, 2.5),
+        (false, 100.25, 2.5),
+        (true, 99.5, 2.5),
+        (false, 100.5, 2.5),
+      ]
+    );
+    assert!(orders
+      .iter()
+      .all(|o| matches!(o.post_only, PostOnlyParam::MustPostOnly)));
+
+    let mut trx = engine.new_tx();
+    engine.place_orders(orders, &mut trx).await?;
+    trx.send_tx(id(), None).await?;
+    match rpc.sent_drift_ixs()?.as_slice() {
+      [InstructionType::PlaceOrders(ix)] => assert_eq!(ix._params.len(), 4),
+      ixs => panic!("Expected one PlaceOrders instruction, got {}", ixs.len()),
+    }
+    Ok(())
+  }
+}
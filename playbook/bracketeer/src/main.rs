@@ -16,23 +16,29 @@ async fn main() -> anyhow::Result<()> {
   Ok(())
 }
 
-#[cfg(tests)]
+#[cfg(test)]
 mod tests {
   use super::*;
+  use log::info;
+  use nexus::drift_client::{BidAsk, Cache, DriftUtils, Orderbook};
   use solana_client::nonblocking::rpc_client::RpcClient;
+  use solana_sdk::pubkey::Pubkey;
+  use solana_sdk::signer::Signer;
   use std::collections::HashSet;
+  use std::sync::Arc;
   use yellowstone_grpc_proto::prelude::{
     CommitmentLevel, SubscribeRequestFilterAccounts, SubscribeRequestFilterSlots,
   };
 
   #[tokio::test]
+  #[ignore = "requires config.yaml, RPC_URL and GRPC"]
   async fn bid_ask_prices() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
     init_logger();
 
     // SOL-PERP
     let market = MarketId::perp(0);
-    let baker = Bracketeer::new(0, market, None).await?;
+    let baker = Engine::new(0, market, None).await?;
 
     let now = std::time::Instant::now();
     while now.elapsed() < std::time::Duration::from_secs(60 * 10) {
@@ -61,14 +67,13 @@ mod tests {
   }
 
   #[tokio::test]
+  #[ignore = "requires RPC_URL and GRPC"]
   async fn orderbook() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
     init_logger();
 
     let market_id = MarketId::perp(0);
-    let baker = Bracketeer::new(0, market_id, None).await?;
     let cache = Cache::new(200);
-    let orderbook = Orderbook::new(vec![market_id]);
     let grpc = std::env::var("GRPC")?;
     let x_token = std::env::var("X_TOKEN")?;
     let signer = read_keypair_from_env("SIGNER")?;
@@ -110,26 +115,27 @@ mod tests {
       .await?;
     info!("time to load cache: {:?}", now.elapsed());
     let now = std::time::Instant::now();
-    orderbook.write().await.load(users)?;
+    let orderbook = Orderbook::new(vec![market_id], &users).await?;
     info!("time to load orderbook: {:?}", now.elapsed());
 
-    let cfg = GeyserConfig {
-      grpc,
-      x_token,
-      slots: Some(SubscribeRequestFilterSlots {
-        filter_by_commitment: Some(true),
-      }),
-      accounts: Some(SubscribeRequestFilterAccounts {
-        account: vec![],
-        owner: vec![drift_cpi::id().to_string(), PYTH_PROGRAM_ID.to_string()],
-        filters: vec![],
-      }),
-      transactions: None,
-      blocks_meta: None,
-      commitment: CommitmentLevel::Processed,
-    };
+    let cfg = GeyserConfig::new(grpc, x_token, CommitmentLevel::Processed)
+      .slots(
+        "slots",
+        SubscribeRequestFilterSlots {
+          filter_by_commitment: Some(true),
+        },
+      )
+      .accounts(
+        "accounts",
+        SubscribeRequestFilterAccounts {
+          account: vec![],
+          owner: vec![drift_cpi::id().to_string(), PYTH_PROGRAM_ID.to_string()],
+          filters: vec![],
+        },
+        &[FilterRoute::Cache, FilterRoute::Orderbook],
+      );
     // stream updates from gRPC
-    let nexus = NexusClient::new(cfg)?;
+    let nexus = NexusClient::new(cfg)?.rpc(Arc::new(rpc));
 
     let _orderbook = orderbook.clone();
     let _cache = cache.clone();
@@ -629,6 +629,18 @@ fn optimize_entropy_1d_backtest() -> anyhow::Result<()> {
   }
   println!("optimized backtest in {}s", timer.seconds());
 
+  let zscore_roi = summaries
+    .iter()
+    .filter_map(|params| params.zscore.map(|zscore| (zscore, params.pct_roi)))
+    .collect::<Vec<_>>();
+  Plot::plot_scatter(
+    &zscore_roi,
+    &format!("entropy_{}_zscore_roi.png", timeframe),
+    "Z-Score vs ROI",
+    "% ROI",
+    "Z-Score Threshold",
+  )?;
+
   // top 3 roi
   {
     summaries.sort_by(|a, b| b.pct_roi.partial_cmp(&a.pct_roi).unwrap_or(Ordering::Equal));
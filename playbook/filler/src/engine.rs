@@ -0,0 +1,481 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::*;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+use tokio::time::Instant;
+use yellowstone_grpc_proto::prelude::{
+  CommitmentLevel, SubscribeRequestFilterAccounts, SubscribeRequestFilterSlots,
+};
+
+use crate::config::Config;
+use nexus::drift_client::*;
+use nexus::*;
+
+/// Taker order that crosses a maker's resting order
+struct Fill {
+  taker: Pubkey,
+  order: Order,
+  maker: Pubkey,
+}
+
+pub struct Engine {
+  read_only: bool,
+  retry_until_confirmed: bool,
+  pub signer: Arc<Keypair>,
+  pub rpc: Arc<dyn RpcSource>,
+  pub drift: DriftClient,
+  pub market: MarketId,
+  pub cache: Cache,
+  pub orderbook: Orderbook,
+  pub health: StreamHealth,
+  retry_after: Duration,
+  /// Last attempt to fill or trigger each user's order
+  attempts: Mutex<HashMap<(Pubkey, u32), Instant>>,
+}
+
+impl Engine {
+  pub async fn new(
+    sub_account_id: u16,
+    market: MarketId,
+    cache_depth: Option<usize>,
+  ) -> anyhow::Result<Self> {
+    let config = Config::read()?;
+    let rpc = Arc::new(RpcClient::new_with_timeout(
+      config.rpc_url.clone(),
+      Duration::from_secs(90),
+    ));
+    let geyser = Arc::new(GrpcClient::new(GeyserConfig::new(
+      config.grpc.clone(),
+      config.x_token.clone(),
+      CommitmentLevel::Processed,
+    )));
+    Self::with_sources(config, sub_account_id, market, cache_depth, rpc, geyser).await
+  }
+
+  pub async fn with_sources(
+    config: Config,
+    sub_account_id: u16,
+    market: MarketId,
+    cache_depth: Option<usize>,
+    rpc: Arc<dyn RpcSource>,
+    geyser: Arc<dyn GeyserSource>,
+  ) -> anyhow::Result<Self> {
+    let Config {
+      read_only,
+      retry_until_confirmed,
+      signer,
+      grpc,
+      x_token,
+      retry_secs,
+      ..
+    } = config;
+    // spot orders are filled by a different instruction with its own accounts
+    if let MarketType::Spot = market.kind {
+      return Err(anyhow::anyhow!(
+        "Filler only fills perp markets, got spot market {}",
+        market.index
+      ));
+    }
+
+    // 200 slots = 80 seconds of account cache
+    let cache_depth = cache_depth.unwrap_or(200);
+    let signer = Arc::new(signer);
+    info!("Engine using wallet: {}", signer.pubkey());
+    let confirmations = ConfirmationTracker::default();
+    let now = Instant::now();
+    let users = DriftUtils::users(&rpc).await?;
+    let orderbook = Orderbook::new(vec![market], &users).await?;
+    info!("orderbook loaded in {:?}", now.elapsed());
+
+    let mut this = Self {
+      read_only,
+      retry_until_confirmed,
+      drift: DriftClient::new(
+        signer.clone(),
+        rpc.clone(),
+        sub_account_id,
+        None,
+        read_only,
+        retry_until_confirmed,
+      )
+      .await?
+      .confirmations(confirmations.clone()),
+      rpc,
+      signer,
+      cache: Cache::new(cache_depth),
+      orderbook,
+      health: StreamHealth::default(),
+      market,
+      retry_after: Duration::from_secs(retry_secs),
+      attempts: Mutex::new(HashMap::new()),
+    };
+
+    let account_filter = this.account_filter(users).await?;
+    let cfg = this.orderbook_geyser_config(grpc, x_token, account_filter)?;
+    // stream updates from gRPC
+    let nexus = NexusClient::with_source(cfg, geyser)
+      .rpc(this.rpc())
+      .confirmations(confirmations);
+    this.health = nexus.health();
+    let cache = this.cache.clone();
+    let orderbook = this.orderbook.clone();
+    tokio::task::spawn(async move {
+      nexus.stream(&cache, None, Some(&orderbook), None).await?;
+      Result::<_, anyhow::Error>::Ok(())
+    });
+    Ok(this)
+  }
+
+  pub fn rpc(&self) -> Arc<dyn RpcSource> {
+    self.rpc.clone()
+  }
+  pub async fn cache(&self) -> ReadCache {
+    self.cache.read().await
+  }
+  pub fn user(&self) -> &Pubkey {
+    &self.drift.sub_account
+  }
+  pub async fn orderbook(&self) -> ReadOrderbook {
+    self.orderbook.read().await
+  }
+
+  fn orderbook_geyser_config(
+    &self,
+    grpc: String,
+    x_token: String,
+    account_filter: Vec<Pubkey>,
+  ) -> anyhow::Result<GeyserConfig> {
+    Ok(
+      GeyserConfig::new(grpc, x_token, CommitmentLevel::Processed)
+        .slots(
+          "slots",
+          SubscribeRequestFilterSlots {
+            filter_by_commitment: Some(true),
+          },
+        )
+        // our own transactions, to confirm them as soon as they land
+        .transactions(
+          "confirmations",
+          ConfirmationTracker::geyser_filter(&self.signer.pubkey()),
+        )
+        // markets, oracles and our user
+        .accounts(
+          "accounts",
+          SubscribeRequestFilterAccounts {
+            account: account_filter.into_iter().map(|k| k.to_string()).collect(),
+            owner: vec![],
+            filters: vec![],
+          },
+          &[FilterRoute::Cache],
+        )
+        // taker and maker users are read from the cache to fill and trigger their orders
+        .accounts(
+          "users",
+          SubscribeRequestFilterAccounts {
+            account: vec![],
+            owner: vec![id().to_string()],
+            filters: vec![DriftUtils::grpc_users_filter()],
+          },
+          &[FilterRoute::Cache, FilterRoute::Orderbook],
+        ),
+    )
+  }
+
+  pub async fn start(self) -> anyhow::Result<()> {
+    self.drift.setup_user().await?;
+
+    let this = Arc::new(self);
+    loop {
+      tokio::time::sleep(Duration::from_millis(400)).await;
+      // the orderbook is stale while the geyser stream is down or recovering
+      if let Err(e) = this
+        .drift
+        .ready_to_trade(&this.health, &this.cache().await, &[this.market])
+      {
+        warn!("🟡 {}, pause filling", e);
+        continue;
+      }
+
+      let (fills, triggers) = match this.fillable().await {
+        Ok(res) => res,
+        Err(e) => {
+          debug!("nothing to fill, {}", e);
+          continue;
+        }
+      };
+      if let Ok(mut attempts) = this.attempts.lock() {
+        attempts.retain(|_, at| at.elapsed() < this.retry_after);
+      }
+
+      // a send waits for the transaction to land, so each runs in its own task.
+      // Another filler may have been first, so a failure is not fatal
+      for fill in fills {
+        if !this.attempt(fill.taker, fill.order.order_id) {
+          continue;
+        }
+        let this = this.clone();
+        tokio::task::spawn(async move {
+          if let Err(e) = this.fill(&fill).await {
+            error!("Failed to fill order {}: {:?}", fill.order.order_id, e);
+          }
+        });
+      }
+      for trigger in triggers {
+        if !this.attempt(trigger.user, trigger.order.order_id) {
+          continue;
+        }
+        let this = this.clone();
+        tokio::task::spawn(async move {
+          if let Err(e) = this.trigger(&trigger).await {
+            error!(
+              "Failed to trigger order {}: {:?}",
+              trigger.order.order_id, e
+            );
+          }
+        });
+      }
+    }
+  }
+
+  /// Record an attempt on the order, false if it was attempted within `retry_after`
+  fn attempt(&self, user: Pubkey, order_id: u32) -> bool {
+    let mut attempts = match self.attempts.lock() {
+      Ok(attempts) => attempts,
+      Err(_) => return false,
+    };
+    if attempts.contains_key(&(user, order_id)) {
+      return false;
+    }
+    attempts.insert((user, order_id), Instant::now());
+    true
+  }
+
+  /// Crossing orders in the market and untriggered orders the oracle has crossed
+  async fn fillable(&self) -> anyhow::Result<(Vec<Fill>, Vec<TriggerOrder>)> {
+    let orderbook = self.orderbook().await;
+    let cache = self.cache().await;
+    // a book with one side empty has nothing crossing, but its triggers still fire
+    let fills = match orderbook.l3(&self.market, &cache) {
+      Ok(l3) => l3
+        .crossing_orders()
+        .into_iter()
+        .map(|c| Fill {
+          taker: c.taker.user,
+          order: c.taker.order,
+          maker: c.maker.user,
+        })
+        .collect(),
+      Err(e) => {
+        debug!("no crossing orders, {}", e);
+        vec![]
+      }
+    };
+    let oracle_price = DriftUtils::oracle_price(&self.market, &cache, None)?;
+    let triggers = orderbook
+      .triggers(&self.market)?
+      .triggerable(oracle_price)
+      .into_iter()
+      .cloned()
+      .collect();
+    Ok((fills, triggers))
+  }
+
+  async fn fill(&self, fill: &Fill) -> anyhow::Result<()> {
+    let mut trx = self.new_tx();
+    {
+      let cache = self.cache().await;
+      let taker_user = cache.decoded_account::<User>(&fill.taker, None)?.decoded;
+      let maker_user = cache.decoded_account::<User>(&fill.maker, None)?.decoded;
+      let taker = TakerInfo {
+        taker: fill.taker,
+        taker_user_stats: DriftUtils::user_stats_pda(&taker_user.authority),
+        taker_user,
+        order: fill.order,
+      };
+      let maker = MakerInfo {
+        maker: fill.maker,
+        maker_user_stats: DriftUtils::user_stats_pda(&maker_user.authority),
+        maker_user,
+      };
+      self
+        .drift
+        .fill_perp_order_ix(&cache, &taker, &[maker], &mut trx)
+        .await?;
+    }
+    info!(
+      "🟢 fill order {} of {} against {}",
+      fill.order.order_id,
+      shorten_address(&fill.taker),
+      shorten_address(&fill.maker)
+    );
+    // bid the priority fee of the market other fillers compete in
+    trx.send_tx(self.market.key(), None).await
+  }
+
+  async fn trigger(&self, trigger: &TriggerOrder) -> anyhow::Result<()> {
+    let mut trx = self.new_tx();
+    {
+      let cache = self.cache().await;
+      let user = cache.decoded_account::<User>(&trigger.user, None)?.decoded;
+      self
+        .drift
+        .trigger_order_ix(&cache, &trigger.user, &user, &trigger.order, &mut trx)
+        .await?;
+    }
+    info!(
+      "🟢 trigger order {} of {} at {}",
+      trigger.order.order_id,
+      shorten_address(&trigger.user),
+      trunc!(trigger.trigger_price, 4)
+    );
+    trx.send_tx(self.market.key(), None).await
+  }
+
+  /// Stream these accounts from geyser for usage in the engine, and load the cache with `users`
+  pub async fn account_filter(
+    &self,
+    users: Vec<DecodedAcctCtx<User>>,
+  ) -> anyhow::Result<Vec<Pubkey>> {
+    // accounts to subscribe to
+    let perps = DriftUtils::perp_markets(&self.rpc()).await?;
+    let spots = DriftUtils::spot_markets(&self.rpc()).await?;
+    let perp_markets: Vec<Pubkey> = perps.iter().map(|p| p.key).collect();
+    let spot_markets: Vec<Pubkey> = spots.iter().map(|s| s.key).collect();
+    // all other users are streamed by the "users" memcmp filter
+    let user_keys = [*self.user()];
+    let perp_oracles: Vec<Pubkey> = perps.iter().map(|p| p.decoded.amm.oracle).collect();
+    let spot_oracles: Vec<Pubkey> = spots.iter().map(|s| s.decoded.oracle).collect();
+    let auths = [self.signer.pubkey()];
+    self
+      .cache
+      .write()
+      .await
+      .load_with_all_users(&self.rpc(), Some(users), None, &auths)
+      .await?;
+    let keys = perp_markets
+      .iter()
+      .chain(spot_markets.iter())
+      .chain(user_keys.iter())
+      .chain(perp_oracles.iter())
+      .chain(spot_oracles.iter())
+      .cloned()
+      .collect::<Vec<Pubkey>>();
+    Ok(keys)
+  }
+
+  pub fn new_tx(&self) -> KeypairTrx<'_> {
+    self.drift.new_tx(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nexus::drift_client::fixtures::*;
+
+  fn config(signer: Keypair) -> Config {
+    Config {
+      read_only: false,
+      retry_until_confirmed: false,
+      signer,
+      rpc_url: String::new(),
+      grpc: String::new(),
+      x_token: String::new(),
+      retry_secs: 10,
+    }
+  }
+
+  #[tokio::test]
+  async fn fills_crossing_and_triggers_stops() -> anyhow::Result<()> {
+    let signer = Keypair::new();
+    let (taker, maker, stopped) = (
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+      Pubkey::new_unique(),
+    );
+    let order = |order_id, direction, price, slot| Order {
+      slot,
+      ..perp_order(order_id, direction, price, 1.0)
+    };
+    // around a $100 oracle the taker's newer bid crosses the maker's ask,
+    // and a stop buying above $95 has been met
+    let stop = Order {
+      order_type: OrderType::TriggerLimit,
+      trigger_price: DriftUtils::price_to_u64(95.0),
+      trigger_condition: OrderTriggerCondition::Above,
+      ..order(3, PositionDirection::Long, 90.0, 0)
+    };
+    let rpc = Arc::new(
+      FakeRpc::new()
+        .drift_markets(100.0)
+        .drift_user(&funded_user(&signer.pubkey(), 0, 1_000.0, &[]))
+        .drift_user(&funded_user(
+          &taker,
+          0,
+          1_000.0,
+          &[order(1, PositionDirection::Long, 101.0, 1)],
+        ))
+        .drift_user(&funded_user(
+          &maker,
+          0,
+          1_000.0,
+          &[
+            order(1, PositionDirection::Short, 100.5, 0),
+            order(2, PositionDirection::Long, 99.0, 0),
+          ],
+        ))
+        .drift_user(&funded_user(&stopped, 0, 1_000.0, &[stop])),
+    );
+    let engine = Engine::with_sources(
+      config(signer),
+      0,
+      MarketId::perp(0),
+      None,
+      rpc,
+      Arc::new(FakeGeyser::new()),
+    )
+    .await?;
+
+    let (fills, triggers) = engine.fillable().await?;
+    let fills: Vec<(Pubkey, u32, Pubkey)> = fills
+      .iter()
+      .map(|f| (f.taker, f.order.order_id, f.maker))
+      .collect();
+    assert_eq!(
+      fills,
+      vec![(
+        DriftUtils::user_pda(&taker, 0),
+        1,
+        DriftUtils::user_pda(&maker, 0)
+      )]
+    );
+    let triggers: Vec<(Pubkey, u32)> = triggers
+      .iter()
+      .map(|t| (t.user, t.order.order_id))
+      .collect();
+    assert_eq!(triggers, vec![(DriftUtils::user_pda(&stopped, 0), 3)]);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn rejects_spot_markets() {
+    let rpc = Arc::new(FakeRpc::new().drift_markets(100.0));
+    let engine = Engine::with_sources(
+      config(Keypair::new()),
+      0,
+      MarketId::QUOTE_SPOT,
+      None,
+      rpc,
+      Arc::new(FakeGeyser::new()),
+    )
+    .await;
+    assert!(engine.is_err());
+  }
+}
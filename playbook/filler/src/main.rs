@@ -0,0 +1,17 @@
+use engine::*;
+use nexus::drift_client::MarketId;
+use nexus::*;
+
+mod config;
+mod engine;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+  dotenv::dotenv().ok();
+  init_logger();
+
+  let filler = Engine::new(0, MarketId::SOL_PERP, None).await?;
+  filler.start().await?;
+
+  Ok(())
+}
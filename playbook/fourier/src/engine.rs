@@ -5,7 +5,6 @@ use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Keypair;
 use solana_sdk::signer::Signer;
-use std::collections::HashSet;
 use std::ops::Neg;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -24,11 +23,12 @@ pub struct Engine {
   read_only: bool,
   retry_until_confirmed: bool,
   pub signer: Arc<Keypair>,
-  pub rpc: Arc<RpcClient>,
+  pub rpc: Arc<dyn RpcSource>,
   pub drift: DriftClient,
   pub market: MarketId,
   pub cache: Cache,
   pub orderbook: Orderbook,
+  pub health: StreamHealth,
   pct_stop_loss: f64,
   leverage: f64,
   stop_loss_is_maker: bool,
@@ -39,11 +39,30 @@ pub struct Engine {
 
 impl Engine {
   pub async fn new(sub_account_id: u16, market: MarketId) -> anyhow::Result<Self> {
+    let config = Config::read()?;
+    let rpc = Arc::new(RpcClient::new_with_timeout(
+      config.rpc_url.clone(),
+      Duration::from_secs(90),
+    ));
+    let geyser = Arc::new(GrpcClient::new(GeyserConfig::new(
+      config.grpc.clone(),
+      config.x_token.clone(),
+      CommitmentLevel::Processed,
+    )));
+    Self::with_sources(config, sub_account_id, market, rpc, geyser).await
+  }
+
+  pub async fn with_sources(
+    config: Config,
+    sub_account_id: u16,
+    market: MarketId,
+    rpc: Arc<dyn RpcSource>,
+    geyser: Arc<dyn GeyserSource>,
+  ) -> anyhow::Result<Self> {
     let Config {
       read_only,
       retry_until_confirmed,
       signer,
-      rpc_url,
       grpc,
       x_token,
       pct_stop_loss,
@@ -53,19 +72,15 @@ impl Engine {
       zscore_window,
       cache_depth,
       ..
-    } = Config::read()?;
+    } = config;
 
     let signer = Arc::new(signer);
     info!("Engine using wallet: {}", signer.pubkey());
-    let rpc = Arc::new(RpcClient::new_with_timeout(
-      rpc_url,
-      Duration::from_secs(90),
-    ));
     let now = Instant::now();
     let orderbook = Orderbook::new_from_rpc(vec![market], &rpc).await?;
     info!("orderbook loaded in {:?}", now.elapsed());
 
-    let this = Self {
+    let mut this = Self {
       read_only,
       retry_until_confirmed,
       drift: DriftClient::new(
@@ -81,6 +96,7 @@ impl Engine {
       signer,
       cache: Cache::new(cache_depth),
       orderbook,
+      health: StreamHealth::default(),
       market,
       pct_stop_loss,
       leverage,
@@ -91,25 +107,20 @@ impl Engine {
     };
 
     let account_filter = this.account_filter().await?;
-    let mut filter = HashSet::new();
-    for a in account_filter {
-      filter.insert(a);
-    }
-    let cfg = this.orderbook_geyser_config(grpc, x_token)?;
+    let cfg = this.orderbook_geyser_config(grpc, x_token, account_filter)?;
     // stream updates from gRPC
-    let nexus = NexusClient::new(cfg)?;
+    let nexus = NexusClient::with_source(cfg, geyser).rpc(this.rpc());
+    this.health = nexus.health();
     let cache = this.cache.clone();
     let orderbook = this.orderbook.clone();
     tokio::task::spawn(async move {
-      nexus
-        .stream(&cache, None, Some(&orderbook), Some(filter))
-        .await?;
+      nexus.stream(&cache, None, Some(&orderbook), None).await?;
       Result::<_, anyhow::Error>::Ok(())
     });
     Ok(this)
   }
 
-  pub fn rpc(&self) -> Arc<RpcClient> {
+  pub fn rpc(&self) -> Arc<dyn RpcSource> {
     self.rpc.clone()
   }
   pub async fn cache(&self) -> ReadCache {
@@ -122,23 +133,41 @@ impl Engine {
     self.orderbook.read().await
   }
 
-  fn orderbook_geyser_config(&self, grpc: String, x_token: String) -> anyhow::Result<GeyserConfig> {
-    let drift_program_id = id();
-    Ok(GeyserConfig {
-      grpc,
-      x_token,
-      slots: Some(SubscribeRequestFilterSlots {
-        filter_by_commitment: Some(true),
-      }),
-      accounts: Some(SubscribeRequestFilterAccounts {
-        account: vec![],
-        owner: vec![drift_program_id.to_string(), PYTH_PROGRAM_ID.to_string()],
-        filters: vec![],
-      }),
-      transactions: None,
-      blocks_meta: None,
-      commitment: CommitmentLevel::Processed,
-    })
+  fn orderbook_geyser_config(
+    &self,
+    grpc: String,
+    x_token: String,
+    account_filter: Vec<Pubkey>,
+  ) -> anyhow::Result<GeyserConfig> {
+    Ok(
+      GeyserConfig::new(grpc, x_token, CommitmentLevel::Processed)
+        .slots(
+          "slots",
+          SubscribeRequestFilterSlots {
+            filter_by_commitment: Some(true),
+          },
+        )
+        // markets, oracles and our user
+        .accounts(
+          "accounts",
+          SubscribeRequestFilterAccounts {
+            account: account_filter.into_iter().map(|k| k.to_string()).collect(),
+            owner: vec![],
+            filters: vec![],
+          },
+          &[FilterRoute::Cache],
+        )
+        // only our own user is cached, which is in the account list
+        .accounts(
+          "users",
+          SubscribeRequestFilterAccounts {
+            account: vec![],
+            owner: vec![id().to_string()],
+            filters: vec![DriftUtils::grpc_users_filter()],
+          },
+          &[FilterRoute::Orderbook],
+        ),
+    )
   }
 
   pub async fn start(&mut self) -> anyhow::Result<()> {
@@ -148,6 +177,15 @@ impl Engine {
 
     let mut last_update = Instant::now();
     while run.load(Ordering::Relaxed) {
+      // cache is stale while the geyser stream is down or recovering
+      if let Err(e) = self
+        .drift
+        .ready_to_trade(&self.health, &self.cache().await, &[self.market])
+      {
+        warn!("🟡 {}, pause trading", e);
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        continue;
+      }
       let mut did_act = false;
       let user = self
         .cache()
@@ -314,9 +352,11 @@ impl Engine {
     let cache = self.cache().await;
     let price = self.drift.market_info(self.market, &cache, None)?.price;
 
-    let quote_balance = self.drift.quote_balance(self.market, &cache, None)?;
+    let buying_power = self
+      .drift
+      .buying_power(self.market, self.leverage, &cache, None)?;
     let trade_alloc_ratio = 50.0 / 100.0;
-    let base_amt = quote_balance * self.leverage / price * trade_alloc_ratio;
+    let base_amt = buying_power / price * trade_alloc_ratio;
 
     let sol_ticker = MarketId::SOL_PERP;
     let btc_ticker = MarketId::perp(1);
@@ -23,13 +23,14 @@ pub struct Engine {
   read_only: bool,
   retry_until_confirmed: bool,
   pub signer: Arc<Keypair>,
-  pub rpc: Arc<RpcClient>,
+  pub rpc: Arc<dyn RpcSource>,
   pub drift: DriftClient,
   pub client: Arc<Client>,
   pub copy_user: Pubkey,
   pub market_filter: Option<Vec<MarketId>>,
   pub cache: Cache,
-  rx: Receiver<TxStub>,
+  pub health: StreamHealth,
+  rx: Receiver<Arc<DriftTx>>,
   leverage: f64,
 }
 
@@ -39,29 +40,56 @@ impl Engine {
     copy_user: Pubkey,
     market_filter: Option<Vec<MarketId>>,
     cache_depth: Option<usize>,
+  ) -> anyhow::Result<Self> {
+    let config = Config::read()?;
+    let rpc = Arc::new(RpcClient::new_with_timeout(
+      config.rpc_url.clone(),
+      Duration::from_secs(90),
+    ));
+    let geyser = Arc::new(GrpcClient::new(GeyserConfig::new(
+      config.grpc.clone(),
+      config.x_token.clone(),
+      CommitmentLevel::Processed,
+    )));
+    Self::with_sources(
+      config,
+      sub_account_id,
+      copy_user,
+      market_filter,
+      cache_depth,
+      rpc,
+      geyser,
+    )
+    .await
+  }
+
+  pub async fn with_sources(
+    config: Config,
+    sub_account_id: u16,
+    copy_user: Pubkey,
+    market_filter: Option<Vec<MarketId>>,
+    cache_depth: Option<usize>,
+    rpc: Arc<dyn RpcSource>,
+    geyser: Arc<dyn GeyserSource>,
   ) -> anyhow::Result<Self> {
     let Config {
       read_only,
       retry_until_confirmed,
       signer,
-      rpc_url,
       grpc,
       x_token,
       leverage,
       ..
-    } = Config::read()?;
+    } = config;
 
     // 200 slots = 80 seconds of account cache
     let cache_depth = cache_depth.unwrap_or(200);
     let signer = Arc::new(signer);
     info!("Engine using wallet: {}", signer.pubkey());
-    let rpc = Arc::new(RpcClient::new_with_timeout(
-      rpc_url,
-      Duration::from_secs(90),
-    ));
-    let (tx, rx) = crossbeam::channel::unbounded::<TxStub>();
+    let events = EventBus::default();
+    let rx = events.subscribe();
 
-    let this = Self {
+    let mut this = Self {
       read_only,
       retry_until_confirmed,
       drift: DriftClient::new(
@@ -76,6 +104,7 @@ impl Engine {
       rpc,
       signer,
       cache: Cache::new(cache_depth),
+      health: StreamHealth::default(),
       client: Arc::new(Client::builder().timeout(Duration::from_secs(90)).build()?),
       copy_user,
       market_filter,
@@ -86,16 +115,19 @@ impl Engine {
     let account_filter = this.account_filter().await?;
     let cfg = this.copy_trade_geyser_config(grpc, x_token, account_filter)?;
     // stream updates from gRPC
-    let nexus = NexusClient::new(cfg)?;
+    let nexus = NexusClient::with_source(cfg, geyser)
+      .rpc(this.rpc())
+      .events(events);
+    this.health = nexus.health();
     let cache = this.cache.clone();
     tokio::task::spawn(async move {
-      nexus.stream(&cache, Some(tx), None, None).await?;
+      nexus.stream(&cache, None, None, None).await?;
       Result::<_, anyhow::Error>::Ok(())
     });
     Ok(this)
   }
 
-  pub fn rpc(&self) -> Arc<RpcClient> {
+  pub fn rpc(&self) -> Arc<dyn RpcSource> {
     self.rpc.clone()
   }
   pub async fn cache(&self) -> ReadCache {
@@ -114,48 +146,56 @@ impl Engine {
     x_token: String,
     account_filter: Vec<Pubkey>,
   ) -> anyhow::Result<GeyserConfig> {
-    Ok(GeyserConfig {
-      grpc,
-      x_token,
-      slots: Some(SubscribeRequestFilterSlots {
-        filter_by_commitment: Some(true),
-      }),
-      accounts: Some(SubscribeRequestFilterAccounts {
-        account: account_filter.into_iter().map(|k| k.to_string()).collect(),
-        owner: vec![],
-        // subscribe to all Drift `User` accounts
-        filters: vec![],
-      }),
-      transactions: Some(SubscribeRequestFilterTransactions {
-        vote: Some(false),
-        failed: Some(false),
-        signature: None,
-        account_include: vec![self.copy_user.to_string()],
-        account_exclude: vec![],
-        account_required: vec![],
-      }),
-      blocks_meta: None,
-      commitment: CommitmentLevel::Processed,
-    })
+    Ok(
+      GeyserConfig::new(grpc, x_token, CommitmentLevel::Processed)
+        .slots(
+          "slots",
+          SubscribeRequestFilterSlots {
+            filter_by_commitment: Some(true),
+          },
+        )
+        .accounts(
+          "accounts",
+          SubscribeRequestFilterAccounts {
+            account: account_filter.into_iter().map(|k| k.to_string()).collect(),
+            owner: vec![],
+            filters: vec![],
+          },
+          &[FilterRoute::Cache],
+        )
+        .transactions(
+          "copy_user",
+          SubscribeRequestFilterTransactions {
+            vote: Some(false),
+            failed: Some(false),
+            signature: None,
+            account_include: vec![self.copy_user.to_string()],
+            account_exclude: vec![],
+            account_required: vec![],
+          },
+        ),
+    )
   }
 
   fn orderbook_geyser_config(&self, grpc: String, x_token: String) -> anyhow::Result<GeyserConfig> {
-    let drift_program_id = id();
-    Ok(GeyserConfig {
-      grpc,
-      x_token,
-      slots: Some(SubscribeRequestFilterSlots {
-        filter_by_commitment: Some(true),
-      }),
-      accounts: Some(SubscribeRequestFilterAccounts {
-        account: vec![],
-        owner: vec![drift_program_id.to_string(), PYTH_PROGRAM_ID.to_string()],
-        filters: vec![],
-      }),
-      transactions: None,
-      blocks_meta: None,
-      commitment: CommitmentLevel::Processed,
-    })
+    Ok(
+      GeyserConfig::new(grpc, x_token, CommitmentLevel::Processed)
+        .slots(
+          "slots",
+          SubscribeRequestFilterSlots {
+            filter_by_commitment: Some(true),
+          },
+        )
+        .accounts(
+          "users",
+          SubscribeRequestFilterAccounts {
+            account: vec![],
+            owner: vec![id().to_string()],
+            filters: vec![DriftUtils::grpc_users_filter()],
+          },
+          &[FilterRoute::Orderbook],
+        ),
+    )
   }
 
   pub async fn start(&mut self) -> anyhow::Result<()> {
@@ -163,82 +203,83 @@ impl Engine {
     self.reset_orders().await?;
 
     while let Ok(tx) = self.rx.recv() {
+      // copied orders would be priced off a stale cache while the geyser stream is down
+      if let Err(e) = self
+        .drift
+        .ready_to_trade(&self.health, &self.cache().await, &[])
+      {
+        warn!("skip copy of {}, {}", tx.signature, e);
+        continue;
+      }
       let mut trx = self.new_tx();
-      for ix in tx.ixs {
-        if ix.program == id() {
-          let decoded_ix = InstructionType::decode(&ix.data[..])
-            .map_err(|e| anyhow::anyhow!("Failed to decode instruction: {:?}", e))?;
-          let name = InstructionType::discrim_to_name(ix.data[..8].try_into()?)
-            .map_err(|e| anyhow::anyhow!("Failed to decode discrim to name: {:?}", e))?;
-          info!("{}", name);
-
-          match decoded_ix {
-            InstructionType::PlacePerpOrder(ix) => {
-              let params = ix._params;
-              if self.allow_market(MarketId {
-                index: params.market_index,
-                kind: params.market_type,
-              }) {
-                self.place_orders(tx.slot, vec![params], &mut trx).await?;
-                let price = self.drift.order_price(
-                  MarketId {
-                    index: params.market_index,
-                    kind: params.market_type,
-                  },
-                  &self.cache().await,
-                  Some(tx.slot),
-                  &params,
-                )?;
-                DriftUtils::log_order(&params, &price, Some("PlacePerpOrder"));
-              }
-            }
-            InstructionType::PlaceOrders(ix) => {
-              let mut orders = vec![];
-              for params in ix._params.iter() {
-                if self.allow_market(MarketId {
+      for ix in tx.ixs.iter() {
+        info!("{}", ix.name);
+        match &ix.ix {
+          InstructionType::PlacePerpOrder(ix) => {
+            let params = ix._params;
+            if self.allow_market(MarketId {
+              index: params.market_index,
+              kind: params.market_type,
+            }) {
+              self.place_orders(tx.slot, vec![params], &mut trx).await?;
+              let price = self.drift.order_price(
+                MarketId {
                   index: params.market_index,
                   kind: params.market_type,
-                }) {
-                  orders.push(*params);
-                }
-              }
-              if !orders.is_empty() {
-                self.place_orders(tx.slot, orders, &mut trx).await?;
-              }
+                },
+                &self.cache().await,
+                Some(tx.slot),
+                &params,
+              )?;
+              DriftUtils::log_order(&params, &price, Some("PlacePerpOrder"));
             }
-            InstructionType::CancelOrders(ix) => {
-              if let (Some(index), Some(kind)) = (ix._market_index, ix._market_type) {
-                let market = MarketId::from((index, kind));
-                if self.allow_market(market) {
-                  self
-                    .cancel_orders(Some(market), ix._direction, &mut trx)
-                    .await?;
-                  info!("CancelOrders");
-                }
-              };
-            }
-            InstructionType::PlaceAndTakePerpOrder(ix) => {
-              let params = ix._params;
+          }
+          InstructionType::PlaceOrders(ix) => {
+            let mut orders = vec![];
+            for params in ix._params.iter() {
               if self.allow_market(MarketId {
                 index: params.market_index,
                 kind: params.market_type,
               }) {
-                let price = self.drift.order_price(
-                  MarketId {
-                    index: params.market_index,
-                    kind: params.market_type,
-                  },
-                  &self.cache().await,
-                  Some(tx.slot),
-                  &params,
-                )?;
-                DriftUtils::log_order(&params, &price, Some("PlaceAndTakePerpOrder"));
-                info!("PlaceAndTakePerpOrder: {:#?}", params);
-                info!("https://solana.fm/tx/{}", tx.signature);
+                orders.push(*params);
+              }
+            }
+            if !orders.is_empty() {
+              self.place_orders(tx.slot, orders, &mut trx).await?;
+            }
+          }
+          InstructionType::CancelOrders(ix) => {
+            if let (Some(index), Some(kind)) = (ix._market_index, ix._market_type) {
+              let market = MarketId::from((index, kind));
+              if self.allow_market(market) {
+                self
+                  .cancel_orders(Some(market), ix._direction, &mut trx)
+                  .await?;
+                info!("CancelOrders");
               }
+            };
+          }
+          InstructionType::PlaceAndTakePerpOrder(ix) => {
+            let params = ix._params;
+            if self.allow_market(MarketId {
+              index: params.market_index,
+              kind: params.market_type,
+            }) {
+              let price = self.drift.order_price(
+                MarketId {
+                  index: params.market_index,
+                  kind: params.market_type,
+                },
+                &self.cache().await,
+                Some(tx.slot),
+                &params,
+              )?;
+              DriftUtils::log_order(&params, &price, Some("PlaceAndTakePerpOrder"));
+              info!("PlaceAndTakePerpOrder: {:#?}", params);
+              info!("https://solana.fm/tx/{}", tx.signature);
             }
-            _ => {}
           }
+          _ => {}
         }
       }
       if !self.read_only {
@@ -302,10 +343,17 @@ impl Engine {
     trx: &mut KeypairTrx<'_>,
   ) -> anyhow::Result<()> {
     let market_filter = self.market_filter.as_deref();
-    self
+    let res = self
       .drift
       .copy_place_orders_ix(tx_slot, &self.cache().await, orders, market_filter, trx)
-      .await?;
+      .await;
+    // skip this copy rather than exit, the next one may have fresh data
+    if let Err(e) = res {
+      match e.downcast_ref::<StaleDataError>() {
+        Some(stale) => warn!("skip copy, {}", stale),
+        None => return Err(e),
+      }
+    }
     Ok(())
   }
 
@@ -329,3 +377,91 @@ impl Engine {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nexus::drift_client::fixtures::*;
+
+  #[tokio::test]
+  async fn copies_orders_scaled_to_our_balance() -> anyhow::Result<()> {
+    let signer = Keypair::new();
+    let copy_authority = Pubkey::new_unique();
+    let rpc = Arc::new(
+      FakeRpc::new()
+        .drift_markets(100.0)
+        .drift_user(&funded_user(&signer.pubkey(), 0, 1_000.0, &[]))
+        .drift_user(&funded_user(&copy_authority, 0, 50_000.0, &[])),
+    );
+    let config = Config {
+      read_only: false,
+      retry_until_confirmed: false,
+      signer,
+      rpc_url: String::new(),
+      grpc: String::new(),
+      x_token: String::new(),
+      leverage: 1.0,
+    };
+    let market = MarketId::perp(0);
+    let engine = Engine::with_sources(
+      config,
+      0,
+      DriftUtils::user_pda(&copy_authority, 0),
+      Some(vec![market]),
+      None,
+      rpc.clone(),
+      Arc::new(FakeGeyser::new()),
+    )
+    .await?;
+
+    // two orders of a bracket split our $1000 in the same 1:3 ratio at the $100 oracle price
+    let order = |base: f64| OrderParams {
+      order_type: OrderType::Limit,
+      market_type: MarketType::Perp,
+      direction: PositionDirection::Long,
+      user_order_id: 0,
+      base_asset_amount: DriftUtils::base_to_u64(base),
+      price: DriftUtils::price_to_u64(99.0),
+      market_index: 0,
+      reduce_only: false,
+      post_only: PostOnlyParam::None,
+      immediate_or_cancel: false,
+      max_ts: Some(Time::now().to_unix() + 60),
+      trigger_price: None,
+      trigger_condition: OrderTriggerCondition::Above,
+      oracle_price_offset: None,
+      auction_duration: None,
+      auction_start_price: None,
+      auction_end_price: None,
+    };
+    let slot = engine.cache().await.slot;
+    let mut trx = engine.new_tx();
+    engine
+      .place_orders(slot, vec![order(100.0), order(300.0)], &mut trx)
+      .await?;
+    trx.send_tx(id(), None).await?;
+
+    let copied = match rpc.sent_drift_ixs()?.as_slice() {
+      [InstructionType::PlaceOrders(ix)] => ix._params.clone(),
+      ixs => panic!("Expected one PlaceOrders instruction, got {}", ixs.len()),
+    };
+    let copied: Vec<(f64, u64, Option<i64>)> = copied
+      .iter()
+      .map(|o| {
+        (
+          DriftUtils::base_to_f64(o.base_asset_amount),
+          o.price,
+          o.max_ts,
+        )
+      })
+      .collect();
+    assert_eq!(
+      copied,
+      vec![
+        (2.5, DriftUtils::price_to_u64(99.0), None),
+        (7.5, DriftUtils::price_to_u64(99.0), None),
+      ]
+    );
+    Ok(())
+  }
+}
@@ -31,14 +31,25 @@ async fn main() -> anyhow::Result<()> {
 // Tests
 // ============================================================================
 
-#[cfg(tests)]
+#[cfg(test)]
 mod tests {
   use super::*;
+  use anchor_lang::AnchorDeserialize;
+  use log::info;
+  use nexus::drift_client::*;
+  use solana_sdk::account_info::AccountInfo;
+  use solana_sdk::commitment_config::CommitmentConfig;
+  use solana_sdk::pubkey;
+  use solana_sdk::pubkey::Pubkey;
+  use solana_sdk::signer::Signer;
+  use std::collections::HashMap;
+  use std::str::FromStr;
 
   /// Rust websocket: https://github.com/drift-labs/drift-rs/blob/main/src/websocket_program_account_subscriber.rs
   /// Rust oracle type: https://github.com/drift-labs/protocol-v2/blob/ebe773e4594bccc44e815b4e45ed3b6860ac2c4d/programs/drift/src/state/oracle.rs#L126
   /// Pyth deser: https://github.com/pyth-network/pyth-sdk-rs/blob/main/pyth-sdk-solana/examples/get_accounts.rs#L67
   #[tokio::test]
+  #[ignore = "requires config.yaml, RPC_URL and GRPC"]
   async fn drift_perp_markets() -> anyhow::Result<()> {
     init_logger();
     dotenv::dotenv().ok();
@@ -127,6 +138,7 @@ mod tests {
   }
 
   #[tokio::test]
+  #[ignore = "requires config.yaml, RPC_URL and GRPC"]
   async fn drift_spot_markets() -> anyhow::Result<()> {
     init_logger();
     dotenv::dotenv().ok();
@@ -142,7 +154,6 @@ mod tests {
       name: String,
       oracle: Pubkey,
       oracle_source: OracleSource,
-      oracle_price_data: Option<OraclePriceData>,
       market_index: u16,
       mint: Pubkey,
     }
@@ -158,7 +169,6 @@ mod tests {
         name,
         oracle,
         oracle_source,
-        oracle_price_data: None,
         market_index: spot_market.decoded.market_index,
         mint: spot_market.decoded.mint,
       });
@@ -201,8 +211,9 @@ mod tests {
     Ok(())
   }
 
-  /// cargo test --package imitator --bin imitator top_users -- --exact --show-output
+  /// cargo test --package imitator --bin imitator top_users -- --exact --ignored --show-output
   #[tokio::test]
+  #[ignore = "requires config.yaml, RPC_URL and GRPC"]
   async fn top_users() -> anyhow::Result<()> {
     init_logger();
     dotenv::dotenv().ok();
@@ -230,8 +241,9 @@ mod tests {
     Ok(())
   }
 
-  /// cargo test --package imitator --bin imitator my_pnl -- --exact --show-output
+  /// cargo test --package imitator --bin imitator my_pnl -- --exact --ignored --show-output
   #[tokio::test]
+  #[ignore = "requires config.yaml, RPC_URL and GRPC"]
   async fn my_pnl() -> anyhow::Result<()> {
     init_logger();
     dotenv::dotenv().ok();
@@ -263,8 +275,9 @@ mod tests {
     Ok(())
   }
 
-  /// cargo test --package imitator --bin imitator historical_pnl -- --exact --show-output
+  /// cargo test --package imitator --bin imitator historical_pnl -- --exact --ignored --show-output
   #[tokio::test]
+  #[ignore = "requires config.yaml, RPC_URL and GRPC"]
   async fn historical_pnl() -> anyhow::Result<()> {
     init_logger();
     dotenv::dotenv().ok();
@@ -327,6 +340,7 @@ mod tests {
   }
 
   #[tokio::test]
+  #[ignore = "requires config.yaml, RPC_URL and GRPC"]
   async fn account() -> anyhow::Result<()> {
     init_logger();
     dotenv::dotenv().ok();
@@ -345,6 +359,7 @@ mod tests {
   }
 
   #[tokio::test]
+  #[ignore = "requires config.yaml, RPC_URL and GRPC"]
   async fn spot_balance() -> anyhow::Result<()> {
     init_logger();
     dotenv::dotenv().ok();
@@ -386,6 +401,7 @@ mod tests {
   }
 
   #[tokio::test]
+  #[ignore = "requires GRAPHQL"]
   async fn graphql() -> anyhow::Result<()> {
     init_logger();
     dotenv::dotenv().ok();
@@ -398,8 +414,9 @@ mod tests {
     Ok(())
   }
 
-  /// cargo test --package imitator --bin imitator trader_pnl -- --exact --show-output
+  /// cargo test --package imitator --bin imitator trader_pnl -- --exact --ignored --show-output
   #[tokio::test]
+  #[ignore = "requires config.yaml, RPC_URL and GRPC"]
   async fn trader_pnl() -> anyhow::Result<()> {
     init_logger();
     dotenv::dotenv().ok();
@@ -0,0 +1,479 @@
+#![allow(dead_code)]
+
+use crossbeam::channel::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::*;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+use yellowstone_grpc_proto::prelude::{
+  CommitmentLevel, SubscribeRequestFilterAccounts, SubscribeRequestFilterSlots,
+  SubscribeRequestFilterTransactions,
+};
+
+use crate::config::Config;
+use nexus::drift_client::*;
+use nexus::jit_proxy_cpi::{JitParams, PriceType};
+use nexus::*;
+
+/// Offsets from the oracle price the engine fills takers at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quote {
+  pub bid: f64,
+  pub ask: f64,
+}
+
+impl Quote {
+  /// `pct_spread` either side of the oracle, shifted down when long and up when short
+  /// by up to `pct_skew` at `max_position`, so fills work the position back to flat
+  pub fn new(
+    oracle_price: f64,
+    position: f64,
+    max_position: f64,
+    pct_spread: f64,
+    pct_skew: f64,
+  ) -> Self {
+    let inventory = (position / max_position).clamp(-1.0, 1.0);
+    let skew = inventory * pct_skew / 100.0 * oracle_price;
+    let spread = pct_spread / 100.0 * oracle_price;
+    Self {
+      bid: -spread - skew,
+      ask: spread - skew,
+    }
+  }
+}
+
+/// First slot the taker's auction reaches `price`, `None` if it ends first
+pub fn cross_slot(order: &Order, oracle_price: i64, price: i64) -> Option<u64> {
+  let end = order.slot + order.auction_duration as u64;
+  (order.slot..=end).find(
+    |slot| match DriftUtils::auction_price(order, *slot, oracle_price) {
+      Some(auction_price) => match order.direction {
+        // a taker buying pays more as the auction goes on
+        PositionDirection::Long => auction_price >= price,
+        PositionDirection::Short => auction_price <= price,
+      },
+      None => false,
+    },
+  )
+}
+
+/// Base of an auction being filled, counted in the position until the fill lands or fails
+struct Reservation<'a> {
+  in_flight: &'a Mutex<i64>,
+  base: i64,
+}
+
+impl Drop for Reservation<'_> {
+  fn drop(&mut self) {
+    if let Ok(mut in_flight) = self.in_flight.lock() {
+      *in_flight -= self.base;
+    }
+  }
+}
+
+pub struct Engine {
+  read_only: bool,
+  retry_until_confirmed: bool,
+  pub signer: Arc<Keypair>,
+  pub rpc: Arc<dyn RpcSource>,
+  pub drift: DriftClient,
+  pub market: MarketId,
+  pub cache: Cache,
+  pub health: StreamHealth,
+  rx: Receiver<Arc<DriftTx>>,
+  /// Base the fills in flight add to the position, positive when buying
+  in_flight: Mutex<i64>,
+  pct_spread: f64,
+  max_position: f64,
+  pct_skew: f64,
+}
+
+impl Engine {
+  pub async fn new(
+    sub_account_id: u16,
+    market: MarketId,
+    cache_depth: Option<usize>,
+  ) -> anyhow::Result<Self> {
+    let config = Config::read()?;
+    let rpc = Arc::new(RpcClient::new_with_timeout(
+      config.rpc_url.clone(),
+      Duration::from_secs(90),
+    ));
+    let geyser = Arc::new(GrpcClient::new(GeyserConfig::new(
+      config.grpc.clone(),
+      config.x_token.clone(),
+      CommitmentLevel::Processed,
+    )));
+    Self::with_sources(config, sub_account_id, market, cache_depth, rpc, geyser).await
+  }
+
+  pub async fn with_sources(
+    config: Config,
+    sub_account_id: u16,
+    market: MarketId,
+    cache_depth: Option<usize>,
+    rpc: Arc<dyn RpcSource>,
+    geyser: Arc<dyn GeyserSource>,
+  ) -> anyhow::Result<Self> {
+    let Config {
+      read_only,
+      retry_until_confirmed,
+      signer,
+      grpc,
+      x_token,
+      pct_spread,
+      max_position,
+      pct_skew,
+      ..
+    } = config;
+
+    // 200 slots = 80 seconds of account cache
+    let cache_depth = cache_depth.unwrap_or(200);
+    let signer = Arc::new(signer);
+    info!("Engine using wallet: {}", signer.pubkey());
+    let events = EventBus::default();
+    let rx = events.subscribe();
+
+    let mut this = Self {
+      read_only,
+      retry_until_confirmed,
+      drift: DriftClient::new(
+        signer.clone(),
+        rpc.clone(),
+        sub_account_id,
+        None,
+        read_only,
+        retry_until_confirmed,
+      )
+      .await?,
+      rpc,
+      signer,
+      cache: Cache::new(cache_depth),
+      health: StreamHealth::default(),
+      market,
+      rx,
+      in_flight: Mutex::new(0),
+      pct_spread,
+      max_position,
+      pct_skew,
+    };
+
+    let account_filter = this.account_filter().await?;
+    let cfg = this.auction_geyser_config(grpc, x_token, account_filter)?;
+    // stream updates from gRPC
+    let nexus = NexusClient::with_source(cfg, geyser)
+      .rpc(this.rpc())
+      .events(events);
+    this.health = nexus.health();
+    let cache = this.cache.clone();
+    tokio::task::spawn(async move {
+      nexus.stream(&cache, None, None, None).await?;
+      Result::<_, anyhow::Error>::Ok(())
+    });
+    Ok(this)
+  }
+
+  pub fn rpc(&self) -> Arc<dyn RpcSource> {
+    self.rpc.clone()
+  }
+  pub async fn cache(&self) -> ReadCache {
+    self.cache.read().await
+  }
+  pub fn user(&self) -> &Pubkey {
+    &self.drift.sub_account
+  }
+
+  fn auction_geyser_config(
+    &self,
+    grpc: String,
+    x_token: String,
+    account_filter: Vec<Pubkey>,
+  ) -> anyhow::Result<GeyserConfig> {
+    Ok(
+      GeyserConfig::new(grpc, x_token, CommitmentLevel::Processed)
+        .slots(
+          "slots",
+          SubscribeRequestFilterSlots {
+            filter_by_commitment: Some(true),
+          },
+        )
+        .accounts(
+          "accounts",
+          SubscribeRequestFilterAccounts {
+            account: account_filter.into_iter().map(|k| k.to_string()).collect(),
+            owner: vec![],
+            filters: vec![],
+          },
+          &[FilterRoute::Cache],
+        )
+        // takers are read from the cache to fill their orders
+        .accounts(
+          "users",
+          SubscribeRequestFilterAccounts {
+            account: vec![],
+            owner: vec![id().to_string()],
+            filters: vec![DriftUtils::grpc_users_filter()],
+          },
+          &[FilterRoute::Cache],
+        )
+        // orders placed in the market include it in their accounts
+        .transactions(
+          "takers",
+          SubscribeRequestFilterTransactions {
+            vote: Some(false),
+            failed: Some(false),
+            signature: None,
+            account_include: vec![self.market.key().to_string()],
+            account_exclude: vec![],
+            account_required: vec![],
+          },
+        ),
+    )
+  }
+
+  pub async fn start(self) -> anyhow::Result<()> {
+    self.drift.setup_user().await?;
+
+    // the event bus blocks on receive, so forward it to a channel the runtime can await
+    let (forward, mut txs) = tokio::sync::mpsc::unbounded_channel();
+    let rx = self.rx.clone();
+    tokio::task::spawn_blocking(move || {
+      while let Ok(tx) = rx.recv() {
+        if forward.send(tx).is_err() {
+          break;
+        }
+      }
+    });
+
+    let this = Arc::new(self);
+    while let Some(tx) = txs.recv().await {
+      for event in tx.events.iter() {
+        let record = match &event.event {
+          DriftEvent::Order(record) => record,
+          _ => continue,
+        };
+        if !this.is_taker_auction(record, tx.slot) {
+          continue;
+        }
+        // fills would be priced off a stale cache while the geyser stream is down
+        if let Err(e) = this
+          .drift
+          .ready_to_trade(&this.health, &this.cache().await, &[this.market])
+        {
+          warn!("skip auction in {}, {}", tx.signature, e);
+          continue;
+        }
+        // each fill waits for its auction to cross, so auctions are filled concurrently
+        let (this, taker, order, signature) =
+          (this.clone(), record.user, record.order, tx.signature);
+        tokio::task::spawn(async move {
+          // a missed auction is not fatal, there will be another
+          if let Err(e) = this.fill(taker, order).await {
+            match e.downcast_ref::<StaleDataError>() {
+              Some(stale) => warn!("skip auction, {}", stale),
+              None => error!("Failed to fill auction in {}: {:?}", signature, e),
+            }
+          }
+        });
+      }
+    }
+    Ok(())
+  }
+
+  /// Order placed by another user in the market that starts an auction
+  fn is_taker_auction(&self, record: &OrderRecord, slot: u64) -> bool {
+    let order = &record.order;
+    record.user != *self.user()
+      && MarketId::from((order.market_index, order.market_type)) == self.market
+      && !DriftUtils::order_must_be_triggered(order)
+      && !DriftUtils::order_auction_complete(order, slot).unwrap_or(true)
+  }
+
+  /// Quote the taker from the oracle and our position, and once the auction crosses the quote
+  /// fill it through the jit proxy
+  async fn fill(&self, taker: Pubkey, order: Order) -> anyhow::Result<()> {
+    let (oracle_price, position, taker_user) = {
+      let cache = self.cache().await;
+      let oracle_price = DriftUtils::oracle_price(&self.market, &cache, None)?;
+      let user = cache.decoded_account::<User>(self.user(), None)?.decoded;
+      let position = user
+        .perp_positions
+        .iter()
+        .find(|p| p.market_index == self.market.index)
+        .map(|p| p.base_asset_amount)
+        .unwrap_or(0);
+      let taker_user = cache.decoded_account::<User>(&taker, None)?.decoded;
+      (oracle_price, position, taker_user)
+    };
+    let max_position = DriftUtils::base_to_u64(self.max_position) as i64;
+    // quote and size this fill as if the other fills in flight had landed
+    let (quote, room, _reserved) = {
+      let mut in_flight = self
+        .in_flight
+        .lock()
+        .map_err(|e| anyhow::anyhow!("Failed to lock fills in flight: {}", e))?;
+      let position = position + *in_flight;
+      let quote = Quote::new(
+        oracle_price,
+        position as f64 / BASE_PRECISION as f64,
+        self.max_position,
+        self.pct_spread,
+        self.pct_skew,
+      );
+      // the taker buys from our ask or sells into our bid
+      let room = match order.direction {
+        PositionDirection::Long => position + max_position,
+        PositionDirection::Short => max_position - position,
+      };
+      let base = order
+        .base_asset_amount
+        .saturating_sub(order.base_asset_amount_filled)
+        .min(room.max(0) as u64) as i64;
+      let base = match order.direction {
+        PositionDirection::Long => -base,
+        PositionDirection::Short => base,
+      };
+      *in_flight += base;
+      let reserved = Reservation {
+        in_flight: &self.in_flight,
+        base,
+      };
+      (quote, room, reserved)
+    };
+    let to_price = |offset: f64| (offset * PRICE_PRECISION as f64).round() as i64;
+    let (bid, ask) = (to_price(quote.bid), to_price(quote.ask));
+    let offset = match order.direction {
+      PositionDirection::Long => ask,
+      PositionDirection::Short => bid,
+    };
+    if room <= 0 {
+      debug!("at max position, skip order {}", order.order_id);
+      return Ok(());
+    }
+    let oracle = DriftUtils::price_to_u64(oracle_price) as i64;
+    let cross = match cross_slot(&order, oracle, oracle + offset) {
+      Some(slot) => slot,
+      None => {
+        debug!("auction of order {} never crosses", order.order_id);
+        return Ok(());
+      }
+    };
+    // the proxy rejects the fill until the auction price crosses the quote
+    if !self
+      .wait_for_slot(cross, order.slot + order.auction_duration as u64)
+      .await
+    {
+      return Ok(());
+    }
+
+    let taker_info = TakerInfo {
+      taker,
+      taker_user_stats: DriftUtils::user_stats_pda(&taker_user.authority),
+      taker_user,
+      order,
+    };
+    let params = JitParams {
+      taker_order_id: order.order_id,
+      max_position,
+      min_position: -max_position,
+      bid,
+      ask,
+      price_type: PriceType::Oracle,
+      post_only: None,
+    };
+    let mut trx = self.new_tx();
+    self
+      .drift
+      .jit_ix(&self.cache().await, &taker_info, params, &mut trx)
+      .await?;
+    info!(
+      "🟢 fill order {} of {} at slot {}, oracle: {}, bid: {}, ask: {}",
+      order.order_id,
+      shorten_address(&taker),
+      cross,
+      trunc!(oracle_price, 4),
+      trunc!(oracle_price + quote.bid, 4),
+      trunc!(oracle_price + quote.ask, 4),
+    );
+    // bid the priority fee of the market the auction competes in
+    trx.send_tx(self.market.key(), None).await
+  }
+
+  /// Wait for the streamed slot to reach `slot`, false if the stream passes `deadline` first or goes down
+  async fn wait_for_slot(&self, slot: u64, deadline: u64) -> bool {
+    loop {
+      let current = self.cache().await.slot;
+      if current > deadline || !self.health.is_healthy() {
+        return false;
+      }
+      if current >= slot {
+        return true;
+      }
+      tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+  }
+
+  /// Stream these accounts from geyser for usage in the engine
+  pub async fn account_filter(&self) -> anyhow::Result<Vec<Pubkey>> {
+    // accounts to subscribe to
+    let perps = DriftUtils::perp_markets(&self.rpc()).await?;
+    let spots = DriftUtils::spot_markets(&self.rpc()).await?;
+    let perp_markets: Vec<Pubkey> = perps.iter().map(|p| p.key).collect();
+    let spot_markets: Vec<Pubkey> = spots.iter().map(|s| s.key).collect();
+    let users = [*self.user()];
+    let perp_oracles: Vec<Pubkey> = perps.iter().map(|p| p.decoded.amm.oracle).collect();
+    let spot_oracles: Vec<Pubkey> = spots.iter().map(|s| s.decoded.oracle).collect();
+    let auths = [self.signer.pubkey()];
+    self
+      .cache
+      .write()
+      .await
+      .load(&self.rpc(), &users, None, &auths)
+      .await?;
+    let keys = perp_markets
+      .iter()
+      .chain(spot_markets.iter())
+      .chain(users.iter())
+      .chain(perp_oracles.iter())
+      .chain(spot_oracles.iter())
+      .cloned()
+      .collect::<Vec<Pubkey>>();
+    Ok(keys)
+  }
+
+  pub fn new_tx(&self) -> KeypairTrx<'_> {
+    self.drift.new_tx(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn quote_and_cross_slot() {
+    let flat = Quote::new(100.0, 0.0, 10.0, 0.1, 0.2);
+    assert!((flat.bid + 0.1).abs() < 1e-9 && (flat.ask - 0.1).abs() < 1e-9);
+    // long half the max position, so both sides move down by half the skew
+    let long = Quote::new(100.0, 5.0, 10.0, 0.1, 0.2);
+    assert!((long.bid + 0.2).abs() < 1e-9 && long.ask.abs() < 1e-9);
+
+    // taker buys in an auction from 1% below to 1% above the oracle over 10 slots
+    let order = Order {
+      order_type: OrderType::Oracle,
+      direction: PositionDirection::Long,
+      slot: 100,
+      auction_duration: 10,
+      auction_start_price: -1_000_000,
+      auction_end_price: 1_000_000,
+      ..Default::default()
+    };
+    let oracle = 100_000_000;
+    assert_eq!(cross_slot(&order, oracle, oracle + 100_000), Some(106));
+    assert_eq!(cross_slot(&order, oracle, oracle + 2_000_000), None);
+  }
+}
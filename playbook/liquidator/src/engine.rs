@@ -0,0 +1,549 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::*;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+use tokio::time::Instant;
+use yellowstone_grpc_proto::prelude::{
+  CommitmentLevel, SubscribeRequestFilterAccounts, SubscribeRequestFilterSlots,
+};
+
+use crate::config::Config;
+use nexus::drift_client::*;
+use nexus::*;
+
+/// Position of an unhealthy user to take over
+#[derive(Debug, Clone, Copy)]
+enum Liquidation {
+  Perp {
+    market_index: u16,
+  },
+  Spot {
+    asset_market_index: u16,
+    liability_market_index: u16,
+  },
+  BorrowForPerpPnl {
+    perp_market_index: u16,
+    spot_market_index: u16,
+  },
+}
+
+impl Liquidation {
+  /// Largest perp position first, then the largest borrow against a deposit or positive pnl
+  fn select(user: &User, markets: &MarginMarkets) -> Option<Self> {
+    let perp_value = |p: &PerpPosition| {
+      let price = markets
+        .perp
+        .get(&p.market_index)
+        .map_or(0, |(_, price)| *price);
+      (p.base_asset_amount as i128 * price as i128).unsigned_abs()
+    };
+    let perp = user
+      .perp_positions
+      .iter()
+      .filter(|p| p.base_asset_amount != 0)
+      .max_by_key(|p| perp_value(p));
+    if let Some(perp) = perp {
+      return Some(Liquidation::Perp {
+        market_index: perp.market_index,
+      });
+    }
+
+    let spot_value = |p: &SpotPosition| match markets.spot.get(&p.market_index) {
+      Some((market, price)) => {
+        MarginCalculation::token_amount(p, market) * (*price).max(0) as u128
+          / 10_u128.pow(market.decimals)
+      }
+      None => 0,
+    };
+    let largest = |balance_type: fn(&SpotBalanceType) -> bool| {
+      user
+        .spot_positions
+        .iter()
+        .filter(|p| p.scaled_balance != 0 && balance_type(&p.balance_type))
+        .max_by_key(|p| spot_value(p))
+    };
+    let borrow = largest(|b| matches!(b, SpotBalanceType::Borrow))?;
+    if let Some(deposit) = largest(|b| matches!(b, SpotBalanceType::Deposit)) {
+      return Some(Liquidation::Spot {
+        asset_market_index: deposit.market_index,
+        liability_market_index: borrow.market_index,
+      });
+    }
+    // a closed perp position can still hold unsettled pnl
+    let pnl = |p: &PerpPosition| match markets.perp.get(&p.market_index) {
+      Some((_, price)) => {
+        p.base_asset_amount as i128 * *price as i128 / BASE_PRECISION_I128
+          + p.quote_asset_amount as i128
+      }
+      None => 0,
+    };
+    let perp = user
+      .perp_positions
+      .iter()
+      .filter(|p| pnl(p) > 0)
+      .max_by_key(|p| pnl(p))?;
+    Some(Liquidation::BorrowForPerpPnl {
+      perp_market_index: perp.market_index,
+      spot_market_index: borrow.market_index,
+    })
+  }
+
+  /// Market to bid the priority fee of
+  fn market(&self) -> MarketId {
+    match *self {
+      Liquidation::Perp { market_index } => MarketId::perp(market_index),
+      Liquidation::Spot {
+        liability_market_index,
+        ..
+      } => MarketId::spot(liability_market_index),
+      Liquidation::BorrowForPerpPnl {
+        perp_market_index, ..
+      } => MarketId::perp(perp_market_index),
+    }
+  }
+}
+
+pub struct Engine {
+  read_only: bool,
+  retry_until_confirmed: bool,
+  pub signer: Arc<Keypair>,
+  pub rpc: Arc<dyn RpcSource>,
+  pub drift: DriftClient,
+  pub cache: Cache,
+  pub health: StreamHealth,
+  retry_after: Duration,
+  max_base: f64,
+  max_liability: f64,
+  min_free_collateral: f64,
+  /// Last attempt to liquidate each user
+  attempts: HashMap<Pubkey, Instant>,
+  /// Every other user and the slot it was cached at, kept current by watching the cache
+  users: HashMap<Pubkey, (u64, User)>,
+}
+
+impl Engine {
+  pub async fn new(sub_account_id: u16, cache_depth: Option<usize>) -> anyhow::Result<Self> {
+    let config = Config::read()?;
+    let rpc = Arc::new(RpcClient::new_with_timeout(
+      config.rpc_url.clone(),
+      Duration::from_secs(90),
+    ));
+    let geyser = Arc::new(GrpcClient::new(GeyserConfig::new(
+      config.grpc.clone(),
+      config.x_token.clone(),
+      CommitmentLevel::Processed,
+    )));
+    Self::with_sources(config, sub_account_id, cache_depth, rpc, geyser).await
+  }
+
+  pub async fn with_sources(
+    config: Config,
+    sub_account_id: u16,
+    cache_depth: Option<usize>,
+    rpc: Arc<dyn RpcSource>,
+    geyser: Arc<dyn GeyserSource>,
+  ) -> anyhow::Result<Self> {
+    let Config {
+      read_only,
+      retry_until_confirmed,
+      signer,
+      grpc,
+      x_token,
+      retry_secs,
+      max_base,
+      max_liability,
+      min_free_collateral,
+      ..
+    } = config;
+
+    // 200 slots = 80 seconds of account cache
+    let cache_depth = cache_depth.unwrap_or(200);
+    let signer = Arc::new(signer);
+    info!("Engine using wallet: {}", signer.pubkey());
+    let confirmations = ConfirmationTracker::default();
+    let users = DriftUtils::users(&rpc).await?;
+
+    let mut this = Self {
+      read_only,
+      retry_until_confirmed,
+      drift: DriftClient::new(
+        signer.clone(),
+        rpc.clone(),
+        sub_account_id,
+        None,
+        read_only,
+        retry_until_confirmed,
+      )
+      .await?
+      .confirmations(confirmations.clone()),
+      rpc,
+      signer,
+      cache: Cache::new(cache_depth),
+      health: StreamHealth::default(),
+      retry_after: Duration::from_secs(retry_secs),
+      max_base,
+      max_liability,
+      min_free_collateral,
+      attempts: HashMap::new(),
+      users: HashMap::new(),
+    };
+
+    let account_filter = this.account_filter(users).await?;
+    let cfg = this.liquidator_geyser_config(grpc, x_token, account_filter)?;
+    // stream updates from gRPC
+    let nexus = NexusClient::with_source(cfg, geyser)
+      .rpc(this.rpc())
+      .confirmations(confirmations);
+    this.health = nexus.health();
+    let cache = this.cache.clone();
+    tokio::task::spawn(async move {
+      nexus.stream(&cache, None, None, None).await?;
+      Result::<_, anyhow::Error>::Ok(())
+    });
+    Ok(this)
+  }
+
+  pub fn rpc(&self) -> Arc<dyn RpcSource> {
+    self.rpc.clone()
+  }
+  pub async fn cache(&self) -> ReadCache {
+    self.cache.read().await
+  }
+  pub fn user(&self) -> &Pubkey {
+    &self.drift.sub_account
+  }
+
+  fn liquidator_geyser_config(
+    &self,
+    grpc: String,
+    x_token: String,
+    account_filter: Vec<Pubkey>,
+  ) -> anyhow::Result<GeyserConfig> {
+    Ok(
+      GeyserConfig::new(grpc, x_token, CommitmentLevel::Processed)
+        .slots(
+          "slots",
+          SubscribeRequestFilterSlots {
+            filter_by_commitment: Some(true),
+          },
+        )
+        // our own transactions, to confirm them as soon as they land
+        .transactions(
+          "confirmations",
+          ConfirmationTracker::geyser_filter(&self.signer.pubkey()),
+        )
+        // markets, oracles and our user
+        .accounts(
+          "accounts",
+          SubscribeRequestFilterAccounts {
+            account: account_filter.into_iter().map(|k| k.to_string()).collect(),
+            owner: vec![],
+            filters: vec![],
+          },
+          &[FilterRoute::Cache],
+        )
+        // every user's margin is computed from the cache
+        .accounts(
+          "users",
+          SubscribeRequestFilterAccounts {
+            account: vec![],
+            owner: vec![id().to_string()],
+            filters: vec![DriftUtils::grpc_users_filter()],
+          },
+          &[FilterRoute::Cache],
+        ),
+    )
+  }
+
+  pub async fn start(&mut self) -> anyhow::Result<()> {
+    self.drift.setup_user().await?;
+
+    // decode each user as it changes rather than all of them every check.
+    // Watch before loading the cached users so no change in between is missed
+    let mut user_changes = self.cache.watch_all::<User>();
+    for user in self.cache().await.decoded_accounts::<User>(None)? {
+      self.update_user(user);
+    }
+    let mut interval = tokio::time::interval(Duration::from_millis(400));
+    loop {
+      tokio::select! {
+        Some(change) = user_changes.next() => {
+          self.update_user(change.new);
+          continue;
+        }
+        _ = interval.tick() => {}
+      }
+      // margins are stale while the geyser stream is down or recovering
+      if let Err(e) = self
+        .drift
+        .ready_to_trade(&self.health, &self.cache().await, &[])
+      {
+        warn!("🟡 {}, pause liquidating", e);
+        continue;
+      }
+      // taking over positions needs our own collateral
+      let free_collateral = match self.drift.user_margin(&self.cache().await, None) {
+        Ok(margin) => margin.free_collateral(),
+        Err(e) => {
+          warn!("🟡 failed to compute our margin, {}", e);
+          continue;
+        }
+      };
+      if free_collateral < self.min_free_collateral {
+        warn!(
+          "🟡 free collateral ${} below ${}, pause liquidating",
+          trunc!(free_collateral, 2),
+          self.min_free_collateral
+        );
+        continue;
+      }
+
+      let liquidations = match self.liquidatable().await {
+        Ok(res) => res,
+        Err(e) => {
+          debug!("nothing to liquidate, {}", e);
+          continue;
+        }
+      };
+      let retry_after = self.retry_after;
+      self.attempts.retain(|_, at| at.elapsed() < retry_after);
+
+      // another liquidator may have been first, so a failure is not fatal
+      for (user, liquidation) in liquidations {
+        if self.attempts.contains_key(&user) {
+          continue;
+        }
+        self.attempts.insert(user, Instant::now());
+        if let Err(e) = self.liquidate(&user, liquidation).await {
+          error!("Failed to liquidate {}: {:?}", shorten_address(&user), e);
+        }
+      }
+    }
+  }
+
+  /// Keep the newest state of every user but ours
+  fn update_user(&mut self, user: DecodedAcctCtx<User>) {
+    if user.key == *self.user() {
+      return;
+    }
+    match self.users.get(&user.key) {
+      Some((slot, _)) if *slot > user.slot => {}
+      _ => {
+        self.users.insert(user.key, (user.slot, user.decoded));
+      }
+    }
+  }
+
+  /// Users below their maintenance margin and the position to liquidate of each
+  async fn liquidatable(&self) -> anyhow::Result<Vec<(Pubkey, Liquidation)>> {
+    let markets = MarginMarkets::all(&self.cache().await, None)?;
+    let mut liquidations = vec![];
+    for (key, (_, user)) in self.users.iter() {
+      // a bankrupt user is resolved rather than liquidated, and one being liquidated is taken already
+      if DriftUtils::user_bankrupt(user) || DriftUtils::user_being_liquidated(user) {
+        continue;
+      }
+      let margin = match UserMargin::new(user, &markets) {
+        Ok(margin) => margin,
+        Err(e) => {
+          debug!("skip {}, {}", shorten_address(key), e);
+          continue;
+        }
+      };
+      if !margin.can_be_liquidated() {
+        continue;
+      }
+      if let Some(liquidation) = Liquidation::select(user, &markets) {
+        info!(
+          "🔴 {} health {}, {:?}",
+          shorten_address(key),
+          trunc!(margin.health(), 4),
+          liquidation
+        );
+        liquidations.push((*key, liquidation));
+      }
+    }
+    Ok(liquidations)
+  }
+
+  async fn liquidate(&self, user: &Pubkey, liquidation: Liquidation) -> anyhow::Result<()> {
+    let mut trx = self.new_tx();
+    {
+      let cache = self.cache().await;
+      let user_account = cache.decoded_account::<User>(user, None)?.decoded;
+      let liability_decimals = |market_index: u16| -> anyhow::Result<i32> {
+        let market = cache
+          .decoded_account::<SpotMarket>(&MarketId::spot(market_index).key(), None)?
+          .decoded;
+        Ok(market.decimals as i32)
+      };
+      match liquidation {
+        Liquidation::Perp { market_index } => {
+          let max_base = (self.max_base * BASE_PRECISION as f64) as u64;
+          self
+            .drift
+            .liquidate_perp_ix(
+              &cache,
+              user,
+              &user_account,
+              market_index,
+              max_base,
+              None,
+              &mut trx,
+            )
+            .await?;
+        }
+        Liquidation::Spot {
+          asset_market_index,
+          liability_market_index,
+        } => {
+          let decimals = liability_decimals(liability_market_index)?;
+          let max_liability = (self.max_liability * 10_f64.powi(decimals)) as u128;
+          self
+            .drift
+            .liquidate_spot_ix(
+              &cache,
+              user,
+              &user_account,
+              asset_market_index,
+              liability_market_index,
+              max_liability,
+              None,
+              &mut trx,
+            )
+            .await?;
+        }
+        Liquidation::BorrowForPerpPnl {
+          perp_market_index,
+          spot_market_index,
+        } => {
+          let decimals = liability_decimals(spot_market_index)?;
+          let max_liability = (self.max_liability * 10_f64.powi(decimals)) as u128;
+          self
+            .drift
+            .liquidate_borrow_for_perp_pnl_ix(
+              &cache,
+              user,
+              &user_account,
+              perp_market_index,
+              spot_market_index,
+              max_liability,
+              None,
+              &mut trx,
+            )
+            .await?;
+        }
+      }
+    }
+    info!("🟢 liquidate {} {:?}", shorten_address(user), liquidation);
+    // bid the priority fee of the market other liquidators compete in
+    trx.send_tx(liquidation.market().key(), None).await
+  }
+
+  /// Stream these accounts from geyser for usage in the engine, and load the cache with `users`
+  pub async fn account_filter(
+    &self,
+    users: Vec<DecodedAcctCtx<User>>,
+  ) -> anyhow::Result<Vec<Pubkey>> {
+    // accounts to subscribe to
+    let perps = DriftUtils::perp_markets(&self.rpc()).await?;
+    let spots = DriftUtils::spot_markets(&self.rpc()).await?;
+    let perp_markets: Vec<Pubkey> = perps.iter().map(|p| p.key).collect();
+    let spot_markets: Vec<Pubkey> = spots.iter().map(|s| s.key).collect();
+    // all other users are streamed by the "users" memcmp filter
+    let user_keys = [*self.user()];
+    let perp_oracles: Vec<Pubkey> = perps.iter().map(|p| p.decoded.amm.oracle).collect();
+    let spot_oracles: Vec<Pubkey> = spots.iter().map(|s| s.decoded.oracle).collect();
+    let auths = [self.signer.pubkey()];
+    self
+      .cache
+      .write()
+      .await
+      .load_with_all_users(&self.rpc(), Some(users), None, &auths)
+      .await?;
+    let keys = perp_markets
+      .iter()
+      .chain(spot_markets.iter())
+      .chain(user_keys.iter())
+      .chain(perp_oracles.iter())
+      .chain(spot_oracles.iter())
+      .cloned()
+      .collect::<Vec<Pubkey>>();
+    Ok(keys)
+  }
+
+  pub fn new_tx(&self) -> KeypairTrx<'_> {
+    self.drift.new_tx(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nexus::drift_client::fixtures::zeroed;
+
+  fn spot(decimals: u32) -> SpotMarket {
+    let mut market: SpotMarket = zeroed();
+    market.decimals = decimals;
+    market.cumulative_deposit_interest = SPOT_CUMULATIVE_INTEREST_PRECISION;
+    market.cumulative_borrow_interest = SPOT_CUMULATIVE_INTEREST_PRECISION;
+    market
+  }
+
+  #[test]
+  fn select_liquidation() {
+    // USDC and SOL spot markets, SOL and a $10 perp market
+    let markets = MarginMarkets {
+      spot: HashMap::from([(0, (spot(6), 1_000_000)), (1, (spot(9), 100_000_000))]),
+      perp: HashMap::from([(0, (zeroed(), 100_000_000)), (1, (zeroed(), 10_000_000))]),
+    };
+    let mut user: User = zeroed();
+    assert!(Liquidation::select(&user, &markets).is_none());
+
+    // 1 SOL borrowed against $1000 and 0.5 SOL
+    let mut set_spot = |i: usize, market_index, balance_type, tokens: f64| {
+      let pos = &mut user.spot_positions[i];
+      pos.market_index = market_index;
+      pos.balance_type = balance_type;
+      pos.scaled_balance = (tokens * 1e9) as u64;
+    };
+    set_spot(0, 0, SpotBalanceType::Deposit, 1000.0);
+    set_spot(1, 1, SpotBalanceType::Deposit, 0.5);
+    set_spot(2, 1, SpotBalanceType::Borrow, 1.0);
+    assert!(matches!(
+      Liquidation::select(&user, &markets),
+      Some(Liquidation::Spot {
+        asset_market_index: 0,
+        liability_market_index: 1
+      })
+    ));
+
+    // without deposits the borrow is taken over for the pnl of a closed position
+    user.spot_positions[0].scaled_balance = 0;
+    user.spot_positions[1].scaled_balance = 0;
+    user.perp_positions[0].quote_asset_amount = 50_000_000;
+    assert!(matches!(
+      Liquidation::select(&user, &markets),
+      Some(Liquidation::BorrowForPerpPnl {
+        perp_market_index: 0,
+        spot_market_index: 1
+      })
+    ));
+
+    // open perp positions come first, the largest by value: $200 short over $100 long
+    user.perp_positions[0].base_asset_amount = BASE_PRECISION as i64;
+    user.perp_positions[1].market_index = 1;
+    user.perp_positions[1].base_asset_amount = -20 * BASE_PRECISION as i64;
+    assert!(matches!(
+      Liquidation::select(&user, &markets),
+      Some(Liquidation::Perp { market_index: 1 })
+    ));
+  }
+}
@@ -0,0 +1,16 @@
+use engine::*;
+use nexus::*;
+
+mod config;
+mod engine;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+  dotenv::dotenv().ok();
+  init_logger();
+
+  let mut liquidator = Engine::new(0, None).await?;
+  liquidator.start().await?;
+
+  Ok(())
+}